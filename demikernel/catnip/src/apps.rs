@@ -0,0 +1,129 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Applications served by a core.
+//!
+//! Each application receives its requests on one socket, and is known to the NIC by an app id.
+//! The NIC steers requests to applications by destination port (its MAT pipeline), balances them
+//! over cores according to a load balancer priority, and the core schedules them according to a
+//! scheduling priority. An [AppSpec] gathers all of these, so that they are set up together.
+
+use crate::{fail::Fail, file_table::FileDescriptor};
+use std::collections::HashMap;
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Everything the NIC and the scheduler need to know about an application.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AppSpec {
+    /// Application identifier known to the NIC.
+    pub app_id: u16,
+    /// Destination port the NIC steers to the application.
+    pub port: u16,
+    /// Load balancer priority. Larger numbers mean higher priority.
+    pub nic_priority: u8,
+    /// Scheduling priority. Smaller numbers mean higher priority.
+    pub sched_priority: u8,
+}
+
+/// Applications bound to sockets, with lookups both ways.
+pub struct AppTable {
+    specs: HashMap<FileDescriptor, AppSpec>,
+    fds: HashMap<u16, FileDescriptor>,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [AppTable].
+impl AppTable {
+    pub fn new() -> Self {
+        Self {
+            specs: HashMap::new(),
+            fds: HashMap::new(),
+        }
+    }
+
+    /// Binds the application described by `spec` to socket `fd`. Neither of them may be bound
+    /// already.
+    pub fn bind(&mut self, fd: FileDescriptor, spec: AppSpec) -> Result<(), Fail> {
+        if self.specs.contains_key(&fd) {
+            return Err(Fail::ResourceBusy {
+                details: "Socket already bound to an application",
+            });
+        }
+        if self.fds.contains_key(&spec.app_id) {
+            return Err(Fail::ResourceBusy {
+                details: "Application already bound to a socket",
+            });
+        }
+        self.specs.insert(fd, spec);
+        self.fds.insert(spec.app_id, fd);
+        Ok(())
+    }
+
+    /// Unbinds the application of socket `fd`, if any.
+    pub fn unbind(&mut self, fd: FileDescriptor) -> Option<AppSpec> {
+        let spec = self.specs.remove(&fd)?;
+        self.fds.remove(&spec.app_id);
+        Some(spec)
+    }
+
+    /// Returns the application bound to socket `fd`, if any.
+    pub fn spec(&self, fd: FileDescriptor) -> Option<AppSpec> {
+        self.specs.get(&fd).cloned()
+    }
+
+    /// Returns the socket application `app_id` is bound to, if any.
+    pub fn fd(&self, app_id: u16) -> Option<FileDescriptor> {
+        self.fds.get(&app_id).cloned()
+    }
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+/// Default trait implementation for [AppTable].
+impl Default for AppTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::{AppSpec, AppTable};
+
+    fn spec(app_id: u16) -> AppSpec {
+        AppSpec {
+            app_id,
+            port: 1000 + app_id,
+            nic_priority: 0,
+            sched_priority: 0,
+        }
+    }
+
+    #[test]
+    fn bind_unbind() {
+        let mut apps = AppTable::new();
+        apps.bind(10, spec(1)).unwrap();
+        assert_eq!(apps.spec(10), Some(spec(1)));
+        assert_eq!(apps.fd(1), Some(10));
+
+        // Sockets and applications are bound one to one.
+        assert!(apps.bind(10, spec(2)).is_err());
+        assert!(apps.bind(11, spec(1)).is_err());
+
+        assert_eq!(apps.unbind(10), Some(spec(1)));
+        assert_eq!(apps.fd(1), None);
+        apps.bind(11, spec(1)).unwrap();
+    }
+}
@@ -37,13 +37,13 @@ impl RuntimeBuf for Bytes {
         Self::default()
     }
 
-    fn from_slice(src: &[u8]) -> Self {
+    fn from_slice(src: &[u8]) -> Result<Self, Fail> {
         let buf: Arc<[u8]> = src.into();
-        Self {
+        Ok(Self {
             buf: Some(buf),
             offset: 0,
             len: src.len(),
-        }
+        })
     }
 
     /// Drops the first `n` bytes of the target buffer.
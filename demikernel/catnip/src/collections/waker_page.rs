@@ -6,6 +6,10 @@
 //! the ith task in that page. This way fast bit arithmetic can be used to index into a task's
 //! state and uniquely identify a task among multiple pages.
 use crate::sync::{SharedWaker, WakerU64};
+#[cfg(feature = "scheduler-stats")]
+use crate::preemption::rdtsc;
+#[cfg(feature = "scheduler-stats")]
+use std::cell::Cell;
 use std::{
     alloc::{Allocator, Global, Layout},
     mem,
@@ -34,6 +38,12 @@ pub struct WakerPage {
     completed: WakerU64,
     dropped: WakerU64,
     waker: SharedWaker,
+    /// TSC reading of the first notification of each future since it was last polled.
+    #[cfg(feature = "scheduler-stats")]
+    notified_at: Box<[Cell<u64>; WAKER_PAGE_SIZE]>,
+    #[cfg(feature = "scheduler-stats")]
+    _unused: [u8; 16],
+    #[cfg(not(feature = "scheduler-stats"))]
     _unused: [u8; 24],
 }
 
@@ -51,12 +61,16 @@ impl WakerPage {
             ptr::write(&mut page.completed as *mut _, WakerU64::new(0));
             ptr::write(&mut page.dropped as *mut _, WakerU64::new(0));
             ptr::write(&mut page.waker as *mut _, waker);
+            #[cfg(feature = "scheduler-stats")]
+            ptr::write(&mut page.notified_at as *mut _, Box::new(mem::zeroed()));
         }
         WakerPageRef(ptr)
     }
 
     pub fn notify(&self, ix: usize) {
         debug_assert!(ix < 64);
+        #[cfg(feature = "scheduler-stats")]
+        self.stamp_notified(ix);
         self.notified.fetch_or(1 << ix);
         self.waker.wake();
     }
@@ -72,6 +86,12 @@ impl WakerPage {
         notified
     }
 
+    /// Marks the futures in `mask` as notified again without waking the scheduler. Used to carry
+    /// notifications that were taken but not served over to the next poll.
+    pub fn restore_notified(&self, mask: u64) {
+        self.notified.fetch_or(mask);
+    }
+
     pub fn has_completed(&self, ix: usize) -> bool {
         debug_assert!(ix < 64);
         self.completed.load() & (1 << ix) != 0
@@ -99,11 +119,29 @@ impl WakerPage {
 
     pub fn initialize(&self, ix: usize) {
         debug_assert!(ix < 64);
+        #[cfg(feature = "scheduler-stats")]
+        self.notified_at[ix].set(rdtsc());
         self.notified.fetch_or(1 << ix);
         self.completed.fetch_and(!(1 << ix));
         self.dropped.fetch_and(!(1 << ix));
     }
 
+    /// Records when the future at `ix` got notified, unless it already was.
+    #[cfg(feature = "scheduler-stats")]
+    fn stamp_notified(&self, ix: usize) {
+        if self.notified.load() & (1 << ix) == 0 {
+            self.notified_at[ix].set(rdtsc());
+        }
+    }
+
+    /// Returns the TSC reading of the first notification of the future at `ix` since it was last
+    /// polled.
+    #[cfg(feature = "scheduler-stats")]
+    pub fn notified_at(&self, ix: usize) -> u64 {
+        debug_assert!(ix < 64);
+        self.notified_at[ix].get()
+    }
+
     pub fn clear(&self, ix: usize) {
         debug_assert!(ix < 64);
         let mask = !(1 << ix);
@@ -288,4 +326,22 @@ mod tests {
 
         assert_eq!(p.take_notified(), 1 << 16);
     }
+
+    #[test]
+    fn test_restore_notified() {
+        let waker = SharedWaker::new();
+        let p = WakerPage::new(waker);
+
+        p.initialize(1);
+        p.initialize(2);
+        p.mark_dropped(2);
+
+        let notified = p.take_notified();
+        assert_eq!(notified, 1 << 1);
+        assert_eq!(p.take_notified(), 0);
+
+        p.restore_notified(notified);
+        assert_eq!(p.take_notified(), 1 << 1);
+        assert_eq!(p.take_dropped(), 1 << 2);
+    }
 }
@@ -9,10 +9,17 @@ use crate::{
         arp,
         ethernet2::frame::{EtherType2, Ethernet2Header},
         ipv4,
-        tcp::operations::{AcceptFuture, ConnectFuture, PopFuture, PushFuture},
-        udp::{UdpOperation, UdpPopFuture},
+        tcp::{
+            operations::{AcceptFuture, ConnectFuture, PopFuture, PushFuture},
+            KeepAlive,
+        },
+        udp::{
+            QueuePolicy, QueueStats, ReusePortPolicy, StealGroup, UdpOperation, UdpPopBatchFuture,
+            UdpPopFuture,
+        },
         Protocol,
     },
+    rpc::{RpcClient, RpcOptions, RpcServer},
     runtime::Runtime,
     scheduler::Operation,
 };
@@ -55,7 +62,10 @@ impl<RT: Runtime> Engine<RT> {
     pub fn receive(&mut self, bytes: RT::Buf) -> Result<(), Fail> {
         let (header, payload) = Ethernet2Header::parse(bytes)?;
         debug!("Engine received {:?}", header);
-        if self.rt.local_link_addr() != header.dst_addr && !header.dst_addr.is_broadcast() {
+        if self.rt.local_link_addr() != header.dst_addr
+            && !header.dst_addr.is_broadcast()
+            && !self.ipv4.accepts_link_addr(header.dst_addr)
+        {
             return Err(Fail::Ignored {
                 details: "Physical dst_addr mismatch",
             });
@@ -107,6 +117,56 @@ impl<RT: Runtime> Engine<RT> {
         }
     }
 
+    pub fn set_reuse_port(
+        &mut self,
+        fd: FileDescriptor,
+        policy: ReusePortPolicy,
+    ) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.set_reuse_port(fd, policy),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn join_multicast(&mut self, fd: FileDescriptor, group: Ipv4Addr) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.join_multicast(fd, group),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn leave_multicast(&mut self, fd: FileDescriptor, group: Ipv4Addr) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.leave_multicast(fd, group),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn set_keepalive(
+        &mut self,
+        fd: FileDescriptor,
+        keepalive: Option<KeepAlive>,
+    ) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::TcpSocket) => self.ipv4.tcp.set_keepalive(fd, keepalive),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn set_nodelay(&mut self, fd: FileDescriptor, nodelay: bool) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::TcpSocket) => self.ipv4.tcp.set_nodelay(fd, nodelay),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn set_cork(&mut self, fd: FileDescriptor, cork: bool) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::TcpSocket) => self.ipv4.tcp.set_cork(fd, cork),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
     pub fn accept(&mut self, fd: FileDescriptor) -> Result<Operation<RT>, Fail> {
         match self.file_table.get(fd) {
             Some(File::TcpSocket) => Ok(Operation::from(self.ipv4.tcp.accept(fd))),
@@ -191,6 +251,15 @@ impl<RT: Runtime> Engine<RT> {
         self.ipv4.udp.pop(fd)
     }
 
+    pub fn udp_popbatch(
+        &mut self,
+        fd: FileDescriptor,
+        max_batch: usize,
+        max_wait: Duration,
+    ) -> UdpPopBatchFuture<RT> {
+        self.ipv4.udp.popbatch(fd, max_batch, max_wait)
+    }
+
     pub fn pop(&mut self, fd: FileDescriptor) -> Result<Operation<RT>, Fail> {
         match self.file_table.get(fd) {
             Some(File::TcpSocket) => Ok(Operation::from(self.ipv4.tcp.pop(fd))),
@@ -202,16 +271,83 @@ impl<RT: Runtime> Engine<RT> {
         }
     }
 
-    pub fn popbatch(&mut self, fd: FileDescriptor) -> Result<Operation<RT>, Fail> {
+    pub fn popbatch(
+        &mut self,
+        fd: FileDescriptor,
+        max_batch: usize,
+        max_wait: Duration,
+    ) -> Result<Operation<RT>, Fail> {
         match self.file_table.get(fd) {
             Some(File::UdpSocket) => {
-                let udp_op = UdpOperation::PopBatch(ResultFuture::new(self.ipv4.udp.popbatch(fd)));
+                let future = self.ipv4.udp.popbatch(fd, max_batch, max_wait);
+                let udp_op = UdpOperation::PopBatch(ResultFuture::new(future));
                 Ok(Operation::Udp(udp_op))
             }
             _ => Err(Fail::BadFileDescriptor {}),
         }
     }
 
+    /// Returns the number of requests waiting in the receive queue of `fd`.
+    pub fn queue_len(&self, fd: FileDescriptor) -> Result<usize, Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.queue_len(fd),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn set_queue_capacity(
+        &mut self,
+        fd: FileDescriptor,
+        capacity: usize,
+        policy: QueuePolicy,
+    ) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.set_queue_capacity(fd, capacity, policy),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn queue_stats(&self, fd: FileDescriptor) -> Result<QueueStats, Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.queue_stats(fd),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn enable_steal(
+        &mut self,
+        fd: FileDescriptor,
+        group: StealGroup<Vec<u8>>,
+        core_id: u16,
+        threshold: usize,
+    ) -> Result<(), Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.enable_steal(fd, group, core_id, threshold),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn steal(&mut self, fd: FileDescriptor, max: usize) -> Result<usize, Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.steal(fd, max),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn take_origins(&mut self, fd: FileDescriptor, count: usize) -> Result<Vec<u16>, Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.take_origins(fd, count),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    pub fn take_dropped_origins(&mut self, fd: FileDescriptor) -> Result<Vec<u16>, Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => self.ipv4.udp.take_dropped_origins(fd),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
     pub fn close(&mut self, fd: FileDescriptor) -> Result<(), Fail> {
         match self.file_table.get(fd) {
             Some(File::TcpSocket) => self.ipv4.tcp.close(fd),
@@ -220,6 +356,40 @@ impl<RT: Runtime> Engine<RT> {
         }
     }
 
+    /// Creates an RPC client sending its requests from UDP socket `fd`.
+    pub fn rpc_client(
+        &self,
+        fd: FileDescriptor,
+        options: RpcOptions,
+    ) -> Result<RpcClient<RT>, Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => Ok(RpcClient::new(
+                self.rt.clone(),
+                self.ipv4.udp.clone(),
+                fd,
+                options,
+            )),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    /// Creates an RPC server receiving its requests on UDP socket `fd`.
+    pub fn rpc_server(
+        &self,
+        fd: FileDescriptor,
+        options: RpcOptions,
+    ) -> Result<RpcServer<RT>, Fail> {
+        match self.file_table.get(fd) {
+            Some(File::UdpSocket) => Ok(RpcServer::new(
+                self.rt.clone(),
+                self.ipv4.udp.clone(),
+                fd,
+                options,
+            )),
+            _ => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
     pub fn udp_socket(&mut self) -> Result<FileDescriptor, Fail> {
         self.ipv4.udp.socket()
     }
@@ -232,6 +402,14 @@ impl<RT: Runtime> Engine<RT> {
         self.ipv4.udp.bind(socket_fd, endpoint)
     }
 
+    pub fn udp_connect(
+        &mut self,
+        socket_fd: FileDescriptor,
+        remote_endpoint: ipv4::Endpoint,
+    ) -> Result<(), Fail> {
+        self.ipv4.udp.connect(socket_fd, remote_endpoint)
+    }
+
     pub fn tcp_socket(&mut self) -> FileDescriptor {
         self.ipv4.tcp.socket()
     }
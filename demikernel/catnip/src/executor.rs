@@ -0,0 +1,216 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Awaitable queue tokens and a small single-threaded executor to drive them.
+//!
+//! [LibOS] hands out [QTokenFuture]s for pops, pushes and connects, so applications can be
+//! written as ordinary `async fn`s. The [Executor] owns the [LibOS] and, on every iteration,
+//! polls the background work once and then each application task in priority order. Application
+//! tasks reach the [LibOS] through the shared handle returned by [Executor::libos], and must not
+//! hold it borrowed across an `.await`.
+
+use crate::{
+    file_table::FileDescriptor,
+    libos::{LibOS, QToken},
+    operations::OperationResult,
+    runtime::Runtime,
+};
+use futures::task::noop_waker_ref;
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Future for an operation represented by a queue token.
+pub struct QTokenFuture<RT: Runtime> {
+    rt: RT,
+    /// Queue token, until the operation has been taken out of the scheduler.
+    qt: Option<QToken>,
+}
+
+/// Application task of an [Executor].
+struct Task {
+    /// Priority. Smaller numbers are polled first.
+    prio: usize,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+/// Single-threaded executor for application tasks.
+pub struct Executor<RT: Runtime> {
+    libos: Rc<RefCell<LibOS<RT>>>,
+    /// Live tasks, sorted by priority.
+    tasks: Vec<Task>,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [QTokenFuture].
+impl<RT: Runtime> QTokenFuture<RT> {
+    pub fn new(rt: RT, qt: QToken) -> Self {
+        Self { rt, qt: Some(qt) }
+    }
+}
+
+/// Associate functions for [Executor].
+impl<RT: Runtime> Executor<RT> {
+    pub fn new(libos: LibOS<RT>) -> Self {
+        Self {
+            libos: Rc::new(RefCell::new(libos)),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Returns the handle application tasks use to reach the [LibOS].
+    pub fn libos(&self) -> Rc<RefCell<LibOS<RT>>> {
+        self.libos.clone()
+    }
+
+    /// Adds an application task with priority `prio`. Smaller numbers mean higher priority.
+    pub fn spawn<F: Future<Output = ()> + 'static>(&mut self, prio: usize, future: F) {
+        let ix = self.tasks.partition_point(|t| t.prio <= prio);
+        self.tasks.insert(
+            ix,
+            Task {
+                prio,
+                future: Box::pin(future),
+            },
+        );
+    }
+
+    /// Returns the number of application tasks that have not completed yet.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns `true` if all application tasks have completed.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Polls the background work once, then every application task in priority order.
+    pub fn run_once(&mut self) {
+        self.libos.borrow_mut().poll_bg_work();
+        // Tasks are polled on every iteration, so there is nothing to wake up.
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let mut ix = 0;
+        while ix < self.tasks.len() {
+            if self.tasks[ix].future.as_mut().poll(&mut ctx).is_ready() {
+                self.tasks.remove(ix);
+            } else {
+                ix += 1;
+            }
+        }
+    }
+
+    /// Runs until all application tasks have completed.
+    pub fn run(&mut self) {
+        while !self.tasks.is_empty() {
+            self.run_once();
+        }
+    }
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+/// Future trait implementation for [QTokenFuture].
+impl<RT: Runtime> Future for QTokenFuture<RT> {
+    type Output = (FileDescriptor, OperationResult<RT>);
+
+    fn poll(self: Pin<&mut Self>, _ctx: &mut Context) -> Poll<Self::Output> {
+        let self_ = self.get_mut();
+        let qt = self_.qt.expect("Polled after completion");
+        let handle = self_.rt.scheduler().from_raw_handle(qt).unwrap();
+        if !handle.has_completed() {
+            handle.into_raw();
+            return Poll::Pending;
+        }
+        self_.qt = None;
+        Poll::Ready(self_.rt.scheduler().take(handle).expect_result())
+    }
+}
+
+/// Drop trait implementation for [QTokenFuture].
+impl<RT: Runtime> Drop for QTokenFuture<RT> {
+    /// Cancels the operation if it has not been taken yet.
+    fn drop(&mut self) {
+        if let Some(qt) = self.qt.take() {
+            drop(self.rt.scheduler().from_raw_handle(qt));
+        }
+    }
+}
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::Executor;
+    use crate::{
+        collections::bytes::BytesMut,
+        libos::LibOS,
+        protocols::{ip, ipv4},
+        runtime::Runtime,
+        test_helpers,
+    };
+    use futures::task::{noop_waker_ref, Context};
+    use must_let::must_let;
+    use std::{convert::TryFrom, future::Future, pin::Pin, task::Poll, time::Instant};
+
+    #[test]
+    fn tasks_run_in_priority_order() {
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let now = Instant::now();
+        let libos = LibOS::new(test_helpers::new_alice2_runtime(now), 0, 1).unwrap();
+        let mut executor = Executor::new(libos);
+
+        // Setup Bob.
+        let mut bob = test_helpers::new_bob2(now);
+        let bob_port = ip::Port::try_from(80).unwrap();
+        let bob_addr = ipv4::Endpoint::new(test_helpers::BOB_IPV4, bob_port);
+        let bob_fd = bob.udp_socket().unwrap();
+        bob.udp_bind(bob_fd, bob_addr).unwrap();
+
+        // Each task sends a datagram to Bob from a port of its own. The task with the lower
+        // priority is spawned first.
+        for &(prio, port) in [(1, 81), (0, 80)].iter() {
+            let libos = executor.libos();
+            let port = ip::Port::try_from(port).unwrap();
+            let local = ipv4::Endpoint::new(test_helpers::ALICE_IPV4, port);
+            executor.spawn(prio, async move {
+                let fd = libos
+                    .borrow_mut()
+                    .socket(libc::AF_INET, libc::SOCK_DGRAM, 0)
+                    .unwrap();
+                libos.borrow_mut().bind(fd, local).unwrap();
+                let connect = libos.borrow_mut().connect_async(fd, bob_addr, prio).unwrap();
+                connect.await;
+                let buf = BytesMut::from(&vec![0x5a; 32][..]).freeze();
+                let push = libos.borrow_mut().push_async(fd, buf, prio).unwrap();
+                push.await;
+            });
+        }
+        assert_eq!(executor.len(), 2);
+        executor.run();
+
+        // The task with the higher priority sent first.
+        for &port in [80, 81].iter() {
+            let frame = executor.libos().borrow().rt().pop_frame();
+            bob.receive(frame).unwrap();
+            let mut pop_future = bob.udp_pop(bob_fd);
+            must_let!(let Poll::Ready(Ok((Some(remote), _))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+            assert_eq!(remote.port(), ip::Port::try_from(port).unwrap());
+        }
+    }
+}
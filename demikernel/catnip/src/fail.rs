@@ -31,6 +31,7 @@ custom_error! {#[derive(Clone, PartialEq)] pub Fail
     AddressFamilySupport {} = "address family not supported",
     SocketTypeSupport {} = "socket type not supported",
     BadFileDescriptor {} = "bad file descriptor",
+    PortUnreachable {} = "port unreachable",
 }
 
 impl From<IoError> for Fail {
@@ -92,6 +93,7 @@ impl Fail {
             Fail::AddressFamilySupport { .. } => libc::EAFNOSUPPORT,
             Fail::SocketTypeSupport { .. } => libc::ESOCKTNOSUPPORT,
             Fail::BadFileDescriptor { .. } => libc::EBADF,
+            Fail::PortUnreachable {} => libc::ECONNREFUSED,
         }
     }
 }
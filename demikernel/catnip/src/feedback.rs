@@ -0,0 +1,134 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Batched load feedback to the NIC.
+//!
+//! The NIC load balancer tracks how many requests of each application are outstanding on each
+//! queue, and learns about completed ones through feedback messages. Sockets are associated with
+//! the application they serve, and every request completed on them is credited to the queue it
+//! was received on. Credits are sent in batches once `threshold` of them pile up for a queue and
+//! application, and whatever is left gets flushed when the core runs idle.
+
+use crate::file_table::FileDescriptor;
+use std::collections::HashMap;
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Number of completions batched into a feedback message by default.
+pub const DEFAULT_FEEDBACK_BATCH: u16 = 2;
+
+/// Load feedback of all applications running on a core.
+pub struct Feedback {
+    /// Application served by each socket.
+    apps: HashMap<FileDescriptor, u16>,
+    /// Completions not reported yet, per queue and application.
+    pending: HashMap<(u16, u16), u16>,
+    /// Number of completions that triggers a feedback message.
+    threshold: u16,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [Feedback].
+impl Feedback {
+    pub fn new() -> Self {
+        Self {
+            apps: HashMap::new(),
+            pending: HashMap::new(),
+            threshold: DEFAULT_FEEDBACK_BATCH,
+        }
+    }
+
+    /// Associates socket `fd` with application `app_id`.
+    pub fn set_app(&mut self, fd: FileDescriptor, app_id: u16) {
+        self.apps.insert(fd, app_id);
+    }
+
+    /// Removes the application of socket `fd`, if any.
+    pub fn remove_app(&mut self, fd: FileDescriptor) {
+        self.apps.remove(&fd);
+    }
+
+    /// Returns the application served by socket `fd`, if any.
+    pub fn app(&self, fd: FileDescriptor) -> Option<u16> {
+        self.apps.get(&fd).cloned()
+    }
+
+    /// Sets the number of completions batched into a feedback message.
+    pub fn set_threshold(&mut self, threshold: u16) {
+        assert!(threshold > 0);
+        self.threshold = threshold;
+    }
+
+    /// Records `count` completed requests of application `app_id` received on `queue_id`. Once
+    /// enough of them are pending, they are handed to `send` as `(queue_id, app_id, count)`.
+    pub fn record<F: FnMut(u16, u16, u16)>(
+        &mut self,
+        queue_id: u16,
+        app_id: u16,
+        count: u16,
+        mut send: F,
+    ) {
+        let pending = self.pending.entry((queue_id, app_id)).or_insert(0);
+        *pending = pending.saturating_add(count);
+        if *pending >= self.threshold {
+            send(queue_id, app_id, *pending);
+            *pending = 0;
+        }
+    }
+
+    /// Hands all pending completions to `send`.
+    pub fn flush<F: FnMut(u16, u16, u16)>(&mut self, mut send: F) {
+        for (&(queue_id, app_id), pending) in self.pending.iter_mut() {
+            if *pending > 0 {
+                send(queue_id, app_id, *pending);
+                *pending = 0;
+            }
+        }
+    }
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+/// Default trait implementation for [Feedback].
+impl Default for Feedback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::Feedback;
+
+    #[test]
+    fn batch_and_flush() {
+        let mut feedback = Feedback::new();
+        feedback.set_threshold(3);
+        let mut sent = vec![];
+
+        // Completions are batched per queue and application.
+        feedback.record(0, 1, 2, |q, a, n| sent.push((q, a, n)));
+        feedback.record(4, 1, 1, |q, a, n| sent.push((q, a, n)));
+        assert!(sent.is_empty());
+        feedback.record(0, 1, 1, |q, a, n| sent.push((q, a, n)));
+        assert_eq!(sent, [(0, 1, 3)]);
+
+        // Leftovers go out on flush, and only once.
+        sent.clear();
+        feedback.flush(|q, a, n| sent.push((q, a, n)));
+        assert_eq!(sent, [(4, 1, 1)]);
+        feedback.flush(|q, a, n| sent.push((q, a, n)));
+        assert_eq!(sent.len(), 1);
+    }
+}
@@ -28,9 +28,12 @@ extern crate derive_more;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod apps;
 pub mod collections;
 pub mod engine;
+pub mod executor;
 pub mod fail;
+pub mod feedback;
 pub mod file_table;
 mod futures_utility;
 pub mod interop;
@@ -38,7 +41,9 @@ pub mod libos;
 pub mod logging;
 pub mod operations;
 pub mod options;
+pub mod preemption;
 pub mod protocols;
+pub mod rpc;
 pub mod runtime;
 pub mod scheduler;
 pub mod sync;
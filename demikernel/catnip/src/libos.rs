@@ -7,22 +7,31 @@
 //#[macro_use]
 //extern crate lazy_static;
 use crate::{
+    apps::{AppSpec, AppTable},
     engine::Engine,
+    executor::QTokenFuture,
     fail::Fail,
+    feedback::Feedback,
     file_table::FileDescriptor,
     interop::{dmtr_qresult_t, dmtr_sgarray_t},
     operations::OperationResult,
+    preemption::{PreemptBudget, PreemptToken, Preemption},
     protocols::ipv4::Endpoint,
+    protocols::tcp::KeepAlive,
+    protocols::udp::{QueuePolicy, QueueStats, ReusePortPolicy, StealGroup},
     protocols::Protocol,
+    rpc::{RpcClient, RpcOptions, RpcServer},
     runtime::{Runtime,RECEIVE_BATCH_SIZE},
-    scheduler::{Operation, SchedulerHandle},
+    scheduler::{Operation, SchedulerHandle, SchedulerStats},
 };
+#[cfg(feature = "scheduler-stats")]
+use crate::scheduler::TaskStats;
 use arrayvec::ArrayVec;
 use libc::c_int;
 use must_let::must_let;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::time::{UNIX_EPOCH, SystemTime};
-use std::{collections::HashMap};
+use std::{collections::HashMap, net::Ipv4Addr};
 use std::sync::Mutex;
 #[cfg(feature = "profiler")]
 use perftools::timer;
@@ -35,20 +44,40 @@ const TIMER_RESOLUTION: usize = 64;
 const MAX_RECV_ITERS: usize = 2;
 const MAX_CHANNEL_NUM: usize = 64;
 const MAX_APP_NUM: usize = 16;
+/// Maximum number of requests stolen from sibling cores per socket and iteration.
+const MAX_STEAL_BATCH: usize = 4;
 /// Queue Token for our IO Queue abstraction. Analogous to a file descriptor in POSIX.
 pub type QToken = u64;
 
 pub struct LibOS<RT: Runtime> {
     engine: Engine<RT>,
     rt: RT,
+    core_id: u16,
     ts_iters: usize,
     msg_send_channels: ArrayVec<Sender<(u16, u16, u8)>, MAX_CHANNEL_NUM>,
     msg_recv_channels: Receiver<(u16, u16, u8)>,
     total_usable_cores: u16,
     usable_core_mask: u64,
+    preempt: Preemption,
+    /// Sockets that steal requests from sibling cores when idle.
+    stealing: Vec<FileDescriptor>,
+    /// Load feedback owed to the NIC for completed requests.
+    feedback: Feedback,
+    /// Applications bound to sockets.
+    apps: AppTable,
+    /// Pushes of replies whose requests get credited once they complete.
+    pending_pushes: Vec<PendingPush>,
     // bitmask: Arc<ArrayVec<[u8; MAX_CHANNEL_NUM], MAX_APP_NUM>>,
 }
 
+/// Push of a reply, with the load feedback owed once it completes.
+struct PendingPush {
+    qt: QToken,
+    app_id: u16,
+    /// `(core_id, count)` pairs of requests answered by the push.
+    credits: ArrayVec<(u16, u16), MAX_CHANNEL_NUM>,
+}
+
 lazy_static! {
     static ref app_to_core_bitmasks: Mutex<HashMap<u16, u64>> = {
         let mut m = HashMap::new();
@@ -87,11 +116,17 @@ impl<RT: Runtime> LibOS<RT> {
         Ok(Self {
             engine,
             rt,
+            core_id: core_id as u16,
             ts_iters: 0,
             msg_send_channels: sender,
             msg_recv_channels: receiver,
             total_usable_cores: core_count,
             usable_core_mask: 0xffffffffffffffff << (core_count),
+            preempt: Preemption::new(),
+            stealing: Vec::new(),
+            feedback: Feedback::new(),
+            apps: AppTable::new(),
+            pending_pushes: Vec::new(),
             // bitmask: app_to_core_bitmasks.clone(),
         })
     }
@@ -162,6 +197,114 @@ impl<RT: Runtime> LibOS<RT> {
         self.engine.bind(fd, local)
     }
 
+    ///
+    /// **Brief**
+    ///
+    /// Lets the UDP socket referred to by `fd` share its local endpoint with other sockets of
+    /// this LibOS, in the spirit of `SO_REUSEPORT`. This must be done before [bind](Self::bind).
+    /// Incoming datagrams are distributed over the sockets bound to the same endpoint according
+    /// to the `policy` of the first one bound.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, `Ok(())` is returned. Upon failure, `Fail` is
+    /// returned instead.
+    ///
+    pub fn set_reuse_port(&mut self, fd: FileDescriptor, policy: ReusePortPolicy) -> Result<(), Fail> {
+        trace!("set_reuse_port(): fd={:?} policy={:?}", fd, policy);
+        self.engine.set_reuse_port(fd, policy)
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Makes the UDP socket referred to by `fd` receive datagrams sent to the multicast `group`
+    /// on the port it is bound to. The socket should be bound to either the wildcard address or
+    /// `group` itself. Multicast routers learn about the group through IGMP membership reports.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, `Ok(())` is returned. Upon failure, `Fail` is
+    /// returned instead.
+    ///
+    pub fn join_multicast(&mut self, fd: FileDescriptor, group: Ipv4Addr) -> Result<(), Fail> {
+        trace!("join_multicast(): fd={:?} group={:?}", fd, group);
+        self.engine.join_multicast(fd, group)
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Stops the UDP socket referred to by `fd` from receiving datagrams sent to the multicast
+    /// `group`. Closing the socket leaves all the groups it joined.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, `Ok(())` is returned. Upon failure, `Fail` is
+    /// returned instead.
+    ///
+    pub fn leave_multicast(&mut self, fd: FileDescriptor, group: Ipv4Addr) -> Result<(), Fail> {
+        trace!("leave_multicast(): fd={:?} group={:?}", fd, group);
+        self.engine.leave_multicast(fd, group)
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Sets the keepalive settings of the established TCP connection referred to by `fd`,
+    /// overriding the defaults from the runtime's TCP options. Once the connection has been idle
+    /// for `keepalive.idle`, we send up to `keepalive.probes` probes, `keepalive.interval` apart.
+    /// If none is answered, the connection is aborted and pending pops fail with
+    /// `ConnectionAborted`. Passing `None` disables keepalive.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, `Ok(())` is returned. Upon failure, `Fail` is
+    /// returned instead.
+    ///
+    pub fn set_keepalive(&mut self, fd: FileDescriptor, keepalive: Option<KeepAlive>) -> Result<(), Fail> {
+        trace!("set_keepalive(): fd={:?} keepalive={:?}", fd, keepalive);
+        self.engine.set_keepalive(fd, keepalive)
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Disables Nagle's algorithm on the TCP socket referred to by `fd` if `nodelay` is set, or
+    /// enables it otherwise. With Nagle's algorithm, small pushes wait for outstanding data to be
+    /// acknowledged and get coalesced into larger segments, which saves bandwidth at the expense
+    /// of latency. The default comes from the runtime's TCP options, and leaves it disabled. A
+    /// socket which isn't connected yet applies the setting once it is, and a listening one to
+    /// each connection it accepts.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, `Ok(())` is returned. Upon failure, `Fail` is
+    /// returned instead.
+    ///
+    pub fn set_nodelay(&mut self, fd: FileDescriptor, nodelay: bool) -> Result<(), Fail> {
+        trace!("set_nodelay(): fd={:?} nodelay={:?}", fd, nodelay);
+        self.engine.set_nodelay(fd, nodelay)
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Corks the TCP socket referred to by `fd` if `cork` is set, so that only full-sized
+    /// segments are sent, regardless of Nagle's algorithm. Uncorking sends whatever was held
+    /// back. Closing the connection sends it as well. Like [set_nodelay](Self::set_nodelay), this
+    /// may be called before the socket is connected.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, `Ok(())` is returned. Upon failure, `Fail` is
+    /// returned instead.
+    ///
+    pub fn set_cork(&mut self, fd: FileDescriptor, cork: bool) -> Result<(), Fail> {
+        trace!("set_cork(): fd={:?} cork={:?}", fd, cork);
+        self.engine.set_cork(fd, cork)
+    }
+
     ///
     /// **Brief**
     ///
@@ -232,6 +375,20 @@ impl<RT: Runtime> LibOS<RT> {
         Ok(self.rt.scheduler().insert(future).into_raw())
     }
 
+    /// Similar to [connect](Self::connect) but schedules the operation with priority `prio`.
+    pub fn connectprio(
+        &mut self,
+        fd: FileDescriptor,
+        remote: Endpoint,
+        prio: usize,
+    ) -> Result<QToken, Fail> {
+        #[cfg(feature = "profiler")]
+        timer!("catnip::connectprio");
+        trace!("connectprio(): fd={:?} remote={:?} prio={:?}", fd, remote, prio);
+        let future = self.engine.connect(fd, remote)?;
+        Ok(self.rt.scheduler().insert_page(future, prio).into_raw())
+    }
+
     ///
     /// **Brief**
     ///
@@ -246,6 +403,7 @@ impl<RT: Runtime> LibOS<RT> {
         #[cfg(feature = "profiler")]
         timer!("catnip::close");
         trace!("close(): fd={:?}", fd);
+        self.unbind_app(fd);
         self.engine.close(fd)
     }
 
@@ -263,7 +421,9 @@ impl<RT: Runtime> LibOS<RT> {
             });
         }
         let future = self.engine.push(fd, buf)?;
-        Ok(self.rt.scheduler().insert(future).into_raw())
+        let qt = self.rt.scheduler().insert(future).into_raw();
+        self.complete_on_push(fd, 1, qt);
+        Ok(qt)
     }
 
     /// Similar to [push](Self::push) but uses a [Runtime]-specific buffer instead of the
@@ -278,7 +438,30 @@ impl<RT: Runtime> LibOS<RT> {
             });
         }
         let future = self.engine.push(fd, buf)?;
-        Ok(self.rt.scheduler().insert(future).into_raw())
+        let qt = self.rt.scheduler().insert(future).into_raw();
+        self.complete_on_push(fd, 1, qt);
+        Ok(qt)
+    }
+
+    /// Similar to [push2](Self::push2) but schedules the operation with priority `prio`.
+    pub fn pushprio(
+        &mut self,
+        fd: FileDescriptor,
+        buf: RT::Buf,
+        prio: usize,
+    ) -> Result<QToken, Fail> {
+        #[cfg(feature = "profiler")]
+        timer!("catnip::pushprio");
+        trace!("pushprio(): fd={:?} prio={:?}", fd, prio);
+        if buf.len() == 0 {
+            return Err(Fail::Invalid {
+                details: "zero-length buffer",
+            });
+        }
+        let future = self.engine.push(fd, buf)?;
+        let qt = self.rt.scheduler().insert_page(future, prio).into_raw();
+        self.complete_on_push(fd, 1, qt);
+        Ok(qt)
     }
 
     pub fn pushto(
@@ -296,7 +479,9 @@ impl<RT: Runtime> LibOS<RT> {
             });
         }
         let future = self.engine.pushto(fd, buf, to)?;
-        Ok(self.rt.scheduler().insert(future).into_raw())
+        let qt = self.rt.scheduler().insert(future).into_raw();
+        self.complete_on_push(fd, 1, qt);
+        Ok(qt)
     }
 
     pub fn pushto2(
@@ -313,7 +498,9 @@ impl<RT: Runtime> LibOS<RT> {
             });
         }
         let future = self.engine.pushto(fd, buf, to)?;
-        Ok(self.rt.scheduler().insert(future).into_raw())
+        let qt = self.rt.scheduler().insert(future).into_raw();
+        self.complete_on_push(fd, 1, qt);
+        Ok(qt)
     }
 
     pub fn dyield(
@@ -341,7 +528,9 @@ impl<RT: Runtime> LibOS<RT> {
                 details: "zero-length buffer",
             });
         }
-        self.engine.pushto(fd, buf, to)
+        let op = self.engine.pushto(fd, buf, to)?;
+        self.complete_requests(fd, 1);
+        Ok(op)
     }
 
     pub fn directbatchpushto2(
@@ -356,7 +545,10 @@ impl<RT: Runtime> LibOS<RT> {
                 details: "zero-length buffer",
             });
         }
-        self.engine.batchpushto(fd, batch)
+        let count = batch.len() as u16;
+        let op = self.engine.batchpushto(fd, batch)?;
+        self.complete_requests(fd, count);
+        Ok(op)
     }
 
     ///
@@ -368,6 +560,8 @@ impl<RT: Runtime> LibOS<RT> {
     pub fn drop_qtoken(&mut self, qt: QToken) {
         #[cfg(feature = "profiler")]
         timer!("catnip::drop_qtoken");
+        // Cancelled pushes never complete their requests.
+        self.pending_pushes.retain(|p| p.qt != qt);
         drop(self.rt.scheduler().from_raw_handle(qt).unwrap());
     }
 
@@ -395,18 +589,87 @@ impl<RT: Runtime> LibOS<RT> {
         Ok(self.rt.scheduler().insert_page(future, prio).into_raw())
     }
 
-
-    pub fn popbatch(&mut self, fd: FileDescriptor, prio: usize) -> Result<QToken, Fail> {
+    ///
+    /// **Brief**
+    ///
+    /// Pops up to `max_batch` datagrams from the socket referred to by `fd`. Once some datagrams
+    /// are available, the operation waits up to `max_wait` for the batch to fill up before
+    /// completing with whatever is queued. A `max_wait` of zero completes right away.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, a queue token is returned. This token can be used to wait for
+    /// the batch to arrive. Upon failure, `Fail` is returned instead.
+    ///
+    pub fn popbatch(
+        &mut self,
+        fd: FileDescriptor,
+        prio: usize,
+        max_batch: usize,
+        max_wait: Duration,
+    ) -> Result<QToken, Fail> {
         #[cfg(feature = "profiler")]
-        timer!("catnip::pop");
+        timer!("catnip::popbatch");
 
-        trace!("pop(): fd={:?}", fd);
+        trace!("popbatch(): fd={:?} max_batch={:?} max_wait={:?}", fd, max_batch, max_wait);
+
+        if max_batch == 0 {
+            return Err(Fail::Invalid {
+                details: "Zero batch size",
+            });
+        }
 
-        let future = self.engine.popbatch(fd)?;
+        let future = self.engine.popbatch(fd, max_batch, max_wait)?;
 
         Ok(self.rt.scheduler().insert_page(future, prio).into_raw())
     }
 
+    /// Returns a future that resolves once the operation represented by `qt` completes. The
+    /// future takes over `qt`: dropping it before completion cancels the operation.
+    pub fn qtoken_future(&self, qt: QToken) -> QTokenFuture<RT> {
+        QTokenFuture::new(self.rt.clone(), qt)
+    }
+
+    /// Similar to [popprio](Self::popprio) but returns a future instead of a queue token.
+    pub fn pop_async(&mut self, fd: FileDescriptor, prio: usize) -> Result<QTokenFuture<RT>, Fail> {
+        let qt = self.popprio(fd, prio)?;
+        Ok(self.qtoken_future(qt))
+    }
+
+    /// Similar to [popbatch](Self::popbatch) but returns a future instead of a queue token.
+    pub fn popbatch_async(
+        &mut self,
+        fd: FileDescriptor,
+        prio: usize,
+        max_batch: usize,
+        max_wait: Duration,
+    ) -> Result<QTokenFuture<RT>, Fail> {
+        let qt = self.popbatch(fd, prio, max_batch, max_wait)?;
+        Ok(self.qtoken_future(qt))
+    }
+
+    /// Similar to [pushprio](Self::pushprio) but returns a future instead of a queue token.
+    pub fn push_async(
+        &mut self,
+        fd: FileDescriptor,
+        buf: RT::Buf,
+        prio: usize,
+    ) -> Result<QTokenFuture<RT>, Fail> {
+        let qt = self.pushprio(fd, buf, prio)?;
+        Ok(self.qtoken_future(qt))
+    }
+
+    /// Similar to [connectprio](Self::connectprio) but returns a future instead of a queue token.
+    pub fn connect_async(
+        &mut self,
+        fd: FileDescriptor,
+        remote: Endpoint,
+        prio: usize,
+    ) -> Result<QTokenFuture<RT>, Fail> {
+        let qt = self.connectprio(fd, remote, prio)?;
+        Ok(self.qtoken_future(qt))
+    }
+
     // If this returns a result, `qt` is no longer valid.
     pub fn poll(&mut self, qt: QToken) -> Option<dmtr_qresult_t> {
         #[cfg(feature = "profiler")]
@@ -506,10 +769,76 @@ impl<RT: Runtime> LibOS<RT> {
         }
     }
 
+    ///
+    /// **Brief**
+    ///
+    /// Binds the application described by `spec` to the socket referred to by `fd`, on this
+    /// core. The NIC is told to steer requests sent to `spec.port` to the application and to
+    /// balance them onto this core, the core allocator learns that the application runs here,
+    /// load feedback for replies pushed to `fd` is credited to the application, and preemption
+    /// uses `spec.sched_priority` to tell whether higher-priority requests are pending.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, `Ok(())` is returned. Upon failure, `Fail` is
+    /// returned instead.
+    ///
+    pub fn bind_app(&mut self, fd: FileDescriptor, spec: AppSpec) -> Result<(), Fail> {
+        trace!("bind_app(): fd={:?} spec={:?}", fd, spec);
+        if spec.app_id as usize >= MAX_APP_NUM {
+            return Err(Fail::OutOfRange {
+                details: "Application id",
+            });
+        }
+        self.apps.bind(fd, spec)?;
+        // The NIC registrations cannot fail, so they come once nothing else can.
+        if let Err(e) = self.core_alloc_reg_app(self.core_id, spec.app_id) {
+            self.apps.unbind(fd);
+            return Err(e);
+        }
+        self.rt
+            .config_app_mat(spec.app_id, spec.port, spec.sched_priority);
+        self.rt
+            .register_app(self.core_id, spec.app_id, spec.nic_priority);
+        self.feedback.set_app(fd, spec.app_id);
+        self.preempt
+            .register(spec.app_id, fd, spec.sched_priority as usize);
+        Ok(())
+    }
+
+    /// Undoes the registrations of [bind_app](Self::bind_app) for the socket referred to by `fd`,
+    /// if an application is bound to it.
+    fn unbind_app(&mut self, fd: FileDescriptor) {
+        self.feedback.remove_app(fd);
+        if let Some(spec) = self.apps.unbind(fd) {
+            self.rt.deregister_app(self.core_id, spec.app_id);
+            self.core_alloc_dereg_app(self.core_id, spec.app_id);
+            self.preempt.unregister(spec.app_id, fd);
+        }
+    }
+
+    /// Returns the application bound to the socket referred to by `fd`, if any.
+    pub fn app_spec(&self, fd: FileDescriptor) -> Option<AppSpec> {
+        self.apps.spec(fd)
+    }
+
+    /// Returns the socket application `app_id` is bound to on this core, if any.
+    pub fn app_fd(&self, app_id: u16) -> Option<FileDescriptor> {
+        self.apps.fd(app_id)
+    }
+
     // core allocator function: register an application in a core
-    pub fn core_alloc_reg_app(&mut self, core_id: u16, app_id: u16){
-        assert!(app_id < (MAX_APP_NUM as u16));
-        assert!(core_id < (MAX_CHANNEL_NUM as u16));
+    pub fn core_alloc_reg_app(&mut self, core_id: u16, app_id: u16) -> Result<(), Fail> {
+        if app_id >= MAX_APP_NUM as u16 {
+            return Err(Fail::OutOfRange {
+                details: "Application id",
+            });
+        }
+        if core_id >= MAX_CHANNEL_NUM as u16 {
+            return Err(Fail::OutOfRange {
+                details: "Core id",
+            });
+        }
         
         let mut app_mask_map = app_to_core_bitmasks.lock().unwrap();
 
@@ -521,7 +850,18 @@ impl<RT: Runtime> LibOS<RT> {
         let new_mask = app_mask | (0x1u64 << core_id);
         app_mask_map.insert(app_id, new_mask).unwrap();
         // println!("Info: App {} Mask on core {}, 0x{:x} ", app_id, core_id, new_mask);
+        Ok(())
+    }
 
+    // core allocator function: deregister an application from a core
+    pub fn core_alloc_dereg_app(&mut self, core_id: u16, app_id: u16) {
+        if core_id >= MAX_CHANNEL_NUM as u16 {
+            return;
+        }
+        let mut app_mask_map = app_to_core_bitmasks.lock().unwrap();
+        if let Some(app_mask) = app_mask_map.get_mut(&app_id) {
+            *app_mask &= !(0x1u64 << core_id);
+        }
     }
 
     // For an given application: select a core to scale up
@@ -758,6 +1098,339 @@ impl<RT: Runtime> LibOS<RT> {
             return (300, 0, 0,  OperationResult::Push, hint_array_);
     }
 
+    ///
+    /// **Brief**
+    ///
+    /// Sets the preemption budget of application `app_id`, whose requests arrive on `fd` and are
+    /// scheduled with `sched_priority` (smaller numbers mean higher priority). Requests of this
+    /// application run on the smaller `contended_cycles` budget while requests of a
+    /// higher-priority application are pending on its sockets or reported by the NIC.
+    ///
+    pub fn config_preempt(
+        &mut self,
+        app_id: u16,
+        fd: FileDescriptor,
+        sched_priority: usize,
+        budget: PreemptBudget,
+    ) {
+        trace!(
+            "config_preempt(): app_id={:?} fd={:?} sched_priority={:?} budget={:?}",
+            app_id,
+            fd,
+            sched_priority,
+            budget
+        );
+        self.preempt.configure(app_id, fd, sched_priority, budget);
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Starts the preemption budget for a request of application `app_id`. The returned token
+    /// tells the handler when to yield.
+    ///
+    pub fn start_request(&mut self, app_id: u16) -> PreemptToken {
+        #[cfg(feature = "profiler")]
+        timer!("catnip::start_request");
+        let engine = &self.engine;
+        self.preempt
+            .start(app_id, |fd| engine.queue_len(fd).unwrap_or(0))
+    }
+
+    /// Stops the preemption budget of the running request.
+    pub fn finish_request(&mut self) {
+        self.preempt.finish();
+    }
+
+    /// Returns `true` once the running request has used up its preemption budget.
+    pub fn should_yield(&self) -> bool {
+        self.preempt.token().should_yield()
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Bounds the receive queue of the UDP socket referred to by `fd` to `capacity` datagrams.
+    /// Once the queue is full, `policy` decides which datagram gets dropped.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, `Ok(())` is returned. Upon failure, `Fail` is
+    /// returned instead.
+    ///
+    pub fn set_queue_capacity(
+        &mut self,
+        fd: FileDescriptor,
+        capacity: usize,
+        policy: QueuePolicy,
+    ) -> Result<(), Fail> {
+        trace!(
+            "set_queue_capacity(): fd={:?} capacity={:?} policy={:?}",
+            fd,
+            capacity,
+            policy
+        );
+        self.engine.set_queue_capacity(fd, capacity, policy)
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Returns the receive queue statistics of the UDP socket referred to by `fd`: current
+    /// length, high-watermark and number of dropped datagrams.
+    ///
+    pub fn queue_stats(&self, fd: FileDescriptor) -> Result<QueueStats, Fail> {
+        self.engine.queue_stats(fd)
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Enables work stealing on the UDP socket referred to by `fd`. Once `threshold` requests
+    /// are queued on `fd`, new requests are offered to the other cores of `group`, which is
+    /// shared by all cores of the application. Whenever `fd` runs empty, requests queued by
+    /// sibling cores are stolen. Replies to stolen requests go out on this core's TX queue; use
+    /// [split_feedback](Self::split_feedback) to credit the load feedback to the cores that
+    /// received them.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, `Ok(())` is returned. Upon failure, `Fail` is
+    /// returned instead.
+    ///
+    pub fn enable_work_stealing(
+        &mut self,
+        fd: FileDescriptor,
+        group: StealGroup<Vec<u8>>,
+        threshold: usize,
+    ) -> Result<(), Fail> {
+        trace!("enable_work_stealing(): fd={:?} threshold={:?}", fd, threshold);
+        self.engine.enable_steal(fd, group, self.core_id, threshold)?;
+        if !self.stealing.contains(&fd) {
+            self.stealing.push(fd);
+        }
+        Ok(())
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Splits the load feedback for the next `count` completed requests of `fd` between the cores
+    /// that received them from the NIC.
+    ///
+    /// **Return Value**
+    ///
+    /// A list of `(core_id, count)` pairs, to be fed to the NIC feedback of each core.
+    ///
+    pub fn split_feedback(
+        &mut self,
+        fd: FileDescriptor,
+        count: u16,
+    ) -> ArrayVec<(u16, u16), MAX_CHANNEL_NUM> {
+        let mut credits: ArrayVec<(u16, u16), MAX_CHANNEL_NUM> = ArrayVec::new();
+        let origins = self
+            .engine
+            .take_origins(fd, count as usize)
+            .unwrap_or_default();
+        // Requests that were never tracked were received by this core.
+        let untracked = count - origins.len() as u16;
+        for origin in origins
+            .into_iter()
+            .chain(std::iter::repeat(self.core_id).take(untracked as usize))
+        {
+            match credits.iter_mut().find(|(core_id, _)| *core_id == origin) {
+                Some((_, n)) => *n += 1,
+                None => credits.push((origin, 1)),
+            }
+        }
+        credits
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Associates the socket referred to by `fd` with application `app_id` for NIC load
+    /// feedback. Afterwards, every reply pushed to `fd` counts as a completed request of
+    /// `app_id` once the push completes, and the NIC is told about completed requests in
+    /// batches. Requests that complete without a reply must be reported with
+    /// [complete](Self::complete) instead.
+    ///
+    pub fn set_feedback_app(&mut self, fd: FileDescriptor, app_id: u16) {
+        trace!("set_feedback_app(): fd={:?} app_id={:?}", fd, app_id);
+        self.feedback.set_app(fd, app_id);
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Sets the number of completed requests reported to the NIC at once. Leftovers are
+    /// reported whenever the core runs out of incoming packets.
+    ///
+    pub fn set_feedback_batch(&mut self, threshold: u16) -> Result<(), Fail> {
+        trace!("set_feedback_batch(): threshold={:?}", threshold);
+        if threshold == 0 {
+            return Err(Fail::Invalid {
+                details: "Zero feedback batch",
+            });
+        }
+        self.feedback.set_threshold(threshold);
+        Ok(())
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Reports a request popped from `fd` that completed without pushing a reply.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, `Ok(())` is returned. If `fd` was not associated with an
+    /// application through [set_feedback_app](Self::set_feedback_app), `Fail` is returned
+    /// instead.
+    ///
+    pub fn complete(&mut self, fd: FileDescriptor) -> Result<(), Fail> {
+        trace!("complete(): fd={:?}", fd);
+        if self.feedback.app(fd).is_none() {
+            return Err(Fail::Invalid {
+                details: "No application for feedback",
+            });
+        }
+        self.complete_requests(fd, 1);
+        Ok(())
+    }
+
+    /// Sends the NIC all the load feedback that is still batched.
+    pub fn flush_feedback(&mut self) {
+        let rt = &self.rt;
+        self.feedback
+            .flush(|queue_id, app_id, count| rt.send_app_feedback(queue_id, app_id, count));
+    }
+
+    /// Credits `count` completed requests of `fd` to the queues that received them, if `fd`
+    /// serves an application. Pushes handed out as an [Operation] already carry their result, so
+    /// the requests they answer are credited right away as well.
+    fn complete_requests(&mut self, fd: FileDescriptor, count: u16) {
+        let app_id = match self.feedback.app(fd) {
+            Some(app_id) => app_id,
+            None => return,
+        };
+        let credits = self.split_feedback(fd, count);
+        self.credit(app_id, &credits);
+    }
+
+    /// Credits the `count` requests of `fd` answered by the push represented by `qt` once the
+    /// push completes, if `fd` serves an application.
+    fn complete_on_push(&mut self, fd: FileDescriptor, count: u16, qt: QToken) {
+        let app_id = match self.feedback.app(fd) {
+            Some(app_id) => app_id,
+            None => return,
+        };
+        // Requests are matched to their queues in the order they were popped, so the split
+        // happens right away.
+        let credits = self.split_feedback(fd, count);
+        self.pending_pushes.push(PendingPush {
+            qt,
+            app_id,
+            credits,
+        });
+    }
+
+    /// Credits the requests answered by the pushes that completed. It must run right after the
+    /// scheduler is polled, before the completed pushes can be taken out of it.
+    fn credit_completed_pushes(&mut self) {
+        let mut ix = 0;
+        while ix < self.pending_pushes.len() {
+            let qt = self.pending_pushes[ix].qt;
+            let completed = match self.rt.scheduler().from_raw_handle(qt) {
+                Some(handle) => {
+                    let completed = handle.has_completed();
+                    handle.into_raw();
+                    completed
+                }
+                // Taken out of the scheduler, which only happens once completed.
+                None => true,
+            };
+            if completed {
+                let push = self.pending_pushes.swap_remove(ix);
+                self.credit(push.app_id, &push.credits);
+            } else {
+                ix += 1;
+            }
+        }
+    }
+
+    /// Records `(queue_id, count)` completed requests of application `app_id`.
+    fn credit(&mut self, app_id: u16, credits: &[(u16, u16)]) {
+        let rt = &self.rt;
+        for &(queue_id, n) in credits {
+            self.feedback.record(queue_id, app_id, n, |queue_id, app_id, count| {
+                rt.send_app_feedback(queue_id, app_id, count)
+            });
+        }
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Creates an RPC client that sends its requests from the bound UDP socket referred to by
+    /// `fd`. Datagrams popped from `fd` are to be handed over to the client, which matches them
+    /// to outstanding calls; the client must also be polled so that late calls are sent again.
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, the client is returned. Upon failure, `Fail` is returned
+    /// instead.
+    ///
+    pub fn rpc_client(
+        &self,
+        fd: FileDescriptor,
+        options: RpcOptions,
+    ) -> Result<RpcClient<RT>, Fail> {
+        trace!("rpc_client(): fd={:?}", fd);
+        self.engine.rpc_client(fd, options)
+    }
+
+    ///
+    /// **Brief**
+    ///
+    /// Creates an RPC server that receives its requests on the bound UDP socket referred to by
+    /// `fd`. Each method served is mapped to a scheduling class, with the same meaning as the
+    /// priority given to [popprio](Self::popprio): requests of higher classes are handed out
+    /// first, and [RpcServer::next_class] tells the priority of the next pop. Replies do not go
+    /// through [pushto2](Self::pushto2), so their load feedback is to be reported with
+    /// [complete](Self::complete).
+    ///
+    /// **Return Value**
+    ///
+    /// Upon successful completion, the server is returned. Upon failure, `Fail` is returned
+    /// instead.
+    ///
+    pub fn rpc_server(
+        &self,
+        fd: FileDescriptor,
+        options: RpcOptions,
+    ) -> Result<RpcServer<RT>, Fail> {
+        trace!("rpc_server(): fd={:?}", fd);
+        self.engine.rpc_server(fd, options)
+    }
+
+    /// Returns the statistics gathered by the scheduler.
+    pub fn scheduler_stats(&self) -> SchedulerStats {
+        self.rt.scheduler().stats()
+    }
+
+    /// Returns the per-task statistics gathered by the scheduler: live tasks per priority, polls
+    /// per kind of operation and wake-to-poll latency.
+    #[cfg(feature = "scheduler-stats")]
+    pub fn task_stats(&self) -> TaskStats {
+        self.rt.scheduler().task_stats()
+    }
+
+    /// Resets the statistics gathered by the scheduler.
+    pub fn reset_scheduler_stats(&self) {
+        self.rt.scheduler().reset_stats();
+    }
+
     pub fn is_qd_valid(&self, _fd: FileDescriptor) -> bool {
         true
     }
@@ -767,21 +1440,24 @@ impl<RT: Runtime> LibOS<RT> {
     ///
     /// This function will panic if the specified future had not completed or is _background_ future.
     fn take_operation(&mut self, handle: SchedulerHandle) -> (FileDescriptor, OperationResult<RT>) {
-        match self.rt.scheduler().take(handle) {
-            Operation::Tcp(f) => f.expect_result(),
-            Operation::Udp(f) => f.expect_result(),
-            Operation::Background(..) => panic!("`take_operation` attempted on background task!"),
-        }
+        self.rt.scheduler().take(handle).expect_result()
     }
 
     /// Scheduler will poll all futures that are ready to make progress.
     /// Then ask the runtime to receive new data which we will forward to the engine to parse and
     /// route to the correct protocol.
-    fn poll_bg_work(&mut self) {
+    pub(crate) fn poll_bg_work(&mut self) {
         self.rt.scheduler().poll();
+        self.credit_completed_pushes();
         for _ in 0..MAX_RECV_ITERS {
             let (scaleUpmsg, batch) = self.rt.receive();
+            if !scaleUpmsg.is_empty() {
+                let engine = &self.engine;
+                self.preempt
+                    .on_nic_hints(&scaleUpmsg, |fd| engine.queue_len(fd).unwrap_or(0));
+            }
             if batch.is_empty() {
+                self.flush_feedback();
                 break;
             }
             for pkt in batch {
@@ -790,22 +1466,53 @@ impl<RT: Runtime> LibOS<RT> {
                 }
             }
         }
+        self.steal_work();
+        let engine = &self.engine;
+        self.preempt
+            .refresh(|fd| engine.queue_len(fd).unwrap_or(0));
         if self.ts_iters == 0 {
             self.rt.advance_clock(Instant::now());
         }
         self.ts_iters = (self.ts_iters + 1) % TIMER_RESOLUTION;
     }
 
+    /// Steals requests from sibling cores into the sockets that ran empty, and credits the
+    /// requests dropped by stealing sockets to the cores that received them.
+    fn steal_work(&mut self) {
+        for &fd in &self.stealing {
+            if self.engine.queue_len(fd).unwrap_or(0) == 0 {
+                if let Err(e) = self.engine.steal(fd, MAX_STEAL_BATCH) {
+                    warn!("Failed to steal work: {:?}", e);
+                }
+            }
+            // Dropped requests never complete, so they are credited right away.
+            let dropped = self.engine.take_dropped_origins(fd).unwrap_or_default();
+            if let Some(app_id) = self.feedback.app(fd) {
+                let rt = &self.rt;
+                for origin in dropped {
+                    self.feedback.record(origin, app_id, 1, |queue_id, app_id, count| {
+                        rt.send_app_feedback(queue_id, app_id, count)
+                    });
+                }
+            }
+        }
+    }
+
     fn poll_bg_work1(&mut self) -> ArrayVec<(u16, u16),RECEIVE_BATCH_SIZE> {
         self.rt.scheduler().poll();
+        self.credit_completed_pushes();
         let mut scaleUpmsg = ArrayVec::new();
         //let mut batch1 = ArrayVec::new();
         for _ in 0..MAX_RECV_ITERS {
             let (scaleUpmsg1, batch) = self.rt.receive();
             if scaleUpmsg1.len() > 0{
+                let engine = &self.engine;
+                self.preempt
+                    .on_nic_hints(&scaleUpmsg1, |fd| engine.queue_len(fd).unwrap_or(0));
                 scaleUpmsg = scaleUpmsg1;
             }//batch1 = batch;
             if batch.is_empty() {
+                self.flush_feedback();
                 break;
             }
             for pkt in batch {
@@ -814,6 +1521,10 @@ impl<RT: Runtime> LibOS<RT> {
                 }
             }
         }
+        self.steal_work();
+        let engine = &self.engine;
+        self.preempt
+            .refresh(|fd| engine.queue_len(fd).unwrap_or(0));
         if self.ts_iters == 0 {
             self.rt.advance_clock(Instant::now());
         }
@@ -822,3 +1533,85 @@ impl<RT: Runtime> LibOS<RT> {
         return scaleUpmsg;
     }
 }
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::{app_to_core_bitmasks, AppSpec, LibOS};
+    use crate::{
+        collections::bytes::BytesMut,
+        operations::OperationResult,
+        protocols::{ip, ipv4},
+        runtime::Runtime,
+        test_helpers,
+    };
+    use must_let::must_let;
+    use std::{convert::TryFrom, time::Instant};
+
+    #[test]
+    fn feedback_on_push_completion() {
+        let now = Instant::now();
+
+        // Setup Alice, serving application 1.
+        let mut libos = LibOS::new(test_helpers::new_alice2_runtime(now), 0, 1).unwrap();
+        let alice_port = ip::Port::try_from(80).unwrap();
+        let alice_addr = ipv4::Endpoint::new(test_helpers::ALICE_IPV4, alice_port);
+        let alice_fd = libos.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+        libos.bind(alice_fd, alice_addr).unwrap();
+        libos.set_feedback_app(alice_fd, 1);
+        libos.set_feedback_batch(1).unwrap();
+
+        // Setup Bob.
+        let mut bob = test_helpers::new_bob2(now);
+        let bob_port = ip::Port::try_from(80).unwrap();
+        let bob_addr = ipv4::Endpoint::new(test_helpers::BOB_IPV4, bob_port);
+        let bob_fd = bob.udp_socket().unwrap();
+        bob.udp_bind(bob_fd, bob_addr).unwrap();
+
+        // Bob sends a request to Alice.
+        let buf = BytesMut::from(&vec![0x5a; 32][..]).freeze();
+        bob.udp_pushto(bob_fd, buf, alice_addr).unwrap();
+        bob.rt().poll_scheduler();
+        libos.rt().push_frame(bob.rt().pop_frame());
+        let qt = libos.pop(alice_fd).unwrap();
+        must_let!(let (_, OperationResult::Pop(Some(remote), request)) = libos.wait2(qt));
+        assert_eq!(remote, bob_addr);
+
+        // The request is credited once the reply has been pushed, not when the push is issued.
+        let qt = libos.pushto2(alice_fd, request, remote).unwrap();
+        assert!(libos.rt().take_feedback().is_empty());
+        must_let!(let (_, OperationResult::Push) = libos.wait2(qt));
+        assert_eq!(libos.rt().take_feedback(), [(0, 1, 1)]);
+    }
+
+    #[test]
+    fn close_unbinds_app() {
+        let now = Instant::now();
+        let mut libos = LibOS::new(test_helpers::new_alice2_runtime(now), 0, 1).unwrap();
+        let spec = AppSpec {
+            app_id: 7,
+            port: 80,
+            nic_priority: 1,
+            sched_priority: 0,
+        };
+        let core_mask = || app_to_core_bitmasks.lock().unwrap().get(&7).cloned().unwrap_or(0);
+
+        let fd = libos.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+        libos.bind_app(fd, spec).unwrap();
+        assert_eq!(libos.app_fd(7), Some(fd));
+        assert_eq!(core_mask() & 0x1, 0x1);
+
+        // Closing the socket releases the application and its core.
+        libos.close(fd).unwrap();
+        assert_eq!(libos.app_fd(7), None);
+        assert_eq!(core_mask() & 0x1, 0);
+
+        // The application can be bound again.
+        let fd = libos.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+        libos.bind_app(fd, spec).unwrap();
+        assert_eq!(libos.app_fd(7), Some(fd));
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Time-budgeted cooperative preemption.
+//!
+//! Each request gets a deadline measured in TSC cycles when it starts running. Handlers poll
+//! [PreemptToken::should_yield] at convenient points and hand the core back (e.g. through
+//! `LibOS::dyield`) once the deadline has passed. The budget is configured per application and
+//! shrinks when traffic from a higher-priority application is waiting.
+
+use crate::file_table::FileDescriptor;
+use std::{cell::Cell, collections::HashMap, rc::Rc};
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Budget used for applications that were never configured.
+pub const DEFAULT_BUDGET_CYCLES: u64 = 20_000;
+
+/// Budget used for applications that were never configured, while higher-priority traffic is
+/// pending.
+pub const DEFAULT_CONTENDED_BUDGET_CYCLES: u64 = 2_000;
+
+/// Per-application preemption budget.
+#[derive(Clone, Copy, Debug)]
+pub struct PreemptBudget {
+    /// Cycles a request may run when nothing more important is pending.
+    pub cycles: u64,
+    /// Cycles a request may run while higher-priority traffic is pending.
+    pub contended_cycles: u64,
+}
+
+/// Handle on the deadline of the request that is currently running.
+#[derive(Clone)]
+pub struct PreemptToken {
+    deadline: Rc<Cell<u64>>,
+}
+
+/// Preemption state of an application.
+struct AppPreempt {
+    /// Scheduling priority. Smaller numbers mean higher priority.
+    sched_priority: usize,
+    /// Sockets whose queues carry this application's requests.
+    fds: Vec<FileDescriptor>,
+    budget: PreemptBudget,
+    /// Whether the NIC reported pending load for this application.
+    nic_pending: bool,
+}
+
+/// Preemption budgets of all applications running on a core.
+pub struct Preemption {
+    apps: HashMap<u16, AppPreempt>,
+    /// Application whose request is currently running.
+    current: Option<u16>,
+    token: PreemptToken,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Reads the time-stamp counter.
+#[inline]
+pub fn rdtsc() -> u64 {
+    unsafe { x86::time::rdtsc() }
+}
+
+/// Associate functions for [PreemptBudget].
+impl PreemptBudget {
+    pub fn new(cycles: u64, contended_cycles: u64) -> Self {
+        assert!(contended_cycles <= cycles);
+        Self {
+            cycles,
+            contended_cycles,
+        }
+    }
+}
+
+/// Associate functions for [PreemptToken].
+impl PreemptToken {
+    fn new() -> Self {
+        Self {
+            deadline: Rc::new(Cell::new(u64::MAX)),
+        }
+    }
+
+    /// Returns `true` once the running request has used up its budget.
+    #[inline]
+    pub fn should_yield(&self) -> bool {
+        rdtsc() >= self.deadline.get()
+    }
+
+    /// Returns the number of cycles left before the running request should yield.
+    pub fn remaining_cycles(&self) -> u64 {
+        self.deadline.get().saturating_sub(rdtsc())
+    }
+
+    fn arm(&self, cycles: u64) {
+        self.deadline.set(rdtsc().saturating_add(cycles));
+    }
+
+    /// Moves the deadline closer, never further away.
+    fn tighten(&self, cycles: u64) {
+        let deadline = rdtsc().saturating_add(cycles);
+        if deadline < self.deadline.get() {
+            self.deadline.set(deadline);
+        }
+    }
+
+    fn disarm(&self) {
+        self.deadline.set(u64::MAX);
+    }
+}
+
+/// Associate functions for [Preemption].
+impl Preemption {
+    pub fn new() -> Self {
+        Self {
+            apps: HashMap::new(),
+            current: None,
+            token: PreemptToken::new(),
+        }
+    }
+
+    /// Sets the budget of `app_id` and registers `fd` as one of its sockets.
+    pub fn configure(
+        &mut self,
+        app_id: u16,
+        fd: FileDescriptor,
+        sched_priority: usize,
+        budget: PreemptBudget,
+    ) {
+        let app = self.apps.entry(app_id).or_insert_with(|| AppPreempt {
+            sched_priority,
+            fds: Vec::new(),
+            budget,
+            nic_pending: false,
+        });
+        app.sched_priority = sched_priority;
+        app.budget = budget;
+        if !app.fds.contains(&fd) {
+            app.fds.push(fd);
+        }
+    }
+
+    /// Registers `fd` as one of the sockets of `app_id`, keeping its budget if it has one.
+    pub fn register(&mut self, app_id: u16, fd: FileDescriptor, sched_priority: usize) {
+        let budget = self.budget(app_id);
+        self.configure(app_id, fd, sched_priority, budget);
+    }
+
+    /// Unregisters `fd` from the sockets of `app_id`. The application is forgotten, budget
+    /// included, along with its last socket.
+    pub fn unregister(&mut self, app_id: u16, fd: FileDescriptor) {
+        let remove = match self.apps.get_mut(&app_id) {
+            Some(app) => {
+                app.fds.retain(|&f| f != fd);
+                app.fds.is_empty()
+            }
+            None => false,
+        };
+        if remove {
+            self.apps.remove(&app_id);
+            if self.current == Some(app_id) {
+                self.finish();
+            }
+        }
+    }
+
+    pub fn token(&self) -> PreemptToken {
+        self.token.clone()
+    }
+
+    /// Starts the budget of a request from `app_id`. `queue_len` reports the number of requests
+    /// waiting on a socket.
+    pub fn start(&mut self, app_id: u16, queue_len: impl Fn(FileDescriptor) -> usize) -> PreemptToken {
+        if let Some(app) = self.apps.get_mut(&app_id) {
+            // The NIC hint has been consumed by serving this application.
+            app.nic_pending = false;
+        }
+        let cycles = if self.higher_priority_pending(app_id, queue_len) {
+            self.budget(app_id).contended_cycles
+        } else {
+            self.budget(app_id).cycles
+        };
+        self.current = Some(app_id);
+        self.token.arm(cycles);
+        self.token.clone()
+    }
+
+    /// Ends the request that is currently running.
+    pub fn finish(&mut self) {
+        self.current = None;
+        self.token.disarm();
+    }
+
+    /// Records load hints reported by the NIC. A hint type of `1` asks for more cores.
+    pub fn on_nic_hints(&mut self, hints: &[(u16, u16)], queue_len: impl Fn(FileDescriptor) -> usize) {
+        for &(app_id, hint_type) in hints {
+            if let Some(app) = self.apps.get_mut(&app_id) {
+                app.nic_pending = hint_type == 1;
+            }
+        }
+        self.refresh(queue_len);
+    }
+
+    /// Shrinks the budget of the running request if higher-priority traffic showed up.
+    pub fn refresh(&mut self, queue_len: impl Fn(FileDescriptor) -> usize) {
+        if let Some(app_id) = self.current {
+            if self.higher_priority_pending(app_id, queue_len) {
+                self.token.tighten(self.budget(app_id).contended_cycles);
+            }
+        }
+    }
+
+    fn budget(&self, app_id: u16) -> PreemptBudget {
+        match self.apps.get(&app_id) {
+            Some(app) => app.budget,
+            None => PreemptBudget::default(),
+        }
+    }
+
+    fn higher_priority_pending(
+        &self,
+        app_id: u16,
+        queue_len: impl Fn(FileDescriptor) -> usize,
+    ) -> bool {
+        let priority = match self.apps.get(&app_id) {
+            Some(app) => app.sched_priority,
+            None => usize::MAX,
+        };
+        self.apps
+            .iter()
+            .filter(|(&id, app)| id != app_id && app.sched_priority < priority)
+            .any(|(_, app)| app.nic_pending || app.fds.iter().any(|&fd| queue_len(fd) > 0))
+    }
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+/// Default trait implementation for [PreemptBudget].
+impl Default for PreemptBudget {
+    fn default() -> Self {
+        Self {
+            cycles: DEFAULT_BUDGET_CYCLES,
+            contended_cycles: DEFAULT_CONTENDED_BUDGET_CYCLES,
+        }
+    }
+}
+
+/// Default trait implementation for [Preemption].
+impl Default for Preemption {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::{PreemptBudget, Preemption};
+
+    #[test]
+    fn contended_budget() {
+        let mut preempt = Preemption::new();
+        preempt.configure(1, 10, 0, PreemptBudget::new(u64::MAX / 2, 0));
+        preempt.configure(2, 20, 2, PreemptBudget::new(u64::MAX / 2, 0));
+
+        // Nothing more important is pending.
+        let token = preempt.start(2, |_| 0);
+        assert!(!token.should_yield());
+        preempt.finish();
+
+        // The high-priority application has queued requests.
+        let token = preempt.start(2, |fd| if fd == 10 { 1 } else { 0 });
+        assert!(token.should_yield());
+        preempt.finish();
+
+        // Lower-priority traffic never shrinks the budget.
+        let token = preempt.start(1, |_| 1);
+        assert!(!token.should_yield());
+
+        // NIC hints for the running application do not shrink its own budget.
+        preempt.on_nic_hints(&[(1, 1)], |_| 0);
+        assert!(!token.should_yield());
+        preempt.finish();
+    }
+
+    #[test]
+    fn nic_hints_tighten_running_request() {
+        let mut preempt = Preemption::new();
+        preempt.configure(1, 10, 0, PreemptBudget::new(u64::MAX / 2, 0));
+        preempt.configure(2, 20, 2, PreemptBudget::new(u64::MAX / 2, 0));
+
+        let token = preempt.start(2, |_| 0);
+        assert!(!token.should_yield());
+        preempt.on_nic_hints(&[(1, 1)], |_| 0);
+        assert!(token.should_yield());
+    }
+}
@@ -10,9 +10,12 @@ use super::{
 use crate::futures_utility::UtilityMethods;
 use crate::{
     fail::Fail,
-    protocols::ethernet2::{
-        frame::{EtherType2, Ethernet2Header},
-        MacAddress,
+    protocols::{
+        ethernet2::{
+            frame::{EtherType2, Ethernet2Header},
+            MacAddress,
+        },
+        ipv4,
     },
     runtime::Runtime,
     scheduler::SchedulerHandle,
@@ -182,7 +185,24 @@ impl<RT: Runtime> ArpPeer<RT> {
         }
     }
 
+    /// Returns the link address of broadcast and multicast destinations, which are not
+    /// resolved through ARP.
+    fn group_link_addr(rt: &RT, ipv4_addr: Ipv4Addr) -> Option<MacAddress> {
+        if ipv4_addr.is_multicast() {
+            return Some(MacAddress::from_ipv4_multicast(ipv4_addr));
+        }
+        let subnet_broadcast =
+            ipv4::subnet_broadcast_addr(rt.local_ipv4_addr(), rt.local_ipv4_netmask());
+        if ipv4_addr.is_broadcast() || ipv4_addr == subnet_broadcast {
+            return Some(MacAddress::broadcast());
+        }
+        None
+    }
+
     pub fn try_query(&self, ipv4_addr: Ipv4Addr) -> Option<MacAddress> {
+        if let Some(link_addr) = Self::group_link_addr(&self.rt, ipv4_addr) {
+            return Some(link_addr);
+        }
         self.cache.borrow().get(ipv4_addr).cloned()
     }
 
@@ -192,6 +212,9 @@ impl<RT: Runtime> ArpPeer<RT> {
         let cache = self.cache.clone();
         let arp_options = self.options.clone();
         async move {
+            if let Some(link_addr) = Self::group_link_addr(&rt, ipv4_addr) {
+                return Ok(link_addr);
+            }
             if let Some(&link_addr) = cache.borrow().get(ipv4_addr) {
                 return Ok(link_addr);
             }
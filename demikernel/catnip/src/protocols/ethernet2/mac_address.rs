@@ -3,7 +3,7 @@
 
 use crate::fail::Fail;
 use eui48;
-use std::fmt;
+use std::{fmt, net::Ipv4Addr};
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub struct MacAddress(eui48::MacAddress);
@@ -41,6 +41,18 @@ impl MacAddress {
         self.0.is_unicast()
     }
 
+    pub fn is_multicast(self) -> bool {
+        self.0.is_multicast()
+    }
+
+    /// Returns the link address IPv4 datagrams sent to multicast `group` are delivered to, as
+    /// defined in RFC 1112: 01:00:5e followed by the low-order 23 bits of the group address.
+    pub fn from_ipv4_multicast(group: Ipv4Addr) -> MacAddress {
+        debug_assert!(group.is_multicast());
+        let octets = group.octets();
+        MacAddress::new([0x01, 0x00, 0x5e, octets[1] & 0x7f, octets[2], octets[3]])
+    }
+
     pub fn to_canonical(self) -> String {
         self.0.to_canonical()
     }
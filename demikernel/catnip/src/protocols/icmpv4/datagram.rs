@@ -3,18 +3,37 @@
 
 use crate::{
     fail::Fail,
-    protocols::{ethernet2::frame::Ethernet2Header, ipv4::datagram::Ipv4Header},
+    protocols::{
+        ethernet2::frame::Ethernet2Header,
+        ip, ipv4,
+        ipv4::datagram::{Ipv4Header, Ipv4Protocol2, IPV4_HEADER_SIZE},
+    },
     runtime::PacketBuf,
     runtime::RuntimeBuf,
 };
 
 use byteorder::{ByteOrder, NetworkEndian};
 
-use std::{convert::TryInto, marker::PhantomData, ptr};
+use std::{
+    convert::{TryFrom, TryInto},
+    marker::PhantomData,
+    net::Ipv4Addr,
+    ptr,
+};
 
 #[allow(unused)]
 const MAX_ICMPV4_DATAGRAM_SIZE: usize = 576;
 
+/// Code of Destination Unreachable messages for unbound ports.
+pub const ICMPV4_PORT_UNREACHABLE: u8 = 3;
+
+/// Code of Destination Unreachable messages for datagrams too large to be forwarded without
+/// fragmentation, which carry the MTU of the next hop (RFC 1191, section 4).
+pub const ICMPV4_FRAGMENTATION_NEEDED: u8 = 4;
+
+/// Number of payload bytes of the offending datagram quoted in ICMPv4 error messages.
+pub const ICMPV4_QUOTED_PAYLOAD_SIZE: usize = 8;
+
 //==============================================================================
 // Icmpv4Type2
 //==============================================================================
@@ -22,7 +41,7 @@ const MAX_ICMPV4_DATAGRAM_SIZE: usize = 576;
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Icmpv4Type2 {
     EchoReply { id: u16, seq_num: u16 },
-    DestinationUnreachable,
+    DestinationUnreachable { next_hop_mtu: u16 },
     SourceQuench,
     RedirectMessage,
     EchoRequest { id: u16, seq_num: u16 },
@@ -43,7 +62,10 @@ impl Icmpv4Type2 {
                 let seq_num = NetworkEndian::read_u16(&rest_of_header[2..4]);
                 Ok(EchoReply { id, seq_num })
             }
-            3 => Ok(DestinationUnreachable),
+            3 => {
+                let next_hop_mtu = NetworkEndian::read_u16(&rest_of_header[2..4]);
+                Ok(DestinationUnreachable { next_hop_mtu })
+            }
             4 => Ok(SourceQuench),
             5 => Ok(RedirectMessage),
             8 => {
@@ -72,7 +94,10 @@ impl Icmpv4Type2 {
                 let [seq1, seq2] = seq_num.to_be_bytes();
                 (0, [id1, id2, seq1, seq2])
             }
-            DestinationUnreachable => (3, zero),
+            DestinationUnreachable { next_hop_mtu } => {
+                let [mtu1, mtu2] = next_hop_mtu.to_be_bytes();
+                (3, [0, 0, mtu1, mtu2])
+            }
             SourceQuench => (4, zero),
             RedirectMessage => (5, zero),
             EchoRequest { id, seq_num } => {
@@ -139,15 +164,17 @@ impl Icmpv4Header {
         Ok((Self { icmpv4_type, code }, buf))
     }
 
+    /// Serializes the target header into the start of `buf`. Whatever follows the header in
+    /// `buf` is taken as the message body when computing the checksum.
     pub fn serialize(&self, buf: &mut [u8]) {
-        let buf: &mut [u8; ICMPV4_HEADER_SIZE] =
-            (&mut buf[..ICMPV4_HEADER_SIZE]).try_into().unwrap();
+        let (buf, body) = buf.split_at_mut(ICMPV4_HEADER_SIZE);
+        let buf: &mut [u8; ICMPV4_HEADER_SIZE] = buf.try_into().unwrap();
         let (type_byte, rest_of_header) = self.icmpv4_type.serialize();
         buf[0] = type_byte;
         buf[1] = self.code;
         // Skip the checksum for now.
         buf[4..8].copy_from_slice(&rest_of_header[..]);
-        let checksum = Self::checksum(buf, &[]);
+        let checksum = Self::checksum(buf, body);
         NetworkEndian::write_u16(&mut buf[2..4], checksum);
     }
 
@@ -174,6 +201,68 @@ impl Icmpv4Header {
     }
 }
 
+//==============================================================================
+// Icmpv4Quote
+//==============================================================================
+
+/// Start of the offending datagram, as quoted in an ICMPv4 error message.
+#[derive(Copy, Clone, Debug)]
+pub struct Icmpv4Quote {
+    pub protocol: Ipv4Protocol2,
+    pub src: ipv4::Endpoint,
+    pub dst: ipv4::Endpoint,
+    /// Sequence number of a quoted TCP segment, which follows the ports if the quote is as long as
+    /// RFC 792 requires.
+    pub seq_num: Option<u32>,
+}
+
+/// Associated Functions for Icmpv4Quote
+impl Icmpv4Quote {
+    /// Builds the body of an ICMPv4 error message for the datagram made of `ipv4_hdr` and a
+    /// payload of `payload_len` bytes starting with `payload`.
+    pub fn serialize(ipv4_hdr: &Ipv4Header, payload_len: usize, payload: &[u8]) -> Vec<u8> {
+        let payload = &payload[..payload.len().min(ICMPV4_QUOTED_PAYLOAD_SIZE)];
+        let mut buf = vec![0u8; IPV4_HEADER_SIZE + payload.len()];
+        ipv4_hdr.serialize(&mut buf[..IPV4_HEADER_SIZE], payload_len);
+        buf[IPV4_HEADER_SIZE..].copy_from_slice(payload);
+        buf
+    }
+
+    /// Parses the body of an ICMPv4 error message. Only the addresses and the ports of the
+    /// offending datagram are recovered, along with the sequence number of a TCP segment, as the
+    /// rest of it is usually truncated.
+    pub fn parse(buf: &[u8]) -> Result<Self, Fail> {
+        if buf.len() < IPV4_HEADER_SIZE {
+            return Err(Fail::Malformed {
+                details: "ICMPv4 quote too small for IPv4 header",
+            });
+        }
+        let ihl = (buf[0] & 0xF) as usize * 4;
+        if ihl < IPV4_HEADER_SIZE || buf.len() < ihl + 4 {
+            return Err(Fail::Malformed {
+                details: "ICMPv4 quote too small for ports",
+            });
+        }
+        let protocol = Ipv4Protocol2::try_from(buf[9])?;
+        let src_addr = Ipv4Addr::from(NetworkEndian::read_u32(&buf[12..16]));
+        let dst_addr = Ipv4Addr::from(NetworkEndian::read_u32(&buf[16..20]));
+        let src_port = ip::Port::try_from(NetworkEndian::read_u16(&buf[ihl..(ihl + 2)]))?;
+        let dst_port = ip::Port::try_from(NetworkEndian::read_u16(&buf[(ihl + 2)..(ihl + 4)]))?;
+        let seq_num = match protocol {
+            Ipv4Protocol2::Tcp if buf.len() >= ihl + 8 => {
+                Some(NetworkEndian::read_u32(&buf[(ihl + 4)..(ihl + 8)]))
+            }
+            _ => None,
+        };
+        Ok(Self {
+            protocol,
+            src: ipv4::Endpoint::new(src_addr, src_port),
+            dst: ipv4::Endpoint::new(dst_addr, dst_port),
+            seq_num,
+        })
+    }
+}
+
 //==============================================================================
 // Icmpv4Message
 //==============================================================================
@@ -183,6 +272,8 @@ pub struct Icmpv4Message<T> {
     ethernet2_hdr: Ethernet2Header,
     ipv4_hdr: Ipv4Header,
     icmpv4_hdr: Icmpv4Header,
+    /// Body of the message, written along with the headers.
+    data: Vec<u8>,
     _body_marker: PhantomData<T>,
 }
 
@@ -193,11 +284,22 @@ impl<T> Icmpv4Message<T> {
         ethernet2_hdr: Ethernet2Header,
         ipv4_hdr: Ipv4Header,
         icmpv4_hdr: Icmpv4Header,
+    ) -> Self {
+        Self::with_data(ethernet2_hdr, ipv4_hdr, icmpv4_hdr, Vec::new())
+    }
+
+    /// Creates an ICMP message carrying `data`.
+    pub fn with_data(
+        ethernet2_hdr: Ethernet2Header,
+        ipv4_hdr: Ipv4Header,
+        icmpv4_hdr: Icmpv4Header,
+        data: Vec<u8>,
     ) -> Self {
         Self {
             ethernet2_hdr,
             ipv4_hdr,
             icmpv4_hdr,
+            data,
             _body_marker: PhantomData,
         }
     }
@@ -206,7 +308,10 @@ impl<T> Icmpv4Message<T> {
 /// PacketBuf Trait Implementation for Icmpv4Message
 impl<T> PacketBuf<T> for Icmpv4Message<T> {
     fn header_size(&self) -> usize {
-        self.ethernet2_hdr.compute_size() + self.ipv4_hdr.compute_size() + self.icmpv4_hdr.size()
+        self.ethernet2_hdr.compute_size()
+            + self.ipv4_hdr.compute_size()
+            + self.icmpv4_hdr.size()
+            + self.data.len()
     }
 
     fn body_size(&self) -> usize {
@@ -231,15 +336,18 @@ impl<T> PacketBuf<T> for Icmpv4Message<T> {
             .serialize(&mut buf[cur_pos..(cur_pos + eth_hdr_size)]);
         cur_pos += eth_hdr_size;
 
-        let ipv4_payload_len = icmpv4_hdr_size;
+        let ipv4_payload_len = icmpv4_hdr_size + self.data.len();
         self.ipv4_hdr.serialize(
             &mut buf[cur_pos..(cur_pos + ipv4_hdr_size)],
             ipv4_payload_len,
         );
         cur_pos += ipv4_hdr_size;
 
+        // Write the body first, so that the header checksum covers it.
+        let data_pos = cur_pos + icmpv4_hdr_size;
+        buf[data_pos..(data_pos + self.data.len())].copy_from_slice(&self.data[..]);
         self.icmpv4_hdr
-            .serialize(&mut buf[cur_pos..(cur_pos + icmpv4_hdr_size)]);
+            .serialize(&mut buf[cur_pos..(data_pos + self.data.len())]);
     }
 
     fn take_body(self) -> Option<T> {
@@ -259,3 +367,53 @@ impl<T> PacketBuf<T> for Icmpv4Message<T> {
         return false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Icmpv4Header, Icmpv4Quote, Icmpv4Type2, ICMPV4_FRAGMENTATION_NEEDED, ICMPV4_HEADER_SIZE,
+    };
+    use crate::{
+        collections::bytes::BytesMut,
+        protocols::{
+            ip, ipv4,
+            ipv4::datagram::{Ipv4Header, Ipv4Protocol2, IPV4_HEADER_SIZE},
+        },
+        test_helpers,
+    };
+    use byteorder::{ByteOrder, NetworkEndian};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_fragmentation_needed() {
+        let src = ipv4::Endpoint::new(test_helpers::ALICE_IPV4, ip::Port::try_from(49152).unwrap());
+        let dst = ipv4::Endpoint::new(test_helpers::BOB_IPV4, ip::Port::try_from(80).unwrap());
+
+        // Quote the ports and the sequence number of a TCP segment.
+        let ipv4_hdr = Ipv4Header::new(src.addr, dst.addr, Ipv4Protocol2::Tcp);
+        let mut tcp_hdr = [0u8; 8];
+        NetworkEndian::write_u16(&mut tcp_hdr[0..2], 49152);
+        NetworkEndian::write_u16(&mut tcp_hdr[2..4], 80);
+        NetworkEndian::write_u32(&mut tcp_hdr[4..8], 1000);
+        let quote = Icmpv4Quote::serialize(&ipv4_hdr, 1480, &tcp_hdr);
+
+        let next_hop_mtu = Icmpv4Type2::DestinationUnreachable { next_hop_mtu: 1400 };
+        let mut buf = BytesMut::zeroed(ICMPV4_HEADER_SIZE + quote.len()).unwrap();
+        buf[ICMPV4_HEADER_SIZE..].copy_from_slice(&quote);
+        Icmpv4Header::new(next_hop_mtu, ICMPV4_FRAGMENTATION_NEEDED).serialize(&mut buf[..]);
+
+        let (icmpv4_hdr, body) = Icmpv4Header::parse(buf.freeze()).unwrap();
+        assert_eq!(icmpv4_hdr.icmpv4_type, next_hop_mtu);
+        assert_eq!(icmpv4_hdr.code, ICMPV4_FRAGMENTATION_NEEDED);
+        let quote = Icmpv4Quote::parse(&body[..]).unwrap();
+        assert_eq!(quote.protocol, Ipv4Protocol2::Tcp);
+        assert_eq!(quote.src, src);
+        assert_eq!(quote.dst, dst);
+        assert_eq!(quote.seq_num, Some(1000));
+
+        // A quote cut short of the sequence number still names the connection.
+        let quote = Icmpv4Quote::parse(&body[..(IPV4_HEADER_SIZE + 4)]).unwrap();
+        assert_eq!(quote.dst, dst);
+        assert_eq!(quote.seq_num, None);
+    }
+}
@@ -4,4 +4,8 @@
 mod datagram;
 mod peer;
 
+pub use datagram::{
+    Icmpv4Header, Icmpv4Message, Icmpv4Quote, Icmpv4Type2, ICMPV4_FRAGMENTATION_NEEDED,
+    ICMPV4_PORT_UNREACHABLE, ICMPV4_QUOTED_PAYLOAD_SIZE,
+};
 pub use peer::Icmpv4Peer as Peer;
@@ -1,7 +1,7 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use super::datagram::{Icmpv4Header, Icmpv4Type2};
+use super::datagram::{Icmpv4Header, Icmpv4Quote, Icmpv4Type2, ICMPV4_PORT_UNREACHABLE};
 use crate::{
     fail::Fail,
     protocols::{
@@ -27,10 +27,26 @@ use futures::{
 use crate::futures_utility::UtilityMethods;
 
 use std::{
-    cell::RefCell, collections::HashMap, future::Future, net::Ipv4Addr, num::Wrapping, process,
-    rc::Rc, time::Duration,
+    cell::RefCell,
+    collections::HashMap,
+    future::Future,
+    net::Ipv4Addr,
+    num::Wrapping,
+    process,
+    rc::Rc,
+    time::{Duration, Instant},
 };
 
+//==============================================================================
+// Constants
+//==============================================================================
+
+/// Number of ICMPv4 error messages that may be sent back to back.
+const ERROR_BURST: u32 = 50;
+
+/// Time it takes to earn the right to send one more ICMPv4 error message.
+const ERROR_INTERVAL: Duration = Duration::from_millis(1);
+
 //==============================================================================
 // ReqQueue
 //==============================================================================
@@ -56,6 +72,41 @@ impl ReqQueue {
     }
 }
 
+//==============================================================================
+// ErrorLimiter
+//==============================================================================
+
+/// Token bucket limiting the rate of outgoing ICMPv4 error messages.
+struct ErrorLimiter {
+    tokens: u32,
+    last_refill: Instant,
+}
+
+/// Associate Implementation for ErrorLimiter
+impl ErrorLimiter {
+    /// Creates a full bucket.
+    fn new(now: Instant) -> Self {
+        Self {
+            tokens: ERROR_BURST,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token from the target bucket, if any is left.
+    fn try_take(&mut self, now: Instant) -> bool {
+        let earned = (now - self.last_refill).as_nanos() / ERROR_INTERVAL.as_nanos();
+        if earned > 0 {
+            self.tokens = (self.tokens as u128 + earned).min(ERROR_BURST as u128) as u32;
+            self.last_refill = now;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
 //==============================================================================
 // Icmpv4Peer
 //==============================================================================
@@ -86,6 +137,9 @@ pub struct Icmpv4Peer<RT: Runtime> {
     /// Sequence Number
     seq: Wrapping<u16>,
 
+    /// Rate Limiter for Error Messages
+    errors: ErrorLimiter,
+
     #[allow(unused)]
     handle: SchedulerHandle,
 }
@@ -97,12 +151,14 @@ impl<RT: Runtime> Icmpv4Peer<RT> {
         let requests = ReqQueue::new();
         let future = Self::background(rt.clone(), arp.clone(), rx);
         let handle = rt.spawn(future);
+        let errors = ErrorLimiter::new(rt.now());
         Icmpv4Peer {
             rt,
             arp,
             tx,
             requests: Rc::new(RefCell::new(requests)),
             seq: Wrapping(0),
+            errors,
             handle,
         }
     }
@@ -142,9 +198,15 @@ impl<RT: Runtime> Icmpv4Peer<RT> {
         }
     }
 
-    /// Parses and handles a ICMP message.
-    pub fn receive(&mut self, ipv4_header: &Ipv4Header, buf: RT::Buf) -> Result<(), Fail> {
-        let (icmpv4_hdr, _) = Icmpv4Header::parse(buf)?;
+    /// Parses and handles a ICMP message. For Destination Unreachable messages, the header and
+    /// the offending datagram are handed back so that the transport layer can act on the matching
+    /// socket.
+    pub fn receive(
+        &mut self,
+        ipv4_header: &Ipv4Header,
+        buf: RT::Buf,
+    ) -> Result<Option<(Icmpv4Header, Icmpv4Quote)>, Fail> {
+        let (icmpv4_hdr, data) = Icmpv4Header::parse(buf)?;
         debug!("ICMPv4 received {:?}", icmpv4_hdr);
         match icmpv4_hdr.icmpv4_type {
             Icmpv4Type2::EchoRequest { id, seq_num } => {
@@ -157,11 +219,60 @@ impl<RT: Runtime> Icmpv4Peer<RT> {
                     let _ = tx.send(());
                 }
             }
+            Icmpv4Type2::DestinationUnreachable { .. } => {
+                let quote = Icmpv4Quote::parse(&data[..])?;
+                // Only trust messages about datagrams we could have sent.
+                if quote.src.addr != self.rt.local_ipv4_addr() {
+                    return Err(Fail::Ignored {
+                        details: "ICMPv4 error for foreign datagram",
+                    });
+                }
+                return Ok(Some((icmpv4_hdr, quote)));
+            }
             _ => {
                 warn!("Unsupported ICMPv4 message: {:?}", icmpv4_hdr);
             }
         }
-        Ok(())
+        Ok(None)
+    }
+
+    /// Replies to a datagram sent to an unbound port with a Port Unreachable message.
+    /// `payload_len` is the size of the payload of the datagram, and `payload` its first bytes.
+    pub fn send_port_unreachable(
+        &mut self,
+        ipv4_header: &Ipv4Header,
+        payload_len: usize,
+        payload: &[u8],
+    ) {
+        // Never answer broadcasts, nor bounce errors back faster than allowed.
+        if ipv4_header.dst_addr.is_broadcast() || ipv4_header.src_addr.is_broadcast() {
+            return;
+        }
+        if !self.errors.try_take(self.rt.now()) {
+            debug!("ICMPv4 error to {} rate limited", ipv4_header.src_addr);
+            return;
+        }
+        let dst_link_addr = match self.arp.try_query(ipv4_header.src_addr) {
+            Some(link_addr) => link_addr,
+            None => {
+                debug!("No link address for ICMPv4 error to {}", ipv4_header.src_addr);
+                return;
+            }
+        };
+        let data = Icmpv4Quote::serialize(ipv4_header, payload_len, payload);
+        self.rt.transmit(Icmpv4Message::with_data(
+            Ethernet2Header::new(dst_link_addr, self.rt.local_link_addr(), EtherType2::Ipv4),
+            Ipv4Header::new(
+                self.rt.local_ipv4_addr(),
+                ipv4_header.src_addr,
+                Ipv4Protocol2::Icmpv4,
+            ),
+            Icmpv4Header::new(
+                Icmpv4Type2::DestinationUnreachable { next_hop_mtu: 0 },
+                ICMPV4_PORT_UNREACHABLE,
+            ),
+            data,
+        ));
     }
 
     /// Computes the identifier for an ICPM message.
@@ -0,0 +1,245 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{
+    fail::Fail,
+    protocols::{ethernet2::frame::Ethernet2Header, ipv4::datagram::Ipv4Header},
+    runtime::{PacketBuf, RuntimeBuf},
+};
+use byteorder::{ByteOrder, NetworkEndian};
+use std::{
+    convert::{TryFrom, TryInto},
+    marker::PhantomData,
+    net::Ipv4Addr,
+    ptr,
+};
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Size of IGMPv2 messages (in bytes).
+pub const IGMP_HEADER_SIZE: usize = 8;
+
+/// Group every multicast-capable host belongs to. Membership queries are sent there.
+pub const IGMP_ALL_HOSTS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 1);
+
+/// Group every multicast router belongs to. Leave messages are sent there.
+pub const IGMP_ALL_ROUTERS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 2);
+
+/// Type of an IGMP message.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IgmpType {
+    MembershipQuery,
+    MembershipReportV1,
+    MembershipReportV2,
+    LeaveGroup,
+}
+
+/// IGMPv2 message, as defined in RFC 2236.
+#[derive(Copy, Clone, Debug)]
+pub struct IgmpHeader {
+    pub igmp_type: IgmpType,
+    /// Maximum response time of queries, in tenths of a second.
+    pub max_resp_time: u8,
+    /// Group the message is about. Unspecified in general queries.
+    pub group: Ipv4Addr,
+}
+
+/// IGMP message in an Ethernet frame.
+pub struct IgmpMessage<T> {
+    ethernet2_hdr: Ethernet2Header,
+    ipv4_hdr: Ipv4Header,
+    igmp_hdr: IgmpHeader,
+    _body_marker: PhantomData<T>,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [IgmpType].
+impl IgmpType {
+    fn serialize(self) -> u8 {
+        match self {
+            IgmpType::MembershipQuery => 0x11,
+            IgmpType::MembershipReportV1 => 0x12,
+            IgmpType::MembershipReportV2 => 0x16,
+            IgmpType::LeaveGroup => 0x17,
+        }
+    }
+}
+
+/// Associate functions for [IgmpHeader].
+impl IgmpHeader {
+    /// Creates a header for an IGMP message about `group`.
+    pub fn new(igmp_type: IgmpType, group: Ipv4Addr) -> Self {
+        Self {
+            igmp_type,
+            max_resp_time: 0,
+            group,
+        }
+    }
+
+    pub fn parse<T: RuntimeBuf>(buf: T) -> Result<Self, Fail> {
+        if buf.len() < IGMP_HEADER_SIZE {
+            return Err(Fail::Malformed {
+                details: "IGMP message too small",
+            });
+        }
+        // IGMPv3 queries are longer, but start like IGMPv2 ones.
+        if Self::checksum(&buf[..]) != 0 {
+            return Err(Fail::Malformed {
+                details: "IGMP checksum mismatch",
+            });
+        }
+        let igmp_type = IgmpType::try_from(buf[0])?;
+        let max_resp_time = buf[1];
+        let group = Ipv4Addr::from(NetworkEndian::read_u32(&buf[4..8]));
+        Ok(Self {
+            igmp_type,
+            max_resp_time,
+            group,
+        })
+    }
+
+    pub fn serialize(&self, buf: &mut [u8]) {
+        let buf: &mut [u8; IGMP_HEADER_SIZE] = buf.try_into().unwrap();
+        buf[0] = self.igmp_type.serialize();
+        buf[1] = self.max_resp_time;
+        NetworkEndian::write_u16(&mut buf[2..4], 0);
+        buf[4..8].copy_from_slice(&self.group.octets());
+        let checksum = Self::checksum(&buf[..]);
+        NetworkEndian::write_u16(&mut buf[2..4], checksum);
+    }
+
+    /// Computes the checksum of a whole IGMP message. It is zero for messages that carry a
+    /// valid checksum.
+    fn checksum(buf: &[u8]) -> u16 {
+        let mut state = 0xffffu32;
+        let mut chunks_iter = buf.chunks_exact(2);
+        while let Some(chunk) = chunks_iter.next() {
+            state += NetworkEndian::read_u16(chunk) as u32;
+        }
+        if let Some(&b) = chunks_iter.remainder().get(0) {
+            state += NetworkEndian::read_u16(&[b, 0]) as u32;
+        }
+        while state > 0xffff {
+            state -= 0xffff;
+        }
+        !state as u16
+    }
+}
+
+/// Associate functions for [IgmpMessage].
+impl<T> IgmpMessage<T> {
+    pub fn new(ethernet2_hdr: Ethernet2Header, ipv4_hdr: Ipv4Header, igmp_hdr: IgmpHeader) -> Self {
+        Self {
+            ethernet2_hdr,
+            ipv4_hdr,
+            igmp_hdr,
+            _body_marker: PhantomData,
+        }
+    }
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+/// TryFrom trait implementation for [IgmpType].
+impl TryFrom<u8> for IgmpType {
+    type Error = Fail;
+
+    fn try_from(n: u8) -> Result<Self, Fail> {
+        match n {
+            0x11 => Ok(IgmpType::MembershipQuery),
+            0x12 => Ok(IgmpType::MembershipReportV1),
+            0x16 => Ok(IgmpType::MembershipReportV2),
+            0x17 => Ok(IgmpType::LeaveGroup),
+            _ => Err(Fail::Unsupported {
+                details: "Unsupported IGMP message type",
+            }),
+        }
+    }
+}
+
+/// PacketBuf trait implementation for [IgmpMessage].
+impl<T> PacketBuf<T> for IgmpMessage<T> {
+    fn header_size(&self) -> usize {
+        self.ethernet2_hdr.compute_size() + self.ipv4_hdr.compute_size() + IGMP_HEADER_SIZE
+    }
+
+    fn body_size(&self) -> usize {
+        0
+    }
+
+    fn has_body(&self) -> bool {
+        false
+    }
+
+    unsafe fn get_body(&self) -> *mut T {
+        ptr::null_mut()
+    }
+
+    fn write_header(&self, buf: &mut [u8]) {
+        let eth_hdr_size = self.ethernet2_hdr.compute_size();
+        let ipv4_hdr_size = self.ipv4_hdr.compute_size();
+        let mut cur_pos = 0;
+
+        self.ethernet2_hdr
+            .serialize(&mut buf[cur_pos..(cur_pos + eth_hdr_size)]);
+        cur_pos += eth_hdr_size;
+
+        self.ipv4_hdr
+            .serialize(&mut buf[cur_pos..(cur_pos + ipv4_hdr_size)], IGMP_HEADER_SIZE);
+        cur_pos += ipv4_hdr_size;
+
+        self.igmp_hdr
+            .serialize(&mut buf[cur_pos..(cur_pos + IGMP_HEADER_SIZE)]);
+    }
+
+    fn take_body(self) -> Option<T> {
+        None
+    }
+
+    fn write_header_index(&self, _buf: &mut [u8], _index: usize) {
+        unreachable!("IGMP messages are never batched");
+    }
+
+    unsafe fn get_batch(&self) -> *mut Vec<T> {
+        ptr::null_mut()
+    }
+
+    fn if_batch(&self) -> bool {
+        false
+    }
+}
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::{IgmpHeader, IgmpType, IGMP_HEADER_SIZE};
+    use crate::{collections::bytes::Bytes, runtime::RuntimeBuf};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn serialize_parse() {
+        let group = Ipv4Addr::new(239, 1, 2, 3);
+        let hdr = IgmpHeader::new(IgmpType::MembershipReportV2, group);
+        let mut buf = [0u8; IGMP_HEADER_SIZE];
+        hdr.serialize(&mut buf);
+        assert_eq!(buf[0], 0x16);
+
+        let parsed = IgmpHeader::parse(Bytes::from_slice(&buf).unwrap()).unwrap();
+        assert_eq!(parsed.igmp_type, IgmpType::MembershipReportV2);
+        assert_eq!(parsed.group, group);
+
+        // Corrupted messages are rejected.
+        buf[7] ^= 1;
+        assert!(IgmpHeader::parse(Bytes::from_slice(&buf).unwrap()).is_err());
+    }
+}
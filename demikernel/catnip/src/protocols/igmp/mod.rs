@@ -0,0 +1,8 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+mod datagram;
+mod peer;
+
+pub use datagram::{IgmpHeader, IgmpType, IGMP_ALL_HOSTS, IGMP_ALL_ROUTERS};
+pub use peer::IgmpPeer as Peer;
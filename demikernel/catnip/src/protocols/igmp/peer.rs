@@ -0,0 +1,144 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::datagram::{IgmpHeader, IgmpMessage, IgmpType, IGMP_ALL_HOSTS, IGMP_ALL_ROUTERS};
+use crate::{
+    fail::Fail,
+    protocols::{
+        ethernet2::{
+            frame::{EtherType2, Ethernet2Header},
+            MacAddress,
+        },
+        ipv4::datagram::{Ipv4Header, Ipv4Protocol2},
+    },
+    runtime::Runtime,
+};
+use std::{cell::RefCell, collections::HashMap, net::Ipv4Addr, rc::Rc};
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// IGMP messages never leave the local network.
+const IGMP_TTL: u8 = 1;
+
+///
+/// Internet Group Management Protocol (IGMP)
+///
+/// Keeps track of the multicast groups joined by local sockets, and tells multicast routers
+/// about them. Queries are answered right away rather than after a random delay, so reports of
+/// other hosts are never waited for.
+///
+/// # References
+///
+/// - See https://datatracker.ietf.org/doc/html/rfc2236 for details on IGMPv2.
+///
+#[derive(Clone)]
+pub struct IgmpPeer<RT: Runtime> {
+    rt: RT,
+    /// Number of sockets that joined each group.
+    groups: Rc<RefCell<HashMap<Ipv4Addr, usize>>>,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [IgmpPeer].
+impl<RT: Runtime> IgmpPeer<RT> {
+    pub fn new(rt: RT) -> Self {
+        Self {
+            rt,
+            groups: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Joins `group` on behalf of a socket. The group is reported when the first socket joins.
+    pub fn join(&self, group: Ipv4Addr) {
+        let first = {
+            let mut groups = self.groups.borrow_mut();
+            let count = groups.entry(group).or_insert(0);
+            *count += 1;
+            *count == 1
+        };
+        if first {
+            self.send(IgmpType::MembershipReportV2, group, group);
+        }
+    }
+
+    /// Leaves `group` on behalf of a socket. Routers are told when the last socket leaves.
+    pub fn leave(&self, group: Ipv4Addr) {
+        let last = {
+            let mut groups = self.groups.borrow_mut();
+            match groups.get_mut(&group) {
+                Some(count) if *count > 1 => {
+                    *count -= 1;
+                    false
+                }
+                Some(_) => {
+                    groups.remove(&group);
+                    true
+                }
+                None => false,
+            }
+        };
+        if last {
+            self.send(IgmpType::LeaveGroup, group, IGMP_ALL_ROUTERS);
+        }
+    }
+
+    /// Returns `true` if datagrams sent to multicast address `addr` are to be received.
+    pub fn is_member(&self, addr: Ipv4Addr) -> bool {
+        addr == IGMP_ALL_HOSTS || self.groups.borrow().contains_key(&addr)
+    }
+
+    /// Returns `true` if frames sent to multicast link address `link_addr` are to be received.
+    pub fn accepts_link_addr(&self, link_addr: MacAddress) -> bool {
+        link_addr == MacAddress::from_ipv4_multicast(IGMP_ALL_HOSTS)
+            || self
+                .groups
+                .borrow()
+                .keys()
+                .any(|&group| MacAddress::from_ipv4_multicast(group) == link_addr)
+    }
+
+    /// Parses and handles an IGMP message. Queries are answered with a report for each group
+    /// they are about.
+    pub fn receive(&self, _ipv4_header: &Ipv4Header, buf: RT::Buf) -> Result<(), Fail> {
+        let hdr = IgmpHeader::parse(buf)?;
+        debug!("IGMP received {:?}", hdr);
+        if hdr.igmp_type != IgmpType::MembershipQuery {
+            return Ok(());
+        }
+        let groups: Vec<Ipv4Addr> = if hdr.group.is_unspecified() {
+            self.groups.borrow().keys().cloned().collect()
+        } else if self.groups.borrow().contains_key(&hdr.group) {
+            vec![hdr.group]
+        } else {
+            vec![]
+        };
+        for group in groups {
+            self.send(IgmpType::MembershipReportV2, group, group);
+        }
+        Ok(())
+    }
+
+    /// Sends an IGMP message about `group` to `dst_addr`.
+    fn send(&self, igmp_type: IgmpType, group: Ipv4Addr, dst_addr: Ipv4Addr) {
+        let mut ipv4_hdr =
+            Ipv4Header::new(self.rt.local_ipv4_addr(), dst_addr, Ipv4Protocol2::Igmp);
+        ipv4_hdr.time_to_live = IGMP_TTL;
+        // Routers must look at IGMP messages, even those sent to groups they don't forward
+        // (RFC 2236, section 2).
+        ipv4_hdr.router_alert = true;
+        self.rt.transmit(IgmpMessage::new(
+            Ethernet2Header::new(
+                MacAddress::from_ipv4_multicast(dst_addr),
+                self.rt.local_link_addr(),
+                EtherType2::Ipv4,
+            ),
+            ipv4_hdr,
+            IgmpHeader::new(igmp_type, group),
+        ));
+    }
+}
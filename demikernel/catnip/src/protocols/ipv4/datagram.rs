@@ -16,10 +16,42 @@ pub const DEFAULT_IPV4_TTL: u8 = 64;
 pub const IPV4_IHL_NO_OPTIONS: u8 = 5;
 pub const IPV4_VERSION: u8 = 4;
 
+// Don't Fragment bit of the 3-bit flags field (RFC 791, section 3.1).
+pub const IPV4_FLAG_DONT_FRAGMENT: u8 = 0b010;
+
+// ECN codepoints (RFC 3168, section 5).
+pub const IPV4_ECN_NOT_ECT: u8 = 0;
+pub const IPV4_ECN_ECT0: u8 = 2;
+pub const IPV4_ECN_CE: u8 = 3;
+
+// IPv4 options (RFC 791, section 3.1). A Router Alert option with a value of zero asks routers
+// to examine the datagram (RFC 2113).
+const IPV4_OPTION_END: u8 = 0;
+const IPV4_OPTION_NOP: u8 = 1;
+const IPV4_OPTION_ROUTER_ALERT: [u8; 4] = [0x94, 0x04, 0x00, 0x00];
+
+/// Returns the broadcast address of the subnet of `addr`.
+pub fn subnet_broadcast_addr(addr: Ipv4Addr, netmask: Ipv4Addr) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from(addr) | !u32::from(netmask))
+}
+
+/// Returns the netmask implied by the class of `addr` (RFC 791, section 3.2), for when none is
+/// configured. Addresses past class C have no subnet to speak of.
+pub fn classful_netmask(addr: Ipv4Addr) -> Ipv4Addr {
+    let first_octet = addr.octets()[0];
+    match first_octet.leading_ones() {
+        0 => Ipv4Addr::new(255, 0, 0, 0),
+        1 => Ipv4Addr::new(255, 255, 0, 0),
+        2 => Ipv4Addr::new(255, 255, 255, 0),
+        _ => Ipv4Addr::BROADCAST,
+    }
+}
+
 #[repr(u8)]
 #[derive(FromPrimitive, Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Ipv4Protocol2 {
     Icmpv4 = 0x01,
+    Igmp = 0x02,
     Tcp = 0x06,
     Udp = 0x11,
 }
@@ -41,7 +73,7 @@ impl TryFrom<u8> for Ipv4Protocol2 {
 pub struct Ipv4Header {
     // [ version 4 bits ] [ IHL 4 bits ]
     // The user shouldn't be able to mutate the version, so we parse it out but don't include it
-    // here. Since we don't support IPv4 options, the same holds for the ihl field.
+    // here. The only option we send is Router Alert, so the ihl field follows from `router_alert`.
     // pub version: u8,
     // pub ihl: u8,
 
@@ -64,6 +96,9 @@ pub struct Ipv4Header {
     // header_checksum: u16,
     pub src_addr: Ipv4Addr,
     pub dst_addr: Ipv4Addr,
+
+    // Whether the datagram carries the Router Alert option.
+    pub router_alert: bool,
 }
 
 fn ipv4_checksum(buf: &[u8]) -> u16 {
@@ -83,24 +118,51 @@ fn ipv4_checksum(buf: &[u8]) -> u16 {
     !state as u16
 }
 
+// Looks for the Router Alert option in the options of a header (RFC 791, section 3.1).
+fn has_router_alert(mut options: &[u8]) -> bool {
+    while let Some(&option_type) = options.first() {
+        match option_type {
+            IPV4_OPTION_END => break,
+            IPV4_OPTION_NOP => options = &options[1..],
+            _ => {
+                let option_len = match options.get(1) {
+                    Some(&len) if len >= 2 && len as usize <= options.len() => len as usize,
+                    _ => break,
+                };
+                if options[..option_len] == IPV4_OPTION_ROUTER_ALERT[..] {
+                    return true;
+                }
+                options = &options[option_len..];
+            }
+        }
+    }
+    false
+}
+
 impl Ipv4Header {
     pub fn new(src_addr: Ipv4Addr, dst_addr: Ipv4Addr, protocol: Ipv4Protocol2) -> Self {
         Self {
             dscp: 0,
             ecn: 0,
             identification: 0,
-            flags: 0,
+            // We never fragment, so let routers tell us about a smaller path MTU instead
+            // (RFC 1191, section 3).
+            flags: IPV4_FLAG_DONT_FRAGMENT,
             fragment_offset: 0,
             time_to_live: 0,
             protocol,
             src_addr,
             dst_addr,
+            router_alert: false,
         }
     }
 
     pub fn compute_size(&self) -> usize {
-        // We don't support IPv4 options, so this is always 20.
-        IPV4_HEADER_SIZE
+        if self.router_alert {
+            IPV4_HEADER_SIZE + IPV4_OPTION_ROUTER_ALERT.len()
+        } else {
+            IPV4_HEADER_SIZE
+        }
     }
 
     pub fn parse<T: RuntimeBuf>(mut buf: T) -> Result<(Self, T), Fail> {
@@ -127,10 +189,11 @@ impl Ipv4Header {
                 details: "IPv4 IHL is too small",
             });
         }
-        if ihl > IPV4_IHL_NO_OPTIONS {
-            println!("IPv4 options are unsupported");
-            return Err(Fail::Unsupported {
-                details: "IPv4 options are unsupported",
+        // Options other than Router Alert are skipped.
+        let hdr_size = ihl as usize * 4;
+        if buf.len() < hdr_size {
+            return Err(Fail::Malformed {
+                details: "Datagram too small for IPv4 options",
             });
         }
 
@@ -140,7 +203,7 @@ impl Ipv4Header {
         let total_length = NetworkEndian::read_u16(&hdr_buf[2..4]) as usize;
 
         // The TOTALLEN is definitely malformed if it doesn't have room for our header.
-        if total_length < IPV4_HEADER_SIZE {
+        if total_length < hdr_size {
             println!("IPv4 TOTALLEN smaller than header");
             return Err(Fail::Malformed {
                 details: "IPv4 TOTALLEN smaller than header",
@@ -183,13 +246,14 @@ impl Ipv4Header {
 
         let src_addr = Ipv4Addr::from(NetworkEndian::read_u32(&hdr_buf[12..16]));
         let dst_addr = Ipv4Addr::from(NetworkEndian::read_u32(&hdr_buf[16..20]));
+        let router_alert = has_router_alert(&buf[IPV4_HEADER_SIZE..hdr_size]);
 
         // NB (sujayakar, 11/6/2020): I've noticed that Ethernet transmission is liable to add
         // padding zeros for small payloads, so we can't assert that the Ethernet payload we
         // receives exactly matches the header's TOTALLEN. Therefore, we may need to truncate off
         // padding bytes when they don't line up.
         let padding_bytes = buf.len() - total_length;
-        buf.adjust(IPV4_HEADER_SIZE);
+        buf.adjust(hdr_size);
         buf.trim(padding_bytes);
 
         let header = Self {
@@ -202,15 +266,17 @@ impl Ipv4Header {
             protocol,
             src_addr,
             dst_addr,
+            router_alert,
         };
         Ok((header, buf))
     }
 
     pub fn serialize(&self, buf: &mut [u8], payload_len: usize) {
-        let buf: &mut [u8; IPV4_HEADER_SIZE] = buf.try_into().unwrap();
-        buf[0] = (IPV4_VERSION << 4) | IPV4_IHL_NO_OPTIONS;
+        let hdr_size = self.compute_size();
+        assert_eq!(buf.len(), hdr_size);
+        buf[0] = (IPV4_VERSION << 4) | (hdr_size / 4) as u8;
         buf[1] = (self.dscp << 2) | (self.ecn & 3);
-        NetworkEndian::write_u16(&mut buf[2..4], (IPV4_HEADER_SIZE + payload_len) as u16);
+        NetworkEndian::write_u16(&mut buf[2..4], (hdr_size + payload_len) as u16);
         NetworkEndian::write_u16(&mut buf[4..6], self.identification);
         NetworkEndian::write_u16(
             &mut buf[6..8],
@@ -222,6 +288,9 @@ impl Ipv4Header {
         // Skip the checksum (bytes 10..12) until we finish writing the header.
         buf[12..16].copy_from_slice(&self.src_addr.octets());
         buf[16..20].copy_from_slice(&self.dst_addr.octets());
+        if self.router_alert {
+            buf[IPV4_HEADER_SIZE..].copy_from_slice(&IPV4_OPTION_ROUTER_ALERT);
+        }
 
         // let checksum = ipv4_checksum(buf);
         // let checksum =0;
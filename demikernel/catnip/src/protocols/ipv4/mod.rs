@@ -9,6 +9,6 @@ mod peer;
 #[cfg(test)]
 mod tests;
 
-pub use datagram::{Ipv4Header, Ipv4Protocol2};
+pub use datagram::{classful_netmask, subnet_broadcast_addr, Ipv4Header, Ipv4Protocol2};
 pub use endpoint::Ipv4Endpoint as Endpoint;
 pub use peer::Ipv4Peer as Peer;
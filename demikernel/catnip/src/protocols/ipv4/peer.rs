@@ -5,8 +5,10 @@ use crate::{
     fail::Fail,
     file_table::FileTable,
     protocols::{
-        arp, icmpv4,
-        ipv4::datagram::{Ipv4Header, Ipv4Protocol2},
+        arp,
+        ethernet2::MacAddress,
+        icmpv4, igmp,
+        ipv4::datagram::{subnet_broadcast_addr, Ipv4Header, Ipv4Protocol2},
         tcp, udp,
     },
     runtime::Runtime,
@@ -19,18 +21,21 @@ use crate::file_table::FileDescriptor;
 pub struct Ipv4Peer<RT: Runtime> {
     rt: RT,
     icmpv4: icmpv4::Peer<RT>,
+    igmp: igmp::Peer<RT>,
     pub tcp: tcp::Peer<RT>,
     pub udp: udp::Peer<RT>,
 }
 
 impl<RT: Runtime> Ipv4Peer<RT> {
     pub fn new(rt: RT, arp: arp::Peer<RT>, file_table: FileTable) -> Ipv4Peer<RT> {
-        let udp = udp::Peer::new(rt.clone(), arp.clone(), file_table.clone());
+        let igmp = igmp::Peer::new(rt.clone());
+        let udp = udp::Peer::new(rt.clone(), arp.clone(), igmp.clone(), file_table.clone());
         let icmpv4 = icmpv4::Peer::new(rt.clone(), arp.clone());
         let tcp = tcp::Peer::new(rt.clone(), arp, file_table);
         Ipv4Peer {
             rt,
             icmpv4,
+            igmp,
             tcp,
             udp,
         }
@@ -39,16 +44,61 @@ impl<RT: Runtime> Ipv4Peer<RT> {
     pub fn receive(&mut self, buf: RT::Buf) -> Result<(), Fail> {
         let (header, payload) = Ipv4Header::parse(buf)?;
         debug!("Ipv4 received {:?}", header);
-        if header.dst_addr != self.rt.local_ipv4_addr() && !header.dst_addr.is_broadcast() {
+        if !self.accepts(header.dst_addr) {
             return Err(Fail::Misdelivered {});
         }
         match header.protocol {
-            Ipv4Protocol2::Icmpv4 => self.icmpv4.receive(&header, payload),
+            Ipv4Protocol2::Icmpv4 => match self.icmpv4.receive(&header, payload)? {
+                Some((icmpv4_hdr, quote)) => match (quote.protocol, icmpv4_hdr.icmpv4_type) {
+                    (
+                        Ipv4Protocol2::Tcp,
+                        icmpv4::Icmpv4Type2::DestinationUnreachable { next_hop_mtu },
+                    ) if icmpv4_hdr.code == icmpv4::ICMPV4_FRAGMENTATION_NEEDED => self
+                        .tcp
+                        .fragmentation_needed(quote.src, quote.dst, quote.seq_num, next_hop_mtu),
+                    (Ipv4Protocol2::Udp, icmpv4::Icmpv4Type2::DestinationUnreachable { .. })
+                        if icmpv4_hdr.code == icmpv4::ICMPV4_PORT_UNREACHABLE =>
+                    {
+                        self.udp.port_unreachable(quote.src, quote.dst)
+                    }
+                    _ => Ok(()),
+                },
+                None => Ok(()),
+            },
+            Ipv4Protocol2::Igmp => self.igmp.receive(&header, payload),
             Ipv4Protocol2::Tcp => self.tcp.receive(&header, payload),
-            Ipv4Protocol2::Udp => self.udp.receive(&header, payload),
+            Ipv4Protocol2::Udp => {
+                // Keep the start of the datagram around, in case it has to be quoted in an
+                // ICMPv4 error.
+                let payload_len = payload.len();
+                let mut quote = [0u8; icmpv4::ICMPV4_QUOTED_PAYLOAD_SIZE];
+                let quote_len = payload_len.min(quote.len());
+                quote[..quote_len].copy_from_slice(&payload[..quote_len]);
+                let r = self.udp.receive(&header, payload);
+                if let Err(Fail::PortUnreachable {}) = r {
+                    self.icmpv4
+                        .send_port_unreachable(&header, payload_len, &quote[..quote_len]);
+                }
+                r
+            }
         }
     }
 
+    /// Returns `true` if datagrams sent to `dst_addr` are to be received: those sent to the local
+    /// address, limited and subnet broadcasts, and those sent to joined multicast groups.
+    fn accepts(&self, dst_addr: Ipv4Addr) -> bool {
+        let local_addr = self.rt.local_ipv4_addr();
+        dst_addr == local_addr
+            || dst_addr.is_broadcast()
+            || dst_addr == subnet_broadcast_addr(local_addr, self.rt.local_ipv4_netmask())
+            || (dst_addr.is_multicast() && self.igmp.is_member(dst_addr))
+    }
+
+    /// Returns `true` if frames sent to multicast link address `link_addr` are to be received.
+    pub fn accepts_link_addr(&self, link_addr: MacAddress) -> bool {
+        self.igmp.accepts_link_addr(link_addr)
+    }
+
     pub fn ping(
         &mut self,
         dest_ipv4_addr: Ipv4Addr,
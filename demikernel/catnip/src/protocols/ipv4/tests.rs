@@ -1,10 +1,18 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use crate::{runtime::Runtime, test_helpers};
+use super::{
+    classful_netmask,
+    datagram::{Ipv4Header, Ipv4Protocol2, IPV4_HEADER_SIZE},
+};
+use crate::{
+    collections::bytes::Bytes,
+    runtime::{Runtime, RuntimeBuf},
+    test_helpers,
+};
 use futures::task::{noop_waker_ref, Context};
 use must_let::must_let;
-use std::{future::Future, pin::Pin, task::Poll, time::Duration, time::Instant};
+use std::{future::Future, net::Ipv4Addr, pin::Pin, task::Poll, time::Duration, time::Instant};
 
 //==============================================================================
 // IPv4 Ping
@@ -79,3 +87,46 @@ fn ipv4_ping_loop() {
         assert_eq!(latency, Duration::from_secs(2));
     }
 }
+
+//==============================================================================
+// Header
+//==============================================================================
+
+#[test]
+fn ipv4_router_alert() {
+    let mut hdr = Ipv4Header::new(
+        test_helpers::ALICE_IPV4,
+        Ipv4Addr::new(224, 0, 0, 2),
+        Ipv4Protocol2::Igmp,
+    );
+    hdr.router_alert = true;
+    assert_eq!(hdr.compute_size(), IPV4_HEADER_SIZE + 4);
+    let mut buf = vec![0u8; hdr.compute_size() + 8];
+    hdr.serialize(&mut buf[..IPV4_HEADER_SIZE + 4], 8);
+    assert_eq!(buf[0], 0x46);
+
+    let (parsed, payload) = Ipv4Header::parse(Bytes::from_slice(&buf).unwrap()).unwrap();
+    assert!(parsed.router_alert);
+    assert_eq!(parsed.dst_addr, Ipv4Addr::new(224, 0, 0, 2));
+    assert_eq!(payload.len(), 8);
+}
+
+#[test]
+fn ipv4_classful_netmask() {
+    assert_eq!(
+        classful_netmask(Ipv4Addr::new(10, 1, 2, 3)),
+        Ipv4Addr::new(255, 0, 0, 0)
+    );
+    assert_eq!(
+        classful_netmask(Ipv4Addr::new(172, 16, 0, 1)),
+        Ipv4Addr::new(255, 255, 0, 0)
+    );
+    assert_eq!(
+        classful_netmask(Ipv4Addr::new(192, 168, 1, 1)),
+        Ipv4Addr::new(255, 255, 255, 0)
+    );
+    assert_eq!(
+        classful_netmask(Ipv4Addr::new(239, 1, 2, 3)),
+        Ipv4Addr::BROADCAST
+    );
+}
@@ -4,6 +4,7 @@
 pub mod arp;
 pub mod ethernet2;
 pub mod icmpv4;
+pub mod igmp;
 pub mod ip;
 pub mod ipv4;
 pub mod tcp;
@@ -3,9 +3,15 @@
 
 use super::{
     constants::FALLBACK_MSS,
-    established::state::{receiver::Receiver, sender::Sender, ControlBlock},
+    established::state::{
+        receiver::Receiver,
+        sender::Sender,
+        timestamps::{timestamp_value, Timestamps},
+        ConnectionState, ControlBlock,
+    },
 };
 use crate::{
+    collections::watched::WatchedValue,
     fail::Fail,
     protocols::{
         arp,
@@ -27,6 +33,7 @@ use std::{
     num::Wrapping,
     rc::Rc,
     task::{Context, Poll, Waker},
+    time::Instant,
 };
 
 struct ConnectResult<RT: Runtime> {
@@ -36,6 +43,8 @@ struct ConnectResult<RT: Runtime> {
 
 pub struct ActiveOpenSocket<RT: Runtime> {
     local_isn: SeqNumber,
+    /// Origin of our timestamp clock, shared with the SYN we send.
+    timestamp_origin: Instant,
 
     local: ipv4::Endpoint,
     remote: ipv4::Endpoint,
@@ -61,9 +70,11 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
             result: None,
         };
         let result = Rc::new(RefCell::new(result));
+        let timestamp_origin = rt.now();
 
         let future = Self::background(
             local_isn,
+            timestamp_origin,
             local,
             remote,
             rt.clone(),
@@ -75,6 +86,7 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
         // TODO: Add fast path here when remote is already in the ARP cache (and subtract one retry).
         Self {
             local_isn,
+            timestamp_origin,
             local,
             remote,
             rt,
@@ -105,6 +117,7 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
     }
 
     pub fn receive(&mut self, header: &TcpHeader) {
+        let tcp_options = self.rt.tcp_options();
         let expected_seq = self.local_isn + Wrapping(1);
 
         // Bail if we didn't receive a ACK packet with the right sequence number.
@@ -125,6 +138,40 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
 
         debug!("Received SYN+ACK: {:?}", header);
 
+        let mut remote_window_scale = None;
+        let mut mss = FALLBACK_MSS;
+        let mut remote_sack_permitted = false;
+        let mut remote_timestamp = None;
+        for option in header.iter_options() {
+            match option {
+                TcpOptions2::Timestamp {
+                    sender_timestamp, ..
+                } => {
+                    info!("Received timestamp: {}", sender_timestamp);
+                    remote_timestamp = Some(*sender_timestamp);
+                }
+                TcpOptions2::SelectiveAcknowlegementPermitted => {
+                    info!("Received SACK permitted");
+                    remote_sack_permitted = true;
+                }
+                TcpOptions2::WindowScale(w) => {
+                    info!("Received window scale: {}", w);
+                    remote_window_scale = Some(*w);
+                }
+                TcpOptions2::MaximumSegmentSize(m) => {
+                    info!("Received advertised MSS: {}", m);
+                    mss = *m as usize;
+                }
+                _ => continue,
+            }
+        }
+
+        // Use timestamps only if both our SYN and the SYN+ACK carried them (RFC 7323, section 3.2).
+        let timestamps = match remote_timestamp {
+            Some(t) if tcp_options.timestamps => Some(Timestamps::new(self.timestamp_origin, t)),
+            _ => None,
+        };
+
         // Acknowledge the SYN+ACK segment.
         let remote_link_addr = match self.arp.try_query(self.remote.address()) {
             Some(r) => r,
@@ -132,13 +179,17 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
         };
         let remote_seq_num = header.seq_num + Wrapping(1);
 
-        let tcp_options = self.rt.tcp_options();
-
         let mut tcp_hdr = TcpHeader::new(self.local.port, self.remote.port);
         tcp_hdr.ack = true;
         tcp_hdr.ack_num = remote_seq_num;
         tcp_hdr.window_size = tcp_options.receive_window_size;
         tcp_hdr.seq_num = self.local_isn + Wrapping(1);
+        if let Some(ref timestamps) = timestamps {
+            tcp_hdr.push_option(TcpOptions2::Timestamp {
+                sender_timestamp: timestamps.value(self.rt.now()),
+                echo_timestamp: timestamps.recent(),
+            });
+        }
         debug!("Sending ACK: {:?}", tcp_hdr);
 
         let segment = TcpSegment {
@@ -154,22 +205,6 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
         };
         self.rt.transmit(segment);
 
-        let mut remote_window_scale = None;
-        let mut mss = FALLBACK_MSS;
-        for option in header.iter_options() {
-            match option {
-                TcpOptions2::WindowScale(w) => {
-                    info!("Received window scale: {}", w);
-                    remote_window_scale = Some(*w);
-                }
-                TcpOptions2::MaximumSegmentSize(m) => {
-                    info!("Received advertised MSS: {}", m);
-                    mss = *m as usize;
-                }
-                _ => continue,
-            }
-        }
-
         let (local_window_scale, remote_window_scale) = match remote_window_scale {
             Some(w) => (tcp_options.window_scale as u32, w),
             None => (0, 0),
@@ -207,20 +242,35 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
             tcp_options.congestion_ctrl_type,
             tcp_options.congestion_ctrl_options,
         );
-        let receiver = Receiver::new(remote_seq_num, rx_window_size, local_window_scale);
+        let receiver = Receiver::new(
+            remote_seq_num,
+            rx_window_size,
+            local_window_scale,
+            tcp_options.advertised_mss,
+        );
         let cb = ControlBlock {
             local: self.local,
             remote: self.remote,
             rt: self.rt.clone(),
             arp: self.arp.clone(),
+            state: WatchedValue::new(ConnectionState::Established),
             sender,
             receiver,
+            sack_permitted: tcp_options.selective_acks && remote_sack_permitted,
+            timestamps,
+            // An ECN-setup SYN+ACK has ECE but not CWR (RFC 3168, section 6.1.1).
+            ecn: tcp_options.negotiates_ecn() && header.ece && !header.cwr,
+            keepalive: WatchedValue::new(tcp_options.keepalive),
+            last_heard: WatchedValue::new(self.rt.now()),
+            nodelay: WatchedValue::new(tcp_options.nodelay),
+            cork: WatchedValue::new(false),
         };
         self.set_result(Ok(cb));
     }
 
     fn background(
         local_isn: SeqNumber,
+        timestamp_origin: Instant,
         local: ipv4::Endpoint,
         remote: ipv4::Endpoint,
         rt: RT,
@@ -230,6 +280,7 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
         let tcp_options = rt.tcp_options();
         let handshake_retries: usize = tcp_options.handshake_retries;
         let handshake_timeout = tcp_options.handshake_timeout;
+        let ecn = tcp_options.negotiates_ecn();
 
         async move {
             for _ in 0..handshake_retries {
@@ -245,6 +296,11 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
                 tcp_hdr.syn = true;
                 tcp_hdr.seq_num = local_isn;
                 tcp_hdr.window_size = tcp_options.receive_window_size;
+                if ecn {
+                    // This is an ECN-setup SYN.
+                    tcp_hdr.ece = true;
+                    tcp_hdr.cwr = true;
+                }
 
                 let mss = tcp_options.advertised_mss as u16;
                 tcp_hdr.push_option(TcpOptions2::MaximumSegmentSize(mss));
@@ -253,6 +309,18 @@ impl<RT: Runtime> ActiveOpenSocket<RT> {
                 tcp_hdr.push_option(TcpOptions2::WindowScale(tcp_options.window_scale));
                 info!("Advertising window scale: {}", tcp_options.window_scale);
 
+                if tcp_options.selective_acks {
+                    tcp_hdr.push_option(TcpOptions2::SelectiveAcknowlegementPermitted);
+                    info!("Advertising SACK permitted");
+                }
+
+                if tcp_options.timestamps {
+                    tcp_hdr.push_option(TcpOptions2::Timestamp {
+                        sender_timestamp: timestamp_value(timestamp_origin, rt.now()),
+                        echo_timestamp: 0,
+                    });
+                }
+
                 debug!("Sending SYN {:?}", tcp_hdr);
                 let segment = TcpSegment {
                     ethernet2_hdr: Ethernet2Header {
@@ -1,6 +1,8 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
+use std::time::Duration;
+
 // from [TCP/IP Illustrated](https://learning.oreilly.com/library/view/tcpip-illustrated-volume/9780132808200/ch13.html):
 // > if no MSS option is provided, a default value of 536 bytes is used.
 pub const FALLBACK_MSS: usize = 536;
@@ -8,5 +10,20 @@ pub const FALLBACK_MSS: usize = 536;
 pub const MIN_MSS: usize = 536;
 pub const MAX_MSS: usize = u16::max_value() as usize;
 
-// TODO: does this need to be determined through MTU discovery?
+// Path MTU discovery lowers the MSS of connections whose path can't take segments this large.
 pub const DEFAULT_MSS: usize = 1450;
+
+// Largest number of SACK blocks carried by a segment (RFC 2018).
+pub const MAX_SACK_BLOCKS: usize = 4;
+
+// Longest interval between zero window probes, as the persist timer backs off.
+pub const MAX_PERSIST_TIMEOUT: Duration = Duration::from_secs(60);
+
+// MTU plateaus to guess from when a router doesn't report the next-hop MTU (RFC 1191, section 7).
+pub const PMTU_PLATEAUS: [u16; 11] = [
+    65535, 32000, 17914, 8166, 4352, 2002, 1492, 1006, 508, 296, 68,
+];
+
+// How long to stick to a reduced path MTU before trying larger segments again (RFC 1191, section
+// 6.3).
+pub const PMTU_RAISE_TIMEOUT: Duration = Duration::from_secs(600);
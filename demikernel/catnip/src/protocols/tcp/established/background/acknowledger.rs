@@ -19,7 +19,6 @@ pub async fn acknowledger<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fa
         // - The delay must be less than 500ms
         // - For a stream of full-sized segments, there should be an ack for every other segment.
 
-        // TODO: Implement SACKs
         let (ack_deadline, ack_deadline_changed) = cb.receiver.ack_deadline.watch();
         futures::pin_mut!(ack_deadline_changed);
 
@@ -29,11 +28,21 @@ pub async fn acknowledger<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fa
         };
         futures::pin_mut!(ack_future);
 
+        // The application reading data may reopen our window.
+        let (_, base_seq_no_changed) = cb.receiver.base_seq_no.watch();
+        futures::pin_mut!(base_seq_no_changed);
+
         futures::select_biased! {
             _ = ack_deadline_changed => continue,
+            _ = base_seq_no_changed => {
+                if cb.receiver.window_update_due() {
+                    debug!("Sending window update to {:?}", cb.remote);
+                    cb.receiver.ack_deadline.set(Some(cb.rt.now()));
+                }
+            },
             _ = ack_future => {
+                // This may be a duplicate ACK, sent on receiving out-of-order data.
                 let recv_seq_no = cb.receiver.recv_seq_no.get();
-                assert_ne!(cb.receiver.ack_seq_no.get(), recv_seq_no);
 
                 let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
 
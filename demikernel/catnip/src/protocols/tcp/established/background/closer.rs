@@ -3,7 +3,9 @@
 
 //! Defines functions to be called during the TCP connection termination process.
 
-use super::super::state::{receiver::ReceiverState, sender::SenderState, ControlBlock};
+use super::super::state::{
+    receiver::ReceiverState, sender::SenderState, ConnectionState, ControlBlock,
+};
 use crate::{
     fail::Fail,
     runtime::{Runtime, RuntimeBuf},
@@ -21,10 +23,11 @@ async fn sender_ack_fin<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail
             continue;
         }
 
-        // Wait for all data to be acknowledged.
+        // Wait for all data to be acknowledged, unless the remote retransmitted a FIN we've
+        // already acknowledged.
         let (ack_seq, ack_seq_changed) = cb.receiver.ack_seq_no.watch();
         let recv_seq = cb.receiver.recv_seq_no.get();
-        if ack_seq != recv_seq {
+        if ack_seq != recv_seq && ack_seq != recv_seq + Wrapping(1) {
             ack_seq_changed.await;
             continue;
         }
@@ -42,16 +45,48 @@ async fn sender_ack_fin<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail
     }
 }
 
+/// Sends a FIN right after the data we've sent.
+async fn send_fin<RT: Runtime>(cb: &ControlBlock<RT>) -> Result<(), Fail> {
+    let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
+    let mut header = cb.tcp_header();
+    header.seq_num = cb.sender.sent_seq_no.get();
+    header.fin = true;
+    cb.emit(header, RT::Buf::empty(), remote_link_addr);
+    Ok(())
+}
+
 /// Spawns a future that awaits for sender status to change to Closed . Once status is Closed
-/// sends FIN. Then goes back to a awaiting change until/if any further changes to our SenderState.
+/// sends FIN, and retransmits it until it gets acknowledged. Then goes back to a awaiting change
+/// until/if any further changes to our SenderState.
 async fn sender_send_fin<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
+    let mut fin_retries: usize = 0;
     loop {
         let (sender_st, sender_st_changed) = cb.sender.state.watch();
         match sender_st {
-            SenderState::Open | SenderState::SentFin | SenderState::FinAckd => {
+            SenderState::Open | SenderState::FinAckd => {
                 sender_st_changed.await;
                 continue;
             }
+            SenderState::SentFin => {
+                futures::pin_mut!(sender_st_changed);
+                let timeout = cb.rt.wait(cb.sender.rto.borrow().estimate()).fuse();
+                futures::pin_mut!(timeout);
+                futures::select_biased! {
+                    _ = sender_st_changed => continue,
+                    _ = timeout => {},
+                }
+                // Give up on a remote that never acknowledges our FIN (RFC 1122, section
+                // 4.2.3.5).
+                if fin_retries == cb.rt.tcp_options().retries {
+                    warn!("FIN to {:?} unacknowledged", cb.remote);
+                    cb.sender.abort();
+                    cb.receiver.abort();
+                    return Err(Fail::Timeout {});
+                }
+                fin_retries += 1;
+                cb.sender.rto.borrow_mut().record_failure();
+                send_fin(&cb).await?;
+            }
             SenderState::Closed => {
                 // Wait for `sent_seq_no` to catch up to `unsent_seq_no` and
                 // then send a FIN segment.
@@ -63,13 +98,7 @@ async fn sender_send_fin<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fai
                     continue;
                 }
 
-                // TODO: When do we retransmit this?
-                let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
-                let mut header = cb.tcp_header();
-                header.seq_num = sent_seq;
-                header.fin = true;
-                cb.emit(header, RT::Buf::empty(), remote_link_addr);
-
+                send_fin(&cb).await?;
                 cb.sender.state.set(SenderState::SentFin);
             }
             SenderState::Reset => {
@@ -83,30 +112,50 @@ async fn sender_send_fin<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fai
     }
 }
 
-/// Awaits until connection terminates by our four-way handshake.
+/// Awaits until connection terminates by our four-way handshake. If we closed first, the
+/// connection lingers in TIME_WAIT for 2*MSL, so that the remote can retransmit its FIN should
+/// our last ACK get lost, and so that late segments die out before the ports get reused. A remote
+/// that never closes its side only gets so long in FIN_WAIT_2.
 async fn close_wait<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
     loop {
-        // Wait until the FIN we sent has been ACKed.
-        let (sender_st, sender_st_changed) = cb.sender.state.watch();
-        if sender_st != SenderState::FinAckd {
-            sender_st_changed.await;
-            continue;
+        let (st, st_changed) = cb.state.watch();
+        match st {
+            ConnectionState::FinWait2 => {
+                futures::pin_mut!(st_changed);
+                let timeout = cb.rt.wait(cb.rt.tcp_options().fin_wait_2_timeout).fuse();
+                futures::pin_mut!(timeout);
+                futures::select_biased! {
+                    _ = st_changed => continue,
+                    _ = timeout => {
+                        warn!("Connection to {:?} timed out in FIN_WAIT_2", cb.remote);
+                        cb.state.set(ConnectionState::Closed);
+                        continue;
+                    },
+                }
+            }
+            ConnectionState::TimeWait => {}
+            ConnectionState::Closed => return Err(Fail::ConnectionAborted {}),
+            _ => {
+                st_changed.await;
+                continue;
+            }
         }
 
-        // Wait until we ACK the FIN that was sent to us.
-        let (receiver_st, receiver_st_changed) = cb.receiver.state.watch();
-        if receiver_st != ReceiverState::AckdFin {
-            receiver_st_changed.await;
-            continue;
+        // A retransmitted FIN, which we acknowledge again, restarts the timer (RFC 793, page 76).
+        let (_, receiver_st_changed) = cb.receiver.state.watch();
+        futures::pin_mut!(receiver_st_changed);
+        let timeout = cb.rt.wait(2 * cb.rt.tcp_options().msl).fuse();
+        futures::pin_mut!(timeout);
+        futures::select_biased! {
+            _ = receiver_st_changed => continue,
+            _ = timeout => cb.state.set(ConnectionState::Closed),
         }
-
-        // TODO: Wait for 2*MSL if active close.
-        return Err(Fail::ConnectionAborted {});
     }
 }
 
 /// Launches various closures having to do with connection termination. Neither `sender_ack_fin`
-/// nor `sender_send_fin` terminate so the only way to return is via `close_wait`.
+/// nor `sender_send_fin` terminate so the only way to return is via `close_wait`, once the
+/// connection reaches CLOSED.
 pub async fn connection_terminated<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
     futures::select_biased! {
         r = sender_ack_fin(cb.clone()).fuse() => r,
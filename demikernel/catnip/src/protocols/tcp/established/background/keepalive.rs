@@ -0,0 +1,63 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::super::state::ControlBlock;
+use crate::{
+    fail::Fail,
+    runtime::{Runtime, RuntimeBuf},
+};
+use futures::FutureExt;
+use std::{num::Wrapping, rc::Rc};
+
+/// Probes the remote once the connection has been idle for a while, and aborts the connection if
+/// the remote doesn't answer any of the probes (RFC 1122, section 4.2.3.6).
+pub async fn keepalive<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
+    'top: loop {
+        let (keepalive, keepalive_changed) = cb.keepalive.watch();
+        futures::pin_mut!(keepalive_changed);
+        let keepalive = match keepalive {
+            Some(k) => k,
+            None => {
+                keepalive_changed.await;
+                continue;
+            }
+        };
+
+        // Hearing from the remote starts the idle period over.
+        let (last_heard, last_heard_changed) = cb.last_heard.watch();
+        futures::pin_mut!(last_heard_changed);
+
+        let mut deadline = last_heard + keepalive.idle;
+        for _ in 0..keepalive.probes {
+            let timeout = cb.rt.wait_until(deadline).fuse();
+            futures::pin_mut!(timeout);
+            futures::select_biased! {
+                _ = keepalive_changed => continue 'top,
+                _ = last_heard_changed => continue 'top,
+                _ = timeout => {},
+            }
+
+            // A probe is an ACK for data we've already sent, so the remote must answer it.
+            let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
+            let mut header = cb.tcp_header();
+            header.ack = true;
+            header.ack_num = cb.receiver.recv_seq_no.get();
+            header.seq_num = cb.sender.base_seq_no.get() - Wrapping(1);
+            debug!("Sending keepalive probe: {:?}", header);
+            cb.emit(header, RT::Buf::empty(), remote_link_addr);
+            deadline = cb.rt.now() + keepalive.interval;
+        }
+
+        let timeout = cb.rt.wait_until(deadline).fuse();
+        futures::pin_mut!(timeout);
+        futures::select_biased! {
+            _ = keepalive_changed => continue 'top,
+            _ = last_heard_changed => continue 'top,
+            _ = timeout => {},
+        }
+        warn!("Keepalive probes to {:?} unanswered", cb.remote);
+        cb.sender.abort();
+        cb.receiver.abort();
+        return Err(Fail::ConnectionAborted {});
+    }
+}
@@ -3,15 +3,17 @@
 
 mod acknowledger;
 mod closer;
+mod keepalive;
+mod path_mtu;
 mod retransmitter;
 mod sender;
 
 use self::{
-    acknowledger::acknowledger, closer::connection_terminated, retransmitter::retransmitter,
-    sender::sender,
+    acknowledger::acknowledger, closer::connection_terminated, keepalive::keepalive,
+    path_mtu::path_mtu_discovery, retransmitter::retransmitter, sender::sender,
 };
-use super::state::ControlBlock;
-use crate::{file_table::FileDescriptor, runtime::Runtime};
+use super::state::{ConnectionState, ControlBlock};
+use crate::{file_table::FileDescriptor, protocols::ipv4, runtime::Runtime};
 use futures::channel::mpsc;
 use futures::FutureExt;
 use std::{future::Future, rc::Rc};
@@ -26,8 +28,8 @@ pub type BackgroundFuture<RT> = impl Future<Output = ()>;
 pub fn background<RT: Runtime>(
     cb: Rc<ControlBlock<RT>>,
     fd: FileDescriptor,
-    _dead_socket_tx: mpsc::UnboundedSender<FileDescriptor>,
-    ) -> BackgroundFuture<RT> {
+    dead_socket_tx: mpsc::UnboundedSender<(ipv4::Endpoint, ipv4::Endpoint)>,
+) -> BackgroundFuture<RT> {
     async move {
         let acknowledger = acknowledger(cb.clone()).fuse();
         futures::pin_mut!(acknowledger);
@@ -38,20 +40,32 @@ pub fn background<RT: Runtime>(
         let sender = sender(cb.clone()).fuse();
         futures::pin_mut!(sender);
 
-        let closer = connection_terminated(cb).fuse();
+        let keepalive = keepalive(cb.clone()).fuse();
+        futures::pin_mut!(keepalive);
+
+        let path_mtu = path_mtu_discovery(cb.clone()).fuse();
+        futures::pin_mut!(path_mtu);
+
+        let closer = connection_terminated(cb.clone()).fuse();
         futures::pin_mut!(closer);
 
         let r = futures::select_biased! {
             r = acknowledger => r,
             r = retransmitter => r,
             r = sender => r,
+            r = keepalive => r,
+            r = path_mtu => r,
             r = closer => r,
         };
         error!("Connection (fd {}) terminated: {:?}", fd, r);
 
-        // TODO Properly clean up Peer state for this connection.
-        // dead_socket_tx
-        //     .unbounded_send(fd)
-        //     .expect("Failed to terminate connection");
+        // Let the peer reap the connection, now that it's CLOSED.
+        cb.state.set(ConnectionState::Closed);
+        if dead_socket_tx
+            .unbounded_send((cb.local, cb.remote))
+            .is_err()
+        {
+            warn!("Failed to reap connection (fd {})", fd);
+        }
     }
 }
@@ -0,0 +1,42 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    super::state::ControlBlock,
+    retransmitter::{retransmit, RetransmitCause},
+};
+use crate::{fail::Fail, protocols::tcp::constants::PMTU_RAISE_TIMEOUT, runtime::Runtime};
+use futures::{
+    future::{self, Either},
+    FutureExt,
+};
+use std::rc::Rc;
+
+/// Path MTU discovery (RFC 1191): resends what the network dropped whenever the effective MSS
+/// shrinks, and raises it back to the remote's MSS once it has stayed reduced for a while, to find
+/// out whether the path takes larger segments again.
+pub async fn path_mtu_discovery<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
+    let mut last_mss = cb.sender.effective_mss.get();
+    loop {
+        let (effective_mss, effective_mss_changed) = cb.sender.effective_mss.watch();
+        futures::pin_mut!(effective_mss_changed);
+        if effective_mss < last_mss && !cb.sender.unacked_queue.borrow().is_empty() {
+            retransmit(RetransmitCause::PathMtu, &cb).await?;
+        }
+        last_mss = effective_mss;
+
+        let raise_timeout = if effective_mss < cb.sender.mss {
+            Either::Left(cb.rt.wait(PMTU_RAISE_TIMEOUT).fuse())
+        } else {
+            Either::Right(future::pending())
+        };
+        futures::pin_mut!(raise_timeout);
+        futures::select_biased! {
+            _ = effective_mss_changed => continue,
+            _ = raise_timeout => {
+                debug!("Raising MSS back to {}", cb.sender.mss);
+                cb.sender.effective_mss.set(cb.sender.mss);
+            },
+        }
+    }
+}
@@ -12,45 +12,49 @@ use std::rc::Rc;
 pub enum RetransmitCause {
     TimeOut,
     FastRetransmit,
+    PathMtu,
 }
 
 pub async fn retransmit<RT: Runtime>(
     cause: RetransmitCause,
     cb: &Rc<ControlBlock<RT>>,
 ) -> Result<(), Fail> {
-
-    // Pop unack'ed segment.
-    let mut unacked_queue = cb.sender.unacked_queue.borrow_mut();
-    let segment = match unacked_queue.front_mut() {
-        Some(s) => s,
-        None => {
-            warn!("Retransmission with empty unacknowledged queue");
-            return Ok(());
-        },
+    // On timeout, we only resend the first missing segment. The remote may have discarded data it
+    // selectively acknowledged (RFC 2018, section 8), so we stop trusting the scoreboard as well.
+    // On fast retransmit, we fill every hole the remote told us about. When the path MTU shrinks,
+    // we resend the first segment the network dropped for being too large, which isn't a sign of
+    // congestion (RFC 1191, section 6.5).
+    let all_holes = match cause {
+        RetransmitCause::TimeOut => {
+            cb.sender.scoreboard.borrow_mut().clear();
+            false
+        }
+        RetransmitCause::FastRetransmit => true,
+        RetransmitCause::PathMtu => false,
     };
 
     // TODO: Repacketization
+    let segments = cb.sender.retransmissions(all_holes);
+    if segments.is_empty() {
+        warn!("Retransmission with empty unacknowledged queue");
+        return Ok(());
+    }
 
     // NOTE: Congestion Control Don't think we record a failure on Fast Retransmit, but can't find a definitive source.
-    let mut rto = cb.sender.rto.borrow_mut();
-    match cause {
-        RetransmitCause::TimeOut => rto.record_failure(),
-        RetransmitCause::FastRetransmit => (),
-    };
+    if let RetransmitCause::TimeOut = cause {
+        cb.sender.rto.borrow_mut().record_failure();
+    }
 
-    // Our retransmission timer fired, so we need to resend a packet.
+    // Our retransmission timer fired, so we need to resend the missing segments.
     let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
-
-    // Unset the initial timestamp so we don't use this for RTT estimation.
-    segment.initial_tx.take();
-
-    let seq_no = cb.sender.base_seq_no.get();
-    let mut header = cb.tcp_header();
-    header.seq_num = seq_no;
-    cb.emit(header, segment.bytes.clone(), remote_link_addr);
+    for (seq_no, bytes) in segments {
+        let mut header = cb.tcp_header();
+        header.seq_num = seq_no;
+        cb.emit(header, bytes, remote_link_addr);
+    }
 
     // Set new retransmit deadline
-    let deadline = cb.rt.now() + rto.estimate();
+    let deadline = cb.rt.now() + cb.sender.rto.borrow().estimate();
     cb.sender.retransmit_deadline.set(Some(deadline));
     Ok(())
 }
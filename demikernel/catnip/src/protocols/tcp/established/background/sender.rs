@@ -2,8 +2,15 @@
 // Licensed under the MIT license.
 
 use super::super::state::{sender::UnackedSegment, ControlBlock};
-use crate::{fail::Fail, runtime::Runtime};
-use futures::FutureExt;
+use crate::{
+    fail::Fail,
+    protocols::tcp::constants::MAX_PERSIST_TIMEOUT,
+    runtime::{Runtime, RuntimeBuf},
+};
+use futures::{
+    future::{self, Either},
+    FutureExt,
+};
 use std::{cmp, num::Wrapping, rc::Rc, time::Duration};
 
 pub async fn sender<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
@@ -28,43 +35,23 @@ pub async fn sender<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
         let (win_sz, win_sz_changed) = cb.sender.window_size.watch();
         futures::pin_mut!(win_sz_changed);
 
-        // If we don't have any window size at all, we need to transition to PERSIST state and
-        // repeatedly send window probes until window opens up.
+        // If the remote's window is closed, we enter the PERSIST state and probe it until the
+        // window opens up, backing off exponentially (RFC 1122, section 4.2.2.17). Probes lie below
+        // the remote's window, so that it answers each one with an ACK carrying its current window.
         if win_sz == 0 {
-            let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
-            let buf = cb
-                .sender
-                .pop_one_unsent_byte()
-                .unwrap_or_else(|| panic!("No unsent data? {}, {}", sent_seq, unsent_seq));
-
-            cb.sender.sent_seq_no.modify(|s| s + Wrapping(1));
-            let unacked_segment = UnackedSegment {
-                bytes: buf.clone(),
-                initial_tx: Some(cb.rt.now()),
-            };
-            cb.sender
-                .unacked_queue
-                .borrow_mut()
-                .push_back(unacked_segment);
-
-            let mut header = cb.tcp_header();
-            header.seq_num = sent_seq;
-            cb.emit(header, buf.clone(), remote_link_addr);
-
-            // Note that we loop here *forever*, exponentially backing off.
-            // TODO: Use the correct PERSIST state timer here.
-            let mut timeout = Duration::from_secs(1);
+            let mut timeout = cb.sender.current_rto();
             loop {
                 futures::select_biased! {
                     _ = win_sz_changed => continue 'top,
                     _ = cb.rt.wait(timeout).fuse() => {
-                        timeout *= 2;
+                        timeout = cmp::min(timeout * 2, MAX_PERSIST_TIMEOUT);
                     }
                 }
-                // Retransmit our window probe.
+                let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
                 let mut header = cb.tcp_header();
-                header.seq_num = sent_seq;
-                cb.emit(header, buf.clone(), remote_link_addr);
+                header.seq_num = cb.sender.base_seq_no.get() - Wrapping(1);
+                debug!("Sending zero window probe: {:?}", header);
+                cb.emit(header, RT::Buf::empty(), remote_link_addr);
             }
         }
 
@@ -89,25 +76,56 @@ pub async fn sender<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
         let effective_cwnd = cwnd + ltci;
 
         let Wrapping(sent_data) = sent_seq - base_seq;
-        if win_sz <= sent_data || effective_cwnd <= sent_data || (effective_cwnd - sent_data) <= cb.sender.mss as u32 || (win_sz - sent_data) <= cb.sender.mss as u32 {
+        let Wrapping(unsent_data) = unsent_seq - sent_seq;
+        let usable = cmp::min(
+            win_sz.saturating_sub(sent_data),
+            effective_cwnd.saturating_sub(sent_data),
+        );
+        if usable == 0 || !cb.sender.sws_allows(usable, unsent_data) {
+            // With nothing in flight, no ACK may come to open the window any further, so we stop
+            // waiting for a larger window after an RTO (RFC 1122, section 4.2.3.4).
+            let override_timeout = if usable > 0 && sent_data == 0 {
+                Either::Left(cb.rt.wait(cb.sender.current_rto()).fuse())
+            } else {
+                Either::Right(future::pending())
+            };
+            futures::pin_mut!(override_timeout);
             futures::select_biased! {
                 _ = base_seq_changed => continue 'top,
                 _ = sent_seq_changed => continue 'top,
+                _ = unsent_seq_changed => continue 'top,
                 _ = win_sz_changed => continue 'top,
                 _ = cwnd_changed => continue 'top,
                 _ = ltci_changed => continue 'top,
+                _ = override_timeout => {},
             }
         }
 
-        // Past this point we have data to send and it's valid to send it!
+        // Hold back small segments as Nagle's algorithm and corking call for.
+        let (nodelay, nodelay_changed) = cb.nodelay.watch();
+        futures::pin_mut!(nodelay_changed);
+        let (cork, cork_changed) = cb.cork.watch();
+        futures::pin_mut!(cork_changed);
+        let (_, sender_state_changed) = cb.sender.state.watch();
+        futures::pin_mut!(sender_state_changed);
+
+        if !cb.sender.nagle_allows(unsent_data as usize, nodelay, cork) {
+            futures::select_biased! {
+                _ = base_seq_changed => continue 'top,
+                _ = unsent_seq_changed => continue 'top,
+                _ = nodelay_changed => continue 'top,
+                _ = cork_changed => continue 'top,
+                _ = sender_state_changed => continue 'top,
+            }
+        }
 
-        // TODO: Nagle's algorithm
-        // TODO: Silly window syndrome
+        // Past this point we have data to send and it's valid to send it!
         let remote_link_addr = cb.arp.query(cb.remote.address()).await?;
 
         // Form an outgoing packet.
+        let mut header = cb.tcp_header();
         let max_size = cmp::min(
-            cmp::min((win_sz - sent_data) as usize, cb.sender.mss),
+            cmp::min((win_sz - sent_data) as usize, cb.max_payload_size(&header)),
             (effective_cwnd - sent_data) as usize,
         );
         let segment_data = cb
@@ -117,9 +135,10 @@ pub async fn sender<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
         let segment_data_len = segment_data.len();
         assert!(segment_data_len > 0);
 
-        cb.sender.congestion_ctrl.on_send(&cb.sender, sent_data);
+        cb.sender
+            .congestion_ctrl
+            .on_send(&cb.sender, sent_data, cb.rt.now());
 
-        let mut header = cb.tcp_header();
         header.seq_num = sent_seq;
         cb.emit(header, segment_data.clone(), remote_link_addr);
 
@@ -139,5 +158,11 @@ pub async fn sender<RT: Runtime>(cb: Rc<ControlBlock<RT>>) -> Result<!, Fail> {
             let rto = cb.sender.rto.borrow().estimate();
             cb.sender.retransmit_deadline.set(Some(cb.rt.now() + rto));
         }
+
+        // Space out segments according to the pacing rate, instead of bursting the whole window.
+        if let Some(pacing_rate) = cb.sender.congestion_ctrl.get_pacing_rate() {
+            let delay = Duration::from_secs_f64(segment_data_len as f64 / pacing_rate);
+            cb.rt.wait(delay).await;
+        }
     }
 }
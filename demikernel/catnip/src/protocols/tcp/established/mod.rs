@@ -4,11 +4,17 @@
 mod background;
 pub mod state;
 
-use self::{background::background, state::ControlBlock};
+use self::{
+    background::background,
+    state::{ConnectionState, ControlBlock},
+};
 use crate::{
     fail::Fail,
     file_table::FileDescriptor,
-    protocols::{ipv4, tcp::segment::TcpHeader},
+    protocols::{
+        ipv4::{self, datagram::Ipv4Header},
+        tcp::{options::TcpKeepAlive, segment::TcpHeader, SeqNumber},
+    },
     runtime::Runtime,
     scheduler::SchedulerHandle,
 };
@@ -21,6 +27,8 @@ use std::{
 
 pub struct EstablishedSocket<RT: Runtime> {
     pub cb: Rc<ControlBlock<RT>>,
+    /// File descriptor the connection was opened with, which the application may have closed.
+    pub fd: FileDescriptor,
     #[allow(unused)]
     background_work: SchedulerHandle,
 }
@@ -29,19 +37,20 @@ impl<RT: Runtime> EstablishedSocket<RT> {
     pub fn new(
         cb: ControlBlock<RT>,
         fd: FileDescriptor,
-        dead_socket_tx: mpsc::UnboundedSender<FileDescriptor>,
+        dead_socket_tx: mpsc::UnboundedSender<(ipv4::Endpoint, ipv4::Endpoint)>,
     ) -> Self {
         let cb = Rc::new(cb);
         let future = background(cb.clone(), fd, dead_socket_tx);
         let handle = cb.rt.spawn(future);
         Self {
             cb: cb.clone(),
+            fd,
             background_work: handle,
         }
     }
 
-    pub fn receive(&self, header: &TcpHeader, data: RT::Buf) {
-        self.cb.receive(header, data)
+    pub fn receive(&self, ip_header: &Ipv4Header, header: &TcpHeader, data: RT::Buf) {
+        self.cb.receive(ip_header, header, data)
     }
 
     pub fn send(&self, buf: RT::Buf) -> Result<(), Fail> {
@@ -64,10 +73,30 @@ impl<RT: Runtime> EstablishedSocket<RT> {
         self.cb.close()
     }
 
+    pub fn state(&self) -> ConnectionState {
+        self.cb.state.get()
+    }
+
+    pub fn set_keepalive(&self, keepalive: Option<TcpKeepAlive>) {
+        self.cb.keepalive.set(keepalive)
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) {
+        self.cb.nodelay.set(nodelay)
+    }
+
+    pub fn set_cork(&self, cork: bool) {
+        self.cb.cork.set(cork)
+    }
+
     pub fn remote_mss(&self) -> usize {
         self.cb.remote_mss()
     }
 
+    pub fn reduce_path_mtu(&self, seq_num: SeqNumber, mtu: u16) -> Result<(), Fail> {
+        self.cb.reduce_path_mtu(seq_num, mtu)
+    }
+
     pub fn current_rto(&self) -> Duration {
         self.cb.current_rto()
     }
@@ -0,0 +1,366 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::super::{scoreboard::seq_lt, sender::Sender};
+use super::{
+    CongestionControl, ExplicitCongestionNotification, FastRetransmitRecovery, LimitedTransmit,
+    Options, SlowStartCongestionAvoidance,
+};
+use crate::runtime::Runtime;
+use crate::{
+    collections::watched::{WatchFuture, WatchedValue},
+    protocols::tcp::SeqNumber,
+};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::{max, min},
+    collections::VecDeque,
+    convert::TryInto,
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BbrMode {
+    Startup,  // Grow the sending rate exponentially until the bottleneck bandwidth is found
+    Drain,    // Drain the queue created during startup
+    ProbeBw,  // Cycle the sending rate around the bottleneck bandwidth
+    ProbeRtt, // Shrink inflight data to measure the round-trip propagation time again
+}
+
+#[derive(Debug)]
+pub struct SentRecord {
+    pub seq_no: SeqNumber, // First sequence number of the segment
+    pub delivered: u64,    // Bytes delivered when the segment was sent
+    pub sent_at: Instant,  // The moment at which the segment was sent
+}
+
+// Implementation of BBR (draft-cardwell-iccrg-bbr-congestion-control), which paces segments at
+// the estimated bottleneck bandwidth instead of reacting to losses.
+#[derive(Debug)]
+pub struct Bbr {
+    pub mss: u32, // Just for convenience, otherwise we have `as u32` or `.try_into().unwrap()` scattered everywhere...
+    pub cwnd: WatchedValue<u32>, // Congestion window: Maximum number of bytes that may be in flight
+    pub mode: Cell<BbrMode>, // Current state of the BBR state machine
+    pub pacing_gain: Cell<f64>, // Factor applied to the bottleneck bandwidth to get the pacing rate
+    pub cwnd_gain: Cell<f64>, // Factor applied to the bandwidth-delay product to get the target cwnd
+
+    // Model of the network path
+    pub btl_bw: Cell<f64>, // Estimated bottleneck bandwidth in bytes per second: The maximum of recent delivery rates
+    pub bw_samples: RefCell<VecDeque<(u64, f64)>>, // Recent delivery rates, tagged with the round in which they were measured
+    pub min_rtt: Cell<Option<Duration>>, // Estimated round-trip propagation time: The minimum of recent RTTs
+    pub min_rtt_stamp: Cell<Option<Instant>>, // The moment at which min_rtt was measured
+    pub probe_rtt_interval: Duration, // How long a min_rtt estimate lasts before we enter ProbeRtt
+
+    // Delivery rate sampling
+    pub delivered: Cell<u64>, // Total number of bytes acknowledged
+    pub sent: RefCell<VecDeque<SentRecord>>, // Segments in flight, with the delivery state when they were sent
+    pub round_count: Cell<u64>,              // Number of round trips so far
+    pub next_round_delivered: Cell<u64>, // A new round starts once a segment sent with this many bytes delivered is acknowledged
+
+    // State machine
+    pub full_bw: Cell<f64>, // Bottleneck bandwidth at the last time it grew significantly during startup
+    pub full_bw_count: Cell<u32>, // Number of rounds without significant bandwidth growth
+    pub filled_pipe: Cell<bool>, // Did we find the bottleneck bandwidth
+    pub cycle_index: Cell<usize>, // Current phase of the ProbeBw gain cycle
+    pub cycle_stamp: Cell<Option<Instant>>, // The moment at which the current phase began
+    pub probe_rtt_done: Cell<Option<Instant>>, // The moment at which we leave ProbeRtt
+
+    // Fast Retransmit State
+    pub duplicate_ack_count: Cell<u32>, // The number of consecutive duplicate ACKs we've received
+    pub fast_retransmit_now: WatchedValue<bool>, // Flag to cause the retransmitter to retransmit a segment now
+}
+
+impl<RT: Runtime> CongestionControl<RT> for Bbr {
+    fn new(
+        mss: usize,
+        _seq_no: SeqNumber,
+        options: Option<Options>,
+    ) -> Box<dyn CongestionControl<RT>> {
+        let mss: u32 = mss.try_into().unwrap();
+        let options: Options = options.unwrap_or_default();
+        let initial_window = options.get_int("initial_window").unwrap_or(10);
+        assert!(initial_window > 0);
+        let probe_rtt_interval = options.get_int("probe_rtt_interval_ms").unwrap_or(10_000);
+        assert!(probe_rtt_interval > 0);
+
+        Box::new(Self {
+            mss,
+            cwnd: WatchedValue::new(initial_window as u32 * mss),
+            mode: Cell::new(BbrMode::Startup),
+            pacing_gain: Cell::new(Self::HIGH_GAIN),
+            cwnd_gain: Cell::new(Self::HIGH_GAIN),
+
+            btl_bw: Cell::new(0.),
+            bw_samples: RefCell::new(VecDeque::new()),
+            min_rtt: Cell::new(None),
+            min_rtt_stamp: Cell::new(None),
+            probe_rtt_interval: Duration::from_millis(probe_rtt_interval as u64),
+
+            delivered: Cell::new(0),
+            sent: RefCell::new(VecDeque::new()),
+            round_count: Cell::new(0),
+            next_round_delivered: Cell::new(0),
+
+            full_bw: Cell::new(0.),
+            full_bw_count: Cell::new(0),
+            filled_pipe: Cell::new(false),
+            cycle_index: Cell::new(0),
+            cycle_stamp: Cell::new(None),
+            probe_rtt_done: Cell::new(None),
+
+            duplicate_ack_count: Cell::new(0),
+            fast_retransmit_now: WatchedValue::new(false),
+        })
+    }
+}
+
+impl Bbr {
+    // 2/ln(2), the smallest gain which doubles the sending rate every round
+    const HIGH_GAIN: f64 = 2.885;
+    const PACING_GAIN_CYCLE: [f64; 8] = [1.25, 0.75, 1., 1., 1., 1., 1., 1.];
+    const BW_FILTER_ROUNDS: u64 = 10;
+    const FULL_BW_THRESHOLD: f64 = 1.25;
+    const FULL_BW_ROUNDS: u32 = 3;
+    const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+    const MIN_CWND_SEGMENTS: u32 = 4;
+
+    const DUP_ACK_THRESHOLD: u32 = 3;
+
+    fn min_cwnd(&self) -> u32 {
+        Self::MIN_CWND_SEGMENTS * self.mss
+    }
+
+    // Returns the bandwidth-delay product, once we've measured both
+    fn bdp(&self) -> Option<u32> {
+        let min_rtt = self.min_rtt.get()?;
+        if self.btl_bw.get() == 0. {
+            return None;
+        }
+        Some((self.btl_bw.get() * min_rtt.as_secs_f64()) as u32)
+    }
+
+    // Takes an RTT and a delivery rate sample from the latest segment covered by the ACK, and
+    // returns whether a new round started
+    fn update_model(&self, ack_seq_no: SeqNumber, min_rtt_expired: bool, now: Instant) -> bool {
+        let mut latest = None;
+        {
+            let mut sent = self.sent.borrow_mut();
+            while let Some(record) = sent.front() {
+                if !seq_lt(record.seq_no, ack_seq_no) {
+                    break;
+                }
+                latest = sent.pop_front();
+            }
+        }
+        let record = match latest {
+            Some(r) => r,
+            None => return false,
+        };
+
+        let rtt = now - record.sent_at;
+        if self.min_rtt.get().map(|m| rtt <= m).unwrap_or(true) || min_rtt_expired {
+            self.min_rtt.set(Some(rtt));
+            self.min_rtt_stamp.set(Some(now));
+        }
+
+        let round_start = record.delivered >= self.next_round_delivered.get();
+        if round_start {
+            self.next_round_delivered.set(self.delivered.get());
+            self.round_count.set(self.round_count.get() + 1);
+        }
+
+        if rtt > Duration::new(0, 0) {
+            let rate = (self.delivered.get() - record.delivered) as f64 / rtt.as_secs_f64();
+            let round_count = self.round_count.get();
+            let mut bw_samples = self.bw_samples.borrow_mut();
+            bw_samples.push_back((round_count, rate));
+            while let Some(&(round, _)) = bw_samples.front() {
+                if round + Self::BW_FILTER_ROUNDS > round_count {
+                    break;
+                }
+                bw_samples.pop_front();
+            }
+            let btl_bw = bw_samples.iter().map(|&(_, r)| r).fold(0., f64::max);
+            self.btl_bw.set(btl_bw);
+        }
+        round_start
+    }
+
+    fn enter_probe_bw(&self, now: Instant) {
+        self.mode.set(BbrMode::ProbeBw);
+        self.cwnd_gain.set(2.);
+        // Start right after the probing and draining phases, so as not to disturb the pipe.
+        self.cycle_index.set(2);
+        self.cycle_stamp.set(Some(now));
+        self.pacing_gain
+            .set(Self::PACING_GAIN_CYCLE[self.cycle_index.get()]);
+    }
+
+    fn update_mode(
+        &self,
+        round_start: bool,
+        min_rtt_expired: bool,
+        bytes_in_flight: u32,
+        now: Instant,
+    ) {
+        match self.mode.get() {
+            BbrMode::Startup => {
+                if round_start {
+                    let btl_bw = self.btl_bw.get();
+                    if btl_bw >= self.full_bw.get() * Self::FULL_BW_THRESHOLD {
+                        self.full_bw.set(btl_bw);
+                        self.full_bw_count.set(0);
+                    } else {
+                        self.full_bw_count.set(self.full_bw_count.get() + 1);
+                    }
+                    if self.full_bw_count.get() >= Self::FULL_BW_ROUNDS {
+                        self.filled_pipe.set(true);
+                        self.mode.set(BbrMode::Drain);
+                        self.pacing_gain.set(1. / Self::HIGH_GAIN);
+                    }
+                }
+            }
+            BbrMode::Drain => {
+                if self.bdp().map(|b| bytes_in_flight <= b).unwrap_or(true) {
+                    self.enter_probe_bw(now);
+                }
+            }
+            BbrMode::ProbeBw => {
+                let min_rtt = self.min_rtt.get().unwrap_or_default();
+                if self
+                    .cycle_stamp
+                    .get()
+                    .map_or(true, |stamp| now > stamp + min_rtt)
+                {
+                    let cycle_index = (self.cycle_index.get() + 1) % Self::PACING_GAIN_CYCLE.len();
+                    self.cycle_index.set(cycle_index);
+                    self.cycle_stamp.set(Some(now));
+                    self.pacing_gain.set(Self::PACING_GAIN_CYCLE[cycle_index]);
+                }
+            }
+            BbrMode::ProbeRtt => {
+                if self.probe_rtt_done.get().map_or(true, |done| now >= done) {
+                    self.min_rtt_stamp.set(Some(now));
+                    if self.filled_pipe.get() {
+                        self.enter_probe_bw(now);
+                    } else {
+                        self.mode.set(BbrMode::Startup);
+                        self.pacing_gain.set(Self::HIGH_GAIN);
+                        self.cwnd_gain.set(Self::HIGH_GAIN);
+                    }
+                }
+                return;
+            }
+        }
+
+        // Measure the propagation delay again if we haven't seen it for a while
+        if min_rtt_expired {
+            self.mode.set(BbrMode::ProbeRtt);
+            self.pacing_gain.set(1.);
+            self.probe_rtt_done
+                .set(Some(now + Self::PROBE_RTT_DURATION));
+        }
+    }
+
+    fn update_cwnd(&self, bytes_acknowledged: u32) {
+        if self.mode.get() == BbrMode::ProbeRtt {
+            self.cwnd.set(self.min_cwnd());
+            return;
+        }
+        let cwnd = self.cwnd.get();
+        let cwnd = match self.bdp() {
+            Some(bdp) => {
+                let target = max((self.cwnd_gain.get() * bdp as f64) as u32, self.min_cwnd());
+                if self.filled_pipe.get() {
+                    min(cwnd + bytes_acknowledged, target)
+                } else if cwnd < target {
+                    cwnd + bytes_acknowledged
+                } else {
+                    cwnd
+                }
+            }
+            // Until we have a model of the path, grow like slow start
+            None => cwnd + bytes_acknowledged,
+        };
+        self.cwnd.set(max(cwnd, self.min_cwnd()));
+    }
+}
+
+impl<RT: Runtime> SlowStartCongestionAvoidance<RT> for Bbr {
+    fn get_cwnd(&self) -> u32 {
+        self.cwnd.get()
+    }
+    fn watch_cwnd(&self) -> (u32, WatchFuture<'_, u32>) {
+        self.cwnd.watch()
+    }
+
+    fn get_pacing_rate(&self) -> Option<f64> {
+        let btl_bw = self.btl_bw.get();
+        if btl_bw == 0. {
+            return None;
+        }
+        Some(self.pacing_gain.get() * btl_bw)
+    }
+
+    fn on_send(&self, sender: &Sender<RT>, _num_sent_bytes: u32, now: Instant) {
+        self.sent.borrow_mut().push_back(SentRecord {
+            seq_no: sender.sent_seq_no.get(),
+            delivered: self.delivered.get(),
+            sent_at: now,
+        });
+    }
+
+    fn on_ack_received(&self, sender: &Sender<RT>, ack_seq_no: SeqNumber, now: Instant) {
+        let bytes_acknowledged = (ack_seq_no - sender.base_seq_no.get()).0;
+        if bytes_acknowledged == 0 {
+            // ACK is a duplicate
+            let duplicate_ack_count = self.duplicate_ack_count.get() + 1;
+            self.duplicate_ack_count.set(duplicate_ack_count);
+            if duplicate_ack_count == Self::DUP_ACK_THRESHOLD {
+                self.fast_retransmit_now.set(true);
+            }
+            return;
+        }
+        self.duplicate_ack_count.set(0);
+
+        self.delivered
+            .set(self.delivered.get() + bytes_acknowledged as u64);
+        let min_rtt_expired = self
+            .min_rtt_stamp
+            .get()
+            .map_or(false, |stamp| now > stamp + self.probe_rtt_interval);
+        let round_start = self.update_model(ack_seq_no, min_rtt_expired, now);
+        let bytes_in_flight = (sender.sent_seq_no.get() - ack_seq_no).0;
+        self.update_mode(round_start, min_rtt_expired, bytes_in_flight, now);
+        self.update_cwnd(bytes_acknowledged);
+    }
+
+    fn on_rto(&self, _sender: &Sender<RT>) {
+        // Fall back to packet conservation, while keeping our model of the path
+        self.cwnd.set(self.mss);
+        self.duplicate_ack_count.set(0);
+        // Retransmitted segments would give bogus samples
+        self.sent.borrow_mut().clear();
+    }
+}
+
+impl<RT: Runtime> FastRetransmitRecovery<RT> for Bbr {
+    fn get_duplicate_ack_count(&self) -> u32 {
+        self.duplicate_ack_count.get()
+    }
+
+    fn get_retransmit_now_flag(&self) -> bool {
+        self.fast_retransmit_now.get()
+    }
+    fn watch_retransmit_now_flag(&self) -> (bool, WatchFuture<'_, bool>) {
+        self.fast_retransmit_now.watch()
+    }
+
+    fn on_fast_retransmit(&self, _sender: &Sender<RT>) {
+        self.fast_retransmit_now.set_without_notify(false);
+    }
+}
+
+impl<RT: Runtime> LimitedTransmit<RT> for Bbr {}
+impl<RT: Runtime> ExplicitCongestionNotification<RT> for Bbr {}
@@ -3,8 +3,8 @@
 
 use super::super::sender::Sender;
 use super::{
-    CongestionControl, FastRetransmitRecovery, LimitedTransmit, Options,
-    SlowStartCongestionAvoidance,
+    CongestionControl, ExplicitCongestionNotification, FastRetransmitRecovery, LimitedTransmit,
+    Options, SlowStartCongestionAvoidance,
 };
 use crate::runtime::Runtime;
 use crate::{
@@ -295,7 +295,7 @@ impl<RT: Runtime> SlowStartCongestionAvoidance<RT> for Cubic {
         }
     }
 
-    fn on_send(&self, sender: &Sender<RT>, num_bytes_sent: u32) {
+    fn on_send(&self, sender: &Sender<RT>, num_bytes_sent: u32, _now: Instant) {
         self.last_send_time.set(Instant::now());
         self.rtt_at_last_send.set(sender.current_rto());
         self.limited_transmit_cwnd_increase.set_without_notify(
@@ -305,7 +305,7 @@ impl<RT: Runtime> SlowStartCongestionAvoidance<RT> for Cubic {
         );
     }
 
-    fn on_ack_received(&self, sender: &Sender<RT>, ack_seq_no: SeqNumber) {
+    fn on_ack_received(&self, sender: &Sender<RT>, ack_seq_no: SeqNumber, _now: Instant) {
         let bytes_acknowledged = ack_seq_no - sender.base_seq_no.get();
         if bytes_acknowledged.0 == 0 {
             // ACK is a duplicate
@@ -368,3 +368,6 @@ impl<RT: Runtime> LimitedTransmit<RT> for Cubic {
         self.limited_transmit_cwnd_increase.watch()
     }
 }
+
+// TODO: React to ECN marks like to a loss (RFC 8312, section 4.5).
+impl<RT: Runtime> ExplicitCongestionNotification<RT> for Cubic {}
@@ -0,0 +1,198 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::super::{scoreboard::seq_lt, sender::Sender};
+use super::{
+    CongestionControl, ExplicitCongestionNotification, FastRetransmitRecovery, LimitedTransmit,
+    NewReno, Options, SlowStartCongestionAvoidance,
+};
+use crate::runtime::Runtime;
+use crate::{collections::watched::WatchFuture, protocols::tcp::SeqNumber};
+use std::{cell::Cell, fmt::Debug, time::Instant};
+
+// Implementation of DCTCP (RFC8257). Losses are handled as in NewReno, while ECN marks shrink cwnd
+// in proportion to the fraction of marked bytes.
+#[derive(Debug)]
+pub struct Dctcp {
+    pub reno: NewReno,               // NewReno state, which handles everything but ECN
+    pub g: f64,                      // Estimation gain for alpha
+    pub alpha: Cell<f64>,            // Estimate of the fraction of bytes which encounter congestion
+    pub window_end: Cell<SeqNumber>, // Sequence number at which the current observation window ends
+    pub bytes_acked: Cell<u32>,      // Bytes acknowledged during the current observation window
+    pub bytes_marked: Cell<u32>, // Bytes acknowledged with ECE during the current observation window
+}
+
+impl<RT: Runtime> CongestionControl<RT> for Dctcp {
+    fn new(
+        mss: usize,
+        seq_no: SeqNumber,
+        options: Option<Options>,
+    ) -> Box<dyn CongestionControl<RT>> {
+        Box::new(Self::with_options(
+            mss,
+            seq_no,
+            &options.unwrap_or_default(),
+        ))
+    }
+
+    fn reacts_to_ecn() -> bool {
+        true
+    }
+}
+
+impl Dctcp {
+    pub fn with_options(mss: usize, seq_no: SeqNumber, options: &Options) -> Self {
+        // Defaults according to RFC8257, section 4.2
+        let g = options.get_float("g").unwrap_or(1. / 16.);
+        assert!(g > 0. && g <= 1.);
+        let initial_alpha = options.get_float("initial_alpha").unwrap_or(1.);
+        assert!((0. ..=1.).contains(&initial_alpha));
+
+        Self {
+            reno: NewReno::with_options(mss, seq_no, options),
+            g,
+            alpha: Cell::new(initial_alpha),
+            window_end: Cell::new(seq_no),
+            bytes_acked: Cell::new(0),
+            bytes_marked: Cell::new(0),
+        }
+    }
+
+    fn update_alpha<RT: Runtime>(&self, sender: &Sender<RT>, ack_seq_no: SeqNumber, ece: bool) {
+        let bytes_acknowledged = (ack_seq_no - sender.base_seq_no.get()).0;
+        self.bytes_acked
+            .set(self.bytes_acked.get() + bytes_acknowledged);
+        if ece {
+            self.bytes_marked
+                .set(self.bytes_marked.get() + bytes_acknowledged);
+        }
+
+        // Once a window of data has been acknowledged, fold its fraction of marked bytes into alpha
+        if !seq_lt(ack_seq_no, self.window_end.get()) {
+            let bytes_acked = self.bytes_acked.replace(0);
+            let bytes_marked = self.bytes_marked.replace(0);
+            if bytes_acked > 0 {
+                let fraction = bytes_marked as f64 / bytes_acked as f64;
+                self.alpha
+                    .set((1. - self.g) * self.alpha.get() + self.g * fraction);
+            }
+            self.window_end.set(sender.sent_seq_no.get());
+        }
+    }
+}
+
+impl<RT: Runtime> SlowStartCongestionAvoidance<RT> for Dctcp {
+    fn get_cwnd(&self) -> u32 {
+        SlowStartCongestionAvoidance::<RT>::get_cwnd(&self.reno)
+    }
+    fn watch_cwnd(&self) -> (u32, WatchFuture<'_, u32>) {
+        SlowStartCongestionAvoidance::<RT>::watch_cwnd(&self.reno)
+    }
+
+    fn on_send(&self, sender: &Sender<RT>, num_bytes_sent: u32, now: Instant) {
+        self.reno.on_send(sender, num_bytes_sent, now)
+    }
+
+    fn on_ack_received(&self, sender: &Sender<RT>, ack_seq_no: SeqNumber, now: Instant) {
+        self.reno.on_ack_received(sender, ack_seq_no, now)
+    }
+
+    fn on_rto(&self, sender: &Sender<RT>) {
+        self.reno.on_rto(sender)
+    }
+}
+
+impl<RT: Runtime> FastRetransmitRecovery<RT> for Dctcp {
+    fn get_duplicate_ack_count(&self) -> u32 {
+        FastRetransmitRecovery::<RT>::get_duplicate_ack_count(&self.reno)
+    }
+
+    fn get_retransmit_now_flag(&self) -> bool {
+        FastRetransmitRecovery::<RT>::get_retransmit_now_flag(&self.reno)
+    }
+    fn watch_retransmit_now_flag(&self) -> (bool, WatchFuture<'_, bool>) {
+        FastRetransmitRecovery::<RT>::watch_retransmit_now_flag(&self.reno)
+    }
+
+    fn on_fast_retransmit(&self, sender: &Sender<RT>) {
+        self.reno.on_fast_retransmit(sender)
+    }
+}
+
+impl<RT: Runtime> LimitedTransmit<RT> for Dctcp {
+    fn get_limited_transmit_cwnd_increase(&self) -> u32 {
+        LimitedTransmit::<RT>::get_limited_transmit_cwnd_increase(&self.reno)
+    }
+    fn watch_limited_transmit_cwnd_increase(&self) -> (u32, WatchFuture<'_, u32>) {
+        LimitedTransmit::<RT>::watch_limited_transmit_cwnd_increase(&self.reno)
+    }
+}
+
+impl<RT: Runtime> ExplicitCongestionNotification<RT> for Dctcp {
+    fn precise_ecn_echo(&self) -> bool {
+        true
+    }
+
+    fn on_ecn_feedback(&self, sender: &Sender<RT>, ack_seq_no: SeqNumber, ece: bool) {
+        self.update_alpha(sender, ack_seq_no, ece);
+        // RFC8257, section 3.3
+        if ece && self.reno.may_react_to_ecn(ack_seq_no) {
+            let cwnd = self.reno.cwnd.get();
+            let reduced_cwnd = (cwnd as f64 * (1. - self.alpha.get() / 2.)) as u32;
+            self.reno.reduce_cwnd_on_ecn(sender, reduced_cwnd);
+        }
+    }
+
+    fn take_cwr(&self) -> bool {
+        ExplicitCongestionNotification::<RT>::take_cwr(&self.reno)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dctcp, ExplicitCongestionNotification, Options, Sender};
+    use crate::{
+        protocols::tcp::established::state::congestion_ctrl as cc, test_helpers::TestRuntime,
+    };
+    use std::num::Wrapping;
+
+    #[test]
+    fn test_alpha_and_cwnd_cut() {
+        let mut options = Options::default();
+        options.insert_int("initial_window".to_string(), 10);
+        options.insert_float("g".to_string(), 0.5);
+        options.insert_float("initial_alpha".to_string(), 0.);
+        let dctcp = Dctcp::with_options(100, Wrapping(0), &options);
+
+        let sender = Sender::<TestRuntime>::new(
+            Wrapping(0),
+            65536,
+            0,
+            100,
+            cc::CongestionControlConstructor::of::<cc::None>(),
+            None,
+        );
+        sender.sent_seq_no.set(Wrapping(1000));
+        sender.unsent_seq_no.set(Wrapping(1000));
+
+        // The first ACK ends the initial observation window, in which every byte was marked, and
+        // cwnd is cut by half of alpha.
+        dctcp.on_ecn_feedback(&sender, Wrapping(100), true);
+        assert!((dctcp.alpha.get() - 0.5).abs() < 1e-9);
+        assert_eq!(dctcp.reno.cwnd.get(), 750);
+        assert!(dctcp.reno.cwr_pending.get());
+        sender.base_seq_no.set(Wrapping(100));
+
+        // cwnd is cut at most once per window of data, and alpha only moves at its end.
+        dctcp.on_ecn_feedback(&sender, Wrapping(600), true);
+        assert!((dctcp.alpha.get() - 0.5).abs() < 1e-9);
+        assert_eq!(dctcp.reno.cwnd.get(), 750);
+        sender.base_seq_no.set(Wrapping(600));
+
+        // 500 of the 900 bytes acknowledged in the window were marked.
+        dctcp.on_ecn_feedback(&sender, Wrapping(1000), false);
+        let alpha = 0.5 * 0.5 + 0.5 * (500. / 900.);
+        assert!((dctcp.alpha.get() - alpha).abs() < 1e-9);
+        assert_eq!(dctcp.reno.cwnd.get(), 750);
+    }
+}
@@ -3,13 +3,19 @@
 
 use super::sender::Sender;
 use crate::{collections::watched::WatchFuture, protocols::tcp::SeqNumber, runtime::Runtime};
-use std::fmt::Debug;
+use std::{fmt::Debug, time::Instant};
 
+mod bbr;
 mod cubic;
+mod dctcp;
+mod newreno;
 mod none;
 mod options;
 pub use self::{
+    bbr::{Bbr, BbrMode},
     cubic::Cubic,
+    dctcp::Dctcp,
+    newreno::NewReno,
     none::None,
     options::{OptionValue, Options},
 };
@@ -22,16 +28,22 @@ pub trait SlowStartCongestionAvoidance<RT: Runtime> {
         (u32::MAX, WatchFuture::Pending)
     }
 
+    // Rate in bytes per second at which the sender should space out segments, if any
+    fn get_pacing_rate(&self) -> Option<f64> {
+        None
+    }
+
     // Called immediately before the cwnd check is performed before data is sent
     fn on_cwnd_check_before_send(&self, _sender: &Sender<RT>) {}
 
-    fn on_ack_received(&self, _sender: &Sender<RT>, _ack_seq_no: SeqNumber) {}
+    // `now` here and in `on_send` is taken from the runtime's clock, which all timing must be based on
+    fn on_ack_received(&self, _sender: &Sender<RT>, _ack_seq_no: SeqNumber, _now: Instant) {}
 
     // Called immediately before retransmit after RTO
     fn on_rto(&self, _sender: &Sender<RT>) {}
 
     // Called immediately before a segment is sent for the 1st time
-    fn on_send(&self, _sender: &Sender<RT>, _num_sent_bytes: u32) {}
+    fn on_send(&self, _sender: &Sender<RT>, _num_sent_bytes: u32, _now: Instant) {}
 }
 
 pub trait FastRetransmitRecovery<RT: Runtime>
@@ -65,8 +77,31 @@ where
     }
 }
 
+pub trait ExplicitCongestionNotification<RT: Runtime>
+where
+    Self: SlowStartCongestionAvoidance<RT>,
+{
+    // Whether our receiver should echo the CE mark of every segment instead of latching ECE until
+    // the remote sends CWR (RFC 8257, section 3.2)
+    fn precise_ecn_echo(&self) -> bool {
+        false
+    }
+
+    // Called immediately before `on_ack_received` for every ACK of a connection using ECN
+    fn on_ecn_feedback(&self, _sender: &Sender<RT>, _ack_seq_no: SeqNumber, _ece: bool) {}
+
+    // Called when sending new data, returns whether the segment should carry CWR
+    fn take_cwr(&self) -> bool {
+        false
+    }
+}
+
 pub trait CongestionControl<RT: Runtime>:
-    SlowStartCongestionAvoidance<RT> + FastRetransmitRecovery<RT> + LimitedTransmit<RT> + Debug
+    SlowStartCongestionAvoidance<RT>
+    + FastRetransmitRecovery<RT>
+    + LimitedTransmit<RT>
+    + ExplicitCongestionNotification<RT>
+    + Debug
 {
     fn new(
         mss: usize,
@@ -75,7 +110,51 @@ pub trait CongestionControl<RT: Runtime>:
     ) -> Box<dyn CongestionControl<RT>>
     where
         Self: Sized;
+
+    // Whether congestion notifications make the congestion control back off. ECN is only
+    // negotiated when they do, or congestion marks would go unheeded (RFC 3168, section 6.1.1)
+    fn reacts_to_ecn() -> bool
+    where
+        Self: Sized,
+    {
+        false
+    }
+}
+
+/// Builds the congestion control of new connections, and tells what it supports before any
+/// connection is set up.
+#[derive(Debug)]
+pub struct CongestionControlConstructor<RT: Runtime> {
+    new: fn(usize, SeqNumber, Option<options::Options>) -> Box<dyn CongestionControl<RT>>,
+    reacts_to_ecn: bool,
+}
+
+impl<RT: Runtime> CongestionControlConstructor<RT> {
+    pub fn of<C: CongestionControl<RT>>() -> Self {
+        Self {
+            new: C::new,
+            reacts_to_ecn: C::reacts_to_ecn(),
+        }
+    }
+
+    pub fn construct(
+        &self,
+        mss: usize,
+        seq_no: SeqNumber,
+        options: Option<options::Options>,
+    ) -> Box<dyn CongestionControl<RT>> {
+        (self.new)(mss, seq_no, options)
+    }
+
+    pub fn reacts_to_ecn(&self) -> bool {
+        self.reacts_to_ecn
+    }
+}
+
+impl<RT: Runtime> Clone for CongestionControlConstructor<RT> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
-pub type CongestionControlConstructor<T> =
-    fn(usize, SeqNumber, Option<options::Options>) -> Box<dyn CongestionControl<T>>;
+impl<RT: Runtime> Copy for CongestionControlConstructor<RT> {}
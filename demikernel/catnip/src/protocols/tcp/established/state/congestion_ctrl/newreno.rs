@@ -0,0 +1,347 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::super::{scoreboard::seq_lt, sender::Sender};
+use super::{
+    CongestionControl, ExplicitCongestionNotification, FastRetransmitRecovery, LimitedTransmit,
+    Options, SlowStartCongestionAvoidance,
+};
+use crate::runtime::Runtime;
+use crate::{
+    collections::watched::{WatchFuture, WatchedValue},
+    protocols::tcp::SeqNumber,
+};
+use std::{
+    cell::Cell,
+    cmp::{max, min},
+    convert::TryInto,
+    fmt::Debug,
+    num::Wrapping,
+    time::Instant,
+};
+
+// Implementation of NewReno (RFC5681 and RFC6582), which also halves cwnd once per window of data
+// when the remote echoes an ECN mark (RFC3168, section 6.1.2).
+#[derive(Debug)]
+pub struct NewReno {
+    pub mss: u32, // Just for convenience, otherwise we have `as u32` or `.try_into().unwrap()` scattered everywhere...
+    // Slow Start / Congestion Avoidance State
+    pub cwnd: WatchedValue<u32>, // Congestion window: Maximum number of bytes that may be in flight ot prevent congestion
+    pub ssthresh: Cell<u32>, // The size of cwnd at which we will change from using slow start to congestion avoidance
+    pub bytes_acked: Cell<u32>, // Bytes acknowledged during congestion avoidance since cwnd last grew (RFC5681, section 3.1)
+
+    // Fast Recovery / Fast Retransmit State
+    pub duplicate_ack_count: Cell<u32>, // The number of consecutive duplicate ACKs we've received
+    pub fast_retransmit_now: WatchedValue<bool>, // Flag to cause the retransmitter to retransmit a segment now
+    pub in_fast_recovery: Cell<bool>, // Are we currently in the `fast recovery` algorithm
+    pub recover: Cell<SeqNumber>, // If we receive dup ACKs with sequence numbers greater than this we'll attempt fast recovery
+
+    pub limited_transmit_cwnd_increase: WatchedValue<u32>, // The amount by which cwnd should be increased due to the limited transit algorithm
+
+    // ECN State
+    pub ecn_recover: Cell<SeqNumber>, // We react to ECN echoes only once ACKs get past this sequence number
+    pub cwr_pending: Cell<bool>,      // Should the next segment with new data carry CWR
+}
+
+impl<RT: Runtime> CongestionControl<RT> for NewReno {
+    fn new(
+        mss: usize,
+        seq_no: SeqNumber,
+        options: Option<Options>,
+    ) -> Box<dyn CongestionControl<RT>> {
+        Box::new(Self::with_options(
+            mss,
+            seq_no,
+            &options.unwrap_or_default(),
+        ))
+    }
+
+    fn reacts_to_ecn() -> bool {
+        true
+    }
+}
+
+impl NewReno {
+    const DUP_ACK_THRESHOLD: u32 = 3;
+
+    pub fn with_options(mss: usize, seq_no: SeqNumber, options: &Options) -> Self {
+        let mss: u32 = mss.try_into().unwrap();
+        // The initial value of cwnd is set according to RFC5681, section 3.1, page 7, unless the
+        // initial window is configured in segments
+        let initial_cwnd = match options.get_int("initial_window") {
+            Some(segments) => {
+                assert!(segments > 0);
+                segments as u32 * mss
+            }
+            None => match mss {
+                0..=1095 => 4 * mss,
+                1096..=2190 => 3 * mss,
+                _ => 2 * mss,
+            },
+        };
+
+        Self {
+            mss,
+            cwnd: WatchedValue::new(initial_cwnd),
+            ssthresh: Cell::new(u32::MAX), // According to RFC5681 ssthresh should be initialised 'arbitrarily high'
+            bytes_acked: Cell::new(0),
+
+            duplicate_ack_count: Cell::new(0),
+            fast_retransmit_now: WatchedValue::new(false),
+            in_fast_recovery: Cell::new(false),
+            recover: Cell::new(seq_no - Wrapping(1)), // Recover set to initial send sequence number, right before the first data byte, according to RFC6582
+
+            limited_transmit_cwnd_increase: WatchedValue::new(0),
+
+            ecn_recover: Cell::new(seq_no),
+            cwr_pending: Cell::new(false),
+        }
+    }
+
+    fn flight_size<RT: Runtime>(sender: &Sender<RT>) -> u32 {
+        (sender.sent_seq_no.get() - sender.base_seq_no.get()).0
+    }
+
+    // Recover is the highest sequence number sent, so that an ACK for everything sent is full
+    fn highest_seq_no_sent<RT: Runtime>(sender: &Sender<RT>) -> SeqNumber {
+        sender.sent_seq_no.get() - Wrapping(1)
+    }
+
+    // Returns whether an ECN echo carried by an ACK for `ack_seq_no` may reduce cwnd, which happens
+    // at most once per window of data and never during fast recovery
+    pub fn may_react_to_ecn(&self, ack_seq_no: SeqNumber) -> bool {
+        !self.in_fast_recovery.get() && !seq_lt(ack_seq_no, self.ecn_recover.get())
+    }
+
+    // Shrinks cwnd to `cwnd` in response to congestion signalled by ECN, and tells the remote
+    // through CWR.
+    pub fn reduce_cwnd_on_ecn<RT: Runtime>(&self, sender: &Sender<RT>, cwnd: u32) {
+        let cwnd = max(cwnd, 2 * self.mss);
+        self.ssthresh.set(cwnd);
+        self.cwnd.set(cwnd);
+        self.bytes_acked.set(0);
+        self.ecn_recover.set(sender.sent_seq_no.get());
+        self.cwr_pending.set(true);
+    }
+
+    fn on_dup_ack_received<RT: Runtime>(&self, sender: &Sender<RT>, ack_seq_no: SeqNumber) {
+        let duplicate_ack_count = self.duplicate_ack_count.get() + 1;
+        self.duplicate_ack_count.set(duplicate_ack_count);
+        if duplicate_ack_count < Self::DUP_ACK_THRESHOLD {
+            self.limited_transmit_cwnd_increase
+                .modify(|ltci| ltci + self.mss);
+        }
+
+        if duplicate_ack_count == Self::DUP_ACK_THRESHOLD
+            && !self.in_fast_recovery.get()
+            && seq_lt(self.recover.get(), ack_seq_no)
+        {
+            // Enter fast recovery, checking against recover as specified in RFC6582
+            self.in_fast_recovery.set(true);
+            self.recover.set(Self::highest_seq_no_sent(sender));
+            let ssthresh = max(Self::flight_size(sender) / 2, 2 * self.mss);
+            self.ssthresh.set(ssthresh);
+            self.cwnd.set(ssthresh + Self::DUP_ACK_THRESHOLD * self.mss);
+            self.fast_retransmit_now.set(true);
+        } else if self.in_fast_recovery.get() {
+            // Inflate cwnd for every segment that has left the network
+            self.cwnd.modify(|c| c + self.mss);
+        }
+    }
+
+    fn on_ack_received_fast_recovery<RT: Runtime>(
+        &self,
+        sender: &Sender<RT>,
+        ack_seq_no: SeqNumber,
+    ) {
+        let bytes_acknowledged = (ack_seq_no - sender.base_seq_no.get()).0;
+        let mss = self.mss;
+
+        if seq_lt(self.recover.get(), ack_seq_no) {
+            // Full acknowledgement
+            let bytes_outstanding = (sender.sent_seq_no.get() - ack_seq_no).0;
+            self.cwnd
+                .set(min(self.ssthresh.get(), max(bytes_outstanding, mss) + mss));
+            self.in_fast_recovery.set(false);
+        } else {
+            // Partial acknowledgement: retransmit the next hole and deflate cwnd
+            self.fast_retransmit_now.set(true);
+            let cwnd = self.cwnd.get().saturating_sub(bytes_acknowledged);
+            if bytes_acknowledged >= mss {
+                self.cwnd.set(cwnd + mss);
+            } else {
+                self.cwnd.set(cwnd);
+            }
+        }
+    }
+
+    fn on_ack_received_ss_ca(&self, bytes_acknowledged: u32) {
+        let mss = self.mss;
+        let cwnd = self.cwnd.get();
+
+        if cwnd < self.ssthresh.get() {
+            // Slow start
+            self.cwnd.modify(|c| c + min(bytes_acknowledged, mss));
+        } else {
+            // Congestion avoidance: grow by one mss per cwnd of acknowledged data
+            let bytes_acked = self.bytes_acked.get() + bytes_acknowledged;
+            if bytes_acked >= cwnd {
+                self.bytes_acked.set(bytes_acked - cwnd);
+                self.cwnd.modify(|c| c + mss);
+            } else {
+                self.bytes_acked.set(bytes_acked);
+            }
+        }
+    }
+}
+
+impl<RT: Runtime> SlowStartCongestionAvoidance<RT> for NewReno {
+    fn get_cwnd(&self) -> u32 {
+        self.cwnd.get()
+    }
+    fn watch_cwnd(&self) -> (u32, WatchFuture<'_, u32>) {
+        self.cwnd.watch()
+    }
+
+    fn on_send(&self, _sender: &Sender<RT>, num_bytes_sent: u32, _now: Instant) {
+        self.limited_transmit_cwnd_increase.set_without_notify(
+            self.limited_transmit_cwnd_increase
+                .get()
+                .saturating_sub(num_bytes_sent),
+        );
+    }
+
+    fn on_ack_received(&self, sender: &Sender<RT>, ack_seq_no: SeqNumber, _now: Instant) {
+        let bytes_acknowledged = (ack_seq_no - sender.base_seq_no.get()).0;
+        if bytes_acknowledged == 0 {
+            // ACK is a duplicate
+            self.on_dup_ack_received(sender, ack_seq_no);
+        } else {
+            self.duplicate_ack_count.set(0);
+            if self.in_fast_recovery.get() {
+                self.on_ack_received_fast_recovery(sender, ack_seq_no);
+            } else {
+                self.on_ack_received_ss_ca(bytes_acknowledged);
+            }
+        }
+    }
+
+    fn on_rto(&self, sender: &Sender<RT>) {
+        // RFC5681, section 3.1, equation 4
+        self.ssthresh
+            .set(max(Self::flight_size(sender) / 2, 2 * self.mss));
+        self.cwnd.set(self.mss);
+        self.bytes_acked.set(0);
+        self.duplicate_ack_count.set(0);
+
+        // Exit fast recovery/retransmit
+        self.recover.set(Self::highest_seq_no_sent(sender));
+        self.in_fast_recovery.set(false);
+    }
+}
+
+impl<RT: Runtime> FastRetransmitRecovery<RT> for NewReno {
+    fn get_duplicate_ack_count(&self) -> u32 {
+        self.duplicate_ack_count.get()
+    }
+
+    fn get_retransmit_now_flag(&self) -> bool {
+        self.fast_retransmit_now.get()
+    }
+    fn watch_retransmit_now_flag(&self) -> (bool, WatchFuture<'_, bool>) {
+        self.fast_retransmit_now.watch()
+    }
+
+    fn on_fast_retransmit(&self, _sender: &Sender<RT>) {
+        self.fast_retransmit_now.set_without_notify(false);
+    }
+}
+
+impl<RT: Runtime> LimitedTransmit<RT> for NewReno {
+    fn get_limited_transmit_cwnd_increase(&self) -> u32 {
+        self.limited_transmit_cwnd_increase.get()
+    }
+    fn watch_limited_transmit_cwnd_increase(&self) -> (u32, WatchFuture<'_, u32>) {
+        self.limited_transmit_cwnd_increase.watch()
+    }
+}
+
+impl<RT: Runtime> ExplicitCongestionNotification<RT> for NewReno {
+    fn on_ecn_feedback(&self, sender: &Sender<RT>, ack_seq_no: SeqNumber, ece: bool) {
+        if ece && self.may_react_to_ecn(ack_seq_no) {
+            self.reduce_cwnd_on_ecn(sender, self.cwnd.get() / 2);
+        }
+    }
+
+    fn take_cwr(&self) -> bool {
+        self.cwr_pending.replace(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FastRetransmitRecovery, NewReno, Options, Sender, SlowStartCongestionAvoidance};
+    use crate::{
+        protocols::tcp::established::state::congestion_ctrl as cc, test_helpers::TestRuntime,
+    };
+    use std::{num::Wrapping, time::Instant};
+
+    #[test]
+    fn test_fast_recovery() {
+        let now = Instant::now();
+        let mut options = Options::default();
+        options.insert_int("initial_window".to_string(), 10);
+        let reno = NewReno::with_options(100, Wrapping(0), &options);
+
+        // Ten segments are in flight, and the first one is lost.
+        let sender = Sender::<TestRuntime>::new(
+            Wrapping(0),
+            65536,
+            0,
+            100,
+            cc::CongestionControlConstructor::of::<cc::None>(),
+            None,
+        );
+        sender.sent_seq_no.set(Wrapping(1000));
+        sender.unsent_seq_no.set(Wrapping(1000));
+
+        // Three duplicate ACKs halve the window, and inflate it by the segments which left.
+        for _ in 0..3 {
+            reno.on_ack_received(&sender, Wrapping(0), now);
+        }
+        assert!(reno.in_fast_recovery.get());
+        assert!(reno.fast_retransmit_now.get());
+        reno.on_fast_retransmit(&sender);
+        assert_eq!(reno.ssthresh.get(), 500);
+        assert_eq!(reno.cwnd.get(), 800);
+        reno.on_ack_received(&sender, Wrapping(0), now);
+        assert_eq!(reno.cwnd.get(), 900);
+
+        // Two more segments go out, which are past the recovery point.
+        sender.sent_seq_no.set(Wrapping(1200));
+        sender.unsent_seq_no.set(Wrapping(1200));
+
+        // A partial ACK retransmits the next hole, and deflates the window by the data
+        // acknowledged, less a segment.
+        reno.on_ack_received(&sender, Wrapping(200), now);
+        assert!(reno.in_fast_recovery.get());
+        assert!(reno.fast_retransmit_now.get());
+        reno.on_fast_retransmit(&sender);
+        assert_eq!(reno.cwnd.get(), 800);
+        sender.base_seq_no.set(Wrapping(200));
+
+        // A full ACK covers all data sent before recovery, and ends it with a window which lets
+        // one more segment go out than is in flight.
+        reno.on_ack_received(&sender, Wrapping(1000), now);
+        assert!(!reno.in_fast_recovery.get());
+        assert!(!reno.fast_retransmit_now.get());
+        assert_eq!(reno.cwnd.get(), 300);
+        sender.base_seq_no.set(Wrapping(1000));
+
+        // Duplicate ACKs for the data sent during recovery may start another one.
+        for _ in 0..3 {
+            reno.on_ack_received(&sender, Wrapping(1000), now);
+        }
+        assert!(reno.in_fast_recovery.get());
+    }
+}
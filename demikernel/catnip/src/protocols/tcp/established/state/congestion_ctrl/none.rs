@@ -2,8 +2,8 @@
 // Licensed under the MIT license.
 
 use super::{
-    CongestionControl, FastRetransmitRecovery, LimitedTransmit, Options,
-    SlowStartCongestionAvoidance,
+    CongestionControl, ExplicitCongestionNotification, FastRetransmitRecovery, LimitedTransmit,
+    Options, SlowStartCongestionAvoidance,
 };
 use crate::{protocols::tcp::SeqNumber, runtime::Runtime};
 use std::fmt::Debug;
@@ -25,3 +25,4 @@ impl<RT: Runtime> CongestionControl<RT> for None {
 impl<RT: Runtime> SlowStartCongestionAvoidance<RT> for None {}
 impl<RT: Runtime> FastRetransmitRecovery<RT> for None {}
 impl<RT: Runtime> LimitedTransmit<RT> for None {}
+impl<RT: Runtime> ExplicitCongestionNotification<RT> for None {}
@@ -4,10 +4,18 @@
 pub mod congestion_ctrl;
 pub mod receiver;
 mod rto;
+mod scoreboard;
 pub mod sender;
+pub mod timestamps;
 
-use self::{receiver::Receiver, sender::Sender};
+use self::{
+    receiver::{Receiver, ReceiverState},
+    scoreboard::seq_lt,
+    sender::{Sender, SenderState},
+    timestamps::Timestamps,
+};
 use crate::{
+    collections::watched::WatchedValue,
     fail::Fail,
     protocols::{
         arp,
@@ -16,12 +24,42 @@ use crate::{
             MacAddress,
         },
         ipv4,
-        ipv4::datagram::{Ipv4Header, Ipv4Protocol2},
-        tcp::segment::{TcpHeader, TcpSegment},
+        ipv4::datagram::{Ipv4Header, Ipv4Protocol2, IPV4_ECN_CE, IPV4_ECN_ECT0},
+        tcp::{
+            constants::MAX_SACK_BLOCKS,
+            options::TcpKeepAlive,
+            segment::{
+                SelectiveAcknowlegement, TcpHeader, TcpOptions2, TcpSegment, MIN_TCP_HEADER_SIZE,
+            },
+            SeqNumber,
+        },
     },
     runtime::Runtime,
 };
-use std::time::Duration;
+use std::{
+    num::Wrapping,
+    time::{Duration, Instant},
+};
+
+/// States of a connection past the handshake (RFC 793, section 3.2).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectionState {
+    Established,
+    /// We closed our side, and our FIN has not been acknowledged yet.
+    FinWait1,
+    /// Our FIN has been acknowledged, and we wait for the remote to close its side.
+    FinWait2,
+    /// Both sides closed at the same time, and our FIN has not been acknowledged yet.
+    Closing,
+    /// We closed first, and wait for twice the MSL before forgetting about the connection.
+    TimeWait,
+    /// The remote closed its side, and we have yet to close ours.
+    CloseWait,
+    /// The remote closed first, and our FIN has not been acknowledged yet.
+    LastAck,
+    /// The connection is gone, and may be reaped.
+    Closed,
+}
 
 /// Transmission control block for representing our TCP connection.
 pub struct ControlBlock<RT: Runtime> {
@@ -31,48 +69,164 @@ pub struct ControlBlock<RT: Runtime> {
     pub rt: RT,
     pub arp: arp::Peer<RT>,
 
+    /// State of the connection, derived from the states of its sender and receiver ends.
+    pub state: WatchedValue<ConnectionState>,
+
     /// The sender end of our connection.
     pub sender: Sender<RT>,
     /// The receiver end of our connection.
     pub receiver: Receiver<RT>,
+
+    /// Whether both ends agreed on selective acknowledgements (RFC 2018) during the handshake.
+    pub sack_permitted: bool,
+    /// Timestamps state, if both ends agreed on the timestamps option (RFC 7323) during the
+    /// handshake.
+    pub timestamps: Option<Timestamps>,
+    /// Whether both ends agreed on Explicit Congestion Notification (RFC 3168) during the
+    /// handshake.
+    pub ecn: bool,
+
+    /// Keepalive settings, if idle connections should be probed.
+    pub keepalive: WatchedValue<Option<TcpKeepAlive>>,
+    /// The moment at which we last received a segment from the remote.
+    pub last_heard: WatchedValue<Instant>,
+
+    /// Whether Nagle's algorithm is disabled, sending small segments right away.
+    pub nodelay: WatchedValue<bool>,
+    /// Whether to hold back small segments until the socket gets uncorked.
+    pub cork: WatchedValue<bool>,
 }
 
 impl<RT: Runtime> ControlBlock<RT> {
-    pub fn receive(&self, header: &TcpHeader, data: RT::Buf) {
+    pub fn receive(&self, ip_header: &Ipv4Header, header: &TcpHeader, data: RT::Buf) {
         debug!("Receiving {} bytes + {:?}", data.len(), header);
         let now = self.rt.now();
         if header.syn {
             warn!("Ignoring duplicate SYN on established connection");
         }
+
+        // Drop old duplicates and compute an RTT sample from the echoed timestamp.
+        let mut rtt_sample = None;
+        if let Some(ref timestamps) = self.timestamps {
+            let timestamp = header.iter_options().find_map(|option| match option {
+                TcpOptions2::Timestamp {
+                    sender_timestamp,
+                    echo_timestamp,
+                } => Some((*sender_timestamp, *echo_timestamp)),
+                _ => None,
+            });
+            // TODO: RFC 7323 says to drop segments without the option, but we accept them.
+            if let Some((sender_timestamp, echo_timestamp)) = timestamp {
+                if !header.rst && timestamps.is_old(sender_timestamp) {
+                    warn!("Dropping old duplicate {:?} (PAWS)", header);
+                    // Acknowledge it right away so a confused peer can resynchronize.
+                    self.receiver.ack_deadline.set(Some(now));
+                    return;
+                }
+                if !seq_lt(self.receiver.ack_seq_no.get(), header.seq_num) {
+                    timestamps.update_recent(sender_timestamp);
+                }
+                if header.ack {
+                    rtt_sample = timestamps.rtt_sample(echo_timestamp, now);
+                }
+            }
+        }
+        // Old duplicates may be replayed long after the remote went away, so they don't count.
+        self.last_heard.set(now);
+
         if header.rst {
             self.sender.receive_rst();
         }
         if header.fin {
             self.receiver.receive_fin();
         }
+        if self.ecn {
+            let precise = self.sender.congestion_ctrl.precise_ecn_echo();
+            let ce = ip_header.ecn == IPV4_ECN_CE;
+            self.receiver.receive_ecn(ce, header.cwr, precise, now);
+        }
         if header.ack {
-            if let Err(e) = self.sender.remote_ack(header.ack_num, now) {
+            if self.ecn {
+                self.sender.remote_ecn_echo(header.ack_num, header.ece);
+            }
+            if let Err(e) = self.sender.remote_ack(header.ack_num, now, rtt_sample) {
                 warn!("Ignoring remote ack for {:?}: {:?}", header, e);
             }
+            if self.sack_permitted {
+                for option in header.iter_options() {
+                    if let TcpOptions2::SelectiveAcknowlegement { num_sacks, sacks } = option {
+                        self.sender.remote_sack(&sacks[..*num_sacks]);
+                    }
+                }
+            }
         }
         if let Err(e) = self.sender.update_remote_window(header.window_size as u16) {
             warn!("Invalid window size update for {:?}: {:?}", header, e);
         }
+        // Answer segments below the receive window with an ACK, keepalive probes in particular
+        // (RFC 793, page 69).
+        if data.is_empty() && !header.rst && seq_lt(header.seq_num, self.receiver.recv_seq_no.get())
+        {
+            self.receiver.ack_deadline.set(Some(now));
+        }
         if !data.is_empty() {
             if let Err(e) = self.receiver.receive_data(header.seq_num, data, now) {
                 warn!("Ignoring remote data for {:?}: {:?}", header, e);
             }
         }
+        self.update_state();
     }
 
     pub fn close(&self) -> Result<(), Fail> {
-        self.sender.close()
+        self.sender.close()?;
+        self.update_state();
+        Ok(())
+    }
+
+    /// Moves the connection along the state diagram of RFC 793 (section 3.2), following the
+    /// changes to the states of the sender and receiver ends. Entering TIME_WAIT starts the 2*MSL
+    /// timer of the closer, which then moves the connection to CLOSED.
+    pub fn update_state(&self) {
+        let (sender_state, receiver_state) = (self.sender.state.get(), self.receiver.state.get());
+        if sender_state == SenderState::Reset || receiver_state == ReceiverState::Aborted {
+            self.state.set(ConnectionState::Closed);
+            return;
+        }
+        let closed = sender_state != SenderState::Open;
+        let fin_acked = sender_state == SenderState::FinAckd;
+        let received_fin = receiver_state != ReceiverState::Open;
+
+        let mut state = self.state.get();
+        loop {
+            let next_state = match state {
+                ConnectionState::Established if closed => ConnectionState::FinWait1,
+                ConnectionState::Established if received_fin => ConnectionState::CloseWait,
+                ConnectionState::FinWait1 if fin_acked => ConnectionState::FinWait2,
+                ConnectionState::FinWait1 if received_fin => ConnectionState::Closing,
+                ConnectionState::FinWait2 if received_fin => ConnectionState::TimeWait,
+                ConnectionState::Closing if fin_acked => ConnectionState::TimeWait,
+                ConnectionState::CloseWait if closed => ConnectionState::LastAck,
+                ConnectionState::LastAck if fin_acked => ConnectionState::Closed,
+                _ => break,
+            };
+            debug!(
+                "{:?} -> {:?}: {:?} -> {:?}",
+                self.local, self.remote, state, next_state
+            );
+            state = next_state;
+        }
+        if state != self.state.get() {
+            self.state.set(state);
+        }
     }
 
     /// Fetch a TCP header filling out various values based on our current state.
     pub fn tcp_header(&self) -> TcpHeader {
         let mut header = TcpHeader::new(self.local.port, self.remote.port);
         header.window_size = self.receiver.hdr_window_size();
+        // Segments carry the next sequence number to send, even pure ACKs, so that the remote
+        // doesn't take them for segments below its window.
+        header.seq_num = self.sender.sent_seq_no.get();
 
         // Check if we have acknowledged all bytes that we have received. If not, piggy back an ACK
         // on this message.
@@ -80,15 +234,100 @@ impl<RT: Runtime> ControlBlock<RT> {
             header.ack_num = ack_seq_no;
             header.ack = true;
         }
+        if self.ecn {
+            header.ece = self.receiver.ecn_echo();
+        }
+
+        // Stamp every segment, echoing the latest timestamp we've seen from the remote.
+        let mut max_sack_blocks = MAX_SACK_BLOCKS;
+        if let Some(ref timestamps) = self.timestamps {
+            header.push_option(TcpOptions2::Timestamp {
+                sender_timestamp: timestamps.value(self.rt.now()),
+                echo_timestamp: timestamps.recent(),
+            });
+            // The timestamp leaves room for only three SACK blocks in the option space.
+            max_sack_blocks -= 1;
+        }
+
+        // Tell the remote about the out-of-order data we hold.
+        if self.sack_permitted {
+            let blocks = self.receiver.sack_blocks(max_sack_blocks);
+            if !blocks.is_empty() {
+                let mut sacks = [SelectiveAcknowlegement {
+                    begin: Wrapping(0),
+                    end: Wrapping(0),
+                }; 4];
+                sacks[..blocks.len()].copy_from_slice(&blocks);
+                header.push_option(TcpOptions2::SelectiveAcknowlegement {
+                    num_sacks: blocks.len(),
+                    sacks,
+                });
+            }
+        }
         header
     }
 
+    /// Returns how much data fits in a segment carrying the options of `header`. Options count
+    /// against the MSS (RFC 6691, section 2).
+    pub fn max_payload_size(&self, header: &TcpHeader) -> usize {
+        let options_size = header.compute_size() - MIN_TCP_HEADER_SIZE;
+        self.sender.effective_mss.get().saturating_sub(options_size)
+    }
+
+    /// Lowers the MSS to fit a path MTU of `mtu` bytes, as reported by an ICMPv4 Fragmentation
+    /// Needed message quoting our segment starting at `seq_num`. Only messages quoting data in
+    /// flight are trusted, as others may have been forged (RFC 5927, section 4.1).
+    pub fn reduce_path_mtu(&self, seq_num: SeqNumber, mtu: u16) -> Result<(), Fail> {
+        let base_seq_no = self.sender.base_seq_no.get();
+        if seq_num - base_seq_no >= self.sender.sent_seq_no.get() - base_seq_no {
+            return Err(Fail::Ignored {
+                details: "ICMPv4 error for segment not in flight",
+            });
+        }
+        // Every segment carries our timestamp, which resent segments have to make room for.
+        // SACK blocks are left out of segments they don't fit in.
+        let mut header = TcpHeader::new(self.local.port, self.remote.port);
+        if self.timestamps.is_some() {
+            header.push_option(TcpOptions2::Timestamp {
+                sender_timestamp: 0,
+                echo_timestamp: 0,
+            });
+        }
+        let options_size = header.compute_size() - MIN_TCP_HEADER_SIZE;
+        self.sender.reduce_path_mtu(mtu, options_size);
+        Ok(())
+    }
+
     /// Transmit this message to our connected peer.
-    pub fn emit(&self, header: TcpHeader, data: RT::Buf, remote_link_addr: MacAddress) {
+    pub fn emit(&self, mut header: TcpHeader, data: RT::Buf, remote_link_addr: MacAddress) {
+        // Retransmissions were sized without the SACK blocks we may hold now. They go without
+        // them rather than exceed the MSS, SACK blocks being sent only as space permits (RFC
+        // 2018, section 4).
+        if data.len() > self.max_payload_size(&header) {
+            let options: Vec<TcpOptions2> = header
+                .iter_options()
+                .filter(|option| !matches!(option, TcpOptions2::SelectiveAcknowlegement { .. }))
+                .cloned()
+                .collect();
+            header.num_options = 0;
+            for option in options {
+                header.push_option(option);
+            }
+        }
         if header.ack {
             self.receiver.update_ack_sent(header.ack_num);
         }
 
+        // Only segments of new data are ECN-capable, retransmissions being left out (RFC 3168,
+        // sections 6.1.4 and 6.1.5), and the first one after a window reduction carries CWR. New
+        // data starts at the next sequence number to send, which callers advance afterwards.
+        let mut ipv4_hdr = Ipv4Header::new(self.local.addr, self.remote.addr, Ipv4Protocol2::Tcp);
+        let new_data = !data.is_empty() && header.seq_num == self.sender.sent_seq_no.get();
+        if self.ecn && new_data {
+            ipv4_hdr.ecn = IPV4_ECN_ECT0;
+            header.cwr = self.sender.congestion_ctrl.take_cwr();
+        }
+
         debug!("Sending {} bytes + {:?}", data.len(), header);
         let segment = TcpSegment {
             ethernet2_hdr: Ethernet2Header {
@@ -96,7 +335,7 @@ impl<RT: Runtime> ControlBlock<RT> {
                 src_addr: self.rt.local_link_addr(),
                 ether_type: EtherType2::Ipv4,
             },
-            ipv4_hdr: Ipv4Header::new(self.local.addr, self.remote.addr, Ipv4Protocol2::Tcp),
+            ipv4_hdr,
             tcp_hdr: header,
             data,
             tx_checksum_offload: self.rt.tcp_options().tx_checksum_offload,
@@ -1,11 +1,16 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
+use super::scoreboard::seq_lt;
 use crate::{
-    collections::watched::WatchedValue, fail::Fail, protocols::tcp::SeqNumber, runtime::Runtime,
+    collections::watched::WatchedValue,
+    fail::Fail,
+    protocols::tcp::{segment::SelectiveAcknowlegement, SeqNumber},
+    runtime::Runtime,
 };
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
+    cmp,
     collections::{BTreeMap, VecDeque},
     convert::TryInto,
     num::Wrapping,
@@ -24,6 +29,8 @@ pub enum ReceiverState {
     ReceivedFin,
     /// We have ACKed the FIN.
     AckdFin,
+    /// The connection was aborted.
+    Aborted,
 }
 
 #[derive(Debug)]
@@ -52,13 +59,21 @@ pub struct Receiver<RT: Runtime> {
 
     pub max_window_size: u32,
     pub window_scale: u32,
+    /// Largest segment the remote may send us, as we advertised it.
+    pub mss: usize,
+    /// Right edge of the window we last advertised, which never moves back.
+    window_edge: Cell<SeqNumber>,
 
     waker: RefCell<Option<Waker>>,
     out_of_order: RefCell<BTreeMap<SeqNumber, RT::Buf>>,
+    /// Sequence number of the latest out-of-order segment, whose SACK block is reported first.
+    last_out_of_order: Cell<Option<SeqNumber>>,
+    /// Whether our ACKs carry ECE, telling the remote we've received CE marked segments.
+    ecn_echo: Cell<bool>,
 }
 
 impl<RT: Runtime> Receiver<RT> {
-    pub fn new(seq_no: SeqNumber, max_window_size: u32, window_scale: u32) -> Self {
+    pub fn new(seq_no: SeqNumber, max_window_size: u32, window_scale: u32, mss: usize) -> Self {
         Self {
             state: WatchedValue::new(ReceiverState::Open),
             base_seq_no: WatchedValue::new(seq_no),
@@ -68,17 +83,24 @@ impl<RT: Runtime> Receiver<RT> {
             ack_deadline: WatchedValue::new(None),
             max_window_size,
             window_scale,
+            mss,
+            window_edge: Cell::new(seq_no + Wrapping(max_window_size)),
             waker: RefCell::new(None),
             out_of_order: RefCell::new(BTreeMap::new()),
+            last_out_of_order: Cell::new(None),
+            ecn_echo: Cell::new(false),
         }
     }
 
+    /// Returns the window to advertise, for a segment about to be sent.
     pub fn hdr_window_size(&self) -> u16 {
-        let Wrapping(bytes_outstanding) = self.recv_seq_no.get() - self.base_seq_no.get();
-        let window_size = self.max_window_size - bytes_outstanding;
+        let recv_seq_no = self.recv_seq_no.get();
+        let window_size = self.window_size();
         let hdr_window_size = (window_size >> self.window_scale)
             .try_into()
             .expect("Window size overflow");
+        self.window_edge
+            .set(recv_seq_no + Wrapping((hdr_window_size as u32) << self.window_scale));
         debug!(
             "Sending window size update -> {} (hdr {}, scale {})",
             (hdr_window_size as u32) << self.window_scale,
@@ -88,6 +110,43 @@ impl<RT: Runtime> Receiver<RT> {
         hdr_window_size
     }
 
+    /// Silly window syndrome avoidance (RFC 1122, section 4.2.3.3): returns the window to
+    /// advertise, which only grows past the window we last advertised once it can grow by a full
+    /// segment or half of our buffer, whichever is smaller, or once all data has been read.
+    fn window_size(&self) -> u32 {
+        let recv_seq_no = self.recv_seq_no.get();
+        let Wrapping(bytes_outstanding) = recv_seq_no - self.base_seq_no.get();
+        let free = self.max_window_size - bytes_outstanding;
+        let advertised = self.advertised_window_size();
+        let threshold = cmp::min(self.max_window_size / 2, self.mss as u32);
+        if bytes_outstanding == 0 || free >= advertised + threshold {
+            free
+        } else {
+            cmp::min(advertised, free)
+        }
+    }
+
+    /// Returns what's left of the window we last advertised.
+    fn advertised_window_size(&self) -> u32 {
+        let recv_seq_no = self.recv_seq_no.get();
+        let window_edge = self.window_edge.get();
+        if seq_lt(recv_seq_no, window_edge) {
+            (window_edge - recv_seq_no).0
+        } else {
+            0
+        }
+    }
+
+    /// Returns whether to send a window update right away, which is the case once reading opens
+    /// up a window the remote could not send a full segment into.
+    pub fn window_update_due(&self) -> bool {
+        if self.state.get() != ReceiverState::Open {
+            return false;
+        }
+        let advertised = self.advertised_window_size();
+        advertised < self.mss as u32 && self.window_size() > advertised
+    }
+
     /// Returns the ack sequence number to use for the next packet based on all the bytes we have
     /// received. This ack sequence number will be piggy backed on the next packet send.
     /// If all received bytes have been acknowledged returns None.
@@ -97,7 +156,9 @@ impl<RT: Runtime> Receiver<RT> {
 
         // It is okay if ack_seq_no is greater than the seq number. This can happen when we have
         // ACKed a FIN so our ACK number is +1 greater than our seq number.
-        if ack_seq_no == recv_seq_no {
+        let fin_ackd =
+            self.state.get() == ReceiverState::AckdFin && ack_seq_no == recv_seq_no + Wrapping(1);
+        if ack_seq_no == recv_seq_no || fin_ackd {
             None
         } else {
             Some(recv_seq_no)
@@ -118,7 +179,34 @@ impl<RT: Runtime> Receiver<RT> {
         self.ack_seq_no.set(ack_seq);
     }
 
+    /// Returns up to `max_blocks` SACK blocks describing the out-of-order data we hold. As per
+    /// RFC 2018, the block containing the most recently received segment comes first.
+    pub fn sack_blocks(&self, max_blocks: usize) -> Vec<SelectiveAcknowlegement> {
+        let mut blocks: Vec<SelectiveAcknowlegement> = Vec::new();
+        for (&seq_no, buf) in self.out_of_order.borrow().iter() {
+            let end = seq_no + Wrapping(buf.len() as u32);
+            match blocks.last_mut() {
+                Some(last) if last.end == seq_no => last.end = end,
+                _ => blocks.push(SelectiveAcknowlegement { begin: seq_no, end }),
+            }
+        }
+        if let Some(latest) = self.last_out_of_order.get() {
+            if let Some(ix) = blocks
+                .iter()
+                .position(|b| b.begin <= latest && latest < b.end)
+            {
+                let block = blocks.remove(ix);
+                blocks.insert(0, block);
+            }
+        }
+        blocks.truncate(max_blocks);
+        blocks
+    }
+
     pub fn peek(&self) -> Result<RT::Buf, Fail> {
+        if self.state.get() == ReceiverState::Aborted {
+            return Err(Fail::ConnectionAborted {});
+        }
         if self.base_seq_no.get() == self.recv_seq_no.get() {
             if self.state.get() != ReceiverState::Open {
                 return Err(Fail::ResourceNotFound {
@@ -141,6 +229,9 @@ impl<RT: Runtime> Receiver<RT> {
     }
 
     pub fn recv(&self) -> Result<Option<RT::Buf>, Fail> {
+        if self.state.get() == ReceiverState::Aborted {
+            return Err(Fail::ConnectionAborted {});
+        }
         if self.base_seq_no.get() == self.recv_seq_no.get() {
             if self.state.get() != ReceiverState::Open {
                 return Err(Fail::ResourceNotFound {
@@ -162,6 +253,9 @@ impl<RT: Runtime> Receiver<RT> {
     }
 
     pub fn poll_recv(&self, ctx: &mut Context) -> Poll<Result<RT::Buf, Fail>> {
+        if self.state.get() == ReceiverState::Aborted {
+            return Poll::Ready(Err(Fail::ConnectionAborted {}));
+        }
         if self.base_seq_no.get() == self.recv_seq_no.get() {
             if self.state.get() != ReceiverState::Open {
                 return Poll::Ready(Err(Fail::ResourceNotFound {
@@ -183,6 +277,36 @@ impl<RT: Runtime> Receiver<RT> {
         Poll::Ready(Ok(segment))
     }
 
+    /// Records whether a segment was marked with CE. By default, we latch ECE until the remote
+    /// acknowledges it with CWR (RFC 3168, section 6.1.3). With `precise`, we echo the mark of the
+    /// latest segment instead, which is what DCTCP expects (RFC 8257, section 3.2).
+    pub fn receive_ecn(&self, ce: bool, cwr: bool, precise: bool, now: Instant) {
+        let ecn_echo = if precise {
+            ce
+        } else {
+            ce || (self.ecn_echo.get() && !cwr)
+        };
+        // Tell the remote about new congestion right away.
+        if ecn_echo != self.ecn_echo.get() {
+            self.ecn_echo.set(ecn_echo);
+            if ecn_echo || precise {
+                self.ack_deadline.set(Some(now));
+            }
+        }
+    }
+
+    pub fn ecn_echo(&self) -> bool {
+        self.ecn_echo.get()
+    }
+
+    /// Aborts the connection, failing pending and future reads.
+    pub fn abort(&self) {
+        self.state.set(ReceiverState::Aborted);
+        if let Some(w) = self.waker.borrow_mut().take() {
+            w.wake()
+        }
+    }
+
     pub fn receive_fin(&self) {
         // Even if we've already ACKd the FIN, we need to resend the ACK if we receive another FIN.
         self.state.set(ReceiverState::ReceivedFin);
@@ -197,17 +321,24 @@ impl<RT: Runtime> Receiver<RT> {
 
         let recv_seq_no = self.recv_seq_no.get();
         if seq_no > recv_seq_no {
+            // Out-of-order segments are acknowledged right away, so that the sender learns about
+            // the hole (and, with SACK, about what we hold past it).
+            self.ack_deadline.set(Some(now));
             let mut out_of_order = self.out_of_order.borrow_mut();
-            if !out_of_order.contains_key(&seq_no) {
-                while out_of_order.len() > MAX_OUT_OF_ORDER {
-                    let (&key, _) = out_of_order.iter().rev().next().unwrap();
-                    out_of_order.remove(&key);
-                }
-                out_of_order.insert(seq_no, buf);
+            if out_of_order.contains_key(&seq_no) {
                 return Err(Fail::Ignored {
-                    details: "Out of order segment (reordered)",
+                    details: "Out of order segment (duplicate)",
                 });
             }
+            while out_of_order.len() > MAX_OUT_OF_ORDER {
+                let (&key, _) = out_of_order.iter().rev().next().unwrap();
+                out_of_order.remove(&key);
+            }
+            out_of_order.insert(seq_no, buf);
+            self.last_out_of_order.set(Some(seq_no));
+            return Err(Fail::Ignored {
+                details: "Out of order segment (reordered)",
+            });
         }
         if seq_no < recv_seq_no {
             return Err(Fail::Ignored {
@@ -222,6 +353,8 @@ impl<RT: Runtime> Receiver<RT> {
             .map(|b| b.len())
             .sum::<usize>();
         if unread_bytes + buf.len() > self.max_window_size as usize {
+            // Let the remote know where our window stands.
+            self.ack_deadline.set(Some(now));
             return Err(Fail::Ignored {
                 details: "Full receive window",
             });
@@ -233,7 +366,6 @@ impl<RT: Runtime> Receiver<RT> {
             w.wake()
         }
 
-        // TODO: How do we handle when the other side is in PERSIST state here?
         if self.ack_deadline.get().is_none() {
             // TODO: Configure this value (and also maybe just have an RT pointer here.)
             self.ack_deadline
@@ -250,6 +382,8 @@ impl<RT: Runtime> Receiver<RT> {
             if let Err(e) = self.receive_data(new_recv_seq_no, old_data, now) {
                 info!("Failed to recover out-of-order packet: {:?}", e);
             }
+            // Filling a hole is acknowledged right away as well.
+            self.ack_deadline.set(Some(now));
         }
 
         Ok(())
@@ -268,10 +402,87 @@ mod tests {
     #[test]
     fn test_out_of_order() {
         let now = Instant::now();
-        let receiver = Receiver::<TestRuntime>::new(Wrapping(0), 65536, 0);
+        let receiver = Receiver::<TestRuntime>::new(Wrapping(0), 65536, 0, 1024);
         let buf = BytesMut::zeroed(16).unwrap().freeze();
         must_let!(let Err(Fail::Ignored { .. }) = receiver.receive_data(Wrapping(16), buf.clone(), now));
         must_let!(let Ok(..) = receiver.receive_data(Wrapping(0), buf.clone(), now));
         assert_eq!(receiver.recv_seq_no.get(), Wrapping(32))
     }
+
+    #[test]
+    fn test_sack_blocks() {
+        let now = Instant::now();
+        let receiver = Receiver::<TestRuntime>::new(Wrapping(0), 65536, 0, 1024);
+        let buf = BytesMut::zeroed(16).unwrap().freeze();
+        for &seq_no in [16, 64, 32, 80].iter() {
+            must_let!(let Err(Fail::Ignored { .. }) = receiver.receive_data(Wrapping(seq_no), buf.clone(), now));
+        }
+        // Contiguous segments are merged, and the latest one comes first.
+        let blocks = receiver.sack_blocks(4);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(
+            (blocks[0].begin, blocks[0].end),
+            (Wrapping(64), Wrapping(96))
+        );
+        assert_eq!(
+            (blocks[1].begin, blocks[1].end),
+            (Wrapping(16), Wrapping(48))
+        );
+        assert_eq!(receiver.sack_blocks(1).len(), 1);
+
+        // Filling the first hole leaves a single block.
+        must_let!(let Ok(..) = receiver.receive_data(Wrapping(0), buf.clone(), now));
+        let blocks = receiver.sack_blocks(4);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            (blocks[0].begin, blocks[0].end),
+            (Wrapping(64), Wrapping(96))
+        );
+    }
+
+    #[test]
+    fn test_ecn_echo() {
+        let now = Instant::now();
+        let receiver = Receiver::<TestRuntime>::new(Wrapping(0), 65536, 0, 1024);
+
+        // ECE is latched until the remote sends CWR.
+        receiver.receive_ecn(true, false, false, now);
+        assert!(receiver.ecn_echo());
+        assert_eq!(receiver.ack_deadline.get(), Some(now));
+        receiver.receive_ecn(false, false, false, now);
+        assert!(receiver.ecn_echo());
+        receiver.receive_ecn(false, true, false, now);
+        assert!(!receiver.ecn_echo());
+
+        // With precise echo, ECE follows the CE mark of every segment.
+        receiver.receive_ecn(true, false, true, now);
+        assert!(receiver.ecn_echo());
+        receiver.receive_ecn(false, false, true, now);
+        assert!(!receiver.ecn_echo());
+    }
+
+    #[test]
+    fn test_silly_window_avoidance() {
+        let now = Instant::now();
+        let receiver = Receiver::<TestRuntime>::new(Wrapping(0), 4096, 0, 1024);
+        assert_eq!(receiver.hdr_window_size(), 4096);
+
+        // Fill up the window, so that it closes.
+        let buf = BytesMut::zeroed(512).unwrap().freeze();
+        for i in 0..8 {
+            must_let!(let Ok(..) = receiver.receive_data(Wrapping(i * 512), buf.clone(), now));
+        }
+        assert_eq!(receiver.hdr_window_size(), 0);
+
+        // Reading less than a full segment doesn't reopen it.
+        must_let!(let Ok(Some(..)) = receiver.recv());
+        assert!(!receiver.window_update_due());
+        assert_eq!(receiver.hdr_window_size(), 0);
+
+        // Reading a full segment does, and the remote should hear about it right away.
+        must_let!(let Ok(Some(..)) = receiver.recv());
+        assert!(receiver.window_update_due());
+        assert_eq!(receiver.hdr_window_size(), 1024);
+        assert!(!receiver.window_update_due());
+    }
 }
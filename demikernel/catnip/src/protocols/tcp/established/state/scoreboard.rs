@@ -0,0 +1,113 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::protocols::tcp::SeqNumber;
+
+/// Returns `true` if `a` comes before `b` in sequence space (RFC 793, section 3.3).
+pub fn seq_lt(a: SeqNumber, b: SeqNumber) -> bool {
+    ((a - b).0 as i32) < 0
+}
+
+/// Byte ranges of unacknowledged data that the remote has selectively acknowledged (RFC 2018).
+/// Blocks are kept sorted and disjoint, as half-open `[begin, end)` ranges.
+#[derive(Debug, Default)]
+pub struct Scoreboard {
+    blocks: Vec<(SeqNumber, SeqNumber)>,
+}
+
+impl Scoreboard {
+    pub fn new() -> Self {
+        Self { blocks: Vec::new() }
+    }
+
+    /// Records that `[begin, end)` was received by the remote.
+    pub fn add(&mut self, begin: SeqNumber, end: SeqNumber) {
+        if !seq_lt(begin, end) {
+            return;
+        }
+        self.blocks.push((begin, end));
+        self.blocks.sort_by(|a, b| {
+            if seq_lt(a.0, b.0) {
+                std::cmp::Ordering::Less
+            } else if a.0 == b.0 {
+                std::cmp::Ordering::Equal
+            } else {
+                std::cmp::Ordering::Greater
+            }
+        });
+        let mut merged: Vec<(SeqNumber, SeqNumber)> = Vec::with_capacity(self.blocks.len());
+        for &(begin, end) in self.blocks.iter() {
+            match merged.last_mut() {
+                Some(last) if !seq_lt(last.1, begin) => {
+                    if seq_lt(last.1, end) {
+                        last.1 = end;
+                    }
+                }
+                _ => merged.push((begin, end)),
+            }
+        }
+        self.blocks = merged;
+    }
+
+    /// Forgets everything below `base_seq_no`, which was cumulatively acknowledged.
+    pub fn advance(&mut self, base_seq_no: SeqNumber) {
+        self.blocks.retain(|&(_, end)| seq_lt(base_seq_no, end));
+        if let Some(first) = self.blocks.first_mut() {
+            if seq_lt(first.0, base_seq_no) {
+                first.0 = base_seq_no;
+            }
+        }
+    }
+
+    /// Forgets all blocks, as the remote is allowed to discard data it selectively acknowledged.
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// Returns `true` if all of `[begin, end)` was selectively acknowledged.
+    pub fn is_sacked(&self, begin: SeqNumber, end: SeqNumber) -> bool {
+        self.blocks
+            .iter()
+            .any(|&(b, e)| !seq_lt(begin, b) && !seq_lt(e, end))
+    }
+
+    /// Returns the end of the highest block, if any.
+    pub fn highest(&self) -> Option<SeqNumber> {
+        self.blocks.last().map(|&(_, end)| end)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{seq_lt, Scoreboard};
+    use std::num::Wrapping;
+
+    #[test]
+    fn test_seq_lt() {
+        assert!(seq_lt(Wrapping(1), Wrapping(2)));
+        assert!(!seq_lt(Wrapping(2), Wrapping(2)));
+        assert!(seq_lt(Wrapping(u32::MAX), Wrapping(1)));
+    }
+
+    #[test]
+    fn test_scoreboard() {
+        let mut scoreboard = Scoreboard::new();
+        scoreboard.add(Wrapping(300), Wrapping(400));
+        scoreboard.add(Wrapping(100), Wrapping(200));
+        scoreboard.add(Wrapping(200), Wrapping(250));
+        assert!(scoreboard.is_sacked(Wrapping(100), Wrapping(250)));
+        assert!(!scoreboard.is_sacked(Wrapping(200), Wrapping(300)));
+        assert_eq!(scoreboard.highest(), Some(Wrapping(400)));
+
+        // Cumulative ACKs trim the scoreboard.
+        scoreboard.advance(Wrapping(320));
+        assert!(!scoreboard.is_sacked(Wrapping(100), Wrapping(200)));
+        assert!(scoreboard.is_sacked(Wrapping(320), Wrapping(400)));
+        scoreboard.advance(Wrapping(400));
+        assert!(scoreboard.is_empty());
+    }
+}
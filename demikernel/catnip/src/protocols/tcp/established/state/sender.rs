@@ -1,16 +1,28 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use super::{congestion_ctrl as cc, rto::RtoCalculator};
+use super::{
+    congestion_ctrl as cc,
+    rto::RtoCalculator,
+    scoreboard::{seq_lt, Scoreboard},
+};
 use crate::{
     collections::watched::WatchedValue,
     fail::Fail,
-    protocols::tcp::SeqNumber,
+    protocols::{
+        ipv4::datagram::IPV4_HEADER_SIZE,
+        tcp::{
+            constants::{MIN_MSS, PMTU_PLATEAUS},
+            segment::{SelectiveAcknowlegement, MIN_TCP_HEADER_SIZE},
+            SeqNumber,
+        },
+    },
     runtime::{Runtime, RuntimeBuf},
 };
 use std::{
     boxed::Box,
-    cell::RefCell,
+    cell::{Cell, RefCell},
+    cmp,
     collections::VecDeque,
     convert::TryInto,
     fmt,
@@ -54,14 +66,22 @@ pub struct Sender<RT: Runtime> {
     pub unsent_seq_no: WatchedValue<SeqNumber>,
 
     pub window_size: WatchedValue<u32>,
+    /// Largest window the remote has offered, for silly window syndrome avoidance.
+    pub max_window_size: Cell<u32>,
     // RFC 1323: Number of bits to shift advertised window, defaults to zero.
     pub window_scale: u8,
 
     pub mss: usize,
+    /// Size of the segments we actually send, which path MTU discovery may bring below `mss`
+    /// (RFC 1191).
+    pub effective_mss: WatchedValue<usize>,
 
     pub retransmit_deadline: WatchedValue<Option<Instant>>,
     pub rto: RefCell<RtoCalculator>,
 
+    /// Unacknowledged data the remote has selectively acknowledged.
+    pub scoreboard: RefCell<Scoreboard>,
+
     pub congestion_ctrl: Box<dyn cc::CongestionControl<RT>>,
 }
 
@@ -72,10 +92,13 @@ impl<RT: Runtime> fmt::Debug for Sender<RT> {
             .field("sent_seq_no", &self.sent_seq_no)
             .field("unsent_seq_no", &self.unsent_seq_no)
             .field("window_size", &self.window_size)
+            .field("max_window_size", &self.max_window_size)
             .field("window_scale", &self.window_scale)
             .field("mss", &self.mss)
+            .field("effective_mss", &self.effective_mss)
             .field("retransmit_deadline", &self.retransmit_deadline)
             .field("rto", &self.rto)
+            .field("scoreboard", &self.scoreboard)
             .finish()
     }
 }
@@ -99,21 +122,29 @@ impl<RT: Runtime> Sender<RT> {
             unsent_seq_no: WatchedValue::new(seq_no),
 
             window_size: WatchedValue::new(window_size),
+            max_window_size: Cell::new(window_size),
             window_scale,
             mss,
+            effective_mss: WatchedValue::new(mss),
 
             retransmit_deadline: WatchedValue::new(None),
             rto: RefCell::new(RtoCalculator::new()),
 
-            congestion_ctrl: cc_constructor(mss, seq_no, congestion_control_options),
+            scoreboard: RefCell::new(Scoreboard::new()),
+
+            congestion_ctrl: cc_constructor.construct(mss, seq_no, congestion_control_options),
         }
     }
 
     pub fn send(&self, buf: RT::Buf, cb: &super::ControlBlock<RT>) -> Result<(), Fail> {
-        if self.state.get() != SenderState::Open {
-            return Err(Fail::Ignored {
-                details: "Sender closed",
-            });
+        match self.state.get() {
+            SenderState::Open => {}
+            SenderState::Reset => return Err(Fail::ConnectionAborted {}),
+            _ => {
+                return Err(Fail::Ignored {
+                    details: "Sender closed",
+                })
+            }
         }
         let buf_len: u32 = buf.len().try_into().map_err(|_| Fail::Ignored {
             details: "Buffer too large",
@@ -133,27 +164,38 @@ impl<RT: Runtime> Sender<RT> {
         // The limited transmit algorithm can increase the effective size of cwnd by up to 2MSS
         let effective_cwnd = cwnd + self.congestion_ctrl.get_limited_transmit_cwnd_increase();
 
-        if win_sz > 0 && win_sz >= in_flight_after_send && effective_cwnd >= in_flight_after_send {
-            if let Some(remote_link_addr) = cb.arp.try_query(cb.remote.address()) {
-                // This hook is primarily intended to record the last time we sent data, so we can later tell if the connection has been idle
-                self.congestion_ctrl.on_send(&self, sent_data);
+        // Paced segments are left to the background sender, which spaces them out.
+        let paced = self.congestion_ctrl.get_pacing_rate().is_some();
 
+        if !paced
+            && sent_seq == self.unsent_seq_no.get()
+            && self.nagle_allows(buf.len(), cb.nodelay.get(), cb.cork.get())
+            && win_sz > 0
+            && win_sz >= in_flight_after_send
+            && effective_cwnd >= in_flight_after_send
+        {
+            if let Some(remote_link_addr) = cb.arp.try_query(cb.remote.address()) {
                 let mut header = cb.tcp_header();
-                header.seq_num = sent_seq;
-                cb.emit(header, buf.clone(), remote_link_addr);
-
-                self.unsent_seq_no.modify(|s| s + Wrapping(buf_len));
-                self.sent_seq_no.modify(|s| s + Wrapping(buf_len));
-                let unacked_segment = UnackedSegment {
-                    bytes: buf,
-                    initial_tx: Some(cb.rt.now()),
-                };
-                self.unacked_queue.borrow_mut().push_back(unacked_segment);
-                if self.retransmit_deadline.get().is_none() {
-                    let rto = self.rto.borrow().estimate();
-                    self.retransmit_deadline.set(Some(cb.rt.now() + rto));
+                if buf.len() <= cb.max_payload_size(&header) {
+                    // This hook is primarily intended to record the last time we sent data, so we can later tell if the connection has been idle
+                    self.congestion_ctrl.on_send(&self, sent_data, cb.rt.now());
+
+                    header.seq_num = sent_seq;
+                    cb.emit(header, buf.clone(), remote_link_addr);
+
+                    self.unsent_seq_no.modify(|s| s + Wrapping(buf_len));
+                    self.sent_seq_no.modify(|s| s + Wrapping(buf_len));
+                    let unacked_segment = UnackedSegment {
+                        bytes: buf,
+                        initial_tx: Some(cb.rt.now()),
+                    };
+                    self.unacked_queue.borrow_mut().push_back(unacked_segment);
+                    if self.retransmit_deadline.get().is_none() {
+                        let rto = self.rto.borrow().estimate();
+                        self.retransmit_deadline.set(Some(cb.rt.now() + rto));
+                    }
+                    return Ok(());
                 }
-                return Ok(());
             }
         }
         // Slow path: Delegating sending the data to background processing.
@@ -173,16 +215,43 @@ impl<RT: Runtime> Sender<RT> {
         Ok(())
     }
 
+    /// Nagle's algorithm (RFC 896, and RFC 1122 section 4.2.3.4): returns whether a segment with
+    /// `len` bytes of new data may go out now. Full-sized segments always may, while smaller ones
+    /// wait for all outstanding data to be acknowledged unless `nodelay` is set, and wait for as
+    /// long as `cork` is set. Once the sender is closed, whatever is left gets flushed.
+    pub fn nagle_allows(&self, len: usize, nodelay: bool, cork: bool) -> bool {
+        if len >= self.effective_mss.get() || self.state.get() != SenderState::Open {
+            return true;
+        }
+        !cork && (nodelay || self.sent_seq_no.get() == self.base_seq_no.get())
+    }
+
     pub fn receive_rst(&self) {
         self.state.set(SenderState::Reset);
     }
 
-    pub fn remote_ack(&self, ack_seq_no: SeqNumber, now: Instant) -> Result<(), Fail> {
+    /// Aborts the connection, failing future writes.
+    pub fn abort(&self) {
+        self.state.set(SenderState::Reset);
+    }
+
+    /// Processes an ACK from the remote. `rtt_sample` is the round-trip time measured from the
+    /// echoed timestamp, if the timestamps option is in use. It is only used when no segment
+    /// acknowledged gives a sample of its own (Karn's algorithm).
+    pub fn remote_ack(
+        &self,
+        ack_seq_no: SeqNumber,
+        now: Instant,
+        rtt_sample: Option<Duration>,
+    ) -> Result<(), Fail> {
         if self.state.get() == SenderState::SentFin
-            && ack_seq_no == self.base_seq_no.get() + Wrapping(1)
+            && ack_seq_no == self.sent_seq_no.get() + Wrapping(1)
         {
-            assert_eq!(self.base_seq_no.get(), self.sent_seq_no.get());
             assert_eq!(self.sent_seq_no.get(), self.unsent_seq_no.get());
+            // The ACK for our FIN may also cover the last of our data.
+            if self.base_seq_no.get() != self.sent_seq_no.get() {
+                self.remote_ack(self.sent_seq_no.get(), now, rtt_sample)?;
+            }
             self.state.set(SenderState::FinAckd);
             return Ok(());
         }
@@ -199,7 +268,7 @@ impl<RT: Runtime> Sender<RT> {
             });
         }
 
-        self.congestion_ctrl.on_ack_received(&self, ack_seq_no);
+        self.congestion_ctrl.on_ack_received(&self, ack_seq_no, now);
         if bytes_acknowledged == Wrapping(0) {
             return Ok(());
         }
@@ -215,6 +284,7 @@ impl<RT: Runtime> Sender<RT> {
 
         // TODO: Do acks need to be on segment boundaries? How does this interact with repacketization?
         let mut bytes_remaining = bytes_acknowledged.0 as usize;
+        let mut clean_sample = false;
         while let Some(segment) = self.unacked_queue.borrow_mut().pop_front() {
             if segment.bytes.len() > bytes_remaining {
                 // TODO: We need to close the connection in this case.
@@ -224,17 +294,25 @@ impl<RT: Runtime> Sender<RT> {
             }
             bytes_remaining -= segment.bytes.len();
 
-            // Add sample for RTO if not a retransmission
-            // TODO: TCP timestamp support.
+            // Add sample for RTO if not a retransmission.
             if let Some(initial_tx) = segment.initial_tx {
                 self.rto.borrow_mut().add_sample(now - initial_tx);
+                clean_sample = true;
             }
             if bytes_remaining == 0 {
                 break;
             }
         }
+        // Timestamps only have millisecond resolution, so they stand in for our own clock when
+        // every segment acknowledged was retransmitted.
+        if !clean_sample {
+            if let Some(rtt) = rtt_sample {
+                self.rto.borrow_mut().add_sample(rtt);
+            }
+        }
         self.base_seq_no.modify(|b| b + bytes_acknowledged);
         let new_base_seq_no = self.base_seq_no.get();
+        self.scoreboard.borrow_mut().advance(new_base_seq_no);
         if new_base_seq_no < base_seq_no {
             // We've wrapped around, and so we need to do some bookkeeping
             self.congestion_ctrl.on_base_seq_no_wraparound(&self);
@@ -243,22 +321,59 @@ impl<RT: Runtime> Sender<RT> {
         Ok(())
     }
 
-    pub fn pop_one_unsent_byte(&self) -> Option<RT::Buf> {
-        let mut queue = self.unsent_queue.borrow_mut();
-
-        let buf = queue.front_mut()?;
-        let mut cloned_buf = buf.clone();
-        let buf_len = buf.len();
+    /// Passes the ECN echo carried by an ACK to the congestion control, before the ACK itself.
+    pub fn remote_ecn_echo(&self, ack_seq_no: SeqNumber, ece: bool) {
+        let bytes_outstanding = self.sent_seq_no.get() - self.base_seq_no.get();
+        let bytes_acknowledged = ack_seq_no - self.base_seq_no.get();
+        if bytes_acknowledged <= bytes_outstanding {
+            self.congestion_ctrl.on_ecn_feedback(&self, ack_seq_no, ece);
+        }
+    }
 
-        // Pop one byte off the buf still in the queue and all but one of the bytes on our clone.
-        buf.adjust(1);
-        cloned_buf.trim(buf_len - 1);
+    /// Records the SACK blocks carried by an ACK. Blocks that do not cover unacknowledged data
+    /// are dropped.
+    pub fn remote_sack(&self, sacks: &[SelectiveAcknowlegement]) {
+        let base_seq_no = self.base_seq_no.get();
+        let sent_seq_no = self.sent_seq_no.get();
+        let mut scoreboard = self.scoreboard.borrow_mut();
+        for sack in sacks {
+            if seq_lt(sack.begin, base_seq_no) || seq_lt(sent_seq_no, sack.end) {
+                debug!("Ignoring SACK block outside of send window: {:?}", sack);
+                continue;
+            }
+            scoreboard.add(sack.begin, sack.end);
+        }
+    }
 
-        Some(cloned_buf)
+    /// Returns the unacknowledged segments to retransmit, along with their sequence numbers. This
+    /// is the first segment the remote is missing and, if `all_holes` is set, every later segment
+    /// missing below the highest selectively acknowledged byte. Segments returned no longer count
+    /// for RTT estimation (Karn's algorithm).
+    pub fn retransmissions(&self, all_holes: bool) -> Vec<(SeqNumber, RT::Buf)> {
+        let scoreboard = self.scoreboard.borrow();
+        let highest_sacked = if all_holes {
+            scoreboard.highest()
+        } else {
+            None
+        };
+        let mut seq_no = self.base_seq_no.get();
+        let mut segments = vec![];
+        for segment in self.unacked_queue.borrow_mut().iter_mut() {
+            let end = seq_no + Wrapping(segment.bytes.len() as u32);
+            if !scoreboard.is_sacked(seq_no, end) {
+                // Past the highest SACKed byte, segments may well be in flight rather than lost.
+                if !segments.is_empty() && !highest_sacked.map_or(false, |h| seq_lt(seq_no, h)) {
+                    break;
+                }
+                segment.initial_tx.take();
+                segments.push((seq_no, segment.bytes.clone()));
+            }
+            seq_no = end;
+        }
+        segments
     }
 
     pub fn pop_unsent(&self, max_bytes: usize) -> Option<RT::Buf> {
-        // TODO: Use a scatter/gather array to coalesce multiple buffers into a single segment.
         let mut unsent_queue = self.unsent_queue.borrow_mut();
         let mut buf = unsent_queue.pop_front()?;
         let buf_len = buf.len();
@@ -271,6 +386,32 @@ impl<RT: Runtime> Sender<RT> {
 
             unsent_queue.push_front(buf);
             buf = cloned_buf;
+        } else if buf_len < max_bytes && !unsent_queue.is_empty() {
+            // Coalesce small buffers into a single segment, which is the point of Nagle's algorithm.
+            // TODO: Use a scatter/gather array instead of copying.
+            let mut bytes = buf.to_vec();
+            let mut num_taken = 0;
+            let mut partial_take = 0;
+            for next in unsent_queue.iter() {
+                if bytes.len() == max_bytes {
+                    break;
+                }
+                let take = cmp::min(next.len(), max_bytes - bytes.len());
+                bytes.extend_from_slice(&next[..take]);
+                if take < next.len() {
+                    partial_take = take;
+                    break;
+                }
+                num_taken += 1;
+            }
+            // Without a buffer to coalesce into, the first buffer goes out on its own.
+            if let Ok(coalesced) = RT::Buf::from_slice(&bytes) {
+                unsent_queue.drain(..num_taken);
+                if partial_take > 0 {
+                    unsent_queue.front_mut().unwrap().adjust(partial_take);
+                }
+                buf = coalesced;
+            }
         }
         Some(buf)
     }
@@ -293,11 +434,80 @@ impl<RT: Runtime> Sender<RT> {
             "Updating window size -> {} (hdr {}, scale {})",
             window_size, window_size_hdr, self.window_scale
         );
-        self.window_size.set(window_size);
+        // Only wake up the background sender for actual changes, so that a zero window doesn't
+        // reset the persist timer.
+        if window_size != self.window_size.get() {
+            self.window_size.set(window_size);
+        }
+        if window_size > self.max_window_size.get() {
+            self.max_window_size.set(window_size);
+        }
 
         Ok(())
     }
 
+    /// Silly window syndrome avoidance (RFC 1122, section 4.2.3.4): returns whether to send a
+    /// segment when `usable` bytes of window are available for `unsent` bytes of data. We only
+    /// send a segment if it is full-sized, carries all of our data, or fills at least half of the
+    /// largest window the remote has offered.
+    pub fn sws_allows(&self, usable: u32, unsent: u32) -> bool {
+        let len = cmp::min(usable, unsent);
+        len as usize >= self.effective_mss.get()
+            || len == unsent
+            || len >= self.max_window_size.get() / 2
+    }
+
+    /// Shrinks the effective MSS to fit a path MTU of `mtu` bytes, as reported by an ICMPv4
+    /// Fragmentation Needed message (RFC 1191, section 6.2). Routers predating RFC 1191 report an
+    /// MTU of zero, in which case we guess the next plateau below the current path MTU (section
+    /// 7). Reports that wouldn't lower the MSS are ignored. Unacknowledged segments are split to
+    /// the new size, less the `options_size` bytes of options they get resent with, as the network
+    /// won't take them as they are.
+    pub fn reduce_path_mtu(&self, mtu: u16, options_size: usize) {
+        let headers_size = IPV4_HEADER_SIZE + MIN_TCP_HEADER_SIZE;
+        let effective_mss = self.effective_mss.get();
+        let mtu = match mtu as usize {
+            0 => PMTU_PLATEAUS
+                .iter()
+                .map(|&plateau| plateau as usize)
+                .find(|&plateau| plateau < effective_mss + headers_size)
+                .unwrap_or(0),
+            mtu => mtu,
+        };
+        let mss = cmp::max(mtu.saturating_sub(headers_size), MIN_MSS);
+        if mss >= effective_mss {
+            return;
+        }
+        debug!(
+            "Path MTU {}: lowering MSS {} -> {}",
+            mtu, effective_mss, mss
+        );
+
+        let max_payload_size = mss.saturating_sub(options_size);
+        let mut unacked_queue = self.unacked_queue.borrow_mut();
+        let mut segments = VecDeque::with_capacity(unacked_queue.len());
+        for segment in unacked_queue.drain(..) {
+            if segment.bytes.len() <= max_payload_size {
+                segments.push_back(segment);
+                continue;
+            }
+            // The pieces are resent right away, so they no longer count for RTT estimation.
+            let mut bytes = segment.bytes;
+            while !bytes.is_empty() {
+                let len = cmp::min(bytes.len(), max_payload_size);
+                let mut piece = bytes.clone();
+                piece.trim(bytes.len() - len);
+                bytes.adjust(len);
+                segments.push_back(UnackedSegment {
+                    bytes: piece,
+                    initial_tx: None,
+                });
+            }
+        }
+        *unacked_queue = segments;
+        self.effective_mss.set(mss);
+    }
+
     pub fn remote_mss(&self) -> usize {
         self.mss
     }
@@ -306,3 +516,249 @@ impl<RT: Runtime> Sender<RT> {
         self.rto.borrow().estimate()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Sender, UnackedSegment};
+    use crate::{
+        collections::bytes::BytesMut,
+        protocols::tcp::{
+            established::state::congestion_ctrl as cc, segment::SelectiveAcknowlegement,
+        },
+        test_helpers::TestRuntime,
+    };
+    use std::{
+        num::Wrapping,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_retransmit_holes() {
+        let now = Instant::now();
+        let sender = Sender::<TestRuntime>::new(
+            Wrapping(0),
+            65536,
+            0,
+            100,
+            cc::CongestionControlConstructor::of::<cc::None>(),
+            None,
+        );
+        for _ in 0..5 {
+            let bytes = BytesMut::zeroed(100).unwrap().freeze();
+            sender.unacked_queue.borrow_mut().push_back(UnackedSegment {
+                bytes,
+                initial_tx: Some(now),
+            });
+        }
+        sender.sent_seq_no.set(Wrapping(500));
+
+        // Without SACK information, only the first segment is resent.
+        let segments = sender.retransmissions(true);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].0, Wrapping(0));
+
+        // The remote holds [100, 200) and [300, 400), so the holes are at 0 and 200. The last
+        // segment may still be in flight.
+        sender.remote_sack(&[
+            SelectiveAcknowlegement {
+                begin: Wrapping(300),
+                end: Wrapping(400),
+            },
+            SelectiveAcknowlegement {
+                begin: Wrapping(100),
+                end: Wrapping(200),
+            },
+        ]);
+        let seq_nos: Vec<_> = sender
+            .retransmissions(true)
+            .into_iter()
+            .map(|(seq_no, _)| seq_no)
+            .collect();
+        assert_eq!(seq_nos, [Wrapping(0), Wrapping(200)]);
+        assert_eq!(sender.retransmissions(false).len(), 1);
+
+        // Retransmitted segments no longer yield RTT samples.
+        assert!(sender.unacked_queue.borrow()[2].initial_tx.is_none());
+        assert!(sender.unacked_queue.borrow()[4].initial_tx.is_some());
+    }
+
+    #[test]
+    fn test_rtt_sample_preference() {
+        let now = Instant::now();
+        let sender = Sender::<TestRuntime>::new(
+            Wrapping(0),
+            65536,
+            0,
+            100,
+            cc::CongestionControlConstructor::of::<cc::None>(),
+            None,
+        );
+        let push = |initial_tx| {
+            sender.unacked_queue.borrow_mut().push_back(UnackedSegment {
+                bytes: BytesMut::zeroed(100).unwrap().freeze(),
+                initial_tx,
+            });
+            sender.sent_seq_no.modify(|s| s + Wrapping(100));
+        };
+
+        // Our own clock wins over the echoed timestamp.
+        push(Some(now));
+        let later = now + Duration::from_secs(1);
+        sender
+            .remote_ack(Wrapping(100), later, Some(Duration::from_secs(3)))
+            .unwrap();
+        assert_eq!(sender.current_rto(), Duration::from_secs(3));
+
+        // The timestamp still gives a sample for retransmitted segments.
+        push(None);
+        sender
+            .remote_ack(Wrapping(200), later, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert!(sender.current_rto() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_ecn_reduces_cwnd_once_per_window() {
+        let mut options = cc::Options::default();
+        options.insert_int("initial_window".to_string(), 10);
+        options.insert_float("g".to_string(), 1.);
+        let constructors: [cc::CongestionControlConstructor<TestRuntime>; 2] = [
+            cc::CongestionControlConstructor::of::<cc::NewReno>(),
+            cc::CongestionControlConstructor::of::<cc::Dctcp>(),
+        ];
+        for &constructor in constructors.iter() {
+            let sender = Sender::<TestRuntime>::new(
+                Wrapping(0),
+                65536,
+                0,
+                100,
+                constructor,
+                Some(options.clone()),
+            );
+            sender.sent_seq_no.set(Wrapping(1000));
+            assert_eq!(sender.congestion_ctrl.get_cwnd(), 1000);
+
+            // NewReno halves cwnd, and so does DCTCP when every byte in the window is marked.
+            sender.remote_ecn_echo(Wrapping(500), true);
+            assert_eq!(sender.congestion_ctrl.get_cwnd(), 500);
+            assert!(sender.congestion_ctrl.take_cwr());
+            assert!(!sender.congestion_ctrl.take_cwr());
+
+            // Further echoes for the same window of data are ignored.
+            sender.remote_ecn_echo(Wrapping(600), true);
+            assert_eq!(sender.congestion_ctrl.get_cwnd(), 500);
+        }
+    }
+
+    #[test]
+    fn test_bbr_pacing_rate() {
+        let sender = Sender::<TestRuntime>::new(
+            Wrapping(0),
+            65536,
+            0,
+            100,
+            cc::CongestionControlConstructor::of::<cc::Bbr>(),
+            None,
+        );
+        assert!(sender.congestion_ctrl.get_pacing_rate().is_none());
+
+        // Send a segment, and get it acknowledged a millisecond later.
+        let now = Instant::now();
+        sender.congestion_ctrl.on_send(&sender, 0, now);
+        sender.unacked_queue.borrow_mut().push_back(UnackedSegment {
+            bytes: BytesMut::zeroed(100).unwrap().freeze(),
+            initial_tx: Some(now),
+        });
+        sender.sent_seq_no.set(Wrapping(100));
+        sender
+            .remote_ack(Wrapping(100), now + Duration::from_millis(1), None)
+            .unwrap();
+
+        // The delivery rate sample of 100 bytes/ms, times the startup gain, gives the pacing rate.
+        let pacing_rate = sender.congestion_ctrl.get_pacing_rate().unwrap();
+        assert!((pacing_rate - 2.885 * 100_000.).abs() < 1.);
+    }
+
+    #[test]
+    fn test_silly_window_avoidance() {
+        let sender = Sender::<TestRuntime>::new(
+            Wrapping(0),
+            1200,
+            0,
+            1000,
+            cc::CongestionControlConstructor::of::<cc::None>(),
+            None,
+        );
+
+        // Full-sized segments, and segments carrying all of our data, may go out.
+        assert!(sender.sws_allows(1000, 5000));
+        assert!(sender.sws_allows(500, 500));
+
+        // So may smaller ones filling half of the largest window offered, but no smaller.
+        assert!(sender.sws_allows(600, 5000));
+        assert!(!sender.sws_allows(500, 5000));
+    }
+
+    #[test]
+    fn test_reduce_path_mtu() {
+        let now = Instant::now();
+        let sender = Sender::<TestRuntime>::new(
+            Wrapping(0),
+            65536,
+            0,
+            1460,
+            cc::CongestionControlConstructor::of::<cc::None>(),
+            None,
+        );
+        for _ in 0..2 {
+            sender.unacked_queue.borrow_mut().push_back(UnackedSegment {
+                bytes: BytesMut::zeroed(1460).unwrap().freeze(),
+                initial_tx: Some(now),
+            });
+        }
+
+        // Segments too large for the reported MTU are split to the new MSS.
+        sender.reduce_path_mtu(1000, 0);
+        assert_eq!(sender.effective_mss.get(), 960);
+        let lens: Vec<_> = sender
+            .unacked_queue
+            .borrow()
+            .iter()
+            .map(|s| s.bytes.len())
+            .collect();
+        assert_eq!(lens, [960, 500, 960, 500]);
+        assert!(sender.unacked_queue.borrow()[0].initial_tx.is_none());
+
+        // Reports can only lower the MSS.
+        sender.reduce_path_mtu(1200, 0);
+        assert_eq!(sender.effective_mss.get(), 960);
+
+        // Without a next-hop MTU, we guess the next plateau, but never go below the minimum MSS.
+        sender.reduce_path_mtu(0, 0);
+        assert_eq!(sender.effective_mss.get(), 536);
+        assert_eq!(sender.unacked_queue.borrow().len(), 6);
+
+        // Pieces leave room for the options they get resent with.
+        let sender = Sender::<TestRuntime>::new(
+            Wrapping(0),
+            65536,
+            0,
+            1460,
+            cc::CongestionControlConstructor::of::<cc::None>(),
+            None,
+        );
+        sender.unacked_queue.borrow_mut().push_back(UnackedSegment {
+            bytes: BytesMut::zeroed(1460).unwrap().freeze(),
+            initial_tx: Some(now),
+        });
+        sender.reduce_path_mtu(1000, 12);
+        assert_eq!(sender.effective_mss.get(), 960);
+        let lens: Vec<_> = sender
+            .unacked_queue
+            .borrow()
+            .iter()
+            .map(|s| s.bytes.len())
+            .collect();
+        assert_eq!(lens, [948, 512]);
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+/// Returns the value of a millisecond timestamp clock started at `origin` (RFC 7323, section 5.4).
+pub fn timestamp_value(origin: Instant, now: Instant) -> u32 {
+    (now - origin).as_millis() as u32
+}
+
+/// Returns `true` if timestamp `a` is older than timestamp `b`, modulo 2^32.
+fn timestamp_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// State of the TCP timestamps option (RFC 7323), once both ends agreed on it.
+#[derive(Debug)]
+pub struct Timestamps {
+    /// Origin of our timestamp clock.
+    origin: Instant,
+    /// Latest timestamp received from the remote, to be echoed back (TS.Recent).
+    recent: Cell<u32>,
+}
+
+impl Timestamps {
+    pub fn new(origin: Instant, recent: u32) -> Self {
+        Self {
+            origin,
+            recent: Cell::new(recent),
+        }
+    }
+
+    /// Returns the timestamp to send at `now` (TSval).
+    pub fn value(&self, now: Instant) -> u32 {
+        timestamp_value(self.origin, now)
+    }
+
+    /// Returns the timestamp to echo back to the remote (TSecr).
+    pub fn recent(&self) -> u32 {
+        self.recent.get()
+    }
+
+    /// Protection Against Wrapped Sequences: returns `true` if a segment stamped with
+    /// `sender_timestamp` is an old duplicate and must be dropped (RFC 7323, section 5.3).
+    pub fn is_old(&self, sender_timestamp: u32) -> bool {
+        timestamp_lt(sender_timestamp, self.recent.get())
+    }
+
+    /// Remembers `sender_timestamp` as the one to echo back, unless it is older than the current
+    /// one. Callers only do so for segments that do not lie past what we have acknowledged.
+    pub fn update_recent(&self, sender_timestamp: u32) {
+        if !timestamp_lt(sender_timestamp, self.recent.get()) {
+            self.recent.set(sender_timestamp);
+        }
+    }
+
+    /// Returns the round-trip time measured by an ACK echoing `echo_timestamp`.
+    pub fn rtt_sample(&self, echo_timestamp: u32, now: Instant) -> Option<Duration> {
+        let elapsed = self.value(now).wrapping_sub(echo_timestamp);
+        // Echoes from the future are bogus.
+        if (elapsed as i32) < 0 {
+            return None;
+        }
+        Some(Duration::from_millis(elapsed as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timestamps;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_paws() {
+        let timestamps = Timestamps::new(Instant::now(), 1000);
+        assert!(!timestamps.is_old(1000));
+        assert!(timestamps.is_old(999));
+
+        // The echoed timestamp only moves forward, even across wraparound.
+        timestamps.update_recent(u32::MAX - 10);
+        assert_eq!(timestamps.recent(), 1000);
+        timestamps.update_recent(2000);
+        assert_eq!(timestamps.recent(), 2000);
+        assert!(timestamps.is_old(1000));
+    }
+
+    #[test]
+    fn test_rtt_sample() {
+        let origin = Instant::now();
+        let timestamps = Timestamps::new(origin, 0);
+        let now = origin + Duration::from_millis(50);
+        assert_eq!(
+            timestamps.rtt_sample(20, now),
+            Some(Duration::from_millis(30))
+        );
+        assert_eq!(timestamps.rtt_sample(60, now), None);
+    }
+}
@@ -4,13 +4,41 @@
 use crate::protocols::{ipv4, tcp::SeqNumber};
 #[allow(unused_imports)]
 use crc::{crc32, Hasher32};
+use siphasher::sip::SipHasher;
 #[allow(unused_imports)]
 use std::{hash::Hasher, num::Wrapping};
 
+/// Maximum segment sizes a SYN cookie can encode. We pick the largest one which doesn't exceed the
+/// remote's, or the smallest one, which every host must accept.
+const SYN_COOKIE_MSS: [u16; 8] = [536, 1024, 1220, 1360, 1440, 1460, 4312, 8960];
+/// Window scale field of a SYN cookie for remotes which don't scale their window.
+const SYN_COOKIE_NO_WINDOW_SCALE: u32 = 0xf;
+/// Bits of a SYN cookie holding the keyed hash.
+const SYN_COOKIE_HASH_MASK: u32 = 0xf_ffff;
+
+/// Connection parameters which a SYN cookie encodes, so that we can accept the connection without
+/// having kept any state since the SYN.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SynCookie {
+    pub mss: usize,
+    pub window_scale: Option<u8>,
+}
+
+/// Secret key of the hash of the SYN cookies generated during `slot`.
+#[derive(Clone, Copy, Debug)]
+struct CookieSecret {
+    slot: u32,
+    key: [u64; 2],
+}
+
 #[allow(dead_code)]
 pub struct IsnGenerator {
     nonce: u32,
     counter: Wrapping<u16>,
+    /// Secrets of the SYN cookies of two consecutive slots, indexed by the parity of the slot.
+    /// Each slot gets a fresh secret, so that one leaking only lets cookies be forged for as long
+    /// as they stay valid.
+    cookie_secrets: [Option<CookieSecret>; 2],
 }
 
 impl IsnGenerator {
@@ -18,6 +46,7 @@ impl IsnGenerator {
         Self {
             nonce,
             counter: Wrapping(0),
+            cookie_secrets: [None; 2],
         }
     }
 
@@ -39,4 +68,166 @@ impl IsnGenerator {
         self.counter += Wrapping(1);
         isn
     }
+
+    /// Makes sure that `slot` has a secret to generate cookies with, taking `new_key()` as the
+    /// secret of a slot that just started.
+    pub fn rotate_cookie_secret(&mut self, slot: u32, new_key: impl FnOnce() -> [u64; 2]) {
+        let secret = &mut self.cookie_secrets[(slot & 1) as usize];
+        if secret.map_or(true, |s| s.slot != slot) {
+            *secret = Some(CookieSecret {
+                slot,
+                key: new_key(),
+            });
+        }
+    }
+
+    /// Generates an ISN encoding `cookie`, for a connection whose SYN we don't keep state for.
+    /// From the top, it has 5 bits of `slot`, a coarse clock bounding the lifetime of the cookie,
+    /// 3 bits of MSS index, 4 bits of window scale, and 20 bits of a keyed hash covering the
+    /// connection and all of the above. The secret of `slot` must have been set up with
+    /// [rotate_cookie_secret](Self::rotate_cookie_secret).
+    pub fn generate_cookie(
+        &self,
+        local: &ipv4::Endpoint,
+        remote: &ipv4::Endpoint,
+        remote_isn: SeqNumber,
+        slot: u32,
+        cookie: SynCookie,
+    ) -> SeqNumber {
+        let mss_index = SYN_COOKIE_MSS
+            .iter()
+            .rposition(|&mss| mss as usize <= cookie.mss)
+            .unwrap_or(0) as u32;
+        // Window scales past 14 are taken as 14 (RFC 7323, section 2.3).
+        let window_scale = cookie
+            .window_scale
+            .map_or(SYN_COOKIE_NO_WINDOW_SCALE, |w| std::cmp::min(w, 14) as u32);
+        let fields = (slot & 0x1f) << 27 | mss_index << 24 | window_scale << 20;
+        let key = self
+            .cookie_key(slot)
+            .expect("No secret for the SYN cookie slot");
+        let hash = Self::cookie_hash(key, local, remote, remote_isn, fields);
+        Wrapping(fields | (hash & SYN_COOKIE_HASH_MASK))
+    }
+
+    /// Returns the parameters encoded in `isn`, if it is a cookie we generated for this
+    /// connection during `slot` or the one before.
+    pub fn validate_cookie(
+        &self,
+        local: &ipv4::Endpoint,
+        remote: &ipv4::Endpoint,
+        remote_isn: SeqNumber,
+        slot: u32,
+        isn: SeqNumber,
+    ) -> Option<SynCookie> {
+        let Wrapping(isn) = isn;
+        let fields = isn & !SYN_COOKIE_HASH_MASK;
+        let cookie_slot = match fields >> 27 {
+            s if s == slot & 0x1f => slot,
+            s if s == slot.wrapping_sub(1) & 0x1f => slot.wrapping_sub(1),
+            _ => return None,
+        };
+        let key = self.cookie_key(cookie_slot)?;
+        let hash = Self::cookie_hash(key, local, remote, remote_isn, fields);
+        if hash & SYN_COOKIE_HASH_MASK != isn & SYN_COOKIE_HASH_MASK {
+            return None;
+        }
+        let window_scale = match (fields >> 20) & 0xf {
+            SYN_COOKIE_NO_WINDOW_SCALE => None,
+            w => Some(w as u8),
+        };
+        Some(SynCookie {
+            mss: SYN_COOKIE_MSS[((fields >> 24) & 0x7) as usize] as usize,
+            window_scale,
+        })
+    }
+
+    /// Returns the secret of the cookies of `slot`, unless it has been rotated out.
+    fn cookie_key(&self, slot: u32) -> Option<[u64; 2]> {
+        match self.cookie_secrets[(slot & 1) as usize] {
+            Some(secret) if secret.slot == slot => Some(secret.key),
+            _ => None,
+        }
+    }
+
+    /// Keyed hash of a cookie. SipHash is a PRF, so that a remote cannot forge cookies without
+    /// knowing `key`, which a checksum such as CRC32 would let it do.
+    fn cookie_hash(
+        key: [u64; 2],
+        local: &ipv4::Endpoint,
+        remote: &ipv4::Endpoint,
+        remote_isn: SeqNumber,
+        fields: u32,
+    ) -> u32 {
+        let mut hash = SipHasher::new_with_keys(key[0], key[1]);
+        hash.write_u32(remote.address().into());
+        hash.write_u16(remote.port().into());
+        hash.write_u32(local.address().into());
+        hash.write_u16(local.port().into());
+        hash.write_u32(remote_isn.0);
+        hash.write_u32(fields);
+        hash.finish() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IsnGenerator, SynCookie};
+    use crate::{
+        protocols::{ip, ipv4},
+        test_helpers,
+    };
+    use std::{convert::TryFrom, num::Wrapping};
+
+    #[test]
+    fn test_syn_cookie() {
+        let mut generator = IsnGenerator::new(0xdead_beef);
+        generator.rotate_cookie_secret(31, || [1, 2]);
+        let local = ipv4::Endpoint::new(test_helpers::BOB_IPV4, ip::Port::try_from(80).unwrap());
+        let remote =
+            ipv4::Endpoint::new(test_helpers::ALICE_IPV4, ip::Port::try_from(50000).unwrap());
+        let remote_isn = Wrapping(1234);
+        let cookie = SynCookie {
+            mss: 1450,
+            window_scale: Some(7),
+        };
+        let isn = generator.generate_cookie(&local, &remote, remote_isn, 31, cookie);
+
+        // The MSS gets rounded down to one the cookie can encode.
+        let expected = SynCookie {
+            mss: 1440,
+            window_scale: Some(7),
+        };
+        for &slot in &[31, 32] {
+            assert_eq!(
+                generator.validate_cookie(&local, &remote, remote_isn, slot, isn),
+                Some(expected)
+            );
+        }
+
+        // Expired or tampered cookies, and cookies for other connections, are rejected.
+        assert_eq!(
+            generator.validate_cookie(&local, &remote, remote_isn, 33, isn),
+            None
+        );
+        assert_eq!(
+            generator.validate_cookie(&local, &remote, remote_isn, 31, isn + Wrapping(1 << 24)),
+            None
+        );
+        assert_eq!(
+            generator.validate_cookie(&local, &remote, remote_isn + Wrapping(1), 31, isn),
+            None
+        );
+
+        // Cookies of the previous slot stay valid across one rotation, but not two.
+        generator.rotate_cookie_secret(32, || [3, 4]);
+        assert!(generator
+            .validate_cookie(&local, &remote, remote_isn, 32, isn)
+            .is_some());
+        generator.rotate_cookie_secret(33, || [5, 6]);
+        assert_eq!(
+            generator.validate_cookie(&local, &remote, remote_isn, 32, isn),
+            None
+        );
+    }
 }
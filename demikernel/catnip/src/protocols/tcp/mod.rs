@@ -18,4 +18,8 @@ use std::num::Wrapping;
 
 pub type SeqNumber = Wrapping<u32>;
 
-pub use self::{established::state::congestion_ctrl, options::TcpOptions as Options, peer::Peer};
+pub use self::{
+    established::state::congestion_ctrl,
+    options::{TcpKeepAlive as KeepAlive, TcpOptions as Options},
+    peer::Peer,
+};
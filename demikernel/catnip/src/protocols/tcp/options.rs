@@ -3,7 +3,7 @@
 use crate::{
     protocols::tcp::{
         constants::{DEFAULT_MSS, MAX_MSS, MIN_MSS},
-        established::state::congestion_ctrl::{self as cc, CongestionControl},
+        established::state::congestion_ctrl as cc,
     },
     runtime::Runtime,
 };
@@ -11,15 +11,43 @@ use std::time::Duration;
 
 pub use crate::protocols::tcp::established::state::congestion_ctrl::CongestionControlConstructor;
 
+/// Keepalive settings: after `idle` time without hearing from the remote, we send up to `probes`
+/// probes `interval` apart, and abort the connection if none of them is answered.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TcpKeepAlive {
+    pub idle: Duration,
+    pub interval: Duration,
+    pub probes: usize,
+}
+
+impl Default for TcpKeepAlive {
+    // Defaults according to RFC 1122, section 4.2.3.6, and common practice.
+    fn default() -> Self {
+        TcpKeepAlive {
+            idle: Duration::from_secs(2 * 60 * 60),
+            interval: Duration::from_secs(75),
+            probes: 9,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TcpOptions<RT: Runtime> {
     pub advertised_mss: usize,
     pub congestion_ctrl_type: CongestionControlConstructor<RT>,
     pub congestion_ctrl_options: Option<cc::Options>,
+    pub ecn: bool,
+    pub fin_wait_2_timeout: Duration,
     pub handshake_retries: usize,
     pub handshake_timeout: Duration,
+    pub keepalive: Option<TcpKeepAlive>,
+    pub msl: Duration,
+    pub nodelay: bool,
     pub receive_window_size: u16,
     pub retries: usize,
+    pub selective_acks: bool,
+    pub syn_cookies: bool,
+    pub timestamps: bool,
     pub trailing_ack_delay: Duration,
     pub window_scale: u8,
     pub rx_checksum_offload: bool,
@@ -30,12 +58,25 @@ impl<RT: Runtime> Default for TcpOptions<RT> {
     fn default() -> Self {
         TcpOptions {
             advertised_mss: DEFAULT_MSS,
-            congestion_ctrl_type: cc::Cubic::new,
+            congestion_ctrl_type: CongestionControlConstructor::of::<cc::Cubic>(),
             congestion_ctrl_options: None,
+            ecn: false,
+            // As Linux does.
+            fin_wait_2_timeout: Duration::from_secs(60),
             handshake_retries: 5,
             handshake_timeout: Duration::from_secs(3),
+            keepalive: None,
+            // RFC 793 suggests two minutes, but stacks commonly use 30 seconds.
+            msl: Duration::from_secs(30),
+            // Nagle's algorithm is opt-in, so that small pushes go out right away unless asked.
+            nodelay: true,
             receive_window_size: 0xffff,
             retries: 5,
+            // Opt-in, like the other TCP extensions.
+            selective_acks: false,
+            syn_cookies: false,
+            // Timestamps take 12 bytes of every segment, so they are opt-in.
+            timestamps: false,
             trailing_ack_delay: Duration::from_micros(1),
             window_scale: 0,
             rx_checksum_offload: false,
@@ -62,6 +103,28 @@ impl<RT: Runtime> TcpOptions<RT> {
         self
     }
 
+    /// Negotiates Explicit Congestion Notification (RFC 3168). It only takes effect with a
+    /// congestion control that reacts to it, such as [cc::NewReno] or [cc::Dctcp].
+    pub fn ecn(mut self, value: bool) -> Self {
+        self.ecn = value;
+        self
+    }
+
+    /// Returns whether connections negotiate ECN: it has to be enabled, and the congestion
+    /// control has to react to it.
+    pub fn negotiates_ecn(&self) -> bool {
+        self.ecn && self.congestion_ctrl_type.reacts_to_ecn()
+    }
+
+    /// Sets how long a connection we closed waits in FIN_WAIT_2 for the remote to close its side.
+    /// Closing a connection gives up on its file descriptor, so nobody would ever reap it if the
+    /// remote never closes.
+    pub fn fin_wait_2_timeout(mut self, value: Duration) -> Self {
+        assert!(value > Duration::new(0, 0));
+        self.fin_wait_2_timeout = value;
+        self
+    }
+
     pub fn handshake_retries(mut self, value: usize) -> Self {
         assert!(value > 0);
         self.handshake_retries = value;
@@ -74,6 +137,29 @@ impl<RT: Runtime> TcpOptions<RT> {
         self
     }
 
+    pub fn keepalive(mut self, value: TcpKeepAlive) -> Self {
+        assert!(value.idle > Duration::new(0, 0));
+        assert!(value.interval > Duration::new(0, 0));
+        assert!(value.probes > 0);
+        self.keepalive = Some(value);
+        self
+    }
+
+    /// Sets the Maximum Segment Lifetime. Connections we actively close linger in TIME_WAIT for
+    /// twice this long, so that late segments do not reach a new connection on the same ports.
+    pub fn msl(mut self, value: Duration) -> Self {
+        assert!(value > Duration::new(0, 0));
+        self.msl = value;
+        self
+    }
+
+    /// Disables Nagle's algorithm on new connections if set, which is the default, so that small
+    /// segments go out right away instead of waiting for outstanding data to be acknowledged.
+    pub fn nodelay(mut self, value: bool) -> Self {
+        self.nodelay = value;
+        self
+    }
+
     pub fn receive_window_size(mut self, value: u16) -> Self {
         assert!(value > 0);
         self.receive_window_size = value;
@@ -86,6 +172,28 @@ impl<RT: Runtime> TcpOptions<RT> {
         self
     }
 
+    /// Negotiates selective acknowledgements (RFC 2018), so that losses are recovered without
+    /// resending data the remote already has.
+    pub fn selective_acks(mut self, value: bool) -> Self {
+        self.selective_acks = value;
+        self
+    }
+
+    /// Answers SYNs with SYN cookies once the listen backlog is full, rather than dropping them.
+    /// Connections accepted through a cookie don't negotiate selective acknowledgements,
+    /// timestamps, or Explicit Congestion Notification.
+    pub fn syn_cookies(mut self, value: bool) -> Self {
+        self.syn_cookies = value;
+        self
+    }
+
+    /// Negotiates the timestamps option (RFC 7323), which gives RTT samples for retransmitted
+    /// data and protects against wrapped sequence numbers (PAWS).
+    pub fn timestamps(mut self, value: bool) -> Self {
+        self.timestamps = value;
+        self
+    }
+
     pub fn trailing_ack_delay(mut self, value: Duration) -> Self {
         self.trailing_ack_delay = value;
         self
@@ -96,3 +204,29 @@ impl<RT: Runtime> TcpOptions<RT> {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CongestionControlConstructor, TcpOptions};
+    use crate::{
+        protocols::tcp::established::state::congestion_ctrl as cc, test_helpers::TestRuntime,
+    };
+
+    #[test]
+    fn test_ecn_needs_reacting_congestion_ctrl() {
+        let options = TcpOptions::<TestRuntime>::default();
+        assert!(!options.negotiates_ecn());
+
+        // Cubic and BBR ignore congestion marks.
+        let options = options.ecn(true);
+        assert!(!options.negotiates_ecn());
+        let options = options.congestion_ctrl_type(CongestionControlConstructor::of::<cc::Bbr>());
+        assert!(!options.negotiates_ecn());
+
+        let options =
+            options.congestion_ctrl_type(CongestionControlConstructor::of::<cc::NewReno>());
+        assert!(options.negotiates_ecn());
+        let options = options.congestion_ctrl_type(CongestionControlConstructor::of::<cc::Dctcp>());
+        assert!(options.negotiates_ecn());
+    }
+}
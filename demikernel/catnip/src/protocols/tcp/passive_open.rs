@@ -3,14 +3,23 @@
 
 use super::{
     constants::FALLBACK_MSS,
-    established::state::{receiver::Receiver, sender::Sender, ControlBlock},
-    isn_generator::IsnGenerator,
+    established::state::{
+        receiver::Receiver,
+        sender::Sender,
+        timestamps::{timestamp_value, Timestamps},
+        ConnectionState, ControlBlock,
+    },
+    isn_generator::{IsnGenerator, SynCookie},
 };
 use crate::{
+    collections::watched::WatchedValue,
     fail::Fail,
     protocols::{
         arp,
-        ethernet2::frame::{EtherType2, Ethernet2Header},
+        ethernet2::{
+            frame::{EtherType2, Ethernet2Header},
+            MacAddress,
+        },
         ipv4,
         ipv4::datagram::{Ipv4Header, Ipv4Protocol2},
         tcp::{
@@ -31,15 +40,25 @@ use std::{
     num::Wrapping,
     rc::Rc,
     task::{Context, Poll, Waker},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+/// Period of the clock which bounds the lifetime of SYN cookies.
+const SYN_COOKIE_PERIOD: Duration = Duration::from_secs(64);
+
 struct InflightAccept {
     local_isn: SeqNumber,
     remote_isn: SeqNumber,
     header_window_size: u16,
     remote_window_scale: Option<u8>,
     mss: usize,
+    /// Whether both ends support selective acknowledgements.
+    sack_permitted: bool,
+    /// Origin of our timestamp clock and the timestamp of the remote's SYN, if both ends
+    /// support timestamps.
+    timestamps: Option<(Instant, u32)>,
+    /// Whether both ends support Explicit Congestion Notification.
+    ecn: bool,
 
     #[allow(unused)]
     handle: SchedulerHandle,
@@ -53,7 +72,12 @@ struct ReadySockets<RT: Runtime> {
 
 impl<RT: Runtime> ReadySockets<RT> {
     fn push_ok(&mut self, cb: ControlBlock<RT>) {
-        assert!(self.endpoints.insert(cb.remote));
+        // Segments from a remote whose connection is ready never get here, but a remote must not
+        // be able to get us to accept it twice if that ever changes.
+        if !self.endpoints.insert(cb.remote) {
+            warn!("Dropping duplicate connection from {:?}", cb.remote);
+            return;
+        }
         self.ready.push_back(Ok(cb));
         if let Some(w) = self.waker.take() {
             w.wake()
@@ -76,7 +100,7 @@ impl<RT: Runtime> ReadySockets<RT> {
             }
         };
         if let Ok(ref cb) = r {
-            assert!(self.endpoints.remove(&cb.remote));
+            self.endpoints.remove(&cb.remote);
         }
         Poll::Ready(r)
     }
@@ -92,6 +116,8 @@ pub struct PassiveSocket<RT: Runtime> {
 
     max_backlog: usize,
     isn_generator: IsnGenerator,
+    /// Origin of the SYN cookie clock.
+    syn_cookie_epoch: Instant,
 
     local: ipv4::Endpoint,
     rt: RT,
@@ -112,6 +138,7 @@ impl<RT: Runtime> PassiveSocket<RT> {
             ready,
             max_backlog,
             isn_generator: IsnGenerator::new(nonce),
+            syn_cookie_epoch: rt.now(),
             local,
             rt,
             arp,
@@ -122,8 +149,15 @@ impl<RT: Runtime> PassiveSocket<RT> {
         self.ready.borrow_mut().poll(ctx)
     }
 
-    pub fn receive(&mut self, ip_header: &Ipv4Header, header: &TcpHeader) -> Result<(), Fail> {
+    pub fn receive(
+        &mut self,
+        ip_header: &Ipv4Header,
+        header: &TcpHeader,
+        data: RT::Buf,
+    ) -> Result<(), Fail> {
         let remote = ipv4::Endpoint::new(ip_header.src_addr, header.src_port);
+        // The listener may be bound to the wildcard address, so take the actual destination.
+        let local = ipv4::Endpoint::new(ip_header.dst_addr, self.local.port);
         if self.ready.borrow().endpoints.contains(&remote) {
             // TODO: What should we do if a packet shows up for a connection that hasn't been
             // `accept`ed yet?
@@ -146,6 +180,9 @@ impl<RT: Runtime> PassiveSocket<RT> {
                 header_window_size,
                 remote_window_scale,
                 mss,
+                sack_permitted,
+                timestamps,
+                ecn,
                 ..
             } = self.inflight.get(&remote).unwrap();
             if header.ack_num != local_isn + Wrapping(1) {
@@ -154,54 +191,44 @@ impl<RT: Runtime> PassiveSocket<RT> {
                 });
             }
 
-            let tcp_options = self.rt.tcp_options();
-            let (local_window_scale, remote_window_scale) = match remote_window_scale {
-                Some(w) => (tcp_options.window_scale as u32, w),
-                None => (0, 0),
-            };
-            let remote_window_size = (header_window_size)
-                .checked_shl(remote_window_scale as u32)
-                .expect("TODO: Window size overflow")
-                .try_into()
-                .expect("TODO: Window size overflow");
-            let local_window_size = (tcp_options.receive_window_size as u32)
-                .checked_shl(local_window_scale as u32)
-                .expect("TODO: Window size overflow");
-            info!(
-                "Window sizes: local {}, remote {}",
-                local_window_size, remote_window_size
-            );
-            info!(
-                "Window scale: local {}, remote {}",
-                local_window_scale, remote_window_scale
-            );
-
-            let sender = Sender::new(
-                local_isn + Wrapping(1),
-                remote_window_size,
+            // Echo the ACK's timestamp from now on, falling back to the SYN's.
+            let timestamps = timestamps.map(|(origin, syn_timestamp)| {
+                let recent = header
+                    .iter_options()
+                    .find_map(|option| match option {
+                        TcpOptions2::Timestamp {
+                            sender_timestamp, ..
+                        } => Some(*sender_timestamp),
+                        _ => None,
+                    })
+                    .unwrap_or(syn_timestamp);
+                Timestamps::new(origin, recent)
+            });
+            self.inflight.remove(&remote);
+            let cb = self.establish(
+                local,
+                remote,
+                local_isn,
+                remote_isn,
+                header_window_size,
                 remote_window_scale,
                 mss,
-                tcp_options.congestion_ctrl_type,
-                tcp_options.congestion_ctrl_options,
-            );
-            let receiver = Receiver::new(
-                remote_isn + Wrapping(1),
-                local_window_size,
-                local_window_scale,
+                sack_permitted,
+                timestamps,
+                ecn,
             );
-            self.inflight.remove(&remote);
-            let cb = ControlBlock {
-                local: self.local,
-                remote,
-                rt: self.rt.clone(),
-                arp: self.arp.clone(),
-                sender,
-                receiver,
-            };
+            self.receive_handshake_data(&cb, header, data);
             self.ready.borrow_mut().push_ok(cb);
             return Ok(());
         }
 
+        // We keep no state for handshakes answered with a SYN cookie, so this may be the ACK
+        // completing one.
+        let tcp_options = self.rt.tcp_options();
+        if tcp_options.syn_cookies && header.ack && !header.syn && !header.rst {
+            return self.receive_cookie(local, remote, header, data);
+        }
+
         // Otherwise, start a new connection.
         if !header.syn || header.ack || header.rst {
             return Err(Fail::Malformed {
@@ -209,27 +236,24 @@ impl<RT: Runtime> PassiveSocket<RT> {
             });
         }
         debug!("Received SYN: {:?}", header);
-        if inflight_len + self.ready.borrow().len() >= self.max_backlog {
-            // TODO: Should we send a RST here?
-            return Err(Fail::ConnectionRefused {});
-        }
-        let local_isn = self.isn_generator.generate(&self.local, &remote);
         let remote_isn = header.seq_num;
-        let future = Self::background(
-            local_isn,
-            remote_isn,
-            self.local,
-            remote,
-            self.rt.clone(),
-            self.arp.clone(),
-            self.ready.clone(),
-        );
-        let handle = self.rt.spawn(future);
 
         let mut remote_window_scale = None;
         let mut mss = FALLBACK_MSS;
+        let mut remote_sack_permitted = false;
+        let mut remote_timestamp = None;
         for option in header.iter_options() {
             match option {
+                TcpOptions2::Timestamp {
+                    sender_timestamp, ..
+                } => {
+                    info!("Received timestamp: {}", sender_timestamp);
+                    remote_timestamp = Some(*sender_timestamp);
+                }
+                TcpOptions2::SelectiveAcknowlegementPermitted => {
+                    info!("Received SACK permitted");
+                    remote_sack_permitted = true;
+                }
                 TcpOptions2::WindowScale(w) => {
                     info!("Received window scale: {:?}", w);
                     remote_window_scale = Some(*w);
@@ -241,23 +265,287 @@ impl<RT: Runtime> PassiveSocket<RT> {
                 _ => continue,
             }
         }
+
+        if inflight_len + self.ready.borrow().len() >= self.max_backlog {
+            if !tcp_options.syn_cookies {
+                // TODO: Should we send a RST here?
+                return Err(Fail::ConnectionRefused {});
+            }
+            let cookie = SynCookie {
+                mss,
+                window_scale: remote_window_scale,
+            };
+            return self.send_cookie(local, remote, remote_isn, cookie);
+        }
+        let local_isn = self.isn_generator.generate(&local, &remote);
+
+        let sack_permitted = tcp_options.selective_acks && remote_sack_permitted;
+        let timestamps = match remote_timestamp {
+            Some(t) if tcp_options.timestamps => Some((self.rt.now(), t)),
+            _ => None,
+        };
+        // An ECN-setup SYN has both ECE and CWR (RFC 3168, section 6.1.1).
+        let ecn = tcp_options.negotiates_ecn() && header.ece && header.cwr;
+
+        let future = Self::background(
+            local_isn,
+            remote_isn,
+            local,
+            remote,
+            sack_permitted,
+            timestamps,
+            ecn,
+            self.rt.clone(),
+            self.arp.clone(),
+            self.ready.clone(),
+        );
+        let handle = self.rt.spawn(future);
+
         let accept = InflightAccept {
             local_isn,
             remote_isn,
             header_window_size: header.window_size,
             remote_window_scale,
             mss,
+            sack_permitted,
+            timestamps,
+            ecn,
             handle,
         };
         self.inflight.insert(remote, accept);
         Ok(())
     }
 
+    /// Returns the current slot of the SYN cookie clock.
+    fn syn_cookie_slot(&self) -> u32 {
+        ((self.rt.now() - self.syn_cookie_epoch).as_secs() / SYN_COOKIE_PERIOD.as_secs()) as u32
+    }
+
+    /// Answers a SYN without keeping any state, encoding what we need to know about the
+    /// connection into the ISN of our SYN+ACK.
+    fn send_cookie(
+        &mut self,
+        local: ipv4::Endpoint,
+        remote: ipv4::Endpoint,
+        remote_isn: SeqNumber,
+        cookie: SynCookie,
+    ) -> Result<(), Fail> {
+        // TODO: Make this work pending on ARP resolution if needed.
+        let remote_link_addr = self
+            .arp
+            .try_query(remote.addr)
+            .ok_or(Fail::ResourceNotFound {
+                details: "SYN+ACK destination not in ARP cache",
+            })?;
+        let slot = self.syn_cookie_slot();
+        let rt = &self.rt;
+        self.isn_generator
+            .rotate_cookie_secret(slot, || [rt.rng_gen(), rt.rng_gen()]);
+        let local_isn = self
+            .isn_generator
+            .generate_cookie(&local, &remote, remote_isn, slot, cookie);
+        debug!("Backlog full, sending SYN cookie to {:?}", remote);
+        let segment = Self::syn_ack(
+            &self.rt,
+            remote_link_addr,
+            local,
+            remote,
+            local_isn,
+            remote_isn,
+            false,
+            None,
+            false,
+        );
+        self.rt.transmit(segment);
+        Ok(())
+    }
+
+    /// Completes a handshake we answered with a SYN cookie, provided the ACK echoes a valid one.
+    fn receive_cookie(
+        &mut self,
+        local: ipv4::Endpoint,
+        remote: ipv4::Endpoint,
+        header: &TcpHeader,
+        data: RT::Buf,
+    ) -> Result<(), Fail> {
+        let remote_isn = header.seq_num - Wrapping(1);
+        let local_isn = header.ack_num - Wrapping(1);
+        let slot = self.syn_cookie_slot();
+        let cookie = self
+            .isn_generator
+            .validate_cookie(&local, &remote, remote_isn, slot, local_isn)
+            .ok_or(Fail::Malformed {
+                details: "Invalid SYN cookie",
+            })?;
+        if self.ready.borrow().len() >= self.max_backlog {
+            return Err(Fail::ConnectionRefused {});
+        }
+        debug!("Received ACK with SYN cookie: {:?}", header);
+        let cb = self.establish(
+            local,
+            remote,
+            local_isn,
+            remote_isn,
+            header.window_size,
+            cookie.window_scale,
+            cookie.mss,
+            false,
+            None,
+            false,
+        );
+        self.receive_handshake_data(&cb, header, data);
+        self.ready.borrow_mut().push_ok(cb);
+        Ok(())
+    }
+
+    /// Queues the data carried by the ACK which completed the handshake of `cb`, so that the
+    /// remote doesn't have to retransmit it.
+    fn receive_handshake_data(&self, cb: &ControlBlock<RT>, header: &TcpHeader, data: RT::Buf) {
+        if data.is_empty() {
+            return;
+        }
+        let now = self.rt.now();
+        if let Err(e) = cb.receiver.receive_data(header.seq_num, data, now) {
+            warn!("Ignoring remote data for {:?}: {:?}", header, e);
+        }
+    }
+
+    /// Creates the control block of a connection whose handshake just completed.
+    #[allow(clippy::too_many_arguments)]
+    fn establish(
+        &self,
+        local: ipv4::Endpoint,
+        remote: ipv4::Endpoint,
+        local_isn: SeqNumber,
+        remote_isn: SeqNumber,
+        header_window_size: u16,
+        remote_window_scale: Option<u8>,
+        mss: usize,
+        sack_permitted: bool,
+        timestamps: Option<Timestamps>,
+        ecn: bool,
+    ) -> ControlBlock<RT> {
+        let tcp_options = self.rt.tcp_options();
+        let (local_window_scale, remote_window_scale) = match remote_window_scale {
+            Some(w) => (tcp_options.window_scale as u32, w),
+            None => (0, 0),
+        };
+        let remote_window_size = (header_window_size)
+            .checked_shl(remote_window_scale as u32)
+            .expect("TODO: Window size overflow")
+            .try_into()
+            .expect("TODO: Window size overflow");
+        let local_window_size = (tcp_options.receive_window_size as u32)
+            .checked_shl(local_window_scale as u32)
+            .expect("TODO: Window size overflow");
+        info!(
+            "Window sizes: local {}, remote {}",
+            local_window_size, remote_window_size
+        );
+        info!(
+            "Window scale: local {}, remote {}",
+            local_window_scale, remote_window_scale
+        );
+
+        let sender = Sender::new(
+            local_isn + Wrapping(1),
+            remote_window_size,
+            remote_window_scale,
+            mss,
+            tcp_options.congestion_ctrl_type,
+            tcp_options.congestion_ctrl_options,
+        );
+        let receiver = Receiver::new(
+            remote_isn + Wrapping(1),
+            local_window_size,
+            local_window_scale,
+            tcp_options.advertised_mss,
+        );
+        ControlBlock {
+            local,
+            remote,
+            rt: self.rt.clone(),
+            arp: self.arp.clone(),
+            state: WatchedValue::new(ConnectionState::Established),
+            sender,
+            receiver,
+            sack_permitted,
+            timestamps,
+            ecn,
+            keepalive: WatchedValue::new(tcp_options.keepalive),
+            last_heard: WatchedValue::new(self.rt.now()),
+            nodelay: WatchedValue::new(tcp_options.nodelay),
+            cork: WatchedValue::new(false),
+        }
+    }
+
+    /// Builds the SYN+ACK answering a SYN, advertising the options agreed on.
+    #[allow(clippy::too_many_arguments)]
+    fn syn_ack(
+        rt: &RT,
+        remote_link_addr: MacAddress,
+        local: ipv4::Endpoint,
+        remote: ipv4::Endpoint,
+        local_isn: SeqNumber,
+        remote_isn: SeqNumber,
+        sack_permitted: bool,
+        timestamps: Option<(Instant, u32)>,
+        ecn: bool,
+    ) -> TcpSegment<RT::Buf> {
+        let tcp_options = rt.tcp_options();
+        let mut tcp_hdr = TcpHeader::new(local.port, remote.port);
+        tcp_hdr.syn = true;
+        tcp_hdr.seq_num = local_isn;
+        tcp_hdr.ack = true;
+        tcp_hdr.ack_num = remote_isn + Wrapping(1);
+        tcp_hdr.window_size = tcp_options.receive_window_size;
+        // This is an ECN-setup SYN+ACK.
+        tcp_hdr.ece = ecn;
+
+        let mss = tcp_options.advertised_mss as u16;
+        tcp_hdr.push_option(TcpOptions2::MaximumSegmentSize(mss));
+        info!("Advertising MSS: {}", mss);
+
+        tcp_hdr.push_option(TcpOptions2::WindowScale(tcp_options.window_scale));
+        info!("Advertising window scale: {}", tcp_options.window_scale);
+
+        // Only permit SACKs if the remote asked for them (RFC 2018, section 2).
+        if sack_permitted {
+            tcp_hdr.push_option(TcpOptions2::SelectiveAcknowlegementPermitted);
+            info!("Advertising SACK permitted");
+        }
+
+        // Likewise, only send a timestamp if the SYN carried one (RFC 7323, section 3.2).
+        if let Some((origin, syn_timestamp)) = timestamps {
+            tcp_hdr.push_option(TcpOptions2::Timestamp {
+                sender_timestamp: timestamp_value(origin, rt.now()),
+                echo_timestamp: syn_timestamp,
+            });
+        }
+
+        debug!("Sending SYN+ACK: {:?}", tcp_hdr);
+        TcpSegment {
+            ethernet2_hdr: Ethernet2Header {
+                dst_addr: remote_link_addr,
+                src_addr: rt.local_link_addr(),
+                ether_type: EtherType2::Ipv4,
+            },
+            ipv4_hdr: Ipv4Header::new(local.addr, remote.addr, Ipv4Protocol2::Tcp),
+            tcp_hdr,
+            data: RT::Buf::empty(),
+            tx_checksum_offload: tcp_options.tx_checksum_offload,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn background(
         local_isn: SeqNumber,
         remote_isn: SeqNumber,
         local: ipv4::Endpoint,
         remote: ipv4::Endpoint,
+        sack_permitted: bool,
+        timestamps: Option<(Instant, u32)>,
+        ecn: bool,
         rt: RT,
         arp: arp::Peer<RT>,
         ready: Rc<RefCell<ReadySockets<RT>>>,
@@ -275,32 +563,17 @@ impl<RT: Runtime> PassiveSocket<RT> {
                         continue;
                     }
                 };
-                let mut tcp_hdr = TcpHeader::new(local.port, remote.port);
-                tcp_hdr.syn = true;
-                tcp_hdr.seq_num = local_isn;
-                tcp_hdr.ack = true;
-                tcp_hdr.ack_num = remote_isn + Wrapping(1);
-                tcp_hdr.window_size = tcp_options.receive_window_size;
-
-                let mss = tcp_options.advertised_mss as u16;
-                tcp_hdr.push_option(TcpOptions2::MaximumSegmentSize(mss));
-                info!("Advertising MSS: {}", mss);
-
-                tcp_hdr.push_option(TcpOptions2::WindowScale(tcp_options.window_scale));
-                info!("Advertising window scale: {}", tcp_options.window_scale);
-
-                debug!("Sending SYN+ACK: {:?}", tcp_hdr);
-                let segment = TcpSegment {
-                    ethernet2_hdr: Ethernet2Header {
-                        dst_addr: remote_link_addr,
-                        src_addr: rt.local_link_addr(),
-                        ether_type: EtherType2::Ipv4,
-                    },
-                    ipv4_hdr: Ipv4Header::new(local.addr, remote.addr, Ipv4Protocol2::Tcp),
-                    tcp_hdr,
-                    data: RT::Buf::empty(),
-                    tx_checksum_offload: tcp_options.tx_checksum_offload,
-                };
+                let segment = Self::syn_ack(
+                    &rt,
+                    remote_link_addr,
+                    local,
+                    remote,
+                    local_isn,
+                    remote_isn,
+                    sack_permitted,
+                    timestamps,
+                    ecn,
+                );
                 rt.transmit(segment);
                 rt.wait(handshake_timeout).await;
             }
@@ -2,7 +2,9 @@
 // Licensed under the MIT license.
 
 use super::{
-    active_open::ActiveOpenSocket, established::EstablishedSocket, isn_generator::IsnGenerator,
+    active_open::ActiveOpenSocket,
+    established::{state::ConnectionState, EstablishedSocket},
+    isn_generator::IsnGenerator,
     passive_open::PassiveSocket,
 };
 use crate::{
@@ -17,17 +19,21 @@ use crate::{
         ipv4::datagram::{Ipv4Header, Ipv4Protocol2},
         tcp::{
             operations::{AcceptFuture, ConnectFuture, ConnectFutureState, PopFuture, PushFuture},
+            options::TcpKeepAlive,
             segment::{TcpHeader, TcpSegment},
         },
     },
     runtime::Runtime,
     runtime::RuntimeBuf,
+    scheduler::SchedulerHandle,
 };
-use futures::channel::mpsc;
+use futures::{channel::mpsc, StreamExt};
 use std::collections::HashMap;
 use std::{
     cell::RefCell,
-    rc::Rc,
+    net::Ipv4Addr,
+    num::Wrapping,
+    rc::{Rc, Weak},
     task::{Context, Poll},
     time::Duration,
 };
@@ -38,11 +44,26 @@ pub struct Peer<RT: Runtime> {
 
 impl<RT: Runtime> Peer<RT> {
     pub fn new(rt: RT, arp: arp::Peer<RT>, file_table: FileTable) -> Self {
-        let (tx, _rx) = mpsc::unbounded();
+        let (tx, rx) = mpsc::unbounded();
         let inner = Rc::new(RefCell::new(Inner::new(rt.clone(), arp, file_table, tx)));
+        let future = Self::background(Rc::downgrade(&inner), rx);
+        inner.borrow_mut().background = Some(rt.spawn(future));
         Self { inner }
     }
 
+    /// Background task reaping connections once they reach CLOSED.
+    async fn background(
+        inner: Weak<RefCell<Inner<RT>>>,
+        mut rx: mpsc::UnboundedReceiver<(ipv4::Endpoint, ipv4::Endpoint)>,
+    ) {
+        while let Some(key) = rx.next().await {
+            match inner.upgrade() {
+                Some(inner) => inner.borrow_mut().reap(key),
+                None => return,
+            }
+        }
+    }
+
     pub fn socket(&self) -> FileDescriptor {
         let mut inner = self.inner.borrow_mut();
         let fd = inner.file_table.alloc(File::TcpSocket);
@@ -75,6 +96,28 @@ impl<RT: Runtime> Peer<RT> {
         self.inner.borrow_mut().receive(ip_header, buf)
     }
 
+    /// Lowers the MSS of the connection from `local` to `remote` after a router reported that
+    /// its segment starting at `seq_num` needed fragmenting to get through a hop with an MTU of
+    /// `next_hop_mtu`.
+    pub fn fragmentation_needed(
+        &self,
+        local: ipv4::Endpoint,
+        remote: ipv4::Endpoint,
+        seq_num: Option<u32>,
+        next_hop_mtu: u16,
+    ) -> Result<(), Fail> {
+        let seq_num = seq_num.ok_or(Fail::Ignored {
+            details: "ICMPv4 error without TCP sequence number",
+        })?;
+        let inner = self.inner.borrow();
+        match inner.established.get(&(local, remote)) {
+            Some(ref s) => s.reduce_path_mtu(Wrapping(seq_num), next_hop_mtu),
+            None => Err(Fail::Ignored {
+                details: "ICMPv4 error for unknown connection",
+            }),
+        }
+    }
+
     pub fn listen(&self, fd: FileDescriptor, backlog: usize) -> Result<(), Fail> {
         let mut inner = self.inner.borrow_mut();
         let local = match inner.sockets.get_mut(&fd) {
@@ -119,13 +162,21 @@ impl<RT: Runtime> Peer<RT> {
             .passive
             .get_mut(local)
             .expect("sockets/local inconsistency");
-        let cb = match passive.poll_accept(ctx) {
-            Poll::Pending => return Poll::Pending,
-            Poll::Ready(Ok(e)) => e,
-            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+        let cb = loop {
+            match passive.poll_accept(ctx) {
+                Poll::Pending => return Poll::Pending,
+                // Don't hand out a second file descriptor for a connection we already track.
+                Poll::Ready(Ok(cb)) if inner.established.contains_key(&(cb.local, cb.remote)) => {
+                    warn!("Dropping duplicate connection {:?}", (cb.local, cb.remote));
+                }
+                Poll::Ready(Ok(e)) => break e,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            }
         };
+        let options = inner.socket_options.get(&fd).copied().unwrap_or_default();
         let fd = inner.file_table.alloc(File::TcpSocket);
         let established = EstablishedSocket::new(cb, fd, inner.dead_socket_tx.clone());
+        options.apply(&established);
         let key = (established.cb.local, established.cb.remote);
 
         let socket = Socket::Established {
@@ -292,18 +343,31 @@ impl<RT: Runtime> Peer<RT> {
     }
 
     pub fn close(&self, fd: FileDescriptor) -> Result<(), Fail> {
-        let inner = self.inner.borrow_mut();
+        let mut inner = self.inner.borrow_mut();
         match inner.sockets.get(&fd) {
             Some(Socket::Established { local, remote }) => {
                 let key = (*local, *remote);
-                match inner.established.get(&key) {
-                    Some(ref s) => s.close()?,
+                let s = match inner.established.get(&key) {
+                    Some(s) => s,
                     None => {
                         return Err(Fail::Malformed {
                             details: "Socket not established",
                         })
                     }
+                };
+                // The connection outlives its file descriptor until it reaches CLOSED, going
+                // through TIME_WAIT if we close first.
+                let r = match s.state() {
+                    ConnectionState::Closed => Ok(()),
+                    _ => s.close(),
+                };
+                let closed = s.state() == ConnectionState::Closed;
+                inner.sockets.remove(&fd);
+                inner.file_table.free(fd);
+                if closed {
+                    inner.remove_established(key);
                 }
+                return r;
             }
             Some(..) => {
                 // TODO: Implement close for listening sockets.
@@ -314,6 +378,52 @@ impl<RT: Runtime> Peer<RT> {
         Ok(())
     }
 
+    /// Overrides the keepalive settings of an established connection, `None` disabling keepalive.
+    pub fn set_keepalive(
+        &self,
+        fd: FileDescriptor,
+        keepalive: Option<TcpKeepAlive>,
+    ) -> Result<(), Fail> {
+        if let Some(k) = keepalive {
+            if k.probes == 0 || k.idle == Duration::new(0, 0) || k.interval == Duration::new(0, 0) {
+                return Err(Fail::Invalid {
+                    details: "Keepalive needs probes, and nonzero idle time and interval",
+                });
+            }
+        }
+        self.inner
+            .borrow()
+            .established_socket(fd)?
+            .set_keepalive(keepalive);
+        Ok(())
+    }
+
+    /// Disables or re-enables Nagle's algorithm. A socket which isn't established yet applies the
+    /// setting once it is, and a listening one to each connection it accepts.
+    pub fn set_nodelay(&self, fd: FileDescriptor, nodelay: bool) -> Result<(), Fail> {
+        let mut inner_ = self.inner.borrow_mut();
+        let inner = &mut *inner_;
+        match inner.sockets.get(&fd) {
+            Some(Socket::Established { .. }) => inner.established_socket(fd)?.set_nodelay(nodelay),
+            Some(..) => inner.socket_options.entry(fd).or_default().nodelay = Some(nodelay),
+            None => return Err(Fail::Malformed { details: "Bad FD" }),
+        }
+        Ok(())
+    }
+
+    /// Corks or uncorks a socket, like [set_nodelay](Self::set_nodelay) for sockets which aren't
+    /// established yet. Uncorking sends what was held back.
+    pub fn set_cork(&self, fd: FileDescriptor, cork: bool) -> Result<(), Fail> {
+        let mut inner_ = self.inner.borrow_mut();
+        let inner = &mut *inner_;
+        match inner.sockets.get(&fd) {
+            Some(Socket::Established { .. }) => inner.established_socket(fd)?.set_cork(cork),
+            Some(..) => inner.socket_options.entry(fd).or_default().cork = Some(cork),
+            None => return Err(Fail::Malformed { details: "Bad FD" }),
+        }
+        Ok(())
+    }
+
     pub fn remote_mss(&self, fd: FileDescriptor) -> Result<usize, Fail> {
         let inner = self.inner.borrow();
         let key = match inner.sockets.get(&fd) {
@@ -389,6 +499,24 @@ enum Socket {
     },
 }
 
+/// Options set on a socket before it got established, which its connection takes on once it is.
+#[derive(Clone, Copy, Debug, Default)]
+struct SocketOptions {
+    nodelay: Option<bool>,
+    cork: Option<bool>,
+}
+
+impl SocketOptions {
+    fn apply<RT: Runtime>(&self, socket: &EstablishedSocket<RT>) {
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay);
+        }
+        if let Some(cork) = self.cork {
+            socket.set_cork(cork);
+        }
+    }
+}
+
 pub struct Inner<RT: Runtime> {
     isn_generator: IsnGenerator,
 
@@ -397,6 +525,8 @@ pub struct Inner<RT: Runtime> {
 
     // FD -> local port
     sockets: HashMap<FileDescriptor, Socket>,
+    /// Options of the sockets which aren't established yet.
+    socket_options: HashMap<FileDescriptor, SocketOptions>,
 
     passive: HashMap<ipv4::Endpoint, PassiveSocket<RT>>,
     connecting: HashMap<(ipv4::Endpoint, ipv4::Endpoint), ActiveOpenSocket<RT>>,
@@ -405,7 +535,10 @@ pub struct Inner<RT: Runtime> {
     rt: RT,
     arp: arp::Peer<RT>,
 
-    dead_socket_tx: mpsc::UnboundedSender<FileDescriptor>,
+    dead_socket_tx: mpsc::UnboundedSender<(ipv4::Endpoint, ipv4::Endpoint)>,
+
+    #[allow(unused)]
+    background: Option<SchedulerHandle>,
 }
 
 impl<RT: Runtime> Inner<RT> {
@@ -413,19 +546,60 @@ impl<RT: Runtime> Inner<RT> {
         rt: RT,
         arp: arp::Peer<RT>,
         file_table: FileTable,
-        dead_socket_tx: mpsc::UnboundedSender<FileDescriptor>,
+        dead_socket_tx: mpsc::UnboundedSender<(ipv4::Endpoint, ipv4::Endpoint)>,
     ) -> Self {
         Self {
             isn_generator: IsnGenerator::new(rt.rng_gen()),
             file_table,
             ephemeral_ports: EphemeralPorts::new(&rt),
             sockets: HashMap::new(),
+            socket_options: HashMap::new(),
             passive: HashMap::new(),
             connecting: HashMap::new(),
             established: HashMap::new(),
             rt,
             arp,
             dead_socket_tx,
+            background: None,
+        }
+    }
+
+    /// Looks up the connection of an established socket.
+    fn established_socket(&self, fd: FileDescriptor) -> Result<&EstablishedSocket<RT>, Fail> {
+        let key = match self.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) => (*local, *remote),
+            Some(..) => {
+                return Err(Fail::Malformed {
+                    details: "Socket not established",
+                })
+            }
+            None => return Err(Fail::Malformed { details: "Bad FD" }),
+        };
+        self.established.get(&key).ok_or(Fail::Malformed {
+            details: "Socket not established",
+        })
+    }
+
+    /// Forgets about a connection which reached CLOSED, unless the application still holds its
+    /// file descriptor, in which case closing it does.
+    fn reap(&mut self, key: (ipv4::Endpoint, ipv4::Endpoint)) {
+        let fd = match self.established.get(&key) {
+            Some(s) => s.fd,
+            None => return,
+        };
+        match self.sockets.get(&fd) {
+            Some(Socket::Established { local, remote }) if (*local, *remote) == key => (),
+            _ => self.remove_established(key),
+        }
+    }
+
+    fn remove_established(&mut self, key: (ipv4::Endpoint, ipv4::Endpoint)) {
+        debug!("Reaping connection {:?}", key);
+        self.established.remove(&key);
+        // Only connections we opened use ephemeral ports, since private ports can't be bound.
+        let (local, _) = key;
+        if local.port.is_private() {
+            self.ephemeral_ports.free(local.port);
         }
     }
 
@@ -446,7 +620,7 @@ impl<RT: Runtime> Inner<RT> {
 
         if let Some(s) = self.established.get(&key) {
             debug!("Routing to established connection: {:?}", key);
-            s.receive(&tcp_hdr, data);
+            s.receive(ip_hdr, &tcp_hdr, data);
             return Ok(());
         }
         if let Some(s) = self.connecting.get_mut(&key) {
@@ -455,9 +629,16 @@ impl<RT: Runtime> Inner<RT> {
             return Ok(());
         }
         let (local, _) = key;
-        if let Some(s) = self.passive.get_mut(&local) {
+        // A listener bound to the destination address takes precedence over the wildcard one.
+        let wildcard = ipv4::Endpoint::new(Ipv4Addr::UNSPECIFIED, local.port);
+        let passive = if self.passive.contains_key(&local) {
+            self.passive.get_mut(&local)
+        } else {
+            self.passive.get_mut(&wildcard)
+        };
+        if let Some(s) = passive {
             debug!("Routing to passive connection: {:?}", local);
-            return s.receive(ip_hdr, &tcp_hdr);
+            return s.receive(ip_hdr, &tcp_hdr, data);
         }
 
         // The packet isn't for an open port; send a RST segment.
@@ -527,6 +708,9 @@ impl<RT: Runtime> Inner<RT> {
 
         let cb = result?;
         let socket = EstablishedSocket::new(cb, fd, self.dead_socket_tx.clone());
+        if let Some(options) = self.socket_options.remove(&fd) {
+            options.apply(&socket);
+        }
         assert!(self.established.insert(key, socket).is_none());
         let (local, remote) = key;
         self.sockets
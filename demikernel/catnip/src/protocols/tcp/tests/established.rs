@@ -4,25 +4,39 @@
 use crate::{
     collections::bytes::{Bytes, BytesMut},
     engine::Engine,
+    fail::Fail,
     file_table::FileDescriptor,
     protocols::{
+        ethernet2::{frame::ETHERNET2_HEADER_SIZE, EtherType2, Ethernet2Header},
+        icmpv4::{
+            Icmpv4Header, Icmpv4Message, Icmpv4Quote, Icmpv4Type2, ICMPV4_FRAGMENTATION_NEEDED,
+        },
         ip::{self},
-        ipv4::{self},
+        ipv4::{self, datagram::IPV4_HEADER_SIZE, Ipv4Header, Ipv4Protocol2},
         tcp::{
+            constants::{MAX_PERSIST_TIMEOUT, PMTU_RAISE_TIMEOUT},
+            established::state::congestion_ctrl::{
+                CongestionControl, CongestionControlConstructor, ExplicitCongestionNotification,
+                FastRetransmitRecovery, LimitedTransmit, Options, SlowStartCongestionAvoidance,
+            },
             operations::PushFuture,
+            segment::{TcpHeader, MIN_TCP_HEADER_SIZE},
             tests::{
                 check_packet_data, check_packet_pure_ack,
                 setup::{advance_clock, connection_setup},
             },
+            KeepAlive, SeqNumber,
         },
     },
-    runtime::Runtime,
+    runtime::{PacketBuf, Runtime},
     test_helpers::{self, TestRuntime},
 };
+use byteorder::{ByteOrder, NetworkEndian};
 use futures::task::noop_waker_ref;
 use must_let::must_let;
 use rand;
 use std::{
+    cmp,
     collections::VecDeque,
     convert::TryFrom,
     future::Future,
@@ -30,7 +44,7 @@ use std::{
     ops::Add,
     pin::Pin,
     task::{Context, Poll},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 //=============================================================================
@@ -46,6 +60,44 @@ fn cook_buffer(size: usize, stamp: Option<u8>) -> Bytes {
 
 //=============================================================================
 
+/// Pacing rate of [FixedPacing] (in bytes per second).
+const PACING_RATE: f64 = 1_000_000.;
+
+/// Congestion control that paces segments at a fixed rate, with an unlimited window.
+#[derive(Debug)]
+struct FixedPacing {}
+
+impl<RT: Runtime> CongestionControl<RT> for FixedPacing {
+    fn new(
+        _mss: usize,
+        _seq_no: SeqNumber,
+        _options: Option<Options>,
+    ) -> Box<dyn CongestionControl<RT>> {
+        Box::new(Self {})
+    }
+}
+
+impl<RT: Runtime> SlowStartCongestionAvoidance<RT> for FixedPacing {
+    fn get_pacing_rate(&self) -> Option<f64> {
+        Some(PACING_RATE)
+    }
+}
+impl<RT: Runtime> FastRetransmitRecovery<RT> for FixedPacing {}
+impl<RT: Runtime> LimitedTransmit<RT> for FixedPacing {}
+impl<RT: Runtime> ExplicitCongestionNotification<RT> for FixedPacing {}
+
+//=============================================================================
+
+/// Parses the TCP header of a frame.
+fn parse_tcp_header(bytes: Bytes) -> TcpHeader {
+    let (_, eth2_payload) = Ethernet2Header::parse(bytes).unwrap();
+    let (ipv4_header, ipv4_payload) = Ipv4Header::parse(eth2_payload).unwrap();
+    let (tcp_header, _) = TcpHeader::parse(&ipv4_header, ipv4_payload, false).unwrap();
+    tcp_header
+}
+
+//=============================================================================
+
 fn send_data(
     ctx: &mut Context,
     now: &mut Instant,
@@ -418,3 +470,609 @@ pub fn test_send_recv_with_delay() {
         );
     }
 }
+
+//=============================================================================
+
+/// Tests that Nagle's algorithm coalesces small pushes while data is
+/// outstanding unless disabled, and that corking holds small segments back.
+#[test]
+pub fn test_nagle_and_cork() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: ip::Port = ip::Port::try_from(80).unwrap();
+    let listen_addr: ipv4::Endpoint = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let window_size: u16 = client.rt().tcp_options().receive_window_size;
+
+    let (server_fd, client_fd): (FileDescriptor, FileDescriptor) = connection_setup(
+        &mut ctx,
+        &mut now,
+        &mut server,
+        &mut client,
+        listen_port,
+        listen_addr,
+    );
+
+    let buf: Bytes = cook_buffer(64, None);
+    let check_segment = |bytes: Bytes, seq_no: Wrapping<u32>| -> usize {
+        check_packet_data(
+            bytes,
+            test_helpers::ALICE_MAC,
+            test_helpers::BOB_MAC,
+            test_helpers::ALICE_IPV4,
+            test_helpers::BOB_IPV4,
+            window_size,
+            seq_no,
+            None,
+        )
+    };
+
+    // With Nagle's algorithm, the first push goes out right away, while the
+    // next ones wait for it to be acknowledged.
+    client.set_nodelay(client_fd, false).unwrap();
+    let _ = client.tcp_push(client_fd, buf.clone());
+    let bytes: Bytes = client.rt().pop_frame();
+    assert_eq!(check_segment(bytes.clone(), Wrapping(1)), 64);
+    let _ = client.tcp_push(client_fd, buf.clone());
+    let _ = client.tcp_push(client_fd, buf.clone());
+    client.rt().poll_scheduler();
+    assert!(client.rt().pop_frame_unchecked().is_none());
+
+    // Once it is, they go out as a single segment.
+    recv_data(&mut ctx, &mut server, &mut client, server_fd, bytes);
+    recv_pure_ack(
+        &mut now,
+        &mut server,
+        &mut client,
+        window_size,
+        Wrapping(65),
+    );
+    client.rt().poll_scheduler();
+    let bytes: Bytes = client.rt().pop_frame();
+    assert_eq!(check_segment(bytes, Wrapping(65)), 128);
+
+    // Without Nagle's algorithm, small pushes don't wait.
+    client.set_nodelay(client_fd, true).unwrap();
+    let _ = client.tcp_push(client_fd, buf.clone());
+    let bytes: Bytes = client.rt().pop_frame();
+    assert_eq!(check_segment(bytes, Wrapping(193)), 64);
+
+    // Unless the socket is corked.
+    client.set_cork(client_fd, true).unwrap();
+    let _ = client.tcp_push(client_fd, buf.clone());
+    client.rt().poll_scheduler();
+    assert!(client.rt().pop_frame_unchecked().is_none());
+    client.set_cork(client_fd, false).unwrap();
+    client.rt().poll_scheduler();
+    let bytes: Bytes = client.rt().pop_frame();
+    assert_eq!(check_segment(bytes, Wrapping(257)), 64);
+}
+
+//=============================================================================
+
+/// Tests that paced segments are spaced out by the pacing rate, waiting on the
+/// clock of the runtime.
+#[test]
+pub fn test_pacing() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: ip::Port = ip::Port::try_from(80).unwrap();
+    let listen_addr: ipv4::Endpoint = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let tcp_options = client.rt().tcp_options();
+    let window_size: u16 = tcp_options.receive_window_size;
+    client.rt().set_tcp_options(
+        tcp_options.congestion_ctrl_type(CongestionControlConstructor::of::<FixedPacing>()),
+    );
+
+    let (_, client_fd): (FileDescriptor, FileDescriptor) = connection_setup(
+        &mut ctx,
+        &mut now,
+        &mut server,
+        &mut client,
+        listen_port,
+        listen_addr,
+    );
+
+    // Push several segments worth of data at once.
+    let _ = client.tcp_push(client_fd, cook_buffer(4096, None));
+    client.rt().poll_scheduler();
+    let mut seq_no: Wrapping<u32> = Wrapping(1);
+    for _ in 0..3 {
+        // One segment goes out...
+        let bytes: Bytes = client.rt().pop_frame();
+        let len: usize = check_packet_data(
+            bytes,
+            test_helpers::ALICE_MAC,
+            test_helpers::BOB_MAC,
+            test_helpers::ALICE_IPV4,
+            test_helpers::BOB_IPV4,
+            window_size,
+            seq_no,
+            None,
+        );
+        seq_no += Wrapping(len as u32);
+        assert!(client.rt().pop_frame_unchecked().is_none());
+
+        // ...and the next one waits until the pacing rate allows it.
+        let delay: Duration = Duration::from_secs_f64(len as f64 / PACING_RATE);
+        now += delay - Duration::from_micros(1);
+        client.rt().advance_clock(now);
+        client.rt().poll_scheduler();
+        assert!(client.rt().pop_frame_unchecked().is_none());
+
+        now += Duration::from_micros(1);
+        client.rt().advance_clock(now);
+        client.rt().poll_scheduler();
+    }
+}
+
+//=============================================================================
+
+/// Tests that a connection whose remote stops answering is aborted once all
+/// keepalive probes go unanswered.
+#[test]
+pub fn test_keepalive_abort() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: ip::Port = ip::Port::try_from(80).unwrap();
+    let listen_addr: ipv4::Endpoint = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+
+    let (_, client_fd): (FileDescriptor, FileDescriptor) = connection_setup(
+        &mut ctx,
+        &mut now,
+        &mut server,
+        &mut client,
+        listen_port,
+        listen_addr,
+    );
+
+    let keepalive = KeepAlive {
+        idle: Duration::from_secs(1),
+        interval: Duration::from_secs(1),
+        probes: 2,
+    };
+    let no_probes = KeepAlive {
+        probes: 0,
+        ..keepalive
+    };
+    must_let!(let Err(Fail::Invalid { .. }) = client.set_keepalive(client_fd, Some(no_probes)));
+    client.set_keepalive(client_fd, Some(keepalive)).unwrap();
+    let mut pop_future = client.tcp_pop(client_fd);
+
+    // Drop every probe, as if the server had gone away.
+    let mut num_probes: usize = 0;
+    for _ in 0..4 {
+        advance_clock(Some(&mut server), Some(&mut client), &mut now);
+        client.rt().poll_scheduler();
+        while client.rt().pop_frame_unchecked().is_some() {
+            num_probes += 1;
+        }
+    }
+    assert_eq!(num_probes, 2);
+
+    // Both reads and writes fail.
+    must_let!(let Poll::Ready(Err(Fail::ConnectionAborted {})) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    let mut push_future: PushFuture<TestRuntime> = client.tcp_push(client_fd, cook_buffer(8, None));
+    must_let!(let Poll::Ready(Err(Fail::ConnectionAborted {})) = Future::poll(Pin::new(&mut push_future), &mut ctx));
+}
+
+//=============================================================================
+
+/// Tests that the side closing first lingers in TIME_WAIT, acknowledging a
+/// retransmitted FIN, and forgets about the connection after 2*MSL.
+#[test]
+pub fn test_active_close_time_wait() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: ip::Port = ip::Port::try_from(80).unwrap();
+    let listen_addr: ipv4::Endpoint = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let msl: Duration = client.rt().tcp_options().msl;
+
+    let (server_fd, client_fd): (FileDescriptor, FileDescriptor) = connection_setup(
+        &mut ctx,
+        &mut now,
+        &mut server,
+        &mut client,
+        listen_port,
+        listen_addr,
+    );
+
+    // Client: FIN_WAIT_1.
+    client.close(client_fd).unwrap();
+    client.rt().poll_scheduler();
+    let bytes: Bytes = client.rt().pop_frame();
+    let header: TcpHeader = parse_tcp_header(bytes.clone());
+    assert!(header.fin);
+    assert_eq!(header.seq_num, Wrapping(1));
+    server.receive(bytes).unwrap();
+
+    // Server: CLOSE_WAIT. Client: FIN_WAIT_2.
+    server.rt().poll_scheduler();
+    let bytes: Bytes = server.rt().pop_frame();
+    let header: TcpHeader = parse_tcp_header(bytes.clone());
+    assert!(header.ack);
+    assert_eq!(header.ack_num, Wrapping(2));
+    client.receive(bytes).unwrap();
+
+    // Server: LAST_ACK. Client: TIME_WAIT.
+    server.close(server_fd).unwrap();
+    server.rt().poll_scheduler();
+    let fin: Bytes = server.rt().pop_frame();
+    assert!(parse_tcp_header(fin.clone()).fin);
+    client.receive(fin.clone()).unwrap();
+
+    // Server: CLOSED.
+    client.rt().poll_scheduler();
+    let bytes: Bytes = client.rt().pop_frame();
+    let header: TcpHeader = parse_tcp_header(bytes.clone());
+    assert!(header.ack);
+    assert_eq!(header.ack_num, Wrapping(2));
+    server.receive(bytes).unwrap();
+
+    // A retransmitted FIN gets acknowledged again while in TIME_WAIT.
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    client.receive(fin.clone()).unwrap();
+    client.rt().poll_scheduler();
+    let header: TcpHeader = parse_tcp_header(client.rt().pop_frame());
+    assert!(!header.rst);
+    assert!(header.ack);
+    assert_eq!(header.ack_num, Wrapping(2));
+
+    // Client: CLOSED after 2*MSL, once the connection is reaped.
+    now += 2 * msl;
+    client.rt().advance_clock(now);
+    client.rt().poll_scheduler();
+    client.rt().poll_scheduler();
+    assert!(client.rt().pop_frame_unchecked().is_none());
+
+    // Segments for the connection now get reset.
+    client.receive(fin).unwrap();
+    let header: TcpHeader = parse_tcp_header(client.rt().pop_frame());
+    assert!(header.rst);
+}
+
+//=============================================================================
+
+/// Tests that a connection whose FIN never gets acknowledged is aborted once
+/// out of retransmissions.
+#[test]
+pub fn test_fin_retransmission_abort() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: ip::Port = ip::Port::try_from(80).unwrap();
+    let listen_addr: ipv4::Endpoint = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let tcp_options = client.rt().tcp_options();
+    client.rt().set_tcp_options(tcp_options.retries(2));
+
+    let (server_fd, client_fd): (FileDescriptor, FileDescriptor) = connection_setup(
+        &mut ctx,
+        &mut now,
+        &mut server,
+        &mut client,
+        listen_port,
+        listen_addr,
+    );
+
+    // Drop every FIN, as if the server had gone away.
+    client.close(client_fd).unwrap();
+    let mut num_fins: usize = 0;
+    for _ in 0..100 {
+        client.rt().poll_scheduler();
+        while let Some(bytes) = client.rt().pop_frame_unchecked() {
+            assert!(parse_tcp_header(bytes).fin);
+            num_fins += 1;
+        }
+        advance_clock(None, Some(&mut client), &mut now);
+    }
+    assert_eq!(num_fins, 3);
+
+    // The connection is gone, so segments for it get reset.
+    let _ = server.tcp_push(server_fd, cook_buffer(8, None));
+    server.rt().poll_scheduler();
+    client.receive(server.rt().pop_frame()).unwrap();
+    let header: TcpHeader = parse_tcp_header(client.rt().pop_frame());
+    assert!(header.rst);
+}
+
+//=============================================================================
+
+/// Tests that a connection we closed does not wait forever in FIN_WAIT_2 for
+/// the remote to close its side.
+#[test]
+pub fn test_fin_wait_2_timeout() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: ip::Port = ip::Port::try_from(80).unwrap();
+    let listen_addr: ipv4::Endpoint = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let tcp_options = client.rt().tcp_options();
+    client
+        .rt()
+        .set_tcp_options(tcp_options.fin_wait_2_timeout(Duration::from_secs(10)));
+
+    let (server_fd, client_fd): (FileDescriptor, FileDescriptor) = connection_setup(
+        &mut ctx,
+        &mut now,
+        &mut server,
+        &mut client,
+        listen_port,
+        listen_addr,
+    );
+
+    // Client: FIN_WAIT_1.
+    client.close(client_fd).unwrap();
+    client.rt().poll_scheduler();
+    server.receive(client.rt().pop_frame()).unwrap();
+
+    // Server: CLOSE_WAIT, and never closes. Client: FIN_WAIT_2.
+    server.rt().poll_scheduler();
+    client.receive(server.rt().pop_frame()).unwrap();
+    client.rt().poll_scheduler();
+    for _ in 0..9 {
+        advance_clock(None, Some(&mut client), &mut now);
+        client.rt().poll_scheduler();
+    }
+
+    // Still there: data from the server does not get reset.
+    let _ = server.tcp_push(server_fd, cook_buffer(8, None));
+    server.rt().poll_scheduler();
+    client.receive(server.rt().pop_frame()).unwrap();
+    assert!(client.rt().pop_frame_unchecked().is_none());
+
+    // Client: CLOSED once the timeout expires, after which segments get reset.
+    advance_clock(None, Some(&mut client), &mut now);
+    client.rt().poll_scheduler();
+    client.rt().poll_scheduler();
+    while client.rt().pop_frame_unchecked().is_some() {}
+    let _ = server.tcp_push(server_fd, cook_buffer(8, None));
+    server.rt().poll_scheduler();
+    client.receive(server.rt().pop_frame()).unwrap();
+    let header: TcpHeader = parse_tcp_header(client.rt().pop_frame());
+    assert!(header.rst);
+}
+
+//=============================================================================
+
+/// Tests that a zero window gets probed with exponential backoff, capped at
+/// the longest persist timeout, and that data resumes once the application
+/// reads and reopens the window.
+#[test]
+pub fn test_zero_window_probes() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: ip::Port = ip::Port::try_from(80).unwrap();
+    let listen_addr: ipv4::Endpoint = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers. The server's window only takes a single push.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let tcp_options = server.rt().tcp_options();
+    server
+        .rt()
+        .set_tcp_options(tcp_options.receive_window_size(1024).window_scale(0));
+    let window_size: u16 = client.rt().tcp_options().receive_window_size;
+
+    let (server_fd, client_fd): (FileDescriptor, FileDescriptor) = connection_setup(
+        &mut ctx,
+        &mut now,
+        &mut server,
+        &mut client,
+        listen_port,
+        listen_addr,
+    );
+
+    // Fill the server's window, which it acknowledges as closed.
+    let buf: Bytes = cook_buffer(1024, None);
+    let _ = client.tcp_push(client_fd, buf.clone());
+    server.receive(client.rt().pop_frame()).unwrap();
+    recv_pure_ack(&mut now, &mut server, &mut client, 0, Wrapping(1025));
+
+    // The next push waits for the window to open, probing it meanwhile.
+    let _ = client.tcp_push(client_fd, cook_buffer(8, None));
+    client.rt().poll_scheduler();
+    assert!(client.rt().pop_frame_unchecked().is_none());
+    let mut probes: Vec<u64> = Vec::new();
+    for t in 1..=300 {
+        advance_clock(None, Some(&mut client), &mut now);
+        client.rt().poll_scheduler();
+        while let Some(bytes) = client.rt().pop_frame_unchecked() {
+            let header: TcpHeader = parse_tcp_header(bytes);
+            assert_eq!(header.seq_num, Wrapping(1024));
+            probes.push(t);
+        }
+        if probes.len() == 8 {
+            break;
+        }
+    }
+
+    // Each probe waits twice as long as the previous one, up to the cap.
+    let max_timeout: u64 = MAX_PERSIST_TIMEOUT.as_secs();
+    let gaps: Vec<u64> = probes.windows(2).map(|p| p[1] - p[0]).collect();
+    assert_eq!(gaps.len(), 7);
+    for g in gaps.windows(2) {
+        assert_eq!(g[1], cmp::min(2 * g[0], max_timeout));
+    }
+    assert!(gaps[0] < max_timeout);
+    assert_eq!(gaps[6], max_timeout);
+
+    // Reading reopens the window, and the held back data goes out.
+    let mut pop_future = server.tcp_pop(server_fd);
+    must_let!(let Poll::Ready(Ok(_)) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    advance_clock(Some(&mut server), None, &mut now);
+    server.rt().poll_scheduler();
+    client.receive(server.rt().pop_frame()).unwrap();
+    client.rt().poll_scheduler();
+    let bytes: Bytes = client.rt().pop_frame();
+    let bufsize: usize = check_packet_data(
+        bytes,
+        test_helpers::ALICE_MAC,
+        test_helpers::BOB_MAC,
+        test_helpers::ALICE_IPV4,
+        test_helpers::BOB_IPV4,
+        window_size,
+        Wrapping(1025),
+        None,
+    );
+    assert_eq!(bufsize, 8);
+}
+
+//=============================================================================
+
+/// Cooks an ICMPv4 Fragmentation Needed message from Bob to Alice, quoting the
+/// segment of `frame` as if it started at `seq_num`.
+fn cook_fragmentation_needed(frame: Bytes, seq_num: SeqNumber, next_hop_mtu: u16) -> Bytes {
+    let (_, eth2_payload) = Ethernet2Header::parse(frame).unwrap();
+    let (ipv4_header, ipv4_payload) = Ipv4Header::parse(eth2_payload).unwrap();
+    let mut quote: Vec<u8> =
+        Icmpv4Quote::serialize(&ipv4_header, ipv4_payload.len(), &ipv4_payload[..]);
+    NetworkEndian::write_u32(&mut quote[(IPV4_HEADER_SIZE + 4)..], seq_num.0);
+    let message: Icmpv4Message<Bytes> = Icmpv4Message::with_data(
+        Ethernet2Header::new(
+            test_helpers::ALICE_MAC,
+            test_helpers::BOB_MAC,
+            EtherType2::Ipv4,
+        ),
+        Ipv4Header::new(
+            test_helpers::BOB_IPV4,
+            test_helpers::ALICE_IPV4,
+            Ipv4Protocol2::Icmpv4,
+        ),
+        Icmpv4Header::new(
+            Icmpv4Type2::DestinationUnreachable { next_hop_mtu },
+            ICMPV4_FRAGMENTATION_NEEDED,
+        ),
+        quote,
+    );
+    let mut buf: BytesMut = BytesMut::zeroed(message.header_size()).unwrap();
+    message.write_header(&mut buf[..]);
+    buf.freeze()
+}
+
+/// Tests that a Fragmentation Needed message about data in flight shrinks the
+/// segments we resend to the path MTU, and that the MSS goes back up after a
+/// while.
+#[test]
+pub fn test_path_mtu_discovery() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: ip::Port = ip::Port::try_from(80).unwrap();
+    let listen_addr: ipv4::Endpoint = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server: Engine<TestRuntime> = test_helpers::new_bob2(now);
+    let mut client: Engine<TestRuntime> = test_helpers::new_alice2(now);
+    let window_size: u16 = client.rt().tcp_options().receive_window_size;
+    // Timestamps give every segment an option to leave room for.
+    server
+        .rt()
+        .set_tcp_options(server.rt().tcp_options().timestamps(true));
+    client
+        .rt()
+        .set_tcp_options(client.rt().tcp_options().timestamps(true));
+
+    let (_, client_fd): (FileDescriptor, FileDescriptor) = connection_setup(
+        &mut ctx,
+        &mut now,
+        &mut server,
+        &mut client,
+        listen_port,
+        listen_addr,
+    );
+    let check_segment = |bytes: Bytes, seq_no: Wrapping<u32>| -> usize {
+        check_packet_data(
+            bytes,
+            test_helpers::ALICE_MAC,
+            test_helpers::BOB_MAC,
+            test_helpers::ALICE_IPV4,
+            test_helpers::BOB_IPV4,
+            window_size,
+            seq_no,
+            None,
+        )
+    };
+
+    // A segment too large for the path gets lost.
+    let _ = client.tcp_push(client_fd, cook_buffer(1400, None));
+    let bytes: Bytes = client.rt().pop_frame();
+    assert_eq!(check_segment(bytes.clone(), Wrapping(1)), 1400);
+
+    // Messages quoting data that isn't in flight are ignored.
+    let message: Bytes = cook_fragmentation_needed(bytes.clone(), Wrapping(1401), 1000);
+    must_let!(let Err(Fail::Ignored { .. }) = client.receive(message));
+    client.rt().poll_scheduler();
+    assert!(client.rt().pop_frame_unchecked().is_none());
+
+    // Others get the data resent in segments which fit, leaving room for the
+    // timestamp option.
+    let message: Bytes = cook_fragmentation_needed(bytes, Wrapping(1), 1000);
+    client.receive(message).unwrap();
+    client.rt().poll_scheduler();
+    let bytes: Bytes = client.rt().pop_frame();
+    assert_eq!(bytes.len() - ETHERNET2_HEADER_SIZE, 1000);
+    let bufsize: usize = check_segment(bytes.clone(), Wrapping(1));
+    assert_eq!(bufsize, 1000 - IPV4_HEADER_SIZE - MIN_TCP_HEADER_SIZE - 12);
+    server.receive(bytes).unwrap();
+
+    // Let the server acknowledge all of the data, the rest of it going out on
+    // retransmission.
+    for _ in 0..10 {
+        advance_clock(Some(&mut server), Some(&mut client), &mut now);
+        client.rt().poll_scheduler();
+        while let Some(bytes) = client.rt().pop_frame_unchecked() {
+            server.receive(bytes).unwrap();
+        }
+        server.rt().poll_scheduler();
+        while let Some(bytes) = server.rt().pop_frame_unchecked() {
+            client.receive(bytes).unwrap();
+        }
+    }
+
+    // Once the MSS has stayed reduced for long enough, full-sized segments go
+    // out again.
+    now += PMTU_RAISE_TIMEOUT;
+    server.rt().advance_clock(now);
+    client.rt().advance_clock(now);
+    client.rt().poll_scheduler();
+    let _ = client.tcp_push(client_fd, cook_buffer(1400, None));
+    client.rt().poll_scheduler();
+    let bytes: Bytes = client.rt().pop_frame();
+    assert_eq!(check_segment(bytes, Wrapping(1401)), 1400);
+}
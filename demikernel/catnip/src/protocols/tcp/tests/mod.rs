@@ -69,7 +69,8 @@ pub fn check_packet_pure_ack(
     let (tcp_header, tcp_payload) = TcpHeader::parse(&ipv4_header, ipv4_payload, false).unwrap();
     assert_eq!(tcp_payload.len(), 0);
     assert_eq!(tcp_header.window_size, window_size);
-    assert_eq!(tcp_header.seq_num, Wrapping(0));
+    // The side sending pure ACKs hasn't sent any data.
+    assert_eq!(tcp_header.seq_num, Wrapping(1));
     assert_eq!(tcp_header.ack, true);
     assert_eq!(tcp_header.ack_num, ack_num);
 }
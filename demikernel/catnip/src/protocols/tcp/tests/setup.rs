@@ -547,3 +547,110 @@ fn test_good_connect() {
         listen_addr,
     );
 }
+
+/// Tests 3-way connection setup with a listener bound to the wildcard address.
+#[test]
+fn test_good_connect_wildcard_listen() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: ip::Port = ip::Port::try_from(80).unwrap();
+    let listen_addr: ipv4::Endpoint = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+    let wildcard_addr: ipv4::Endpoint = ipv4::Endpoint::new(Ipv4Addr::UNSPECIFIED, listen_port);
+
+    // Setup peers.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+
+    // Server: LISTEN state on the wildcard address.
+    let mut accept_future = connection_setup_closed_listen(&mut server, wildcard_addr);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Client: SYN_SENT state.
+    let (_, mut connect_future, mut bytes) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Server: SYN_RCVD state. The SYN+ACK comes from the address the SYN was sent to.
+    bytes = connection_setup_listen_syn_rcvd(&mut server, bytes);
+    check_packet_syn_ack(
+        bytes.clone(),
+        test_helpers::BOB_MAC,
+        test_helpers::ALICE_MAC,
+        test_helpers::BOB_IPV4,
+        test_helpers::ALICE_IPV4,
+        listen_port,
+    );
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Client and server: ESTABLISHED state.
+    bytes = connection_setup_syn_sent_established(&mut client, bytes);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+    connection_setup_sync_rcvd_established(&mut server, bytes);
+
+    must_let!(let Poll::Ready(Ok(_)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut connect_future), &mut ctx));
+}
+
+/// Tests connection setup through a SYN cookie, with the data segment which follows the handshake
+/// completing it in place of the lost ACK.
+#[test]
+fn test_syn_cookie_connect() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Connection parameters
+    let listen_port: ip::Port = ip::Port::try_from(80).unwrap();
+    let listen_addr: ipv4::Endpoint = ipv4::Endpoint::new(test_helpers::BOB_IPV4, listen_port);
+
+    // Setup peers.
+    let mut server = test_helpers::new_bob2(now);
+    let mut client = test_helpers::new_alice2(now);
+    let tcp_options = server.rt().tcp_options();
+    server.rt().set_tcp_options(tcp_options.syn_cookies(true));
+
+    // Server: LISTEN state, with a backlog of one.
+    let listen_fd: FileDescriptor = server.tcp_socket();
+    server.tcp_bind(listen_fd, listen_addr).unwrap();
+    server.tcp_listen(listen_fd, 1).unwrap();
+    let mut accept_future: AcceptFuture<TestRuntime> = server.tcp_accept(listen_fd);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Fill the backlog with a handshake that never completes.
+    let (_, _, bytes) = connection_setup_listen_syn_sent(&mut client, listen_addr);
+    connection_setup_listen_syn_rcvd(&mut server, bytes);
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Server: answers the next SYN with a cookie.
+    let (client_fd, mut connect_future, mut bytes) =
+        connection_setup_listen_syn_sent(&mut client, listen_addr);
+    bytes = connection_setup_listen_syn_rcvd(&mut server, bytes);
+    let (_, _, syn_ack) = extract_headers(bytes.clone());
+    assert!(syn_ack.syn && syn_ack.ack);
+    assert_eq!(syn_ack.ack_num, Wrapping(1));
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Client: ESTABLISHED state. Its ACK gets lost, but the data segment it sends next also
+    // acknowledges the cookie.
+    let ack: Bytes = connection_setup_syn_sent_established(&mut client, bytes);
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut connect_future), &mut ctx));
+    let data: Bytes = Bytes::from_slice(&[1, 2, 3, 4]).unwrap();
+    let mut push_future = client.tcp_push(client_fd, data.clone());
+    must_let!(let Poll::Ready(Ok(())) = Future::poll(Pin::new(&mut push_future), &mut ctx));
+    bytes = client.rt().pop_frame();
+    advance_clock(Some(&mut server), Some(&mut client), &mut now);
+
+    // Server: ESTABLISHED state. The late ACK doesn't set up the connection a second time.
+    server.receive(bytes).unwrap();
+    server.receive(ack).unwrap();
+    server.rt().poll_scheduler();
+    must_let!(let Poll::Ready(Ok(server_fd)) = Future::poll(Pin::new(&mut accept_future), &mut ctx));
+    let mut accept_future: AcceptFuture<TestRuntime> = server.tcp_accept(listen_fd);
+    assert!(Future::poll(Pin::new(&mut accept_future), &mut ctx).is_pending());
+
+    // The data riding on the completing ACK is there to read.
+    let mut pop_future = server.tcp_pop(server_fd);
+    must_let!(let Poll::Ready(Ok(buf)) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(&buf[..], &data[..]);
+}
@@ -1,13 +1,73 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
-use crate::protocols::ipv4;
+use super::steal::{StealGroup, StolenRequest};
+use crate::{fail::Fail, protocols::ipv4};
 
-use std::{collections::VecDeque, task::Waker};
+use std::{cmp, collections::VecDeque, task::Waker};
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// What to drop when a datagram arrives at a full [Listener].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Drop the arriving datagram.
+    TailDrop,
+    /// Drop the oldest queued datagram.
+    HeadDrop,
+    /// Drop the newest datagram of the lowest priority class, which may be the arriving one.
+    /// The priority class of a datagram is its DSCP; higher values mean higher priority.
+    DropLowestClass,
+}
+
+/// Receive queue statistics of a [Listener].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Number of datagrams currently queued.
+    pub len: usize,
+    /// Largest number of datagrams ever queued at once.
+    pub high_watermark: usize,
+    /// Number of datagrams dropped because the queue was full.
+    pub dropped: u64,
+}
+
+/// Datagram queued in a [Listener].
+struct Entry<T> {
+    remote: Option<ipv4::Endpoint>,
+    data: T,
+    /// Priority class.
+    class: u8,
+    /// Core that received the datagram.
+    origin: u16,
+}
 
 pub struct Listener<T> {
-    buf: VecDeque<(Option<ipv4::Endpoint>, T)>,
+    buf: VecDeque<Entry<T>>,
     waker: Option<Waker>,
+    /// Maximum number of queued datagrams and what to drop past it, if bounded.
+    capacity: Option<(usize, QueuePolicy)>,
+    stats: QueueStats,
+    /// Work stealing state, if enabled.
+    steal: Option<ListenerSteal>,
+    /// Connected peer, if any. Datagrams from other endpoints are not accepted.
+    peer: Option<ipv4::Endpoint>,
+    /// Error reported by the network, to be returned by the next pop.
+    error: Option<Fail>,
+}
+
+/// Work stealing state of a [Listener].
+struct ListenerSteal {
+    group: StealGroup<Vec<u8>>,
+    /// Core this listener lives on.
+    core_id: u16,
+    /// Queue length past which new requests are offered to the steal group.
+    threshold: usize,
+    /// Core that received each popped request, until they get credited.
+    popped_origins: VecDeque<u16>,
+    /// Core that received each dropped request, until they get credited.
+    dropped_origins: Vec<u16>,
 }
 
 //==============================================================================
@@ -18,21 +78,179 @@ pub struct Listener<T> {
 impl<T> Listener<T> {
     /// Creates a new listener.
     pub fn new(buf: VecDeque<(Option<ipv4::Endpoint>, T)>, waker: Option<Waker>) -> Self {
-        Self { buf, waker }
+        let buf: VecDeque<Entry<T>> = buf
+            .into_iter()
+            .map(|(remote, data)| Entry {
+                remote,
+                data,
+                class: 0,
+                origin: 0,
+            })
+            .collect();
+        let stats = QueueStats {
+            len: buf.len(),
+            high_watermark: buf.len(),
+            dropped: 0,
+        };
+        Self {
+            buf,
+            waker,
+            capacity: None,
+            stats,
+            steal: None,
+            peer: None,
+            error: None,
+        }
+    }
+
+    /// Bounds the target listener to `capacity` datagrams, dropping according to `policy`.
+    /// Datagrams queued past the new capacity are dropped right away.
+    pub fn set_capacity(&mut self, capacity: usize, policy: QueuePolicy) {
+        assert!(capacity > 0);
+        self.capacity = Some((capacity, policy));
+        while self.buf.len() > capacity {
+            self.drop_one(policy, None);
+        }
+        self.stats.len = self.buf.len();
+    }
+
+    /// Returns the receive queue statistics of the target listener.
+    pub fn stats(&self) -> QueueStats {
+        self.stats
     }
+
+    /// Enables work stealing: once `threshold` requests are queued, new requests are offered to
+    /// the other cores of `group`.
+    pub fn enable_steal(&mut self, group: StealGroup<Vec<u8>>, core_id: u16, threshold: usize) {
+        for entry in self.buf.iter_mut() {
+            entry.origin = core_id;
+        }
+        self.steal = Some(ListenerSteal {
+            group,
+            core_id,
+            threshold,
+            popped_origins: VecDeque::new(),
+            dropped_origins: Vec::new(),
+        });
+    }
+
+    /// Returns the steal group of the target listener, if work stealing is enabled.
+    pub fn steal_group(&self) -> Option<(StealGroup<Vec<u8>>, u16)> {
+        self.steal.as_ref().map(|s| (s.group.clone(), s.core_id))
+    }
+
+    /// Returns the steal group new requests are to be offered to, if the target listener is
+    /// backed up past its threshold.
+    pub fn overflow_group(&self) -> Option<(&StealGroup<Vec<u8>>, u16)> {
+        match self.steal {
+            Some(ref steal) if self.buf.len() >= steal.threshold => {
+                Some((&steal.group, steal.core_id))
+            }
+            _ => None,
+        }
+    }
+
+    /// Restricts the target listener to datagrams sent by `peer`.
+    pub fn set_peer(&mut self, peer: Option<ipv4::Endpoint>) {
+        self.peer = peer;
+    }
+
+    /// Returns the connected peer of the target listener, if any.
+    pub fn peer(&self) -> Option<ipv4::Endpoint> {
+        self.peer
+    }
+
+    /// Returns `true` if the target listener accepts datagrams sent by `remote`.
+    pub fn accepts(&self, remote: Option<ipv4::Endpoint>) -> bool {
+        match self.peer {
+            Some(peer) => remote == Some(peer),
+            None => true,
+        }
+    }
+
     /// Pushes data to the target listener.
     pub fn len(&mut self)  -> usize{
         self.buf.len()
     }
 
-    /// Pushes data to the target listener.
-    pub fn push_data(&mut self, endpoint: Option<ipv4::Endpoint>, data: T) {
-        self.buf.push_back((endpoint, data));
+    /// Pushes data of priority `class` to the target listener. Returns `false` if the data was
+    /// dropped instead of being queued.
+    pub fn push_data(&mut self, endpoint: Option<ipv4::Endpoint>, data: T, class: u8) -> bool {
+        let origin = self.steal.as_ref().map(|s| s.core_id).unwrap_or(0);
+        self.enqueue(Entry {
+            remote: endpoint,
+            data,
+            class,
+            origin,
+        })
+    }
+
+    /// Pushes a request taken from the steal group to the target listener. Stolen requests never
+    /// make room for themselves, so the request is handed back if the target listener is full.
+    pub fn push_stolen(&mut self, request: StolenRequest<T>) -> Result<(), StolenRequest<T>> {
+        if let Some((capacity, _)) = self.capacity {
+            if self.buf.len() >= capacity {
+                return Err(request);
+            }
+        }
+        self.enqueue(Entry {
+            remote: request.remote,
+            data: request.buf,
+            class: request.class,
+            origin: request.origin,
+        });
+        Ok(())
     }
 
     /// Pops data from the target listener.
     pub fn pop_data(&mut self) -> Option<(Option<ipv4::Endpoint>, T)> {
-        self.buf.pop_front()
+        let entry = self.buf.pop_front()?;
+        self.stats.len = self.buf.len();
+        if let Some(ref mut steal) = self.steal {
+            steal.popped_origins.push_back(entry.origin);
+        }
+        Some((entry.remote, entry.data))
+    }
+
+    /// Takes the cores that received the next `count` popped requests. Returns nothing if work
+    /// stealing is disabled.
+    pub fn take_origins(&mut self, count: usize) -> Vec<u16> {
+        match self.steal {
+            Some(ref mut steal) => {
+                let count = cmp::min(count, steal.popped_origins.len());
+                steal.popped_origins.drain(..count).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Takes the cores that received the requests dropped so far. Returns nothing if work
+    /// stealing is disabled.
+    pub fn take_dropped_origins(&mut self) -> Vec<u16> {
+        match self.steal {
+            Some(ref mut steal) => steal.dropped_origins.split_off(0),
+            None => Vec::new(),
+        }
+    }
+
+    /// Records that a request received on core `origin` was dropped.
+    pub fn record_drop(&mut self, origin: u16) {
+        if let Some(ref mut steal) = self.steal {
+            steal.dropped_origins.push(origin);
+        }
+    }
+
+    /// Records an error reported by the network and wakes up the pending pop, if any.
+    pub fn set_error(&mut self, error: Fail) {
+        self.error = Some(error);
+        if let Some(w) = self.waker.take() {
+            w.wake()
+        }
+    }
+
+    /// Takes the error reported by the network, if any.
+    pub fn take_error(&mut self) -> Option<Fail> {
+        self.error.take()
     }
 
     /// Takes the waker of the target listener.
@@ -44,6 +262,57 @@ impl<T> Listener<T> {
     pub fn put_waker(&mut self, waker: Option<Waker>) {
         self.waker = waker;
     }
+
+    /// Queues an entry, making room for it first if the target listener is full. Returns `false`
+    /// if the entry itself was dropped.
+    fn enqueue(&mut self, entry: Entry<T>) -> bool {
+        let queued = match self.capacity {
+            Some((capacity, policy)) if self.buf.len() >= capacity => {
+                if self.drop_one(policy, Some(entry.class)) {
+                    self.buf.push_back(entry);
+                    true
+                } else {
+                    self.stats.dropped += 1;
+                    self.record_drop(entry.origin);
+                    false
+                }
+            }
+            _ => {
+                self.buf.push_back(entry);
+                true
+            }
+        };
+        self.stats.len = self.buf.len();
+        self.stats.high_watermark = cmp::max(self.stats.high_watermark, self.buf.len());
+        queued
+    }
+
+    /// Drops a queued entry according to `policy`, to make room for an arriving entry of priority
+    /// `class`, if any. Returns `false` if the arriving entry should be dropped instead.
+    fn drop_one(&mut self, policy: QueuePolicy, class: Option<u8>) -> bool {
+        let victim = match policy {
+            QueuePolicy::TailDrop if class.is_some() => None,
+            QueuePolicy::TailDrop => Some(self.buf.len() - 1),
+            QueuePolicy::HeadDrop => Some(0),
+            QueuePolicy::DropLowestClass => {
+                // Newest entry among those of the lowest class.
+                let lowest = self.buf.iter().map(|e| e.class).min();
+                match (lowest, class) {
+                    (Some(lowest), Some(class)) if class <= lowest => None,
+                    (Some(lowest), _) => self.buf.iter().rposition(|e| e.class == lowest),
+                    (None, _) => None,
+                }
+            }
+        };
+        match victim.and_then(|ix| self.buf.remove(ix)) {
+            Some(entry) => {
+                self.stats.dropped += 1;
+                self.record_drop(entry.origin);
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 //==============================================================================
@@ -57,6 +326,97 @@ impl<T> Default for Listener<T> {
         Self {
             buf: VecDeque::new(),
             waker: None,
+            capacity: None,
+            stats: QueueStats::default(),
+            steal: None,
+            peer: None,
+            error: None,
         }
     }
 }
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::steal::{StealGroup, StolenRequest},
+        Listener, QueuePolicy,
+    };
+
+    fn drain(listener: &mut Listener<u32>) -> Vec<u32> {
+        let mut out = vec![];
+        while let Some((_, data)) = listener.pop_data() {
+            out.push(data);
+        }
+        out
+    }
+
+    #[test]
+    fn tail_drop() {
+        let mut listener = Listener::default();
+        listener.set_capacity(2, QueuePolicy::TailDrop);
+        assert!(listener.push_data(None, 1, 0));
+        assert!(listener.push_data(None, 2, 0));
+        assert!(!listener.push_data(None, 3, 0));
+        let stats = listener.stats();
+        assert_eq!((stats.len, stats.high_watermark, stats.dropped), (2, 2, 1));
+        assert_eq!(drain(&mut listener), [1, 2]);
+        assert_eq!(listener.stats().len, 0);
+    }
+
+    #[test]
+    fn head_drop() {
+        let mut listener = Listener::default();
+        listener.set_capacity(2, QueuePolicy::HeadDrop);
+        assert!(listener.push_data(None, 1, 0));
+        assert!(listener.push_data(None, 2, 0));
+        assert!(listener.push_data(None, 3, 0));
+        assert_eq!(listener.stats().dropped, 1);
+        assert_eq!(drain(&mut listener), [2, 3]);
+    }
+
+    #[test]
+    fn drop_lowest_class() {
+        let mut listener = Listener::default();
+        listener.set_capacity(3, QueuePolicy::DropLowestClass);
+        assert!(listener.push_data(None, 1, 10));
+        assert!(listener.push_data(None, 2, 0));
+        assert!(listener.push_data(None, 3, 0));
+
+        // The newest datagram of class 0 makes room for class 46.
+        assert!(listener.push_data(None, 4, 46));
+        // Nothing queued is of a lower class than an arriving class 0 datagram.
+        assert!(!listener.push_data(None, 5, 0));
+
+        assert_eq!(listener.stats().dropped, 2);
+        assert_eq!(drain(&mut listener), [1, 2, 4]);
+    }
+
+    #[test]
+    fn stolen_requests() {
+        let mut listener = Listener::default();
+        listener.enable_steal(StealGroup::new(2, 1), 0, 8);
+        listener.set_capacity(2, QueuePolicy::DropLowestClass);
+        let stolen = |buf, class| StolenRequest {
+            origin: 1,
+            remote: None,
+            buf,
+            class,
+        };
+        assert!(listener.push_stolen(stolen(1, 10)).is_ok());
+        assert!(listener.push_data(None, 2, 0));
+
+        // A full listener hands stolen requests back.
+        assert_eq!(listener.push_stolen(stolen(3, 46)).unwrap_err().buf, 3);
+        assert_eq!(listener.stats().dropped, 0);
+
+        // The stolen request kept its class, so the local one makes room for class 46.
+        assert!(listener.push_data(None, 4, 46));
+        assert_eq!(listener.take_dropped_origins(), [0]);
+        assert_eq!(drain(&mut listener), [1, 4]);
+        assert_eq!(listener.take_origins(2), [1, 0]);
+    }
+}
@@ -6,13 +6,19 @@ mod listener;
 mod operations;
 mod options;
 pub mod peer;
+mod reuseport;
 mod socket;
+mod steal;
 
 #[cfg(test)]
 mod tests;
 
 pub use datagram::UdpHeader;
+pub use listener::{QueuePolicy, QueueStats};
+pub use operations::PopBatchFuture as UdpPopBatchFuture;
 pub use operations::PopFuture as UdpPopFuture;
 pub use operations::UdpOperation;
 pub use options::UdpOptions as Options;
 pub use peer::UdpPeer as Peer;
+pub use reuseport::{ReusePortClassifier, ReusePortPolicy};
+pub use steal::StealGroup;
@@ -13,10 +13,12 @@ use crate::{operations::OperationResult, protocols::ipv4};
 use std::collections::VecDeque;
 use std::{
     cell::RefCell,
+    cmp::min,
     future::Future,
     pin::Pin,
     rc::Rc,
     task::{Context, Poll},
+    time::Duration,
 };
 
 //==============================================================================
@@ -32,11 +34,19 @@ pub struct PopFuture<RT: Runtime> {
     listener: Result<Rc<RefCell<Listener<RT::Buf>>>, Fail>,
 }
 
+/// Future for PopBatch Operation
 pub struct PopBatchFuture<RT: Runtime> {
+    rt: RT,
     /// File descriptor.
     fd: FileDescriptor,
     /// Listener.
     listener: Result<Rc<RefCell<Listener<RT::Buf>>>, Fail>,
+    /// Maximum number of datagrams returned.
+    max_batch: usize,
+    /// Maximum time spent waiting for a batch to fill up.
+    max_wait: Duration,
+    /// Fires once `max_wait` has elapsed since the first datagram became available.
+    timeout: Option<Pin<Box<RT::WaitFuture>>>,
 }
 
 /// Operations on UDP Layer
@@ -96,11 +106,26 @@ impl<RT: Runtime> PopFuture<RT> {
     }
 }
 
-/// Associate functions for [PopFuture].
+/// Associate functions for [PopBatchFuture].
 impl<RT: Runtime> PopBatchFuture<RT> {
-    /// Creates a future for the pop operation.
-    pub fn new(fd: FileDescriptor, listener: Result<Rc<RefCell<Listener<RT::Buf>>>, Fail>) -> Self {
-        Self { fd, listener }
+    /// Creates a future for the pop batch operation. The future completes with at most
+    /// `max_batch` datagrams, once that many are available or `max_wait` has elapsed since the
+    /// first one was.
+    pub fn new(
+        rt: RT,
+        fd: FileDescriptor,
+        listener: Result<Rc<RefCell<Listener<RT::Buf>>>, Fail>,
+        max_batch: usize,
+        max_wait: Duration,
+    ) -> Self {
+        Self {
+            rt,
+            fd,
+            listener,
+            max_batch,
+            max_wait,
+            timeout: None,
+        }
     }
 }
 
@@ -118,6 +143,9 @@ impl<RT: Runtime> Future for PopFuture<RT> {
             Err(ref e) => Poll::Ready(Err(e.clone())),
             Ok(ref l) => {
                 let mut listener = l.borrow_mut();
+                if let Some(e) = listener.take_error() {
+                    return Poll::Ready(Err(e));
+                }
                 if let Some(r) = listener.pop_data() {
                     return Poll::Ready(Ok(r));
                 }
@@ -130,7 +158,7 @@ impl<RT: Runtime> Future for PopFuture<RT> {
 }
 
 
-/// Future trait implementation for [PopFuture].
+/// Future trait implementation for [PopBatchFuture].
 impl<RT: Runtime> Future for PopBatchFuture<RT> {
     type Output = Result<Vec<(Option<ipv4::Endpoint>, RT::Buf)>, Fail>;
 
@@ -140,13 +168,29 @@ impl<RT: Runtime> Future for PopBatchFuture<RT> {
             Err(ref e) => Poll::Ready(Err(e.clone())),
             Ok(ref l) => {
                 let mut listener = l.borrow_mut();
+                if let Some(e) = listener.take_error() {
+                    return Poll::Ready(Err(e));
+                }
                 let l_len = listener.len();
-                let mut bufs: Vec<(Option<Ipv4Endpoint>, <RT as Runtime>::Buf)> = Vec::with_capacity(l_len);
-                if l_len > 0{
-                    for i in 0 .. l_len{
+                if l_len > 0 && l_len < self_.max_batch && self_.max_wait > Duration::ZERO {
+                    // Wait for the batch to fill up, but no longer than `max_wait`.
+                    let rt = &self_.rt;
+                    let max_wait = self_.max_wait;
+                    let timeout = self_
+                        .timeout
+                        .get_or_insert_with(|| Box::pin(rt.wait(max_wait)));
+                    if Future::poll(timeout.as_mut(), ctx).is_pending() {
+                        listener.put_waker(Some(ctx.waker().clone()));
+                        return Poll::Pending;
+                    }
+                }
+                if l_len > 0 {
+                    let batch_len = min(l_len, self_.max_batch);
+                    let mut bufs: Vec<(Option<Ipv4Endpoint>, <RT as Runtime>::Buf)> = Vec::with_capacity(batch_len);
+                    for _ in 0..batch_len {
                         if let Some(r) = listener.pop_data() {
                             bufs.push(r);
-                        }   
+                        }
                     }
                     return Poll::Ready(Ok(bufs));
                 }
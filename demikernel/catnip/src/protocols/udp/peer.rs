@@ -3,9 +3,11 @@
 
 use super::{
     datagram::{UdpDatagram, UdpHeader},
-    listener::Listener,
+    listener::{Listener, QueuePolicy, QueueStats},
     operations::{PopFuture, PopBatchFuture},
+    reuseport::{ReusePortGroup, ReusePortPolicy},
     socket::Socket,
+    steal::{StealGroup, StolenRequest},
 };
 
 use crate::{
@@ -14,14 +16,16 @@ use crate::{
     protocols::{
         arp,
         ethernet2::frame::{EtherType2, Ethernet2Header},
+        igmp,
+        ip::port::EphemeralPorts,
         ipv4,
         ipv4::datagram::{Ipv4Header, Ipv4Protocol2}, udp::datagram::UdpBatchDatagram,
     },
-    runtime::Runtime,
+    runtime::{Runtime, RuntimeBuf},
     scheduler::SchedulerHandle,
 };
 use futures::{channel::mpsc, stream::StreamExt};
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, net::Ipv4Addr, rc::Rc, time::Duration};
 
 #[cfg(feature="profiler")]
 use perftools::timer;
@@ -44,16 +48,21 @@ type OutgoingReceiver<T> = mpsc::UnboundedReceiver<OutgoingReq<T>>;
 struct UdpPeerInner<RT: Runtime> {
     rt: RT,
     arp: arp::Peer<RT>,
+    igmp: igmp::Peer<RT>,
     file_table: FileTable,
+    ephemeral_ports: EphemeralPorts,
 
     sockets: HashMap<FileDescriptor, Socket>,
-    bound: HashMap<ipv4::Endpoint, Rc<RefCell<Listener<RT::Buf>>>>,
+    /// Listener of each bound socket.
+    listeners: HashMap<FileDescriptor, Rc<RefCell<Listener<RT::Buf>>>>,
+    bound: HashMap<ipv4::Endpoint, ReusePortGroup<RT::Buf>>,
 
     outgoing: OutgoingSender<RT::Buf>,
     #[allow(unused)]
     handle: SchedulerHandle,
 }
 
+#[derive(Clone)]
 pub struct UdpPeer<RT: Runtime> {
     inner: Rc<RefCell<UdpPeerInner<RT>>>,
 }
@@ -68,6 +77,7 @@ impl<RT: Runtime> UdpPeerInner<RT> {
     fn new(
         rt: RT,
         arp: arp::Peer<RT>,
+        igmp: igmp::Peer<RT>,
         file_table: FileTable,
         tx: OutgoingSender<RT::Buf>,
         handle: SchedulerHandle,
@@ -75,14 +85,136 @@ impl<RT: Runtime> UdpPeerInner<RT> {
         Self {
             rt,
             arp,
+            igmp,
             file_table,
+            ephemeral_ports: EphemeralPorts::new(&rt),
             sockets: HashMap::new(),
+            listeners: HashMap::new(),
             bound: HashMap::new(),
             outgoing: tx,
             handle,
         }
     }
 
+    /// Returns the sockets bound to receive datagrams sent to `local`. Sockets bound to `local`
+    /// itself take precedence over those bound to the wildcard address.
+    fn lookup(&mut self, local: &ipv4::Endpoint) -> Option<&mut ReusePortGroup<RT::Buf>> {
+        let key = if self.bound.contains_key(local) {
+            *local
+        } else {
+            ipv4::Endpoint::new(Ipv4Addr::UNSPECIFIED, local.port)
+        };
+        self.bound.get_mut(&key)
+    }
+
+    /// Hands a broadcast or multicast datagram to every socket bound to receive it, both those
+    /// bound to `local` itself and those bound to the wildcard address. Multicasts only go to the
+    /// sockets that joined the group.
+    fn deliver_all(
+        &self,
+        local: &ipv4::Endpoint,
+        remote: Option<ipv4::Endpoint>,
+        data: RT::Buf,
+        class: u8,
+    ) -> Result<(), Fail> {
+        let wildcard = ipv4::Endpoint::new(Ipv4Addr::UNSPECIFIED, local.port);
+        let mut targets = Vec::new();
+        for key in [*local, wildcard].iter() {
+            let group = match self.bound.get(key) {
+                Some(group) => group,
+                None => continue,
+            };
+            for (fd, listener) in group.members() {
+                let joined = match self.sockets.get(&fd) {
+                    Some(s) => s.groups().contains(&local.addr),
+                    None => false,
+                };
+                if (local.addr.is_multicast() && !joined) || !listener.borrow().accepts(remote) {
+                    continue;
+                }
+                targets.push(listener);
+            }
+        }
+        // The last socket takes the datagram itself, the others get a copy.
+        let last = targets.pop().ok_or(Fail::Ignored {
+            details: "No socket for broadcast or multicast datagram",
+        })?;
+        for listener in targets {
+            Self::push_to(&mut listener.borrow_mut(), remote, data.clone(), class);
+        }
+        Self::push_to(&mut last.borrow_mut(), remote, data, class);
+        Ok(())
+    }
+
+    /// Queues a datagram on a listener and wakes up its receiver. A listener backed up past its
+    /// work stealing threshold offers a copy of the datagram to its steal group instead.
+    fn push_to(
+        l: &mut Listener<RT::Buf>,
+        remote: Option<ipv4::Endpoint>,
+        data: RT::Buf,
+        class: u8,
+    ) {
+        if let Some((group, core_id)) = l.overflow_group() {
+            if group
+                .offer(core_id, remote, data[..].to_vec(), class)
+                .is_ok()
+            {
+                return;
+            }
+        }
+        if l.push_data(remote, data, class) {
+            if let Some(w) = l.take_waker() {
+                w.wake()
+            }
+        }
+    }
+
+    /// Creates the listener of a socket and adds the socket to the group bound to `local`.
+    fn register(
+        &mut self,
+        fd: FileDescriptor,
+        local: ipv4::Endpoint,
+        reuse_port: Option<ReusePortPolicy>,
+    ) {
+        let listener = Rc::new(RefCell::new(Listener::default()));
+        self.listeners.insert(fd, listener.clone());
+        match self.bound.get_mut(&local) {
+            Some(group) => group.join(fd, listener),
+            None => {
+                self.bound
+                    .insert(local, ReusePortGroup::new(fd, listener, reuse_port));
+            }
+        }
+    }
+
+    /// Binds a socket to an ephemeral port of the local address.
+    fn bind_ephemeral(&mut self, fd: FileDescriptor) -> Result<ipv4::Endpoint, Fail> {
+        // Skip ports that were bound explicitly, and hand them back afterwards.
+        let mut skipped = Vec::new();
+        let local = loop {
+            match self.ephemeral_ports.alloc() {
+                Ok(port) => {
+                    let local = ipv4::Endpoint::new(self.rt.local_ipv4_addr(), port);
+                    if self.lookup(&local).is_none() {
+                        break Ok(local);
+                    }
+                    skipped.push(port);
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        for port in skipped {
+            self.ephemeral_ports.free(port);
+        }
+        let local = local?;
+
+        let socket = self.sockets.get_mut(&fd).unwrap();
+        socket.set_local(Some(local));
+        socket.set_ephemeral(true);
+        self.register(fd, local, None);
+        Ok(local)
+    }
+
     /// Sends a UDP packet.
     fn send_datagram(
         &self,
@@ -156,11 +288,16 @@ impl<RT: Runtime> UdpPeerInner<RT> {
 /// Associate functions for [UdpPeer].
 impl<RT: Runtime> UdpPeer<RT> {
     /// Creates a Udp peer.
-    pub fn new(rt: RT, arp: arp::Peer<RT>, file_table: FileTable) -> Self {
+    pub fn new(
+        rt: RT,
+        arp: arp::Peer<RT>,
+        igmp: igmp::Peer<RT>,
+        file_table: FileTable,
+    ) -> Self {
         let (tx, rx) = mpsc::unbounded();
         let future = Self::background(rt.clone(), arp.clone(), rx);
         let handle = rt.spawn(future);
-        let inner = UdpPeerInner::new(rt, arp, file_table, tx, handle);
+        let inner = UdpPeerInner::new(rt, arp, igmp, file_table, tx, handle);
         Self {
             inner: Rc::new(RefCell::new(inner)),
         }
@@ -222,11 +359,15 @@ impl<RT: Runtime> UdpPeer<RT> {
         timer!("udp::bind");
 
         let mut inner = self.inner.borrow_mut();
-        // Endpoint in use.
-        if inner.bound.contains_key(&addr) {
-            return Err(Fail::Malformed {
-                details: "Port already listening",
-            });
+        let reuse_port = inner.sockets.get(&fd).and_then(|s| s.reuse_port());
+
+        // Endpoint in use, unless both sides asked for reuse-port.
+        if let Some(group) = inner.bound.get(&addr) {
+            if !group.reuse() || reuse_port.is_none() {
+                return Err(Fail::Malformed {
+                    details: "Port already listening",
+                });
+            }
         }
 
         // Update file descriptor with local endpoint.
@@ -238,27 +379,86 @@ impl<RT: Runtime> UdpPeer<RT> {
         }
 
         // Register listener.
-        let listener = Listener::default();
-        if inner
-            .bound
-                .insert(addr, Rc::new(RefCell::new(listener)))
-                .is_some()
-        {
-            return Err(Fail::AddressInUse {});
+        inner.register(fd, addr, reuse_port);
+
+        Ok(())
+    }
+
+    /// Lets a socket share its local endpoint with other sockets that also enable reuse-port.
+    /// This must be done before binding the socket. Datagrams are distributed over the sockets
+    /// sharing an endpoint according to the policy of the first one bound.
+    pub fn set_reuse_port(&self, fd: FileDescriptor, policy: ReusePortPolicy) -> Result<(), Fail> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.sockets.get_mut(&fd) {
+            Some(s) if s.local().is_none() => {
+                s.set_reuse_port(Some(policy));
+                Ok(())
+            }
+            Some(_) => Err(Fail::Invalid {
+                details: "Socket already bound",
+            }),
+            None => Err(Fail::BadFileDescriptor {}),
+        }
+    }
+
+    /// Makes a socket receive datagrams sent to multicast `group`, on the port it is bound to.
+    /// The socket should be bound to either the wildcard address or the group address.
+    pub fn join_multicast(&self, fd: FileDescriptor, group: Ipv4Addr) -> Result<(), Fail> {
+        if !group.is_multicast() {
+            return Err(Fail::Invalid {
+                details: "Not a multicast group",
+            });
+        }
+        let mut inner = self.inner.borrow_mut();
+        match inner.sockets.get_mut(&fd) {
+            Some(s) if s.join_group(group) => (),
+            Some(_) => {
+                return Err(Fail::Invalid {
+                    details: "Group already joined",
+                })
+            }
+            None => return Err(Fail::BadFileDescriptor {}),
         }
+        inner.igmp.join(group);
+        Ok(())
+    }
 
+    /// Stops a socket from receiving datagrams sent to multicast `group`.
+    pub fn leave_multicast(&self, fd: FileDescriptor, group: Ipv4Addr) -> Result<(), Fail> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.sockets.get_mut(&fd) {
+            Some(s) if s.leave_group(group) => (),
+            Some(_) => {
+                return Err(Fail::Invalid {
+                    details: "Group not joined",
+                })
+            }
+            None => return Err(Fail::BadFileDescriptor {}),
+        }
+        inner.igmp.leave(group);
         Ok(())
     }
 
-    ///
-    /// Dummy accept operation.
-    ///
-    /// - TODO: we should drop this function because it is meaningless for UDP.
-    ///
-    pub fn connect(&self, _fd: FileDescriptor, _addr: ipv4::Endpoint) -> Result<(), Fail> {
-        Err(Fail::Malformed {
-            details: "Operation not supported",
-        })
+    /// Connects a socket to a remote endpoint. Afterwards, data pushed to the socket goes to
+    /// `addr` and only datagrams sent by `addr` are received. Unbound sockets are bound to an
+    /// ephemeral port first.
+    pub fn connect(&self, fd: FileDescriptor, addr: ipv4::Endpoint) -> Result<(), Fail> {
+        #[cfg(feature="profiler")]
+        timer!("udp::connect");
+
+        let mut inner = self.inner.borrow_mut();
+        let local = match inner.sockets.get(&fd) {
+            Some(s) => s.local(),
+            None => return Err(Fail::BadFileDescriptor {}),
+        };
+        if local.is_none() {
+            inner.bind_ephemeral(fd)?;
+        }
+
+        inner.sockets.get_mut(&fd).unwrap().set_remote(Some(addr));
+        inner.listeners[&fd].borrow_mut().set_peer(Some(addr));
+
+        Ok(())
     }
 
     /// Closes a socket.
@@ -273,10 +473,24 @@ impl<RT: Runtime> UdpPeer<RT> {
             None => {return Err(Fail::BadFileDescriptor {})}
         };
 
+        // Leave multicast groups.
+        for &group in socket.groups() {
+            inner.igmp.leave(group);
+        }
+
         // Remove endpoint biding.
         if let Some(local) = socket.local() {
-            if inner.bound.remove(&local).is_none() {
-                return Err(Fail::BadFileDescriptor {});
+            inner.listeners.remove(&fd);
+            match inner.bound.get_mut(&local) {
+                Some(group) => {
+                    if group.leave(fd) {
+                        inner.bound.remove(&local);
+                    }
+                }
+                None => return Err(Fail::BadFileDescriptor {}),
+            }
+            if socket.is_ephemeral() {
+                inner.ephemeral_ports.free(local.port);
             }
         }
 
@@ -299,22 +513,53 @@ impl<RT: Runtime> UdpPeer<RT> {
             .src_port()
             .map(|p| ipv4::Endpoint::new(ipv4_header.src_addr, p));
 
-        // TODO: Send ICMPv4 error in this condition.
-        let listener = inner.bound.get_mut(&local).ok_or(Fail::Malformed {
-            details: "Port not bound",
-        })?;
+        // Broadcasts and multicasts go to every socket listening on the port.
+        if ipv4_header.dst_addr != inner.rt.local_ipv4_addr() {
+            return inner.deliver_all(&local, remote, data, ipv4_header.dscp);
+        }
+
+        // The caller answers with an ICMPv4 Port Unreachable message.
+        let group = inner.lookup(&local).ok_or(Fail::PortUnreachable {})?;
+        let listener = group
+            .select(remote, local, &data[..])
+            .ok_or(Fail::Ignored {
+                details: "Datagram from unconnected peer",
+            })?;
 
         // Consume data and wakeup receiver.
         let mut l = listener.borrow_mut();
         // println!("enter Listener {:?}", local);
-        l.push_data(remote, data);
-        if let Some(w) = l.take_waker() {
-            w.wake()
-        }
+        UdpPeerInner::<RT>::push_to(&mut l, remote, data, ipv4_header.dscp);
 
         Ok(())
     }
 
+    /// Fails the pending operations of the socket that sent a datagram from `local` to an
+    /// unreachable `remote` port.
+    pub fn port_unreachable(&self, local: ipv4::Endpoint, remote: ipv4::Endpoint) -> Result<(), Fail> {
+        let mut inner = self.inner.borrow_mut();
+        let group = inner.lookup(&local).ok_or(Fail::Ignored {
+            details: "ICMPv4 error for unbound port",
+        })?;
+        // Connected sockets only hear about their own peer. Unconnected ones only hear about it
+        // when they do not share their endpoint, as the sender cannot be told apart otherwise.
+        let alone = group.num_members() == 1;
+        let mut reported = false;
+        for listener in group.listeners() {
+            let mut l = listener.borrow_mut();
+            if l.peer() == Some(remote) || (alone && l.peer().is_none()) {
+                l.set_error(Fail::ConnectionRefused {});
+                reported = true;
+            }
+        }
+        if !reported {
+            return Err(Fail::Ignored {
+                details: "ICMPv4 error for unconnected peer",
+            });
+        }
+        Ok(())
+    }
+
     /// Pushes data to a socket.
     pub fn push(&self, fd: FileDescriptor, buf: RT::Buf) -> Result<(), Fail> {
         #[cfg(feature="profiler")]
@@ -325,8 +570,9 @@ impl<RT: Runtime> UdpPeer<RT> {
             Some(s) if s.local().is_some() && s.remote().is_some() => {
                 inner.send_datagram(buf, s.local(), s.remote().unwrap())
             }
-            Some(s) if s.local().is_some() => Err(Fail::BadFileDescriptor {}),
-            Some(s) if s.remote().is_some() => Err(Fail::BadFileDescriptor {}),
+            Some(_) => Err(Fail::Invalid {
+                details: "Socket not connected",
+            }),
             _ => Err(Fail::Malformed {
                 details: "Invalid file descriptor",
             }),
@@ -357,39 +603,150 @@ impl<RT: Runtime> UdpPeer<RT> {
         inner.send_batchdatagram(batch, local)
     }
 
+    /// Returns the listener of a bound socket.
+    fn listener(&self, fd: FileDescriptor) -> Result<Rc<RefCell<Listener<RT::Buf>>>, Fail> {
+        let inner = self.inner.borrow();
+        inner
+            .listeners
+            .get(&fd)
+            .cloned()
+            .ok_or(Fail::BadFileDescriptor {})
+    }
+
+    /// Enables work stealing on a bound socket living on core `core_id`.
+    pub fn enable_steal(
+        &self,
+        fd: FileDescriptor,
+        group: StealGroup<Vec<u8>>,
+        core_id: u16,
+        threshold: usize,
+    ) -> Result<(), Fail> {
+        if core_id as usize >= group.num_cores() {
+            return Err(Fail::Invalid {
+                details: "core not in steal group",
+            });
+        }
+        self.listener(fd)?
+            .borrow_mut()
+            .enable_steal(group, core_id, threshold);
+        Ok(())
+    }
+
+    /// Moves up to `max` requests from the steal group of a socket to its listener. Returns the
+    /// number of requests moved.
+    pub fn steal(&self, fd: FileDescriptor, max: usize) -> Result<usize, Fail> {
+        let listener = self.listener(fd)?;
+        let mut l = listener.borrow_mut();
+        let (group, core_id) = match l.steal_group() {
+            Some(g) => g,
+            None => return Ok(0),
+        };
+        let mut count = 0;
+        while count < max {
+            let request = match group.steal(core_id) {
+                Some(request) => request,
+                None => break,
+            };
+            // Stolen requests carry a copy of their payload, see [StealGroup]. Without a buffer to
+            // copy it into, the request is better off on the core that received it.
+            let pushed = match RT::Buf::from_slice(&request.buf) {
+                Ok(buf) => {
+                    let stolen = StolenRequest {
+                        origin: request.origin,
+                        remote: request.remote,
+                        buf,
+                        class: request.class,
+                    };
+                    l.push_stolen(stolen).is_ok()
+                }
+                Err(_) => false,
+            };
+            if !pushed {
+                // Hand the request back to the core that received it.
+                let origin = request.origin;
+                if group
+                    .offer(origin, request.remote, request.buf, request.class)
+                    .is_err()
+                {
+                    l.record_drop(origin);
+                }
+                break;
+            }
+            count += 1;
+        }
+        if count > 0 {
+            if let Some(w) = l.take_waker() {
+                w.wake()
+            }
+        }
+        Ok(count)
+    }
+
+    /// Takes the cores that received the next `count` requests popped from a socket.
+    pub fn take_origins(&self, fd: FileDescriptor, count: usize) -> Result<Vec<u16>, Fail> {
+        Ok(self.listener(fd)?.borrow_mut().take_origins(count))
+    }
+
+    /// Takes the cores that received the requests dropped by a socket so far.
+    pub fn take_dropped_origins(&self, fd: FileDescriptor) -> Result<Vec<u16>, Fail> {
+        Ok(self.listener(fd)?.borrow_mut().take_dropped_origins())
+    }
+
+    /// Bounds the receive queue of a socket to `capacity` datagrams.
+    pub fn set_queue_capacity(
+        &self,
+        fd: FileDescriptor,
+        capacity: usize,
+        policy: QueuePolicy,
+    ) -> Result<(), Fail> {
+        if capacity == 0 {
+            return Err(Fail::Invalid {
+                details: "zero queue capacity",
+            });
+        }
+        self.listener(fd)?.borrow_mut().set_capacity(capacity, policy);
+        Ok(())
+    }
+
+    /// Returns the receive queue statistics of a socket.
+    pub fn queue_stats(&self, fd: FileDescriptor) -> Result<QueueStats, Fail> {
+        Ok(self.listener(fd)?.borrow().stats())
+    }
+
+    /// Returns the number of datagrams waiting in the receive queue of a socket.
+    pub fn queue_len(&self, fd: FileDescriptor) -> Result<usize, Fail> {
+        Ok(self.listener(fd)?.borrow_mut().len())
+    }
+
     /// Pops data from a socket.
     pub fn pop(&self, fd: FileDescriptor) -> PopFuture<RT> {
         #[cfg(feature="profiler")]
         timer!("udp::pop");
 
         let inner = self.inner.borrow();
-        let listener = match inner.sockets.get(&fd) {
-            Some(s) if s.local().is_some() => {
-                Ok(inner.bound.get(&s.local().unwrap()).unwrap().clone())
-            }
-            _ => Err(Fail::Malformed {
-                details: "Invalid file descriptor",
-            }),
-        };
+        let listener = inner.listeners.get(&fd).cloned().ok_or(Fail::Malformed {
+            details: "Invalid file descriptor",
+        });
 
         PopFuture::new(fd, listener)
     }
 
-    /// Pops data from a socket.
-    pub fn popbatch(&self, fd: FileDescriptor) -> PopBatchFuture<RT> {
+    /// Pops up to `max_batch` datagrams from a socket, waiting up to `max_wait` for the batch to
+    /// fill up.
+    pub fn popbatch(
+        &self,
+        fd: FileDescriptor,
+        max_batch: usize,
+        max_wait: Duration,
+    ) -> PopBatchFuture<RT> {
         #[cfg(feature="profiler")]
         timer!("udp::pop");
 
         let inner = self.inner.borrow();
-        let listener = match inner.sockets.get(&fd) {
-            Some(s) if s.local().is_some() => {
-                Ok(inner.bound.get(&s.local().unwrap()).unwrap().clone())
-            }
-            _ => Err(Fail::Malformed {
-                details: "Invalid file descriptor",
-            }),
-        };
+        let listener = inner.listeners.get(&fd).cloned().ok_or(Fail::Malformed {
+            details: "Invalid file descriptor",
+        });
 
-        PopBatchFuture::new(fd, listener)
+        PopBatchFuture::new(inner.rt.clone(), fd, listener, max_batch, max_wait)
     }
 }
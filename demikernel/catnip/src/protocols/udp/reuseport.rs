@@ -0,0 +1,243 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Groups of sockets sharing a local endpoint, in the spirit of `SO_REUSEPORT`.
+//!
+//! Every bound endpoint maps to a [ReusePortGroup]. Sockets bound without reuse-port form a group
+//! of their own that nobody else may join. Sockets bound with reuse-port join the group of the
+//! endpoint, and every incoming datagram is handed to one member picked by the
+//! [ReusePortPolicy] of the group.
+
+use super::listener::Listener;
+use crate::{file_table::FileDescriptor, protocols::ipv4};
+use std::{
+    cell::RefCell,
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Application-provided classifier. It is given the source endpoint, the destination endpoint and
+/// the payload of a datagram, and returns the index of the member that should receive it. The
+/// index is taken modulo the size of the group.
+pub type ReusePortClassifier = Rc<dyn Fn(Option<ipv4::Endpoint>, ipv4::Endpoint, &[u8]) -> usize>;
+
+/// How incoming datagrams are distributed over the members of a [ReusePortGroup].
+#[derive(Clone)]
+pub enum ReusePortPolicy {
+    /// Hash of the source and destination endpoints, so that each flow sticks to one member.
+    FlowHash,
+    /// Members take turns.
+    RoundRobin,
+    /// Application-provided classifier.
+    Classifier(ReusePortClassifier),
+}
+
+/// Sockets bound to the same local endpoint.
+pub struct ReusePortGroup<T> {
+    /// Whether other sockets may join the group.
+    reuse: bool,
+    policy: ReusePortPolicy,
+    /// Members, in the order they joined.
+    members: Vec<(FileDescriptor, Rc<RefCell<Listener<T>>>)>,
+    /// Next member in round-robin order.
+    next: usize,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [ReusePortGroup].
+impl<T> ReusePortGroup<T> {
+    /// Creates a group with a single member. If `policy` is set, other sockets bound with
+    /// reuse-port may join the group later on.
+    pub fn new(
+        fd: FileDescriptor,
+        listener: Rc<RefCell<Listener<T>>>,
+        policy: Option<ReusePortPolicy>,
+    ) -> Self {
+        Self {
+            reuse: policy.is_some(),
+            policy: policy.unwrap_or(ReusePortPolicy::FlowHash),
+            members: vec![(fd, listener)],
+            next: 0,
+        }
+    }
+
+    /// Returns `true` if other sockets may join the target group.
+    pub fn reuse(&self) -> bool {
+        self.reuse
+    }
+
+    /// Adds a member to the target group.
+    pub fn join(&mut self, fd: FileDescriptor, listener: Rc<RefCell<Listener<T>>>) {
+        debug_assert!(self.reuse);
+        self.members.push((fd, listener));
+    }
+
+    /// Removes a member from the target group. Returns `true` if the group is now empty.
+    pub fn leave(&mut self, fd: FileDescriptor) -> bool {
+        self.members.retain(|(member, _)| *member != fd);
+        self.members.is_empty()
+    }
+
+    /// Returns the listeners of all members.
+    pub fn listeners(&self) -> impl Iterator<Item = &Rc<RefCell<Listener<T>>>> {
+        self.members.iter().map(|(_, listener)| listener)
+    }
+
+    /// Returns the file descriptors and listeners of all members.
+    pub fn members(&self) -> impl Iterator<Item = (FileDescriptor, &Rc<RefCell<Listener<T>>>)> {
+        self.members.iter().map(|(fd, listener)| (*fd, listener))
+    }
+
+    /// Returns the number of members.
+    pub fn num_members(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Picks the member that receives a datagram sent from `remote` to `local`. A member connected
+    /// to `remote` takes it, and otherwise the policy picks one of the unconnected members. Returns
+    /// `None` if every member is connected to some other remote.
+    pub fn select(
+        &mut self,
+        remote: Option<ipv4::Endpoint>,
+        local: ipv4::Endpoint,
+        payload: &[u8],
+    ) -> Option<&Rc<RefCell<Listener<T>>>> {
+        if remote.is_some() {
+            let connected = self
+                .members
+                .iter()
+                .position(|(_, listener)| listener.borrow().peer() == remote);
+            if let Some(ix) = connected {
+                return Some(&self.members[ix].1);
+            }
+        }
+        let candidates: Vec<usize> = (0..self.members.len())
+            .filter(|&ix| self.members[ix].1.borrow().peer().is_none())
+            .collect();
+        let len = candidates.len();
+        if len == 0 {
+            return None;
+        }
+        let ix = if len == 1 {
+            0
+        } else {
+            match self.policy {
+                ReusePortPolicy::FlowHash => {
+                    let mut hasher = DefaultHasher::new();
+                    remote.hash(&mut hasher);
+                    local.hash(&mut hasher);
+                    (hasher.finish() % len as u64) as usize
+                }
+                ReusePortPolicy::RoundRobin => {
+                    let ix = self.next % len;
+                    self.next = ix + 1;
+                    ix
+                }
+                ReusePortPolicy::Classifier(ref classify) => classify(remote, local, payload) % len,
+            }
+        };
+        Some(&self.members[candidates[ix]].1)
+    }
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+/// Debug trait implementation for [ReusePortPolicy].
+impl fmt::Debug for ReusePortPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReusePortPolicy::FlowHash => write!(f, "FlowHash"),
+            ReusePortPolicy::RoundRobin => write!(f, "RoundRobin"),
+            ReusePortPolicy::Classifier(..) => write!(f, "Classifier"),
+        }
+    }
+}
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::{ReusePortGroup, ReusePortPolicy};
+    use crate::protocols::{ip, ipv4, udp::listener::Listener};
+    use std::{cell::RefCell, convert::TryFrom, net::Ipv4Addr, rc::Rc};
+
+    fn endpoint(port: u16) -> ipv4::Endpoint {
+        ipv4::Endpoint::new(Ipv4Addr::new(10, 0, 0, 1), ip::Port::try_from(port).unwrap())
+    }
+
+    fn new_group(policy: ReusePortPolicy) -> (ReusePortGroup<u32>, Vec<Rc<RefCell<Listener<u32>>>>) {
+        let listeners: Vec<_> = (0..3).map(|_| Rc::new(RefCell::new(Listener::default()))).collect();
+        let mut group = ReusePortGroup::new(0, listeners[0].clone(), Some(policy));
+        group.join(1, listeners[1].clone());
+        group.join(2, listeners[2].clone());
+        (group, listeners)
+    }
+
+    fn selected(group: &mut ReusePortGroup<u32>, remote: u16, payload: &[u8]) -> usize {
+        let picked = group
+            .select(Some(endpoint(remote)), endpoint(80), payload)
+            .unwrap()
+            .clone();
+        group.listeners().position(|l| Rc::ptr_eq(l, &picked)).unwrap()
+    }
+
+    #[test]
+    fn round_robin() {
+        let (mut group, _listeners) = new_group(ReusePortPolicy::RoundRobin);
+        let picks: Vec<usize> = (0..4).map(|_| selected(&mut group, 1000, &[])).collect();
+        assert_eq!(picks, [0, 1, 2, 0]);
+
+        // The remaining members keep taking turns once one leaves.
+        assert!(!group.leave(1));
+        assert_eq!(group.num_members(), 2);
+        let picks: Vec<usize> = (0..2).map(|_| selected(&mut group, 1000, &[])).collect();
+        assert_eq!(picks, [1, 0]);
+    }
+
+    #[test]
+    fn flow_hash_and_classifier() {
+        // A flow always goes to the same member.
+        let (mut group, _listeners) = new_group(ReusePortPolicy::FlowHash);
+        let first = selected(&mut group, 1000, &[]);
+        assert!((0..8).all(|_| selected(&mut group, 1000, &[]) == first));
+
+        // The classifier decides based on the payload.
+        let classify = Rc::new(|_: Option<ipv4::Endpoint>, _: ipv4::Endpoint, payload: &[u8]| {
+            payload[0] as usize
+        });
+        let (mut group, _listeners) = new_group(ReusePortPolicy::Classifier(classify));
+        assert_eq!(selected(&mut group, 1000, &[2]), 2);
+        assert_eq!(selected(&mut group, 1000, &[4]), 1);
+    }
+
+    #[test]
+    fn connected_members() {
+        let (mut group, listeners) = new_group(ReusePortPolicy::RoundRobin);
+        listeners[0].borrow_mut().set_peer(Some(endpoint(1000)));
+        listeners[1].borrow_mut().set_peer(Some(endpoint(2000)));
+
+        // Datagrams from a connected peer go to the member connected to it.
+        assert!((0..4).all(|_| selected(&mut group, 2000, &[]) == 1));
+
+        // Others only go to unconnected members.
+        assert!((0..4).all(|_| selected(&mut group, 3000, &[]) == 2));
+
+        // Nobody takes them once every member is connected elsewhere.
+        listeners[2].borrow_mut().set_peer(Some(endpoint(4000)));
+        assert!(group.select(Some(endpoint(3000)), endpoint(80), &[]).is_none());
+        assert!(group.select(None, endpoint(80), &[]).is_none());
+    }
+}
@@ -1,7 +1,9 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 
+use super::reuseport::ReusePortPolicy;
 use crate::protocols::ipv4;
+use std::net::Ipv4Addr;
 
 //==============================================================================
 // Constants & Structures
@@ -14,6 +16,12 @@ pub struct Socket {
     local: Option<ipv4::Endpoint>,
     /// Remote endpoint.
     remote: Option<ipv4::Endpoint>,
+    /// Whether the local port was allocated from the ephemeral range on connect.
+    ephemeral: bool,
+    /// Distribution policy, if the local endpoint may be shared with other sockets.
+    reuse_port: Option<ReusePortPolicy>,
+    /// Multicast groups joined.
+    groups: Vec<Ipv4Addr>,
 }
 
 //==============================================================================
@@ -30,9 +38,51 @@ impl Socket {
         self.remote
     }
 
+    pub fn reuse_port(&self) -> Option<ReusePortPolicy> {
+        self.reuse_port.clone()
+    }
+
+    pub fn groups(&self) -> &[Ipv4Addr] {
+        &self.groups
+    }
+
+    pub fn is_ephemeral(&self) -> bool {
+        self.ephemeral
+    }
+
     pub fn set_local(&mut self, local: Option<ipv4::Endpoint>) {
         self.local = local;
     }
+
+    pub fn set_remote(&mut self, remote: Option<ipv4::Endpoint>) {
+        self.remote = remote;
+    }
+
+    pub fn set_ephemeral(&mut self, ephemeral: bool) {
+        self.ephemeral = ephemeral;
+    }
+
+    pub fn set_reuse_port(&mut self, reuse_port: Option<ReusePortPolicy>) {
+        self.reuse_port = reuse_port;
+    }
+
+    /// Adds `group` to the groups joined by the target socket. Returns `false` if it was
+    /// already there.
+    pub fn join_group(&mut self, group: Ipv4Addr) -> bool {
+        if self.groups.contains(&group) {
+            return false;
+        }
+        self.groups.push(group);
+        true
+    }
+
+    /// Removes `group` from the groups joined by the target socket. Returns `false` if it was
+    /// not there.
+    pub fn leave_group(&mut self, group: Ipv4Addr) -> bool {
+        let len = self.groups.len();
+        self.groups.retain(|&g| g != group);
+        self.groups.len() != len
+    }
 }
 
 //==============================================================================
@@ -46,6 +96,9 @@ impl Default for Socket {
         Self {
             local: None,
             remote: None,
+            ephemeral: false,
+            reuse_port: None,
+            groups: Vec::new(),
         }
     }
 }
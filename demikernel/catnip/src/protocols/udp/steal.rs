@@ -0,0 +1,137 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Work stealing between the cores of an application.
+//!
+//! Every core of a [StealGroup] owns a bounded lock-free queue. When the [Listener] of a socket
+//! backs up past its threshold, new requests are offered on the queue of the receiving core
+//! instead, and idle cores of the same application steal them from there. Stolen requests remember
+//! the core they were received on, so that load feedback can be credited to the right NIC queue.
+//!
+//! Runtime buffers belong to the core that allocated them, so requests cross cores as a copy of
+//! their payload, and the stealing core rebuilds a buffer of its own from it.
+//!
+//! [Listener]: super::listener::Listener
+
+use crate::protocols::ipv4;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use std::sync::Arc;
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Request moved from the queue of one core to another.
+pub struct StolenRequest<T> {
+    /// Core that received the request.
+    pub origin: u16,
+    /// Sender of the request.
+    pub remote: Option<ipv4::Endpoint>,
+    /// Request payload.
+    pub buf: T,
+    /// Priority class.
+    pub class: u8,
+}
+
+/// Set of per-core queues shared by the cores of an application.
+pub struct StealGroup<T> {
+    queues: Arc<Vec<(Sender<StolenRequest<T>>, Receiver<StolenRequest<T>>)>>,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [StealGroup].
+impl<T> StealGroup<T> {
+    /// Creates a steal group for `num_cores` cores, each one queueing up to `capacity` requests.
+    pub fn new(num_cores: usize, capacity: usize) -> Self {
+        assert!(num_cores > 0);
+        assert!(capacity > 0);
+        let queues = (0..num_cores).map(|_| bounded(capacity)).collect();
+        Self {
+            queues: Arc::new(queues),
+        }
+    }
+
+    /// Returns the number of cores in the group.
+    pub fn num_cores(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// Offers a request of priority `class` received on `core_id` to the group. The request is
+    /// handed back if the queue of `core_id` is full.
+    pub fn offer(
+        &self,
+        core_id: u16,
+        remote: Option<ipv4::Endpoint>,
+        buf: T,
+        class: u8,
+    ) -> Result<(), (Option<ipv4::Endpoint>, T)> {
+        let request = StolenRequest {
+            origin: core_id,
+            remote,
+            buf,
+            class,
+        };
+        self.queues[core_id as usize]
+            .0
+            .try_send(request)
+            .map_err(|e| {
+                let request = e.into_inner();
+                (request.remote, request.buf)
+            })
+    }
+
+    /// Takes a request on behalf of `core_id`. The queue of `core_id` itself is tried first,
+    /// then the queues of its siblings.
+    pub fn steal(&self, core_id: u16) -> Option<StolenRequest<T>> {
+        let num_cores = self.queues.len();
+        (0..num_cores)
+            .map(|i| (core_id as usize + i) % num_cores)
+            .find_map(|ix| self.queues[ix].1.try_recv().ok())
+    }
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+/// Clone trait implementation for [StealGroup].
+impl<T> Clone for StealGroup<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queues: self.queues.clone(),
+        }
+    }
+}
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::StealGroup;
+
+    #[test]
+    fn steal_own_queue_first() {
+        let group: StealGroup<u32> = StealGroup::new(3, 2);
+        assert!(group.offer(0, None, 1, 0).is_ok());
+        assert!(group.offer(2, None, 2, 0).is_ok());
+
+        // Core 2 drains its own queue before stealing from core 0.
+        let r = group.steal(2).unwrap();
+        assert_eq!((r.origin, r.buf), (2, 2));
+        let r = group.steal(2).unwrap();
+        assert_eq!((r.origin, r.buf), (0, 1));
+        assert!(group.steal(2).is_none());
+    }
+
+    #[test]
+    fn offer_full_queue() {
+        let group: StealGroup<u32> = StealGroup::new(1, 1);
+        assert!(group.offer(0, None, 1, 0).is_ok());
+        assert_eq!(group.offer(0, None, 2, 0), Err((None, 2)));
+    }
+}
@@ -5,14 +5,23 @@ use crate::{
     collections::bytes::BytesMut,
     fail::Fail,
     file_table::FileDescriptor,
-    protocols::{ip, ipv4},
+    protocols::{
+        ethernet2::{frame::ETHERNET2_HEADER_SIZE, Ethernet2Header, MacAddress},
+        igmp::{IgmpHeader, IgmpType, IGMP_ALL_ROUTERS},
+        ip, ipv4,
+        ipv4::{datagram::IPV4_HEADER_SIZE, Ipv4Header, Ipv4Protocol2},
+        udp::ReusePortPolicy,
+    },
+    runtime::Runtime,
     test_helpers,
 };
+use byteorder::{ByteOrder, NetworkEndian};
 use futures::task::{noop_waker_ref, Context};
 use must_let::must_let;
 use std::{
     convert::TryFrom,
     future::Future,
+    net::Ipv4Addr,
     pin::Pin,
     task::Poll,
     time::{Duration, Instant},
@@ -89,6 +98,203 @@ fn udp_push_pop() {
     bob.close(bob_fd).unwrap();
 }
 
+#[test]
+fn udp_popbatch() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut now = Instant::now();
+
+    // Setup Alice.
+    let mut alice = test_helpers::new_alice2(now);
+    let alice_port = ip::Port::try_from(80).unwrap();
+    let alice_addr = ipv4::Endpoint::new(test_helpers::ALICE_IPV4, alice_port);
+    let alice_fd: FileDescriptor = alice.udp_socket().unwrap();
+    alice.udp_bind(alice_fd, alice_addr).unwrap();
+
+    // Setup Bob.
+    let mut bob = test_helpers::new_bob2(now);
+    let bob_port = ip::Port::try_from(80).unwrap();
+    let bob_addr = ipv4::Endpoint::new(test_helpers::BOB_IPV4, bob_port);
+    let bob_fd: FileDescriptor = bob.udp_socket().unwrap();
+    bob.udp_bind(bob_fd, bob_addr).unwrap();
+
+    // Send three datagrams to Bob.
+    let buf = BytesMut::from(&vec![0x5a; 32][..]).freeze();
+    for _ in 0..3 {
+        alice.udp_pushto(alice_fd, buf.clone(), bob_addr).unwrap();
+        alice.rt().poll_scheduler();
+        bob.receive(alice.rt().pop_frame()).unwrap();
+    }
+
+    // A full batch is returned right away.
+    let mut pop_future = bob.udp_popbatch(bob_fd, 2, Duration::from_millis(1));
+    must_let!(let Poll::Ready(Ok(bufs)) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(bufs.len(), 2);
+
+    // A partial batch waits until the deadline.
+    let mut pop_future = bob.udp_popbatch(bob_fd, 2, Duration::from_millis(1));
+    assert!(Future::poll(Pin::new(&mut pop_future), &mut ctx).is_pending());
+    now += Duration::from_micros(500);
+    bob.rt().advance_clock(now);
+    assert!(Future::poll(Pin::new(&mut pop_future), &mut ctx).is_pending());
+    now += Duration::from_micros(500);
+    bob.rt().advance_clock(now);
+    must_let!(let Poll::Ready(Ok(bufs)) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(bufs.len(), 1);
+    assert_eq!(bufs[0], (Some(alice_addr), buf));
+
+    // Close peers.
+    alice.close(alice_fd).unwrap();
+    bob.close(bob_fd).unwrap();
+}
+
+//==============================================================================
+// Wildcard Bind
+//==============================================================================
+
+#[test]
+fn udp_bind_wildcard() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Setup Alice.
+    let mut alice = test_helpers::new_alice2(now);
+    let alice_port = ip::Port::try_from(80).unwrap();
+    let alice_addr = ipv4::Endpoint::new(test_helpers::ALICE_IPV4, alice_port);
+    let alice_fd: FileDescriptor = alice.udp_socket().unwrap();
+    alice.udp_bind(alice_fd, alice_addr).unwrap();
+
+    // Setup Bob, bound to the wildcard address.
+    let mut bob = test_helpers::new_bob2(now);
+    let bob_port = ip::Port::try_from(80).unwrap();
+    let bob_addr = ipv4::Endpoint::new(test_helpers::BOB_IPV4, bob_port);
+    let wildcard_addr = ipv4::Endpoint::new(Ipv4Addr::UNSPECIFIED, bob_port);
+    let wildcard_fd: FileDescriptor = bob.udp_socket().unwrap();
+    bob.udp_bind(wildcard_fd, wildcard_addr).unwrap();
+
+    // The wildcard socket receives data sent to Bob.
+    let buf = BytesMut::from(&vec![0x5a; 32][..]).freeze();
+    alice.udp_pushto(alice_fd, buf.clone(), bob_addr).unwrap();
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    let mut pop_future = bob.udp_pop(wildcard_fd);
+    must_let!(let Poll::Ready(Ok((Some(remote_addr), _))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(remote_addr, alice_addr);
+
+    // A socket bound to Bob's address takes precedence.
+    let bob_fd: FileDescriptor = bob.udp_socket().unwrap();
+    bob.udp_bind(bob_fd, bob_addr).unwrap();
+    alice.udp_pushto(alice_fd, buf.clone(), bob_addr).unwrap();
+    alice.rt().poll_scheduler();
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    let mut pop_future = bob.udp_pop(wildcard_fd);
+    assert!(Future::poll(Pin::new(&mut pop_future), &mut ctx).is_pending());
+    let mut pop_future = bob.udp_pop(bob_fd);
+    must_let!(let Poll::Ready(Ok(_)) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+
+    // Close peers.
+    alice.close(alice_fd).unwrap();
+    bob.close(wildcard_fd).unwrap();
+    bob.close(bob_fd).unwrap();
+}
+
+//==============================================================================
+// Reuse Port
+//==============================================================================
+
+#[test]
+fn udp_bind_reuse_port() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Setup Alice.
+    let mut alice = test_helpers::new_alice2(now);
+    let alice_port = ip::Port::try_from(80).unwrap();
+    let alice_addr = ipv4::Endpoint::new(test_helpers::ALICE_IPV4, alice_port);
+    let alice_fd: FileDescriptor = alice.udp_socket().unwrap();
+    alice.udp_bind(alice_fd, alice_addr).unwrap();
+
+    // Setup Bob, with two sockets sharing the same endpoint.
+    let mut bob = test_helpers::new_bob2(now);
+    let bob_port = ip::Port::try_from(80).unwrap();
+    let bob_addr = ipv4::Endpoint::new(test_helpers::BOB_IPV4, bob_port);
+    let bob_fds: Vec<FileDescriptor> = (0..2).map(|_| bob.udp_socket().unwrap()).collect();
+    for &fd in &bob_fds {
+        bob.set_reuse_port(fd, ReusePortPolicy::RoundRobin).unwrap();
+        bob.udp_bind(fd, bob_addr).unwrap();
+    }
+
+    // Sockets without reuse-port may not join.
+    let other_fd: FileDescriptor = bob.udp_socket().unwrap();
+    must_let!(let Err(Fail::Malformed { .. }) = bob.udp_bind(other_fd, bob_addr));
+
+    // Bob's sockets take turns.
+    let buf = BytesMut::from(&vec![0x5a; 32][..]).freeze();
+    for &fd in &bob_fds {
+        alice.udp_pushto(alice_fd, buf.clone(), bob_addr).unwrap();
+        alice.rt().poll_scheduler();
+        bob.receive(alice.rt().pop_frame()).unwrap();
+        let mut pop_future = bob.udp_pop(fd);
+        must_let!(let Poll::Ready(Ok(_)) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    }
+
+    // Close peers.
+    alice.close(alice_fd).unwrap();
+    for fd in bob_fds {
+        bob.close(fd).unwrap();
+    }
+    bob.close(other_fd).unwrap();
+}
+
+//==============================================================================
+// Connect
+//==============================================================================
+
+#[test]
+fn udp_connect() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+
+    // Setup Alice, without binding.
+    let mut alice = test_helpers::new_alice2(now);
+    let alice_fd: FileDescriptor = alice.udp_socket().unwrap();
+
+    // Setup Bob.
+    let mut bob = test_helpers::new_bob2(now);
+    let bob_port = ip::Port::try_from(80).unwrap();
+    let bob_addr = ipv4::Endpoint::new(test_helpers::BOB_IPV4, bob_port);
+    let bob_fd: FileDescriptor = bob.udp_socket().unwrap();
+    bob.udp_bind(bob_fd, bob_addr).unwrap();
+
+    // Pushing requires a remote endpoint.
+    let buf = BytesMut::from(&vec![0x5a; 32][..]).freeze();
+    must_let!(let Err(Fail::Invalid { .. }) = alice.udp_push(alice_fd, buf.clone()));
+
+    // Connect Alice to Bob, which binds her to an ephemeral port.
+    alice.udp_connect(alice_fd, bob_addr).unwrap();
+    alice.udp_push(alice_fd, buf.clone()).unwrap();
+    alice.rt().poll_scheduler();
+
+    // Receive data from Alice.
+    bob.receive(alice.rt().pop_frame()).unwrap();
+    let mut pop_future = bob.udp_pop(bob_fd);
+    must_let!(let Poll::Ready(Ok((Some(alice_addr), received_buf))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(alice_addr.addr, test_helpers::ALICE_IPV4);
+    assert!(alice_addr.port.is_private());
+    assert_eq!(received_buf, buf);
+
+    // Once connected elsewhere, Bob ignores Alice.
+    let other_port = ip::Port::try_from(81).unwrap();
+    let other_addr = ipv4::Endpoint::new(test_helpers::ALICE_IPV4, other_port);
+    bob.udp_connect(bob_fd, other_addr).unwrap();
+    alice.udp_push(alice_fd, buf.clone()).unwrap();
+    alice.rt().poll_scheduler();
+    must_let!(let Err(Fail::Ignored { .. }) = bob.receive(alice.rt().pop_frame()));
+
+    // Close peers.
+    alice.close(alice_fd).unwrap();
+    bob.close(bob_fd).unwrap();
+}
+
 //==============================================================================
 // Ping Pong
 //==============================================================================
@@ -191,6 +397,93 @@ fn udp_loop2_bind_close() {
     }
 }
 
+//==============================================================================
+// Multicast & Broadcast
+//==============================================================================
+
+#[test]
+fn udp_multicast_broadcast() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let now = Instant::now();
+    let group = Ipv4Addr::new(239, 1, 2, 3);
+    let group_mac = MacAddress::from_ipv4_multicast(group);
+
+    // Setup Alice, listening on the group.
+    let mut alice = test_helpers::new_alice2(now);
+    let port = ip::Port::try_from(5000).unwrap();
+    let alice_fd: FileDescriptor = alice.udp_socket().unwrap();
+    alice
+        .udp_bind(alice_fd, ipv4::Endpoint::new(Ipv4Addr::UNSPECIFIED, port))
+        .unwrap();
+    alice.join_multicast(alice_fd, group).unwrap();
+    must_let!(let Err(Fail::Invalid { .. }) = alice.join_multicast(alice_fd, group));
+
+    // Joining sends a membership report to the group.
+    let (eth_hdr, payload) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    assert_eq!(eth_hdr.dst_addr, group_mac);
+    let (ipv4_hdr, payload) = Ipv4Header::parse(payload).unwrap();
+    assert_eq!(ipv4_hdr.protocol, Ipv4Protocol2::Igmp);
+    assert_eq!(ipv4_hdr.dst_addr, group);
+    assert!(ipv4_hdr.router_alert);
+    let igmp_hdr = IgmpHeader::parse(payload).unwrap();
+    assert_eq!(igmp_hdr.igmp_type, IgmpType::MembershipReportV2);
+    assert_eq!(igmp_hdr.group, group);
+
+    // Setup Bob and Carrie. Carrie did not join the group.
+    let mut bob = test_helpers::new_bob2(now);
+    let bob_port = ip::Port::try_from(80).unwrap();
+    let bob_addr = ipv4::Endpoint::new(test_helpers::BOB_IPV4, bob_port);
+    let bob_fd: FileDescriptor = bob.udp_socket().unwrap();
+    bob.udp_bind(bob_fd, bob_addr).unwrap();
+    let mut carrie = test_helpers::new_carrie(now);
+
+    // Another socket of Alice is bound to the group address, but did not join the group.
+    let other_fd: FileDescriptor = alice.udp_socket().unwrap();
+    alice
+        .udp_bind(other_fd, ipv4::Endpoint::new(group, port))
+        .unwrap();
+
+    // Multicast, limited broadcast and subnet broadcast all reach Alice.
+    let destinations = [
+        group,
+        Ipv4Addr::BROADCAST,
+        Ipv4Addr::new(192, 168, 1, 255),
+    ];
+    for (i, &dst) in destinations.iter().enumerate() {
+        let buf = BytesMut::from(&vec![i as u8; 32][..]).freeze();
+        bob.udp_pushto(bob_fd, buf.clone(), ipv4::Endpoint::new(dst, port))
+            .unwrap();
+        let frame = bob.rt().pop_frame();
+        if dst.is_multicast() {
+            must_let!(let Err(Fail::Ignored { .. }) = carrie.receive(frame.clone()));
+        }
+        alice.receive(frame).unwrap();
+        let mut pop_future = alice.udp_pop(alice_fd);
+        must_let!(let Poll::Ready(Ok((Some(remote_addr), received_buf))) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+        assert_eq!(remote_addr, bob_addr);
+        assert_eq!(received_buf, buf);
+    }
+    let mut pop_future = alice.udp_pop(other_fd);
+    assert!(Future::poll(Pin::new(&mut pop_future), &mut ctx).is_pending());
+    alice.close(other_fd).unwrap();
+
+    // Leaving tells routers, and the group is no longer received.
+    alice.leave_multicast(alice_fd, group).unwrap();
+    let (eth_hdr, payload) = Ethernet2Header::parse(alice.rt().pop_frame()).unwrap();
+    assert_eq!(eth_hdr.dst_addr, MacAddress::from_ipv4_multicast(IGMP_ALL_ROUTERS));
+    let (_, payload) = Ipv4Header::parse(payload).unwrap();
+    let igmp_hdr = IgmpHeader::parse(payload).unwrap();
+    assert_eq!(igmp_hdr.igmp_type, IgmpType::LeaveGroup);
+    let buf = BytesMut::from(&vec![0x5a; 32][..]).freeze();
+    bob.udp_pushto(bob_fd, buf, ipv4::Endpoint::new(group, port))
+        .unwrap();
+    must_let!(let Err(Fail::Ignored { .. }) = alice.receive(bob.rt().pop_frame()));
+
+    // Close peers.
+    alice.close(alice_fd).unwrap();
+    bob.close(bob_fd).unwrap();
+}
+
 //==============================================================================
 // Loop Push & Pop
 //==============================================================================
@@ -383,6 +676,7 @@ fn udp_close_bad_file_descriptor() {
 
 #[test]
 fn udp_pop_not_bound() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
     let mut now = Instant::now();
 
     // Setup Alice.
@@ -405,14 +699,36 @@ fn udp_pop_not_bound() {
 
     now += Duration::from_micros(1);
 
+    // Alice waits for a reply.
+    let mut pop_future = alice.udp_pop(alice_fd);
+    assert!(Future::poll(Pin::new(&mut pop_future), &mut ctx).is_pending());
+
     // Receive data from Alice.
     must_let!(let Err(err) = bob.receive(alice.rt().pop_frame()));
-    assert_eq!(
-        err,
-        Fail::Malformed {
-            details: "Port not bound",
-        }
-    );
+    assert_eq!(err, Fail::PortUnreachable {});
+
+    // Bob answers with an ICMPv4 error. Turned into a Protocol Unreachable error, it leaves the
+    // pending pop of Alice alone.
+    let frame = bob.rt().pop_frame();
+    let mut other = frame[..].to_vec();
+    let icmpv4_hdr = &mut other[(ETHERNET2_HEADER_SIZE + IPV4_HEADER_SIZE)..];
+    let old_word = NetworkEndian::read_u16(&icmpv4_hdr[0..2]);
+    icmpv4_hdr[1] = 2;
+    let new_word = NetworkEndian::read_u16(&icmpv4_hdr[0..2]);
+    // Incremental checksum update (RFC 1624).
+    let mut sum =
+        !NetworkEndian::read_u16(&icmpv4_hdr[2..4]) as u32 + !old_word as u32 + new_word as u32;
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    NetworkEndian::write_u16(&mut icmpv4_hdr[2..4], !(sum as u16));
+    alice.receive(BytesMut::from(&other[..]).freeze()).unwrap();
+    assert!(Future::poll(Pin::new(&mut pop_future), &mut ctx).is_pending());
+
+    // The Port Unreachable error itself fails the pending pop of Alice.
+    alice.receive(frame).unwrap();
+    must_let!(let Poll::Ready(Err(err)) = Future::poll(Pin::new(&mut pop_future), &mut ctx));
+    assert_eq!(err, Fail::ConnectionRefused {});
 
     // Close peers.
     alice.close(alice_fd).unwrap();
@@ -0,0 +1,185 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    message::{fragment, Reassembly, RpcHeader, RpcKind},
+    options::RpcOptions,
+};
+use crate::{
+    fail::Fail,
+    file_table::FileDescriptor,
+    protocols::{ipv4, udp},
+    runtime::{Runtime, RuntimeBuf},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Call waiting for its response.
+struct Call {
+    remote: ipv4::Endpoint,
+    method: u16,
+    /// Fragments of the request, kept to send it again.
+    fragments: Vec<Vec<u8>>,
+    /// Number of times the request may still be sent again.
+    retries_left: usize,
+    /// Time at which the request is sent again.
+    deadline: Instant,
+}
+
+///
+/// RPC Client
+///
+/// Sends requests over a bound UDP socket and matches responses to them. Responses are not
+/// popped by the client itself: the application pops datagrams from the socket as usual and
+/// hands them over to [RpcClient::receive]. Calls that go unanswered are sent again on
+/// [RpcClient::poll], and fail with [Fail::Timeout] once out of retries.
+///
+pub struct RpcClient<RT: Runtime> {
+    rt: RT,
+    udp: udp::Peer<RT>,
+    fd: FileDescriptor,
+    options: RpcOptions,
+    next_msg_id: u32,
+    calls: HashMap<u32, Call>,
+    reassembly: Reassembly,
+    /// Calls that completed, in completion order.
+    completions: VecDeque<(u32, Result<Vec<u8>, Fail>)>,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [RpcClient].
+impl<RT: Runtime> RpcClient<RT> {
+    /// Creates a client sending its requests from socket `fd`.
+    pub fn new(rt: RT, udp: udp::Peer<RT>, fd: FileDescriptor, options: RpcOptions) -> Self {
+        let next_msg_id = rt.rng_gen();
+        let reassembly = Reassembly::new(options.reassembly_timeout());
+        Self {
+            rt,
+            udp,
+            fd,
+            options,
+            next_msg_id,
+            calls: HashMap::new(),
+            reassembly,
+            completions: VecDeque::new(),
+        }
+    }
+
+    /// Returns the socket the client sends its requests from.
+    pub fn fd(&self) -> FileDescriptor {
+        self.fd
+    }
+
+    /// Calls `method` on the server at `remote`. Returns the identifier of the call, which its
+    /// completion carries.
+    pub fn call(
+        &mut self,
+        remote: ipv4::Endpoint,
+        method: u16,
+        payload: &[u8],
+    ) -> Result<u32, Fail> {
+        let msg_id = self.next_msg_id;
+        let fragments = fragment(
+            RpcKind::Request,
+            method,
+            msg_id,
+            payload,
+            self.options.max_fragment_size(),
+        )?;
+        self.send(remote, &fragments)?;
+        self.next_msg_id = self.next_msg_id.wrapping_add(1);
+        let call = Call {
+            remote,
+            method,
+            fragments,
+            retries_left: self.options.retries(),
+            deadline: self.rt.now() + self.options.timeout(),
+        };
+        self.calls.insert(msg_id, call);
+        Ok(msg_id)
+    }
+
+    /// Handles a datagram popped from the socket. Datagrams that are not responses to
+    /// outstanding calls are ignored.
+    pub fn receive(&mut self, remote: ipv4::Endpoint, buf: &[u8]) -> Result<(), Fail> {
+        let (hdr, payload) = RpcHeader::parse(buf)?;
+        match self.calls.get(&hdr.msg_id) {
+            Some(call)
+                if hdr.kind == RpcKind::Response
+                    && call.remote == remote
+                    && call.method == hdr.method => {}
+            _ => {
+                return Err(Fail::Ignored {
+                    details: "Not a response to an outstanding call",
+                })
+            }
+        }
+        let now = self.rt.now();
+        if let Some((_, response)) = self.reassembly.insert(remote, &hdr, payload, now) {
+            self.calls.remove(&hdr.msg_id);
+            self.completions.push_back((hdr.msg_id, Ok(response)));
+        }
+        Ok(())
+    }
+
+    /// Sends again the calls whose responses are late, and fails the ones out of retries. A call
+    /// that cannot be sent again does not hold back the others: the first error is returned once
+    /// all late calls have been handled.
+    pub fn poll(&mut self) -> Result<(), Fail> {
+        let now = self.rt.now();
+        self.reassembly.expire(now);
+        let mut late: Vec<u32> = self
+            .calls
+            .iter()
+            .filter(|(_, call)| call.deadline <= now)
+            .map(|(&msg_id, _)| msg_id)
+            .collect();
+        late.sort_unstable();
+        let mut result = Ok(());
+        for msg_id in late {
+            let call = self.calls.get_mut(&msg_id).unwrap();
+            if call.retries_left == 0 {
+                self.calls.remove(&msg_id);
+                self.completions.push_back((msg_id, Err(Fail::Timeout {})));
+                continue;
+            }
+            call.retries_left -= 1;
+            call.deadline = now + self.options.timeout();
+            let (remote, fragments) = (call.remote, call.fragments.clone());
+            result = result.and(self.send(remote, &fragments));
+        }
+        result
+    }
+
+    /// Takes the next completed call, if any.
+    pub fn take_completion(&mut self) -> Option<(u32, Result<Vec<u8>, Fail>)> {
+        self.completions.pop_front()
+    }
+
+    /// Gives up on call `msg_id`. Its response is ignored if it ever arrives.
+    pub fn cancel(&mut self, msg_id: u32) -> bool {
+        self.calls.remove(&msg_id).is_some()
+    }
+
+    /// Returns the number of calls waiting for their responses.
+    pub fn num_outstanding(&self) -> usize {
+        self.calls.len()
+    }
+
+    fn send(&self, remote: ipv4::Endpoint, fragments: &[Vec<u8>]) -> Result<(), Fail> {
+        for buf in fragments {
+            self.udp
+                .pushto(self.fd, RT::Buf::from_slice(buf)?, remote)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use crate::{fail::Fail, protocols::ipv4};
+use byteorder::{ByteOrder, NetworkEndian};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Size of the header prepended to every fragment (in bytes).
+pub const RPC_HEADER_SIZE: usize = 12;
+
+/// Version of the wire format.
+const RPC_VERSION: u8 = 1;
+
+/// Largest number of fragments a message may be split into.
+pub const RPC_MAX_FRAGMENTS: usize = 1024;
+
+/// Largest number of incomplete messages kept at once. Fragments of further messages are dropped
+/// until some complete or expire.
+pub const RPC_MAX_PARTIALS: usize = 256;
+
+/// Whether a message is a request or a response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RpcKind {
+    Request,
+    Response,
+}
+
+/// Header of an RPC fragment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RpcHeader {
+    pub kind: RpcKind,
+    /// Method called. Responses carry the method of their request.
+    pub method: u16,
+    /// Message identifier, chosen by the client. Responses carry the identifier of their
+    /// request.
+    pub msg_id: u32,
+    /// Index of the fragment within its message.
+    pub frag_idx: u16,
+    /// Number of fragments in the message.
+    pub frag_count: u16,
+}
+
+/// Message being put back together.
+struct Partial {
+    method: u16,
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    /// Time at which the message is given up on.
+    deadline: Instant,
+}
+
+/// Reassembles messages out of their fragments.
+pub struct Reassembly {
+    partials: HashMap<(ipv4::Endpoint, RpcKind, u32), Partial>,
+    /// How long fragments of an incomplete message are kept.
+    timeout: Duration,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [RpcHeader].
+impl RpcHeader {
+    pub fn parse(buf: &[u8]) -> Result<(Self, &[u8]), Fail> {
+        if buf.len() < RPC_HEADER_SIZE {
+            return Err(Fail::Malformed {
+                details: "RPC fragment too small",
+            });
+        }
+        if buf[0] != RPC_VERSION {
+            return Err(Fail::Unsupported {
+                details: "Unsupported RPC version",
+            });
+        }
+        let kind = match buf[1] {
+            0 => RpcKind::Request,
+            1 => RpcKind::Response,
+            _ => {
+                return Err(Fail::Malformed {
+                    details: "Invalid RPC message kind",
+                })
+            }
+        };
+        let hdr = Self {
+            kind,
+            method: NetworkEndian::read_u16(&buf[2..4]),
+            msg_id: NetworkEndian::read_u32(&buf[4..8]),
+            frag_idx: NetworkEndian::read_u16(&buf[8..10]),
+            frag_count: NetworkEndian::read_u16(&buf[10..12]),
+        };
+        if hdr.frag_count == 0
+            || hdr.frag_count as usize > RPC_MAX_FRAGMENTS
+            || hdr.frag_idx >= hdr.frag_count
+        {
+            return Err(Fail::Malformed {
+                details: "Invalid RPC fragment index",
+            });
+        }
+        Ok((hdr, &buf[RPC_HEADER_SIZE..]))
+    }
+
+    pub fn serialize(&self, buf: &mut [u8]) {
+        buf[0] = RPC_VERSION;
+        buf[1] = match self.kind {
+            RpcKind::Request => 0,
+            RpcKind::Response => 1,
+        };
+        NetworkEndian::write_u16(&mut buf[2..4], self.method);
+        NetworkEndian::write_u32(&mut buf[4..8], self.msg_id);
+        NetworkEndian::write_u16(&mut buf[8..10], self.frag_idx);
+        NetworkEndian::write_u16(&mut buf[10..12], self.frag_count);
+    }
+}
+
+/// Splits a message into fragments of at most `max_fragment_size` bytes, headers included.
+pub fn fragment(
+    kind: RpcKind,
+    method: u16,
+    msg_id: u32,
+    payload: &[u8],
+    max_fragment_size: usize,
+) -> Result<Vec<Vec<u8>>, Fail> {
+    assert!(max_fragment_size > RPC_HEADER_SIZE);
+    let chunk_size = max_fragment_size - RPC_HEADER_SIZE;
+    let frag_count = std::cmp::max(1, (payload.len() + chunk_size - 1) / chunk_size);
+    if frag_count > RPC_MAX_FRAGMENTS {
+        return Err(Fail::Invalid {
+            details: "RPC message too large",
+        });
+    }
+    let fragments = (0..frag_count)
+        .map(|frag_idx| {
+            let start = frag_idx * chunk_size;
+            let end = std::cmp::min(start + chunk_size, payload.len());
+            let mut buf = vec![0u8; RPC_HEADER_SIZE + (end - start)];
+            RpcHeader {
+                kind,
+                method,
+                msg_id,
+                frag_idx: frag_idx as u16,
+                frag_count: frag_count as u16,
+            }
+            .serialize(&mut buf[..RPC_HEADER_SIZE]);
+            buf[RPC_HEADER_SIZE..].copy_from_slice(&payload[start..end]);
+            buf
+        })
+        .collect();
+    Ok(fragments)
+}
+
+/// Associate functions for [Reassembly].
+impl Reassembly {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            partials: HashMap::new(),
+            timeout,
+        }
+    }
+
+    /// Adds a fragment sent by `remote`. Returns the method and the payload of the message once
+    /// all of its fragments have arrived. Fragments of new messages are dropped while
+    /// [RPC_MAX_PARTIALS] messages are incomplete.
+    pub fn insert(
+        &mut self,
+        remote: ipv4::Endpoint,
+        hdr: &RpcHeader,
+        payload: &[u8],
+        now: Instant,
+    ) -> Option<(u16, Vec<u8>)> {
+        let key = (remote, hdr.kind, hdr.msg_id);
+        if hdr.frag_count == 1 && !self.partials.contains_key(&key) {
+            return Some((hdr.method, payload.to_vec()));
+        }
+        if !self.partials.contains_key(&key) && self.partials.len() >= RPC_MAX_PARTIALS {
+            self.expire(now);
+            if self.partials.len() >= RPC_MAX_PARTIALS {
+                return None;
+            }
+        }
+        let timeout = self.timeout;
+        let partial = self.partials.entry(key).or_insert_with(|| Partial {
+            method: hdr.method,
+            fragments: vec![None; hdr.frag_count as usize],
+            received: 0,
+            deadline: now + timeout,
+        });
+        // Fragments that disagree with the first one are dropped.
+        if partial.method != hdr.method || partial.fragments.len() != hdr.frag_count as usize {
+            return None;
+        }
+        let slot = &mut partial.fragments[hdr.frag_idx as usize];
+        if slot.is_none() {
+            *slot = Some(payload.to_vec());
+            partial.received += 1;
+        }
+        if partial.received < partial.fragments.len() {
+            return None;
+        }
+        let partial = self.partials.remove(&key).unwrap();
+        let payload = partial.fragments.into_iter().flatten().flatten().collect();
+        Some((partial.method, payload))
+    }
+
+    /// Drops the incomplete messages whose fragments have been waited for too long.
+    pub fn expire(&mut self, now: Instant) {
+        self.partials.retain(|_, partial| partial.deadline > now);
+    }
+
+    /// Returns the number of incomplete messages.
+    pub fn num_partials(&self) -> usize {
+        self.partials.len()
+    }
+}
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::{fragment, Reassembly, RpcHeader, RpcKind, RPC_HEADER_SIZE, RPC_MAX_PARTIALS};
+    use crate::protocols::{ip, ipv4};
+    use std::{
+        convert::TryFrom,
+        net::Ipv4Addr,
+        time::{Duration, Instant},
+    };
+
+    fn remote() -> ipv4::Endpoint {
+        ipv4::Endpoint::new(
+            Ipv4Addr::new(10, 0, 0, 1),
+            ip::Port::try_from(1000).unwrap(),
+        )
+    }
+
+    #[test]
+    fn fragment_reassemble() {
+        let now = Instant::now();
+        let payload: Vec<u8> = (0..100).collect();
+        let fragments = fragment(RpcKind::Request, 7, 42, &payload, RPC_HEADER_SIZE + 30).unwrap();
+        assert_eq!(fragments.len(), 4);
+
+        // Fragments may arrive out of order and more than once.
+        let mut reassembly = Reassembly::new(Duration::from_millis(10));
+        let mut message = None;
+        for ix in [3, 1, 1, 0, 2].iter() {
+            assert!(message.is_none());
+            let (hdr, body) = RpcHeader::parse(&fragments[*ix]).unwrap();
+            assert_eq!((hdr.method, hdr.msg_id), (7, 42));
+            message = reassembly.insert(remote(), &hdr, body, now);
+        }
+        assert_eq!(message, Some((7, payload)));
+        assert_eq!(reassembly.num_partials(), 0);
+    }
+
+    #[test]
+    fn frag_count_mismatch() {
+        let now = Instant::now();
+        let payload: Vec<u8> = (0..100).collect();
+        let fragments = fragment(RpcKind::Request, 7, 42, &payload, RPC_HEADER_SIZE + 30).unwrap();
+        let mut reassembly = Reassembly::new(Duration::from_millis(10));
+        let (hdr, body) = RpcHeader::parse(&fragments[0]).unwrap();
+        assert!(reassembly.insert(remote(), &hdr, body, now).is_none());
+
+        // A fragment of the same message claiming a different number of fragments is dropped,
+        // even if it would complete the message on its own.
+        for frag_count in [1, 2, 5].iter() {
+            let mut hdr = hdr;
+            hdr.frag_count = *frag_count;
+            hdr.frag_idx = frag_count - 1;
+            assert!(reassembly.insert(remote(), &hdr, body, now).is_none());
+        }
+        assert_eq!(reassembly.num_partials(), 1);
+
+        let mut message = None;
+        for buf in &fragments[1..] {
+            let (hdr, body) = RpcHeader::parse(buf).unwrap();
+            message = reassembly.insert(remote(), &hdr, body, now);
+        }
+        assert_eq!(message, Some((7, payload)));
+    }
+
+    #[test]
+    fn expire_partial() {
+        let now = Instant::now();
+        let fragments =
+            fragment(RpcKind::Response, 1, 1, &[0u8; 40], RPC_HEADER_SIZE + 30).unwrap();
+        let mut reassembly = Reassembly::new(Duration::from_millis(10));
+        let (hdr, body) = RpcHeader::parse(&fragments[0]).unwrap();
+        assert!(reassembly.insert(remote(), &hdr, body, now).is_none());
+
+        reassembly.expire(now + Duration::from_millis(5));
+        assert_eq!(reassembly.num_partials(), 1);
+        reassembly.expire(now + Duration::from_millis(10));
+        assert_eq!(reassembly.num_partials(), 0);
+    }
+
+    #[test]
+    fn max_partials() {
+        let now = Instant::now();
+        let mut reassembly = Reassembly::new(Duration::from_millis(10));
+        let first = |msg_id| {
+            let fragments = fragment(
+                RpcKind::Request,
+                1,
+                msg_id,
+                &[0u8; 40],
+                RPC_HEADER_SIZE + 30,
+            );
+            fragments.unwrap().swap_remove(0)
+        };
+        for msg_id in 0..RPC_MAX_PARTIALS as u32 {
+            let buf = first(msg_id);
+            let (hdr, body) = RpcHeader::parse(&buf).unwrap();
+            assert!(reassembly.insert(remote(), &hdr, body, now).is_none());
+        }
+        assert_eq!(reassembly.num_partials(), RPC_MAX_PARTIALS);
+
+        // Fragments of one more message are dropped...
+        let buf = first(RPC_MAX_PARTIALS as u32);
+        let (hdr, body) = RpcHeader::parse(&buf).unwrap();
+        assert!(reassembly.insert(remote(), &hdr, body, now).is_none());
+        assert_eq!(reassembly.num_partials(), RPC_MAX_PARTIALS);
+
+        // ...until older ones expire.
+        let later = now + Duration::from_millis(10);
+        assert!(reassembly.insert(remote(), &hdr, body, later).is_none());
+        assert_eq!(reassembly.num_partials(), 1);
+    }
+}
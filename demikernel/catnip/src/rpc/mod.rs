@@ -0,0 +1,22 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+//! Request-response RPC over UDP.
+//!
+//! Messages carry a method and a message id chosen by the client, and are split into fragments
+//! small enough to fit in one buffer each. Clients send requests again until they get a response
+//! or run out of retries. Servers dispatch requests by method, each method being mapped to a
+//! scheduling class, and keep their replies around for a while to answer duplicate requests.
+
+mod client;
+mod message;
+mod options;
+mod server;
+
+#[cfg(test)]
+mod tests;
+
+pub use client::RpcClient;
+pub use message::{RpcHeader, RpcKind, RPC_HEADER_SIZE, RPC_MAX_FRAGMENTS, RPC_MAX_PARTIALS};
+pub use options::RpcOptions;
+pub use server::{RpcRequest, RpcServer};
@@ -0,0 +1,164 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::message::RPC_HEADER_SIZE;
+use std::time::Duration;
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Control Options for RPC
+#[derive(Clone, Debug)]
+pub struct RpcOptions {
+    /// Time waited for a response before a call is sent again.
+    timeout: Duration,
+    /// Number of times a call is sent again before it fails.
+    retries: usize,
+    /// Largest datagram sent, RPC header included.
+    max_fragment_size: usize,
+    /// Time the fragments of an incomplete message are kept.
+    reassembly_timeout: Duration,
+    /// Time a reply is kept around to answer duplicate requests.
+    reply_cache_timeout: Duration,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [RpcOptions].
+impl RpcOptions {
+    /// Creates custom options for RPC.
+    pub fn new(
+        timeout: Option<Duration>,
+        retries: Option<usize>,
+        max_fragment_size: Option<usize>,
+        reassembly_timeout: Option<Duration>,
+        reply_cache_timeout: Option<Duration>,
+    ) -> Self {
+        let mut options = Self::default();
+        if let Some(value) = timeout {
+            options = options.set_timeout(value);
+        }
+        if let Some(value) = retries {
+            options = options.set_retries(value);
+        }
+        if let Some(value) = max_fragment_size {
+            options = options.set_max_fragment_size(value);
+        }
+        if let Some(value) = reassembly_timeout {
+            options = options.set_reassembly_timeout(value);
+        }
+        if let Some(value) = reply_cache_timeout {
+            options = options.set_reply_cache_timeout(value);
+        }
+        options
+    }
+
+    /// Returns the time waited for a response before a call is sent again.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Returns the number of times a call is sent again before it fails.
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+
+    /// Returns the largest datagram sent, RPC header included.
+    pub fn max_fragment_size(&self) -> usize {
+        self.max_fragment_size
+    }
+
+    /// Returns the time the fragments of an incomplete message are kept.
+    pub fn reassembly_timeout(&self) -> Duration {
+        self.reassembly_timeout
+    }
+
+    /// Returns the time a reply is kept around to answer duplicate requests.
+    pub fn reply_cache_timeout(&self) -> Duration {
+        self.reply_cache_timeout
+    }
+
+    /// Sets the time waited for a response before a call is sent again.
+    fn set_timeout(mut self, value: Duration) -> Self {
+        assert!(value > Duration::from_secs(0));
+        self.timeout = value;
+        self
+    }
+
+    /// Sets the number of times a call is sent again before it fails.
+    fn set_retries(mut self, value: usize) -> Self {
+        self.retries = value;
+        self
+    }
+
+    /// Sets the largest datagram sent, RPC header included.
+    fn set_max_fragment_size(mut self, value: usize) -> Self {
+        assert!(value > RPC_HEADER_SIZE);
+        self.max_fragment_size = value;
+        self
+    }
+
+    /// Sets the time the fragments of an incomplete message are kept.
+    fn set_reassembly_timeout(mut self, value: Duration) -> Self {
+        self.reassembly_timeout = value;
+        self
+    }
+
+    /// Sets the time a reply is kept around to answer duplicate requests.
+    fn set_reply_cache_timeout(mut self, value: Duration) -> Self {
+        self.reply_cache_timeout = value;
+        self
+    }
+}
+
+//==============================================================================
+// Trait Implementations
+//==============================================================================
+
+/// Implementation of [Default] trait for [RpcOptions].
+impl Default for RpcOptions {
+    /// Creates default options for RPC.
+    fn default() -> Self {
+        RpcOptions {
+            timeout: Duration::from_millis(10),
+            retries: 3,
+            // Small enough for a fragment to fit in one buffer with all the headers.
+            max_fragment_size: 1024,
+            reassembly_timeout: Duration::from_millis(100),
+            reply_cache_timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+//==============================================================================
+// Unit Tests
+//==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::RpcOptions;
+    use std::time::Duration;
+
+    /// Tests instantiations flavors for [RpcOptions].
+    #[test]
+    fn test_rpc_options() {
+        // Default options.
+        let options_default = RpcOptions::default();
+        assert_eq!(options_default.retries(), 3);
+        assert_eq!(options_default.max_fragment_size(), 1024);
+
+        // Custom options.
+        let options_custom =
+            RpcOptions::new(Some(Duration::from_millis(1)), Some(0), Some(64), None, None);
+        assert_eq!(options_custom.timeout(), Duration::from_millis(1));
+        assert_eq!(options_custom.retries(), 0);
+        assert_eq!(options_custom.max_fragment_size(), 64);
+        assert_eq!(
+            options_custom.reassembly_timeout(),
+            options_default.reassembly_timeout()
+        );
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{
+    message::{fragment, Reassembly, RpcHeader, RpcKind},
+    options::RpcOptions,
+};
+use crate::{
+    fail::Fail,
+    file_table::FileDescriptor,
+    protocols::{ipv4, udp},
+    runtime::{Runtime, RuntimeBuf},
+};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    time::Instant,
+};
+
+//==============================================================================
+// Constants & Structures
+//==============================================================================
+
+/// Request received by a server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RpcRequest {
+    /// Client that sent the request.
+    pub remote: ipv4::Endpoint,
+    /// Identifier chosen by the client.
+    pub msg_id: u32,
+    pub method: u16,
+    /// Scheduling class of the method.
+    pub class: usize,
+    pub payload: Vec<u8>,
+}
+
+/// Where a request stands, as far as duplicates are concerned.
+enum ReplyState {
+    /// The request is queued or being handled. Duplicates are dropped until `expires`, after
+    /// which the client has given up on it.
+    InProgress { expires: Instant },
+    /// The request was replied to. Duplicates get the same reply until `expires`.
+    Done {
+        fragments: Vec<Vec<u8>>,
+        expires: Instant,
+    },
+}
+
+///
+/// RPC Server
+///
+/// Receives requests over a bound UDP socket and dispatches them by method. Each method is
+/// registered with a scheduling class, following the convention of the scheduler priorities
+/// (smaller numbers mean higher priority), and requests of higher classes are handed out first.
+/// Like [super::RpcClient], the server does not pop from the socket itself: the application pops
+/// datagrams as usual and hands them over to [RpcServer::receive].
+///
+pub struct RpcServer<RT: Runtime> {
+    rt: RT,
+    udp: udp::Peer<RT>,
+    fd: FileDescriptor,
+    options: RpcOptions,
+    /// Scheduling class of each method.
+    methods: HashMap<u16, usize>,
+    reassembly: Reassembly,
+    /// Requests waiting to be handled, per scheduling class.
+    pending: BTreeMap<usize, VecDeque<RpcRequest>>,
+    replies: HashMap<(ipv4::Endpoint, u32), ReplyState>,
+}
+
+//==============================================================================
+// Associate Functions
+//==============================================================================
+
+/// Associate functions for [RpcServer].
+impl<RT: Runtime> RpcServer<RT> {
+    /// Creates a server receiving its requests on socket `fd`.
+    pub fn new(rt: RT, udp: udp::Peer<RT>, fd: FileDescriptor, options: RpcOptions) -> Self {
+        let reassembly = Reassembly::new(options.reassembly_timeout());
+        Self {
+            rt,
+            udp,
+            fd,
+            options,
+            methods: HashMap::new(),
+            reassembly,
+            pending: BTreeMap::new(),
+            replies: HashMap::new(),
+        }
+    }
+
+    /// Returns the socket the server receives its requests on.
+    pub fn fd(&self) -> FileDescriptor {
+        self.fd
+    }
+
+    /// Serves `method`, handing out its requests with scheduling class `class`.
+    pub fn register_method(&mut self, method: u16, class: usize) {
+        self.methods.insert(method, class);
+    }
+
+    /// Returns the scheduling class of `method`, if it is served.
+    pub fn class(&self, method: u16) -> Option<usize> {
+        self.methods.get(&method).cloned()
+    }
+
+    /// Handles a datagram popped from the socket. Once all fragments of a request are in, it is
+    /// queued in the class of its method. Duplicates of requests already replied to get the same
+    /// reply again, while duplicates of requests not replied to yet are dropped.
+    pub fn receive(&mut self, remote: ipv4::Endpoint, buf: &[u8]) -> Result<(), Fail> {
+        let (hdr, payload) = RpcHeader::parse(buf)?;
+        if hdr.kind != RpcKind::Request {
+            return Err(Fail::Ignored {
+                details: "Not a request",
+            });
+        }
+        let class = match self.methods.get(&hdr.method) {
+            Some(&class) => class,
+            None => {
+                return Err(Fail::Unsupported {
+                    details: "Unknown RPC method",
+                })
+            }
+        };
+        match self.replies.get(&(remote, hdr.msg_id)) {
+            // The first fragment of a duplicate stands for the whole request.
+            Some(ReplyState::Done { fragments, .. }) if hdr.frag_idx == 0 => {
+                return self.send(remote, fragments)
+            }
+            Some(_) => return Ok(()),
+            None => {}
+        }
+        let now = self.rt.now();
+        if let Some((method, payload)) = self.reassembly.insert(remote, &hdr, payload, now) {
+            // Requests that are never replied to must not be remembered forever.
+            let retries = self.options.retries() as u32;
+            let expires = now + self.options.timeout() * (retries + 1);
+            self.replies
+                .insert((remote, hdr.msg_id), ReplyState::InProgress { expires });
+            self.pending
+                .entry(class)
+                .or_insert_with(VecDeque::new)
+                .push_back(RpcRequest {
+                    remote,
+                    msg_id: hdr.msg_id,
+                    method,
+                    class,
+                    payload,
+                });
+        }
+        Ok(())
+    }
+
+    /// Returns the highest scheduling class with requests waiting, if any. Applications that pop
+    /// with priorities may use it to pick the priority of their next pop.
+    pub fn next_class(&self) -> Option<usize> {
+        self.pending
+            .iter()
+            .find(|(_, requests)| !requests.is_empty())
+            .map(|(&class, _)| class)
+    }
+
+    /// Takes the next request to handle: the oldest one of the highest class.
+    pub fn next_request(&mut self) -> Option<RpcRequest> {
+        let class = self.next_class()?;
+        self.pending.get_mut(&class).unwrap().pop_front()
+    }
+
+    /// Returns the number of requests waiting to be handled.
+    pub fn num_pending(&self) -> usize {
+        self.pending.values().map(|requests| requests.len()).sum()
+    }
+
+    /// Replies to `request`. The reply is kept for a while to answer duplicates of the request.
+    pub fn reply(&mut self, request: &RpcRequest, payload: &[u8]) -> Result<(), Fail> {
+        let fragments = fragment(
+            RpcKind::Response,
+            request.method,
+            request.msg_id,
+            payload,
+            self.options.max_fragment_size(),
+        )?;
+        self.send(request.remote, &fragments)?;
+        let expires = self.rt.now() + self.options.reply_cache_timeout();
+        self.replies.insert(
+            (request.remote, request.msg_id),
+            ReplyState::Done { fragments, expires },
+        );
+        Ok(())
+    }
+
+    /// Drops incomplete requests, cached replies and records of unanswered requests that have
+    /// been kept too long.
+    pub fn poll(&mut self) {
+        let now = self.rt.now();
+        self.reassembly.expire(now);
+        self.replies.retain(|_, state| match state {
+            ReplyState::InProgress { expires } | ReplyState::Done { expires, .. } => *expires > now,
+        });
+    }
+
+    fn send(&self, remote: ipv4::Endpoint, fragments: &[Vec<u8>]) -> Result<(), Fail> {
+        for buf in fragments {
+            self.udp
+                .pushto(self.fd, RT::Buf::from_slice(buf)?, remote)?;
+        }
+        Ok(())
+    }
+}
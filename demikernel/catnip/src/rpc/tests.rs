@@ -0,0 +1,201 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT license.
+
+use super::{RpcOptions, RpcRequest};
+use crate::{
+    engine::Engine,
+    fail::Fail,
+    file_table::FileDescriptor,
+    protocols::{ip, ipv4},
+    runtime::Runtime,
+    test_helpers::{self, TestRuntime},
+};
+use futures::task::{noop_waker_ref, Context};
+use must_let::must_let;
+use std::{
+    convert::TryFrom,
+    future::Future,
+    pin::Pin,
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+//==============================================================================
+// Helpers
+//==============================================================================
+
+/// Creates a UDP socket bound to `port` on the local address of `engine`.
+fn bind(engine: &mut Engine<TestRuntime>, port: u16) -> (FileDescriptor, ipv4::Endpoint) {
+    let addr = ipv4::Endpoint::new(
+        engine.rt().local_ipv4_addr(),
+        ip::Port::try_from(port).unwrap(),
+    );
+    let fd = engine.udp_socket().unwrap();
+    engine.udp_bind(fd, addr).unwrap();
+    (fd, addr)
+}
+
+/// Delivers all frames sent by `from` to `to`. Returns the number of frames delivered.
+fn deliver(from: &mut Engine<TestRuntime>, to: &mut Engine<TestRuntime>) -> usize {
+    from.rt().poll_scheduler();
+    let mut count = 0;
+    while let Some(frame) = from.rt().pop_frame_unchecked() {
+        to.receive(frame).unwrap();
+        count += 1;
+    }
+    count
+}
+
+/// Pops all datagrams queued on socket `fd`.
+fn pop_all(engine: &mut Engine<TestRuntime>, fd: FileDescriptor) -> Vec<(ipv4::Endpoint, Vec<u8>)> {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut datagrams = vec![];
+    loop {
+        let mut pop_future = engine.udp_pop(fd);
+        match Future::poll(Pin::new(&mut pop_future), &mut ctx) {
+            Poll::Ready(Ok((Some(remote), buf))) => datagrams.push((remote, buf.to_vec())),
+            _ => return datagrams,
+        }
+    }
+}
+
+//==============================================================================
+// Call & Reply
+//==============================================================================
+
+#[test]
+fn rpc_call_reply() {
+    let now = Instant::now();
+    let options = RpcOptions::new(None, None, Some(64), None, None);
+
+    let mut alice = test_helpers::new_alice2(now);
+    let (alice_fd, alice_addr) = bind(&mut alice, 80);
+    let mut client = alice.rpc_client(alice_fd, options.clone()).unwrap();
+
+    let mut bob = test_helpers::new_bob2(now);
+    let (bob_fd, bob_addr) = bind(&mut bob, 90);
+    let mut server = bob.rpc_server(bob_fd, options).unwrap();
+    server.register_method(1, 1);
+    server.register_method(2, 0);
+
+    // A large request is fragmented, and requests of higher classes come out first.
+    let large: Vec<u8> = (0..200).map(|i| i as u8).collect();
+    let large_id = client.call(bob_addr, 1, &large).unwrap();
+    let small_id = client.call(bob_addr, 2, b"ping").unwrap();
+    assert_eq!(deliver(&mut alice, &mut bob), 5);
+    let requests = pop_all(&mut bob, bob_fd);
+    for (remote, buf) in &requests {
+        server.receive(*remote, buf).unwrap();
+    }
+    assert_eq!(server.num_pending(), 2);
+    assert_eq!(server.next_class(), Some(0));
+    must_let!(let Some(RpcRequest { method: 2, class: 0, .. }) = server.next_request());
+    let request = server.next_request().unwrap();
+    assert_eq!(request.remote, alice_addr);
+    assert_eq!(request.msg_id, large_id);
+    assert_eq!(request.payload, large);
+    assert!(server.next_request().is_none());
+
+    // The response makes it back to the client.
+    let mut response = large.clone();
+    response.reverse();
+    server.reply(&request, &response).unwrap();
+    assert_eq!(deliver(&mut bob, &mut alice), 4);
+    for (remote, buf) in pop_all(&mut alice, alice_fd) {
+        client.receive(remote, &buf).unwrap();
+    }
+    must_let!(let Some((msg_id, Ok(received))) = client.take_completion());
+    assert_eq!(msg_id, large_id);
+    assert_eq!(received, response);
+    assert!(client.take_completion().is_none());
+    assert_eq!(client.num_outstanding(), 1);
+    assert!(client.cancel(small_id));
+
+    // Duplicate requests get the cached reply, and are not handed out again.
+    for (remote, buf) in &requests[..4] {
+        server.receive(*remote, buf).unwrap();
+    }
+    assert_eq!(deliver(&mut bob, &mut alice), 4);
+    assert_eq!(server.num_pending(), 0);
+}
+
+//==============================================================================
+// Timeouts
+//==============================================================================
+
+#[test]
+fn rpc_timeout() {
+    let mut now = Instant::now();
+    let options = RpcOptions::new(Some(Duration::from_millis(1)), Some(1), None, None, None);
+
+    let mut alice = test_helpers::new_alice2(now);
+    let (alice_fd, _) = bind(&mut alice, 80);
+    let mut client = alice.rpc_client(alice_fd, options).unwrap();
+    let mut bob = test_helpers::new_bob2(now);
+    let (bob_fd, bob_addr) = bind(&mut bob, 90);
+
+    let msg_id = client.call(bob_addr, 1, b"hello").unwrap();
+    assert_eq!(deliver(&mut alice, &mut bob), 1);
+
+    // Nothing is sent again before the deadline.
+    client.poll().unwrap();
+    assert_eq!(deliver(&mut alice, &mut bob), 0);
+
+    // The call is sent again once, then fails.
+    now += Duration::from_millis(1);
+    alice.rt().advance_clock(now);
+    client.poll().unwrap();
+    assert_eq!(deliver(&mut alice, &mut bob), 1);
+    assert!(client.take_completion().is_none());
+
+    now += Duration::from_millis(1);
+    alice.rt().advance_clock(now);
+    client.poll().unwrap();
+    assert_eq!(deliver(&mut alice, &mut bob), 0);
+    must_let!(let Some((id, Err(Fail::Timeout {}))) = client.take_completion());
+    assert_eq!(id, msg_id);
+    assert_eq!(client.num_outstanding(), 0);
+
+    // Both copies of the request reached the server, which sees them as one.
+    let mut server = bob.rpc_server(bob_fd, RpcOptions::default()).unwrap();
+    server.register_method(1, 0);
+    for (remote, buf) in pop_all(&mut bob, bob_fd) {
+        server.receive(remote, &buf).unwrap();
+    }
+    assert_eq!(server.num_pending(), 1);
+}
+
+#[test]
+fn rpc_unanswered_request_expires() {
+    let mut now = Instant::now();
+    let options = RpcOptions::new(Some(Duration::from_millis(1)), Some(1), None, None, None);
+
+    let mut alice = test_helpers::new_alice2(now);
+    let (alice_fd, _) = bind(&mut alice, 80);
+    let mut client = alice.rpc_client(alice_fd, options.clone()).unwrap();
+    let mut bob = test_helpers::new_bob2(now);
+    let (bob_fd, bob_addr) = bind(&mut bob, 90);
+    let mut server = bob.rpc_server(bob_fd, options).unwrap();
+    server.register_method(1, 0);
+
+    client.call(bob_addr, 1, b"hello").unwrap();
+    assert_eq!(deliver(&mut alice, &mut bob), 1);
+    let requests = pop_all(&mut bob, bob_fd);
+    server.receive(requests[0].0, &requests[0].1).unwrap();
+    assert!(server.next_request().is_some());
+
+    // The request is never replied to. Duplicates are dropped while the client may still send
+    // them...
+    now += Duration::from_millis(1);
+    bob.rt().advance_clock(now);
+    server.poll();
+    server.receive(requests[0].0, &requests[0].1).unwrap();
+    assert_eq!(server.num_pending(), 0);
+
+    // ...and the request is forgotten once the client has given up on it.
+    now += Duration::from_millis(1);
+    bob.rt().advance_clock(now);
+    server.poll();
+    server.receive(requests[0].0, &requests[0].1).unwrap();
+    assert_eq!(server.num_pending(), 1);
+}
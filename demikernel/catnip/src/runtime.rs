@@ -1,8 +1,9 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT license.
 use crate::{
+    fail::Fail,
     interop::dmtr_sgarray_t,
-    protocols::{arp, ethernet2::MacAddress, tcp, udp},
+    protocols::{arp, ethernet2::MacAddress, ipv4, tcp, udp},
     scheduler::{Operation, Scheduler, SchedulerHandle},
 };
 use arrayvec::ArrayVec;
@@ -21,7 +22,8 @@ pub const RECEIVE_BATCH_SIZE: usize = 16;
 pub trait RuntimeBuf: Clone + Debug + Deref<Target = [u8]> + Sized + Unpin {
     fn empty() -> Self;
 
-    fn from_slice(bytes: &[u8]) -> Self;
+    /// Copies `bytes` into a new buffer. Fails if no buffer can be had for them.
+    fn from_slice(bytes: &[u8]) -> Result<Self, Fail>;
 
     /// Remove `num_bytes` from the beginning of the buffer.
     fn adjust(&mut self, num_bytes: usize);
@@ -64,6 +66,11 @@ pub trait Runtime: Clone + Unpin + 'static {
 
     fn local_link_addr(&self) -> MacAddress;
     fn local_ipv4_addr(&self) -> Ipv4Addr;
+    /// Returns the mask of the local IPv4 subnet, which tells apart subnet broadcasts. Defaults to
+    /// the classful netmask of the local address.
+    fn local_ipv4_netmask(&self) -> Ipv4Addr {
+        ipv4::classful_netmask(self.local_ipv4_addr())
+    }
     fn arp_options(&self) -> arp::Options;
     fn tcp_options(&self) -> tcp::Options<Self>;
     fn udp_options(&self) -> udp::Options;
@@ -77,6 +84,23 @@ pub trait Runtime: Clone + Unpin + 'static {
         Standard: Distribution<T>;
     fn rng_shuffle<T>(&self, slice: &mut [T]);
 
+    /// Tells the NIC that requests sent to `port` belong to application `app_id`, to be
+    /// scheduled with `priority`. Smaller numbers mean higher priority. Runtimes without a NIC
+    /// load balancer ignore it.
+    fn config_app_mat(&self, _app_id: u16, _port: u16, _priority: u8) {}
+
+    /// Tells the NIC load balancer that application `app_id` runs on `queue_id` with `priority`.
+    /// Larger numbers mean higher priority. Runtimes without a NIC load balancer ignore it.
+    fn register_app(&self, _queue_id: u16, _app_id: u16, _priority: u8) {}
+
+    /// Tells the NIC load balancer that application `app_id` no longer runs on `queue_id`.
+    /// Runtimes without a NIC load balancer ignore it.
+    fn deregister_app(&self, _queue_id: u16, _app_id: u16) {}
+
+    /// Tells the NIC load balancer that `count` requests of application `app_id` received on
+    /// `queue_id` have completed. Runtimes without a NIC load balancer ignore it.
+    fn send_app_feedback(&self, _queue_id: u16, _app_id: u16, _count: u16) {}
+
     fn spawn<F: Future<Output = ()> + 'static>(&self, future: F) -> SchedulerHandle;
     fn scheduler(&self) -> &Scheduler<Operation<Self>>;
 }
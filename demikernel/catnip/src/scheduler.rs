@@ -19,6 +19,8 @@
 //
 use crate::{
     collections::waker_page::{WakerPage, WakerPageRef, WAKER_PAGE_SIZE},
+    file_table::FileDescriptor,
+    operations::OperationResult,
     protocols::{tcp::operations::TcpOperation, udp::UdpOperation},
     runtime::Runtime,
     sync::SharedWaker,
@@ -34,6 +36,9 @@ use std::{
 use bit_iter::*;
 use unicycle::pin_slab::PinSlab;
 
+/// Default number of tasks polled on each page per call to [Scheduler::poll].
+pub const DEFAULT_POLL_BUDGET: usize = WAKER_PAGE_SIZE;
+
 /// The different types of operations our [Scheduler] can hold and multiplex between.
 ///
 /// [Operation]s are tasks (top-level futures which are managed by our scheduler). This is
@@ -66,12 +71,52 @@ impl<RT: Runtime> Future for Operation<RT> {
     }
 }
 
+impl<RT: Runtime> Operation<RT> {
+    /// Returns the result of a completed operation along with the file descriptor it ran on.
+    ///
+    /// This function will panic if the operation had not completed or is a _background_ task.
+    pub fn expect_result(self) -> (FileDescriptor, OperationResult<RT>) {
+        match self {
+            Operation::Tcp(f) => f.expect_result(),
+            Operation::Udp(f) => f.expect_result(),
+            Operation::Background(..) => panic!("`expect_result` attempted on background task!"),
+        }
+    }
+}
+
+impl<RT: Runtime> SchedulerTask for Operation<RT> {
+    fn kind(&self) -> TaskKind {
+        match self {
+            Operation::Tcp(..) => TaskKind::Tcp,
+            Operation::Udp(..) => TaskKind::Udp,
+            Operation::Background(..) => TaskKind::Background,
+        }
+    }
+}
+
 impl<T: Into<TcpOperation<RT>>, RT: Runtime> From<T> for Operation<RT> {
     fn from(f: T) -> Self {
         Operation::Tcp(f.into())
     }
 }
 
+/// Kinds of tasks held by the [Scheduler], as told apart by its instrumentation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskKind {
+    Tcp,
+    Udp,
+    Background,
+    Other,
+}
+
+/// Tasks held by the [Scheduler].
+pub trait SchedulerTask {
+    /// Returns the kind of this task.
+    fn kind(&self) -> TaskKind {
+        TaskKind::Other
+    }
+}
+
 /// Handle returned by the scheduler once a future has been added. This handle uniquely identifies
 /// a future to the scheduler.
 #[allow(rustdoc::private_intra_doc_links)]
@@ -105,13 +150,101 @@ impl Drop for SchedulerHandle {
     }
 }
 
+/// Statistics gathered by [Scheduler::poll].
+#[derive(Clone, Debug, Default)]
+pub struct SchedulerStats {
+    /// Number of calls to [Scheduler::poll].
+    pub polls: u64,
+    /// Number of tasks polled on each page.
+    pub polled_per_page: Vec<u64>,
+    /// Number of dropped tasks removed from the scheduler.
+    pub reclaimed: u64,
+}
+
+/// Per-task statistics gathered by the [Scheduler] when built with the `scheduler-stats` feature.
+/// Latencies are measured in TSC cycles.
+#[cfg(feature = "scheduler-stats")]
+#[derive(Clone, Debug, Default)]
+pub struct TaskStats {
+    /// Number of live tasks on each page, that is, at each priority.
+    pub live_per_priority: Vec<usize>,
+    /// Number of polls of TCP operations.
+    pub tcp_polls: u64,
+    /// Number of polls of UDP operations.
+    pub udp_polls: u64,
+    /// Number of polls of background tasks.
+    pub background_polls: u64,
+    /// Number of polls of tasks of any other kind.
+    pub other_polls: u64,
+    /// Number of measured wake-to-poll latencies.
+    pub wake_to_poll_samples: u64,
+    /// Sum of the measured wake-to-poll latencies.
+    pub wake_to_poll_total_cycles: u64,
+    /// Largest measured wake-to-poll latency.
+    pub wake_to_poll_max_cycles: u64,
+}
+
+/// Associate functions for [TaskStats].
+#[cfg(feature = "scheduler-stats")]
+impl TaskStats {
+    /// Returns the number of polls of tasks of the given kind.
+    pub fn polls(&self, kind: TaskKind) -> u64 {
+        match kind {
+            TaskKind::Tcp => self.tcp_polls,
+            TaskKind::Udp => self.udp_polls,
+            TaskKind::Background => self.background_polls,
+            TaskKind::Other => self.other_polls,
+        }
+    }
+
+    /// Returns the mean wake-to-poll latency.
+    pub fn mean_wake_to_poll_cycles(&self) -> u64 {
+        if self.wake_to_poll_samples == 0 {
+            return 0;
+        }
+        self.wake_to_poll_total_cycles / self.wake_to_poll_samples
+    }
+
+    fn on_insert(&mut self, page_ix: usize) {
+        if self.live_per_priority.len() <= page_ix {
+            self.live_per_priority.resize(page_ix + 1, 0);
+        }
+        self.live_per_priority[page_ix] += 1;
+    }
+
+    fn on_remove(&mut self, page_ix: usize) {
+        self.live_per_priority[page_ix] -= 1;
+    }
+
+    fn on_poll(&mut self, kind: TaskKind, notified_at: u64) {
+        match kind {
+            TaskKind::Tcp => self.tcp_polls += 1,
+            TaskKind::Udp => self.udp_polls += 1,
+            TaskKind::Background => self.background_polls += 1,
+            TaskKind::Other => self.other_polls += 1,
+        }
+        let latency = crate::preemption::rdtsc().saturating_sub(notified_at);
+        self.wake_to_poll_samples += 1;
+        self.wake_to_poll_total_cycles += latency;
+        self.wake_to_poll_max_cycles = std::cmp::max(self.wake_to_poll_max_cycles, latency);
+    }
+
+    /// Resets the counters, but not the live task counts.
+    fn reset(&mut self) {
+        *self = Self {
+            live_per_priority: self.live_per_priority.clone(),
+            ..Self::default()
+        };
+    }
+}
+
 /// The scheduler
 /// runs on a single thread multiplexing between all available work.
-pub struct Scheduler<F: Future<Output = ()> + Unpin> {
+pub struct Scheduler<F: Future<Output = ()> + Unpin + SchedulerTask> {
     inner: Rc<RefCell<Inner<F>>>,
 }
 
-impl<F: Future<Output = ()> + Unpin> Clone for Scheduler<F> {
+impl<F: Future<Output = ()> + Unpin + SchedulerTask> Clone for Scheduler<F> {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
@@ -119,19 +252,23 @@ impl<F: Future<Output = ()> + Unpin> Clone for Scheduler<F> {
     }
 }
 
-impl<F: Future<Output = ()> + Unpin> Default for Scheduler<F> {
+impl<F: Future<Output = ()> + Unpin + SchedulerTask> Default for Scheduler<F> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<F: Future<Output = ()> + Unpin> Scheduler<F> {
+impl<F: Future<Output = ()> + Unpin + SchedulerTask> Scheduler<F> {
     /// New empty scheduler with default settings.
     pub fn new() -> Self {
         let inner = Inner {
             slab: PinSlab::new(),
             pages: vec![],
             root_waker: SharedWaker::new(),
+            poll_budget: DEFAULT_POLL_BUDGET,
+            stats: SchedulerStats::default(),
+            #[cfg(feature = "scheduler-stats")]
+            task_stats: TaskStats::default(),
         };
         Self {
             inner: Rc::new(RefCell::new(inner)),
@@ -145,6 +282,8 @@ impl<F: Future<Output = ()> + Unpin> Scheduler<F> {
         let (page, subpage_ix) = inner.page(key);
         assert!(!page.was_dropped(subpage_ix));
         page.clear(subpage_ix);
+        #[cfg(feature = "scheduler-stats")]
+        inner.task_stats.on_remove(key as usize / WAKER_PAGE_SIZE);
         // println!("page take: {}, {}, {}", key, subpage_ix, key%WAKER_PAGE_SIZE as u64);
         inner.slab.remove_unpin((key%WAKER_PAGE_SIZE as u64 ) as usize).unwrap()
     }
@@ -183,81 +322,134 @@ impl<F: Future<Output = ()> + Unpin> Scheduler<F> {
         }
     }
 
+    /// Sets the maximum number of tasks polled on each page per call to [poll](Self::poll).
+    /// Notified tasks over the budget stay notified and are polled on the next call.
+    pub fn set_poll_budget(&self, budget: usize) {
+        assert!(budget > 0);
+        self.inner.borrow_mut().poll_budget = budget;
+    }
+
+    /// Returns the statistics gathered so far.
+    pub fn stats(&self) -> SchedulerStats {
+        self.inner.borrow().stats.clone()
+    }
+
+    /// Returns the per-task statistics gathered so far.
+    #[cfg(feature = "scheduler-stats")]
+    pub fn task_stats(&self) -> TaskStats {
+        self.inner.borrow().task_stats.clone()
+    }
+
+    /// Resets the statistics gathered so far.
+    pub fn reset_stats(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.stats = SchedulerStats::default();
+        #[cfg(feature = "scheduler-stats")]
+        inner.task_stats.reset();
+    }
+
     /// Poll all futures which are ready to run again. Tasks in our scheduler are notified when
     /// relevant data or events happen. The relevant event have callback function (the waker) which
     /// they can invoke to notify the scheduler that future should be polled again.
+    ///
+    /// Pages are visited in order, so tasks on lower pages (higher priority) run first, but every
+    /// page gets to poll up to the poll budget on each call.
     pub fn poll(&self) {
         let mut inner = self.inner.borrow_mut();
         // inner.root_waker.register(ctx.waker());
 
+        let num_pages = inner.pages.len();
+        if inner.stats.polled_per_page.len() < num_pages {
+            inner.stats.polled_per_page.resize(num_pages, 0);
+        }
+        inner.stats.polls += 1;
+
         // TODO rewrite this loop to use high-level iterators instead of indexes.
-        // Iterate through all our pages finding the tasks that are ready to be polled again
-        // (notified) and dropped tasks which can be removed.
-        // println!("Enter sche----");
-        // let  mut check_if = 0;
-        for page_ix in 0..inner.pages.len() {
+        // Iterate through all our pages, removing the dropped tasks and polling the tasks that
+        // are ready to be polled again (notified).
+        for page_ix in 0..num_pages {
             let (notified, dropped) = {
                 let page = &mut inner.pages[page_ix];
-                (page.take_notified(), page.take_dropped())
+                let dropped = page.take_dropped();
+                (page.take_notified() & !dropped, dropped)
             };
-            // Non-zero means at least one future in this page should be polled.
-            if notified != 0 {
-                // check_if =1;
-                // Iterate through this page's bit vector polling the futures that are ready.
-                for subpage_ix in BitIter::from(notified) {
-                    // println!("Check {}, {}", page_ix, subpage_ix);
-                    if subpage_ix != 0 {
-                        // Get future using our page indices and poll it!
-                        let ix = page_ix * WAKER_PAGE_SIZE + subpage_ix;
-                        let waker =
-                            unsafe { Waker::from_raw(inner.pages[page_ix].raw_waker(subpage_ix)) };
-                        let mut sub_ctx = Context::from_waker(&waker);
-                        // println!("cal ori {}, offset {}", ix, ix%WAKER_PAGE_SIZE);
-                        let pinned_ref = inner.slab.get_pin_mut((ix%WAKER_PAGE_SIZE ) as usize ).unwrap();
-                        let pinned_ptr = unsafe { Pin::into_inner_unchecked(pinned_ref) as *mut _ };
-
-                        drop(inner);
-                        let pinned_ref = unsafe { Pin::new_unchecked(&mut *pinned_ptr) };
-                        let poll_result = { Future::poll(pinned_ref, &mut sub_ctx) };
-                        inner = self.inner.borrow_mut();
-
-                        match poll_result {
-                            Poll::Ready(()) => inner.pages[page_ix].mark_completed(subpage_ix),
-                            Poll::Pending => (),
-                        }
-                    }
-                }
-                break;
-            }
+
+            // Reclaim dropped tasks first, so that they are never polled again.
             if dropped != 0 {
-                println!("Entered dropped");
                 for subpage_ix in BitIter::from(dropped) {
                     if subpage_ix != 0 {
                         let ix = page_ix * WAKER_PAGE_SIZE + subpage_ix;
                         inner.slab.remove((ix%WAKER_PAGE_SIZE ) as usize);
                         inner.pages[page_ix].clear(subpage_ix);
+                        inner.stats.reclaimed += 1;
+                        #[cfg(feature = "scheduler-stats")]
+                        inner.task_stats.on_remove(page_ix);
                     }
                 }
             }
-        }
 
-        // if(check_if == 1){
-        //     println!("------------");
-        // }
+            // Non-zero means at least one future in this page should be polled.
+            if notified == 0 {
+                continue;
+            }
+            let budget = inner.poll_budget;
+            let mut num_polled = 0;
+            // Iterate through this page's bit vector polling the futures that are ready.
+            for subpage_ix in BitIter::from(notified) {
+                if subpage_ix == 0 {
+                    continue;
+                }
+                if num_polled == budget {
+                    // Out of budget: hand the remaining notifications over to the next poll.
+                    let remaining = notified & (!0u64 << subpage_ix);
+                    inner.pages[page_ix].restore_notified(remaining);
+                    break;
+                }
+                // Get future using our page indices and poll it!
+                let ix = page_ix * WAKER_PAGE_SIZE + subpage_ix;
+                #[cfg(feature = "scheduler-stats")]
+                {
+                    let kind = inner.slab.get((ix%WAKER_PAGE_SIZE ) as usize).unwrap().kind();
+                    let notified_at = inner.pages[page_ix].notified_at(subpage_ix);
+                    inner.task_stats.on_poll(kind, notified_at);
+                }
+                let waker = unsafe { Waker::from_raw(inner.pages[page_ix].raw_waker(subpage_ix)) };
+                let mut sub_ctx = Context::from_waker(&waker);
+                let pinned_ref = inner.slab.get_pin_mut((ix%WAKER_PAGE_SIZE ) as usize ).unwrap();
+                let pinned_ptr = unsafe { Pin::into_inner_unchecked(pinned_ref) as *mut _ };
+
+                drop(inner);
+                let pinned_ref = unsafe { Pin::new_unchecked(&mut *pinned_ptr) };
+                let poll_result = { Future::poll(pinned_ref, &mut sub_ctx) };
+                inner = self.inner.borrow_mut();
+
+                match poll_result {
+                    Poll::Ready(()) => inner.pages[page_ix].mark_completed(subpage_ix),
+                    Poll::Pending => (),
+                }
+                num_polled += 1;
+            }
+            inner.stats.polled_per_page[page_ix] += num_polled as u64;
+        }
     }
 }
 
 /// Actual data used by [Scheduler].
-struct Inner<F: Future<Output = ()> + Unpin> {
+struct Inner<F: Future<Output = ()> + Unpin + SchedulerTask> {
     /// Tasks are held by the scheduler in this memory slab.
     slab: PinSlab<F>,
     /// Holds the current status of which tasks are ready to be polled (scheduled) again.
     /// The statuses are arranged in pages.
     pages: Vec<WakerPageRef>,
     root_waker: SharedWaker,
+    /// Maximum number of tasks polled on each page per call to [Scheduler::poll].
+    poll_budget: usize,
+    stats: SchedulerStats,
+    #[cfg(feature = "scheduler-stats")]
+    task_stats: TaskStats,
 }
 
-impl<F: Future<Output = ()> + Unpin> Inner<F> {
+impl<F: Future<Output = ()> + Unpin + SchedulerTask> Inner<F> {
     /// Our pages hold 64 contiguous future wakers, so we can do simple arithmetic to access the
     /// correct page as well as the index within page.
     /// Given the `key` representing a future, return a reference to that page, `WakerPageRef`. And
@@ -280,6 +472,8 @@ impl<F: Future<Output = ()> + Unpin> Inner<F> {
         // println!("INsert SChedduler: {}", self.pages.len());
         let (page, subpage_ix) = self.page(key as u64);
         page.initialize(subpage_ix);
+        #[cfg(feature = "scheduler-stats")]
+        self.task_stats.on_insert(key / WAKER_PAGE_SIZE);
         key as u64
     }
 
@@ -294,6 +488,106 @@ impl<F: Future<Output = ()> + Unpin> Inner<F> {
         // println!("Prio {} INsert SChedduler: {}, {}",prio, key, self.pages.len());
         let (page, subpage_ix) = self.page(key as u64);
         page.initialize(subpage_ix);
+        #[cfg(feature = "scheduler-stats")]
+        self.task_stats.on_insert(key / WAKER_PAGE_SIZE);
         key as u64
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Scheduler, SchedulerTask};
+    use std::{
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    /// Future that is never ready. If `spin` is set, it wakes itself up on every poll.
+    struct Pending {
+        spin: bool,
+    }
+
+    impl SchedulerTask for Pending {}
+
+    impl Future for Pending {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<()> {
+            if self.spin {
+                ctx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn test_poll_visits_all_pages() {
+        let scheduler = Scheduler::new();
+        // The first slot of a page is never polled.
+        let mut handles = vec![scheduler.insert_page(Pending { spin: false }, 0)];
+        for _ in 0..3 {
+            handles.push(scheduler.insert_page(Pending { spin: false }, 0));
+        }
+        for _ in 0..2 {
+            handles.push(scheduler.insert_page(Pending { spin: false }, 1));
+        }
+        scheduler.set_poll_budget(2);
+
+        // Page 0 does not keep page 1 from being polled.
+        scheduler.poll();
+        assert_eq!(scheduler.stats().polled_per_page, [2, 2]);
+
+        // The task over budget on page 0 is polled on the next call.
+        scheduler.reset_stats();
+        scheduler.poll();
+        assert_eq!(scheduler.stats().polled_per_page, [1, 0]);
+
+        for handle in handles {
+            handle.into_raw();
+        }
+    }
+
+    #[cfg(feature = "scheduler-stats")]
+    #[test]
+    fn test_task_stats() {
+        use super::TaskKind;
+
+        let scheduler = Scheduler::new();
+        let first = scheduler.insert_page(Pending { spin: false }, 0);
+        let a = scheduler.insert_page(Pending { spin: false }, 1);
+        let b = scheduler.insert_page(Pending { spin: false }, 1);
+        assert_eq!(scheduler.task_stats().live_per_priority, [1, 2]);
+
+        scheduler.poll();
+        let stats = scheduler.task_stats();
+        assert_eq!(stats.polls(TaskKind::Other), 2);
+        assert_eq!(stats.wake_to_poll_samples, 2);
+
+        drop(a);
+        scheduler.poll();
+        assert_eq!(scheduler.task_stats().live_per_priority, [1, 1]);
+
+        first.into_raw();
+        b.into_raw();
+    }
+
+    #[test]
+    fn test_poll_reclaims_dropped() {
+        let scheduler = Scheduler::new();
+        let first = scheduler.insert_page(Pending { spin: false }, 0);
+        let a = scheduler.insert_page(Pending { spin: true }, 0);
+        let b = scheduler.insert_page(Pending { spin: true }, 0);
+        scheduler.poll();
+
+        // The notified task `b` keeps the page busy, `a` must still be reclaimed.
+        drop(a);
+        scheduler.poll();
+        let stats = scheduler.stats();
+        assert_eq!(stats.reclaimed, 1);
+        assert_eq!(stats.polled_per_page, [3]);
+
+        first.into_raw();
+        b.into_raw();
+    }
+}
@@ -76,6 +76,7 @@ impl TestRuntime {
             ipv4_addr,
             tcp_options,
             arp_options,
+            feedback: Vec::new(),
         };
         Self {
             inner: Rc::new(RefCell::new(inner)),
@@ -95,6 +96,15 @@ impl TestRuntime {
         self.inner.borrow_mut().incoming.push_back(buf);
     }
 
+    /// Sets the TCP options of connections opened from now on.
+    pub fn set_tcp_options(&self, options: tcp::Options<TestRuntime>) {
+        self.inner.borrow_mut().tcp_options = options;
+    }
+
+    pub fn take_feedback(&self) -> Vec<(u16, u16, u16)> {
+        self.inner.borrow_mut().feedback.split_off(0)
+    }
+
     pub fn poll_scheduler(&self) {
         // let mut ctx = Context::from_waker(noop_waker_ref());
         self.scheduler.poll();
@@ -113,6 +123,8 @@ struct Inner {
     ipv4_addr: Ipv4Addr,
     tcp_options: tcp::Options<TestRuntime>,
     arp_options: arp::Options,
+    /// Load feedback sent to the NIC, as `(queue_id, app_id, count)`.
+    feedback: Vec<(u16, u16, u16)>,
 }
 
 impl Runtime for TestRuntime {
@@ -217,6 +229,10 @@ impl Runtime for TestRuntime {
         self.inner.borrow().ipv4_addr
     }
 
+    fn local_ipv4_netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::new(255, 255, 255, 0)
+    }
+
     fn tcp_options(&self) -> tcp::Options<TestRuntime> {
         self.inner.borrow().tcp_options.clone()
     }
@@ -264,6 +280,13 @@ impl Runtime for TestRuntime {
         slice.shuffle(&mut inner.rng);
     }
 
+    fn send_app_feedback(&self, queue_id: u16, app_id: u16, count: u16) {
+        self.inner
+            .borrow_mut()
+            .feedback
+            .push((queue_id, app_id, count));
+    }
+
     fn spawn<F: Future<Output = ()> + 'static>(&self, future: F) -> SchedulerHandle {
         self.scheduler
             .insert(Operation::Background(future.boxed_local()))
@@ -281,13 +304,18 @@ pub fn new_bob(now: Instant) -> Engine<TestRuntime> {
 }
 
 pub fn new_alice2(now: Instant) -> Engine<TestRuntime> {
+    Engine::new(new_alice2_runtime(now)).unwrap()
+}
+
+/// Returns the runtime of [new_alice2], for tests that build more than an engine on top of it.
+pub fn new_alice2_runtime(now: Instant) -> TestRuntime {
     let rt = TestRuntime::new("alice", now, ALICE_MAC, ALICE_IPV4);
     {
         let arp_options: &mut _ = &mut rt.inner.borrow_mut().arp_options;
         arp_options.initial_values.insert(ALICE_IPV4, ALICE_MAC);
         arp_options.initial_values.insert(BOB_IPV4, BOB_MAC);
     }
-    Engine::new(rt).unwrap()
+    rt
 }
 
 pub fn new_bob2(now: Instant) -> Engine<TestRuntime> {
@@ -202,6 +202,10 @@ impl Runtime for DummyRuntime {
         self.inner.borrow().ipv4_addr.clone()
     }
 
+    fn local_ipv4_netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::new(255, 255, 255, 0)
+    }
+
     fn tcp_options(&self) -> tcp::Options<Self> {
         self.inner.borrow().tcp_options.clone()
     }
@@ -5,8 +5,10 @@
 // use crate::memory::{MemoryConfig, MemoryManager};
 use anyhow::Error;
 use catnip::{
+    apps::AppSpec,
     libos::LibOS,
     operations::OperationResult,
+    preemption::{PreemptBudget, PreemptToken},
     scheduler::SchedulerHandle,
 };
 use catnip_libos::memory::Ixybuf;
@@ -29,7 +31,10 @@ use catnip::{
     protocols::{
         ethernet2::MacAddress,
         ip::Port,
-        ipv4::Endpoint,
+        ipv4::{
+            classful_netmask,
+            Endpoint,
+        },
     },
     runtime::Runtime,
 };
@@ -101,6 +106,10 @@ impl Config {
         if local_ipv4_addr.is_unspecified() || local_ipv4_addr.is_broadcast() {
             Err(format_err!("Invalid IPv4 address"))?;
         }
+        let local_ipv4_netmask: Ipv4Addr = match config_obj["catnip"]["my_ipv4_netmask"].as_str() {
+            Some(netmask) => netmask.parse()?,
+            None => classful_netmask(local_ipv4_addr),
+        };
 
         let remote_ipv4_addr: Ipv4Addr = config_obj["catnip"]["remote_ipv4_addr"]
             .as_str()
@@ -146,6 +155,7 @@ impl Config {
             // memory,
             local_link_addr,
             local_ipv4_addr,
+            local_ipv4_netmask,
             arp_table,
             disable_arp,
             use_jumbo_frames,
@@ -192,7 +202,6 @@ fn run_threads(dev: Ixydev, config_path2: String, queue_id: u16) {
     let mut client_addr = config.addr("server", "client").unwrap();
 
     let mut app_count = 0;
-    let mut fd_to_appid: HashMap<u32, u16> = HashMap::new();
 
     let mut libos = LibOS::new(runtime.clone(), queue_id as usize, CORE_COUNT).unwrap();
 
@@ -201,39 +210,45 @@ fn run_threads(dev: Ixydev, config_path2: String, queue_id: u16) {
     // app 2's socket
     let sockfd2 = libos.socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
 
-    // Config app1.
-    let app_id_1 = 1;
-    let local_addr1 = config.new_port("server", "bind", 5678).unwrap();
-    fd_to_appid.insert(sockfd1, app_id_1);
-
-    // Config NIC's MAT pipeline config_app_mat(appid, port number, priority).
-    // Smaller priority number means this applilcation has higher priority in the requst scheduler.
-    runtime.config_app_mat(app_id_1, 5678, 1);
-
-    // Config NIC's load monitor. Load monitor is not enabled in this testbench.
-    // runtime.config_monitor(app_id_1, 10);
-
-    // Config NIC's load balancer. Tell the NIC that this application is running on core X, with priority Y.
-    // Note that in the load balancer, higher priority number means that this applilcation has higher priority when calculating the rank.
+    // Config app1. This configures the NIC's MAT pipeline (requests to `port` go to this
+    // application), the NIC's load balancer (this application runs on this core with
+    // `nic_priority`), the core allocator and load feedback for replies pushed to the socket.
+    // Smaller `sched_priority` means this application has higher priority in the request
+    // scheduler, while larger `nic_priority` means it ranks higher in the load balancer.
     // TODO: We should make the load balancer use the same priority order as the scheduler. This should be fixed in the future.
-    runtime.register_app(queue_id, app_id_1, 1);
-
-    // Config libos.
-    libos.core_alloc_reg_app(queue_id, app_id_1);
+    let app1 = AppSpec {
+        app_id: 1,
+        port: 5678,
+        nic_priority: 1,
+        sched_priority: 1,
+    };
+    let local_addr1 = config.new_port("server", "bind", app1.port).unwrap();
+    libos.bind_app(sockfd1, app1).unwrap();
     libos.bind(sockfd1, local_addr1).unwrap();
     app_count += 1;
 
+    // Config NIC's load monitor. Load monitor is not enabled in this testbench.
+    // runtime.config_monitor(app1.app_id, 10);
+
     // Same as app1, config app2.
-    let app_id_2 = 2;
-    let local_addr2 = config.new_port("server", "bind", 1234).unwrap();
-    fd_to_appid.insert(sockfd2, app_id_2);
-    runtime.config_app_mat(app_id_2, 1234, 2);
-    // runtime.config_monitor(app_id_2, 10);
-    libos.core_alloc_reg_app(queue_id, app_id_2);
-    runtime.register_app(queue_id, app_id_2, 0);
+    let app2 = AppSpec {
+        app_id: 2,
+        port: 1234,
+        nic_priority: 0,
+        sched_priority: 2,
+    };
+    let local_addr2 = config.new_port("server", "bind", app2.port).unwrap();
+    libos.bind_app(sockfd2, app2).unwrap();
     libos.bind(sockfd2, local_addr2).unwrap();
     app_count += 1;
 
+    // Report completed requests to the NIC load balancer, in batches of FEEDBACK_BATCH.
+    libos.set_feedback_batch(FEEDBACK_BATCH).unwrap();
+
+    // Config preemption budgets. App 2 runs on a shorter budget while app 1 has pending requests.
+    libos.config_preempt(app1.app_id, sockfd1, app1.sched_priority as usize, PreemptBudget::new(PREEMPT_BUDGET_CYCLES, PREEMPT_BUDGET_CYCLES));
+    libos.config_preempt(app2.app_id, sockfd2, app2.sched_priority as usize, PreemptBudget::new(PREEMPT_BUDGET_CYCLES, PREEMPT_CONTENDED_BUDGET_CYCLES));
+
     let ten_millis = time::Duration::from_millis(10);
     thread::sleep(ten_millis);
 
@@ -242,7 +257,7 @@ fn run_threads(dev: Ixydev, config_path2: String, queue_id: u16) {
 
     // insert application's sche token into libos
     // app 1's request has priority 0, which is the highest priority
-    hi_qtokens.push(libos.popbatch(sockfd1, 0).unwrap());
+    hi_qtokens.push(libos.popbatch(sockfd1, 0, MAX_BATCH, MAX_BATCH_WAIT).unwrap());
     // app 2's request has priority 2, which is the lowest priority
     lo_qtokens.push(libos.popprio(sockfd2, 2).unwrap());
     // when app 2 yield, the uncompleted request will have higher priority than app 2's other requsts.
@@ -285,12 +300,11 @@ fn run_threads(dev: Ixydev, config_path2: String, queue_id: u16) {
                 // we assume only app 2 is using pop, app 1 is using pop batch
                 assert!(qtoken_group == 1);
 
-                // App 1 could preempt App 2, so app 2 need yield back to coroutine every fixed interval.
-                let mut if_finish: u8 = 0;
-                unsafe {
-                    if_finish =
-                        process_work(buf.buf_addr_phy(), 1 as u8, (app_count > 1) as u8, 5000);
-                }
+                // App 1 could preempt App 2, so app 2 need yield back to coroutine once its budget runs out.
+                let app_id = libos.app_spec(fd).unwrap().app_id;
+                let token = libos.start_request(app_id);
+                let if_finish = run_with_budget(&buf, app_count > 1, &token);
+                libos.finish_request();
 
                 // If this request completes.
                 if (if_finish == 1) {
@@ -298,17 +312,13 @@ fn run_threads(dev: Ixydev, config_path2: String, queue_id: u16) {
                         Some(endpoint) => client_addr.port = endpoint.port,
                         None => todo!(),
                     }
-                    let app_id = fd_to_appid.get(fd.borrow()).unwrap();
                     let size = buf.data_length;
 
                     // reinsert token.
                     lo_qtokens.push(libos.popprio(fd, 2).unwrap());
 
-                    // send response
+                    // send response, which also reports the request to the NIC.
                     libos.directpushto2(fd, buf, client_addr).unwrap();
-
-                    // load feedback to NIC
-                    runtime.send_app_feedback(queue_id, *app_id, 1);
                     count = count + 1;
                     pkcounter = pkcounter + 1;
                 } else {
@@ -331,13 +341,11 @@ fn run_threads(dev: Ixydev, config_path2: String, queue_id: u16) {
                 assert!(qtoken_group == 0);
 
                 let bn = bufs.len() as u16;
-                let app_id = fd_to_appid.get(fd.borrow()).unwrap();
 
                 // reinsert token.
-                hi_qtokens.push(libos.popbatch(fd, 0).unwrap());
+                hi_qtokens.push(libos.popbatch(fd, 0, MAX_BATCH, MAX_BATCH_WAIT).unwrap());
 
                 let mut send_buf: Vec<(Endpoint, Ixybuf)> = Vec::with_capacity(bufs.len());
-                let mut batched_feedback = 0;
 
                 for (sender, buf) in bufs {
                     unsafe {
@@ -350,18 +358,9 @@ fn run_threads(dev: Ixydev, config_path2: String, queue_id: u16) {
                         },
                         None => todo!(),
                     }
-                    batched_feedback = batched_feedback + 1;
-                    if (batched_feedback >= 2) {
-                        runtime.send_app_feedback(queue_id, *app_id, batched_feedback);
-                        batched_feedback = 0;
-                    }
-
                     send_buf.push((client_addr, buf));
                 }
-                if (batched_feedback > 0) {
-                    runtime.send_app_feedback(queue_id, *app_id, batched_feedback);
-                }
-                // send response in batch
+                // send response in batch, which also reports the requests to the NIC.
                 libos.directbatchpushto2(fd, send_buf);
 
                 batch_count += 1;
@@ -375,10 +374,10 @@ fn run_threads(dev: Ixydev, config_path2: String, queue_id: u16) {
                 let r = context.get(0).unwrap();
                 let buf = &(*r).1;
 
-                let mut if_finish: u8 = 0;
-                unsafe {
-                    if_finish = process_work(buf.buf_addr_phy(), 1 as u8, 1 as u8, 5000);
-                }
+                let app_id = libos.app_spec(fd).unwrap().app_id;
+                let token = libos.start_request(app_id);
+                let if_finish = run_with_budget(buf, true, &token);
+                libos.finish_request();
 
                 if (if_finish == 1) {
                     let r = context.pop().unwrap();
@@ -387,14 +386,12 @@ fn run_threads(dev: Ixydev, config_path2: String, queue_id: u16) {
 
                     client_addr.port = sender.port;
 
-                    let app_id = fd_to_appid.get(fd.borrow()).unwrap();
                     let size = buf.data_length;
 
                     lo_qtokens.push(libos.popprio(fd, 2).unwrap());
 
                     libos.directpushto2(fd, buf, client_addr).unwrap();
 
-                    runtime.send_app_feedback(queue_id, *app_id, 1);
                     count = count + 1;
                     pkcounter = pkcounter + 1;
                     if_yield = 0;
@@ -432,6 +429,32 @@ fn run_threads(dev: Ixydev, config_path2: String, queue_id: u16) {
 
 const CORE_COUNT: u16 = 32;
 
+// Preemption budgets, in TSC cycles.
+const PREEMPT_BUDGET_CYCLES: u64 = 50_000;
+const PREEMPT_CONTENDED_BUDGET_CYCLES: u64 = 10_000;
+// Iterations of `process_work` between two budget checks.
+const PREEMPT_CHECK_ITERS: u32 = 500;
+
+// Largest batch of requests popped at once, and how long to wait for a batch to fill up.
+const MAX_BATCH: usize = 32;
+const MAX_BATCH_WAIT: Duration = Duration::from_micros(0);
+
+// Completed requests reported to the NIC load balancer at once.
+const FEEDBACK_BATCH: u16 = 2;
+
+// Runs the request in `buf` until it completes or, if `preempt` is set, until its budget runs out.
+// Returns 1 if the request completed.
+fn run_with_budget(buf: &Ixybuf, preempt: bool, token: &PreemptToken) -> u8 {
+    loop {
+        let if_finish = unsafe {
+            process_work(buf.buf_addr_phy(), 1 as u8, preempt as u8, PREEMPT_CHECK_ITERS)
+        };
+        if if_finish == 1 || !preempt || token.should_yield() {
+            return if_finish;
+        }
+    }
+}
+
 fn main() -> Result<(), Error> {
     let config_path = env::args()
         .nth(1)
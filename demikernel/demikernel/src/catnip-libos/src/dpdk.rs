@@ -23,6 +23,7 @@ pub fn create_runtime(
     // memory_manager: MemoryManager,
     local_link_addr: MacAddress,
     local_ipv4_addr: Ipv4Addr,
+    local_ipv4_netmask: Ipv4Addr,
     arp_table: HashMap<Ipv4Addr, MacAddress>,
     disable_arp: bool,
     use_jumbo_frames: bool,
@@ -38,6 +39,7 @@ pub fn create_runtime(
     Ok(IxyRuntime::new(
         local_link_addr,
         local_ipv4_addr,
+        local_ipv4_netmask,
         arp_table,
         disable_arp,
         mss,
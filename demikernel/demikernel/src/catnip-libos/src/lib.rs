@@ -236,13 +236,10 @@ pub extern "C" fn dmtr_bind(qd: c_int, saddr: *const sockaddr, size: socklen_t)
         return libc::EINVAL;
     }
     let saddr_in = unsafe { *mem::transmute::<*const sockaddr, *const libc::sockaddr_in>(saddr) };
-    let mut addr = Ipv4Addr::from(u32::from_be_bytes(saddr_in.sin_addr.s_addr.to_le_bytes()));
+    let addr = Ipv4Addr::from(u32::from_be_bytes(saddr_in.sin_addr.s_addr.to_le_bytes()));
     let port = ip::Port::try_from(u16::from_be(saddr_in.sin_port)).unwrap();
 
     with_libos(|libos| {
-        if addr.is_unspecified() {
-            addr = libos.rt().local_ipv4_addr();
-        }
         let endpoint = ipv4::Endpoint::new(addr, port);
         match libos.bind(qd as FileDescriptor, endpoint) {
             Ok(..) => 0,
@@ -1,4 +1,5 @@
-use catnip::runtime::RuntimeBuf;
+use catnip::{fail::Fail, runtime::RuntimeBuf};
+use std::cell::Cell;
 use std::ptr;
 use std::slice;
 use std::sync::Arc;
@@ -7,6 +8,23 @@ use ixy_rs::{
     ixy_tx_batch,memory_allocate_mempool,ixy_rx_batch,ixy_init,pkt_buf_alloc,pkt_buf_free, ixy_device, mempool,pkt_buf,
 };
 
+/// Size of a mempool entry.
+const ENTRY_SIZE: usize = 2048;
+/// Bytes in front of the data of a mempool entry, taken by the `pkt_buf` fields.
+const PKT_BUF_HEADER_SIZE: usize = 64;
+/// Bytes left in front of the data of allocated buffers, for the headers prepended on transmit.
+const HEADROOM: usize = 192;
+
+thread_local! {
+    // The mempool is not thread-safe: buffers are allocated and freed on the core that owns it.
+    static LOCAL_MEMPOOL: Cell<*mut mempool> = Cell::new(ptr::null_mut());
+}
+
+/// Sets the mempool that buffers created on the calling thread are allocated from.
+pub fn set_local_mempool(mempool: *mut mempool) {
+    LOCAL_MEMPOOL.with(|m| m.set(mempool));
+}
+
 #[derive(Debug)]
 pub struct Ixybuf {
     pub ptr: *mut pkt_buf,
@@ -16,11 +34,29 @@ pub struct Ixybuf {
 
 impl RuntimeBuf for Ixybuf{
     fn empty() -> Self {
-        todo!()
+        Self::from_slice(&[]).expect("Mempool exhausted")
     }
 
-    fn from_slice(_: &[u8]) -> Self {
-        todo!()
+    fn from_slice(bytes: &[u8]) -> Result<Self, Fail> {
+        if bytes.len() > ENTRY_SIZE - PKT_BUF_HEADER_SIZE - HEADROOM {
+            return Err(Fail::ResourceExhausted {
+                details: "Buffer too large for mempool entry",
+            });
+        }
+        let mempool = LOCAL_MEMPOOL.with(|m| m.get());
+        assert!(!mempool.is_null(), "No mempool on this thread");
+        let ptr = unsafe { pkt_buf_alloc(mempool) };
+        if ptr.is_null() {
+            return Err(Fail::ResourceExhausted {
+                details: "Mempool exhausted",
+            });
+        }
+        let mut buf = Ixybuf{ptr, data_offset: HEADROOM, data_length: bytes.len()};
+        unsafe {
+            slice::from_raw_parts_mut(buf.buf_addr_phy(), bytes.len()).copy_from_slice(bytes);
+        }
+        buf.setbufsize(bytes.len());
+        Ok(buf)
     }
 
     fn adjust(&mut self, num_bytes: usize) {
@@ -46,9 +82,11 @@ impl RuntimeBuf for Ixybuf{
     }
 }
 
+// Buffers are not shared: cloning copies the data into a buffer of the local mempool, which has
+// no way to report that the mempool is exhausted.
 impl Clone for Ixybuf {
     fn clone(&self) -> Self {
-        todo!()
+        Self::from_slice(&self[..]).expect("Mempool exhausted")
     }
 }
 
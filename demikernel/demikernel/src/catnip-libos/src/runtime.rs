@@ -1,4 +1,4 @@
-use crate::memory::{Ixybuf};
+use crate::memory::{self, Ixybuf};
 use arrayvec::ArrayVec;
 use catnip::{
     collections::bytes::{Bytes, BytesMut},
@@ -31,7 +31,7 @@ use std::{
 };
 use std::os::raw::{c_char, c_int, c_void};
 use ixy_rs::{
-    ixy_tx_batch,memory_allocate_mempool,ixy_rx_batch, ixy_rx_batch_hints, mqnic_port_reset_monitor, mqnic_port_set_monitor, mqnic_rearm_monitor, ixy_init,pkt_buf_alloc,pkt_buf_free, ixy_device, mempool, nic_hints,pkt_buf,register_app, mqnic_rx_feedback,config_app_mat,mqnic_rearm_scale_down_monitor,
+    ixy_tx_batch,memory_allocate_mempool,ixy_rx_batch, ixy_rx_batch_hints, mqnic_port_reset_monitor, mqnic_port_set_monitor, mqnic_rearm_monitor, ixy_init,pkt_buf_alloc,pkt_buf_free, ixy_device, mempool, nic_hints,pkt_buf,register_app, deregister_app, mqnic_rx_feedback,config_app_mat,mqnic_rearm_scale_down_monitor,
 };
 
 
@@ -54,6 +54,7 @@ impl IxyRuntime {
     pub fn new(
         link_addr: MacAddress,
         ipv4_addr: Ipv4Addr,
+        ipv4_netmask: Ipv4Addr,
         arp_table: HashMap<Ipv4Addr, MacAddress>,
         disable_arp: bool,
         mss: usize,
@@ -83,12 +84,14 @@ impl IxyRuntime {
 
         let mut udp_options = udp::Options::new(udp_checksum_offload, udp_checksum_offload);
         let mempool = unsafe {memory_allocate_mempool(1024, 2048)};
+        memory::set_local_mempool(mempool);
 
         let mut nic_hints_array: [nic_hints; RECEIVE_BATCH_SIZE] = unsafe { mem::zeroed() };
         let inner = Inner {
             timer: TimerRc(Rc::new(Timer::new(now))),
             link_addr,
             ipv4_addr,
+            ipv4_netmask,
             rng,
             arp_options,
             tcp_options,
@@ -104,18 +107,6 @@ impl IxyRuntime {
         }
     }
 
-    pub fn register_app(&self, queue_id:u16, app_id:u16, priority:u8){
-        let mut inner = self.inner.borrow_mut();
-        unsafe{
-            register_app(
-                inner.device.ptr,
-                queue_id,
-                app_id,
-                priority,
-            )
-        }
-    }
-
 
     pub fn reset_all_monitors(&self){
         let mut inner = self.inner.borrow_mut();
@@ -162,37 +153,13 @@ impl IxyRuntime {
         }
     }
 
-    
-    
-    pub fn config_app_mat(&self, app_id:u16, port_id:u16, priority:u8){
-        let mut inner = self.inner.borrow_mut();
-        unsafe{
-            config_app_mat(
-                inner.device.ptr,
-                app_id,
-                port_id,
-                priority,
-            )
-        }
-    }
-
-    pub fn send_app_feedback(&self, queue_id:u16, app_id:u16, update_count:u16){
-        let mut inner = self.inner.borrow_mut();
-        unsafe{
-            mqnic_rx_feedback(
-                inner.device.ptr,
-                queue_id,
-                app_id,
-                update_count,
-            )
-        }
-    }
 }
 
 struct Inner {
     timer: TimerRc,
     link_addr: MacAddress,
     ipv4_addr: Ipv4Addr,
+    ipv4_netmask: Ipv4Addr,
     rng: SmallRng,
     arp_options: arp::Options,
     tcp_options: tcp::Options<IxyRuntime>,
@@ -547,6 +514,10 @@ impl Runtime for IxyRuntime {
         self.inner.borrow().ipv4_addr.clone()
     }
 
+    fn local_ipv4_netmask(&self) -> Ipv4Addr {
+        self.inner.borrow().ipv4_netmask
+    }
+
     fn tcp_options(&self) -> tcp::Options<Self> {
         self.inner.borrow().tcp_options.clone()
     }
@@ -594,6 +565,26 @@ impl Runtime for IxyRuntime {
         slice.shuffle(&mut inner.rng);
     }
 
+    fn config_app_mat(&self, app_id: u16, port: u16, priority: u8) {
+        let inner = self.inner.borrow();
+        unsafe { config_app_mat(inner.device.ptr, app_id, port, priority) }
+    }
+
+    fn register_app(&self, queue_id: u16, app_id: u16, priority: u8) {
+        let inner = self.inner.borrow();
+        unsafe { register_app(inner.device.ptr, queue_id, app_id, priority) }
+    }
+
+    fn deregister_app(&self, queue_id: u16, app_id: u16) {
+        let inner = self.inner.borrow();
+        unsafe { deregister_app(inner.device.ptr, queue_id, app_id) }
+    }
+
+    fn send_app_feedback(&self, queue_id: u16, app_id: u16, count: u16) {
+        let inner = self.inner.borrow();
+        unsafe { mqnic_rx_feedback(inner.device.ptr, queue_id, app_id, count) }
+    }
+
     fn spawn<F: Future<Output = ()> + 'static>(&self, future: F) -> SchedulerHandle {
         self.scheduler
             .insert(Operation::Background(future.boxed_local()))